@@ -30,7 +30,7 @@ fn main() {
 
     // Call some functions on the `IAnimal` interface
     let food = Food { deliciousness: 10 };
-    unsafe { animal.eat(&food) };
+    unsafe { animal.eat(food) };
     assert!(unsafe { animal.happiness() } == 10);
 
     // Get a handle to new interface `IDomesticAnimal` which is actually implemented
@@ -58,17 +58,17 @@ fn main() {
     unsafe { domestic_animal_two.train() };
 
     // Call a method on a parent interface
-    unsafe { domestic_animal.eat(&food) };
+    unsafe { domestic_animal.eat(food) };
 
     // Directly cast a child interface into a parent without going through `query_interface`
     let animal: IAnimal = domestic_animal.into();
-    unsafe { animal.eat(&food) };
+    unsafe { animal.eat(food) };
 
     // Get another instance of `BritishShortHairCat` from the factory
     let cat = create_instance::<ICat>(&CLSID_CAT_CLASS)
         .unwrap_or_else(|hr| panic!("Failed to get a cat {:x}", hr));
     println!("Got another cat");
-    unsafe { cat.eat(&food) };
+    unsafe { cat.eat(food) };
 
     assert!(animal.get_interface::<ICat>().is_some());
     assert!(animal.get_interface::<IUnknown>().is_some());
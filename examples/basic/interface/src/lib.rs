@@ -20,7 +20,7 @@ pub const CLSID_CAT_CLASS: IID = IID {
 };
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, com::AbiTransferable)]
 pub struct Food {
     pub deliciousness: usize,
 }
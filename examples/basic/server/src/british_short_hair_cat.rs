@@ -6,7 +6,7 @@ use com::sys::{HRESULT, NOERROR};
 class! {
     /// The implementation class
     /// https://en.wikipedia.org/wiki/British_Shorthair
-    pub class BritishShortHairCat: IDomesticAnimal(IAnimal), ICat(IAnimal) {
+    pub class BritishShortHairCat: IDomesticAnimal, ICat {
         happiness: std::cell::Cell<usize>,
     }
 
@@ -25,9 +25,7 @@ class! {
     }
 
     impl IAnimal for BritishShortHairCat {
-        fn eat(&self, food: *const Food) -> HRESULT {
-            assert!(!food.is_null());
-            let food = unsafe { *food };
+        fn eat(&self, food: Food) -> HRESULT {
             println!("Eating...");
             self.happiness.set(self.happiness.get() + food.deliciousness);
             NOERROR
@@ -3,4 +3,9 @@ mod british_short_hair_cat;
 use british_short_hair_cat::BritishShortHairCat;
 use interface::CLSID_CAT_CLASS;
 
-com::inproc_dll_module![(CLSID_CAT_CLASS, BritishShortHairCat),];
+com::inproc_dll_module![(
+    CLSID_CAT_CLASS,
+    BritishShortHairCat,
+    prog_id = "Animals.BritishShortHairCat.1",
+    version_independent_prog_id = "Animals.BritishShortHairCat"
+)];
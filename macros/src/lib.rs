@@ -1,6 +1,7 @@
 use com_macros_support::interface::expand_interfacess;
 use com_macros_support::Class;
 use com_macros_support::Interfaces;
+use com_macros_support::Mock;
 
 extern crate proc_macro;
 use proc_macro::TokenStream;
@@ -18,3 +19,21 @@ pub fn class(input: TokenStream) -> TokenStream {
     let class = syn::parse_macro_input!(input as Class);
     class.to_tokens().into()
 }
+
+#[proc_macro_derive(AbiTransferable)]
+pub fn derive_abi_transferable(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+    match com_macros_support::abi_transferable::derive(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[proc_macro]
+pub fn mock(input: TokenStream) -> TokenStream {
+    let mock = syn::parse_macro_input!(input as Mock);
+    match mock.to_tokens() {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
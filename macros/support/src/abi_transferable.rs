@@ -0,0 +1,65 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+
+/// `#[derive(AbiTransferable)]` for `#[repr(C)]` structs passed by value
+///
+/// The struct is its own ABI type, so it also has to be `Copy` and each of its fields has
+/// to be `AbiTransferable` with itself as the ABI type.
+pub fn derive(input: &syn::DeriveInput) -> syn::Result<TokenStream> {
+    if let syn::Data::Enum(_) | syn::Data::Union(_) = input.data {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "only structs can derive `AbiTransferable`",
+        ));
+    }
+    if !has_stable_layout(&input.attrs)? {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "structs deriving `AbiTransferable` must be `#[repr(C)]` or `#[repr(transparent)]`",
+        ));
+    }
+    let fields = match &input.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => unreachable!(),
+    };
+    // Every field is copied as is, so it has to be its own ABI type as well
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for field in fields {
+        let ty = &field.ty;
+        let predicate = quote::quote_spanned! {ty.span()=>
+            #ty: ::com::AbiTransferable<Abi = #ty>
+        };
+        where_clause.predicates.push(syn::parse_quote!(#predicate));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        unsafe impl #impl_generics ::com::AbiTransferable for #name #ty_generics #where_clause {
+            type Abi = Self;
+            fn get_abi(&self) -> Self::Abi {
+                *self
+            }
+            fn set_abi(&mut self) -> *mut Self::Abi {
+                self as *mut Self::Abi
+            }
+        }
+    })
+}
+
+fn has_stable_layout(attrs: &[syn::Attribute]) -> syn::Result<bool> {
+    for attr in attrs.iter().filter(|a| a.path.is_ident("repr")) {
+        if let syn::Meta::List(list) = attr.parse_meta()? {
+            for nested in &list.nested {
+                if let syn::NestedMeta::Meta(syn::Meta::Path(path)) = nested {
+                    if path.is_ident("C") || path.is_ident("transparent") {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+    }
+    Ok(false)
+}
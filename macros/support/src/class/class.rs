@@ -2,12 +2,16 @@ use proc_macro2::{Ident, TokenStream};
 use quote::quote;
 use syn::spanned::Spanned;
 
+use crate::interface::SizedArray;
+
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 
 pub struct Class {
     pub name: Ident,
+    pub generics: syn::Generics,
     pub has_class_factory: bool,
+    pub factory: super::class_factory::Factory,
     pub docs: Vec<syn::Attribute>,
     pub visibility: syn::Visibility,
     pub interfaces: Vec<Interface>,
@@ -20,11 +24,40 @@ impl Class {
         let mut out: Vec<TokenStream> = Vec::new();
         out.push(self.to_struct_tokens());
         out.push(self.to_class_trait_impl_tokens());
+        out.push(self.to_vtable_tokens());
         out.push(super::class_factory::generate(self));
 
         TokenStream::from_iter(out)
     }
 
+    /// The generics of the class with the offset parameter `O` of a thunk added
+    ///
+    /// Thunks are nested functions, so they have to declare the class' generics themselves.
+    pub fn thunk_generics(&self) -> syn::Generics {
+        let mut generics = self.generics.clone();
+        let lifetimes = generics.lifetimes().count();
+        generics.params.insert(
+            lifetimes,
+            syn::parse_quote!(O: ::com::production::VTableOffset),
+        );
+        generics
+    }
+
+    /// The generic arguments for calling a thunk declared with `thunk_generics`
+    ///
+    /// Lifetimes are left out so that they are inferred.
+    pub fn thunk_turbofish(&self) -> TokenStream {
+        let types = self.generics.type_params().map(|p| &p.ident);
+        quote! { ::<O, #(#types),*> }
+    }
+
+    /// Whether objects of the class keep the server module in use
+    ///
+    /// Singletons are cached by the module and would otherwise keep it in use forever.
+    pub fn locks_module(&self) -> bool {
+        self.has_class_factory && !self.factory.singleton
+    }
+
     /// Get the paths of all interfaces including parent interfaces
     fn interfaces_paths<'a>(&'a self) -> HashSet<&'a syn::Path> {
         fn get_interface<'a>(interface: &'a Interface, result: &mut HashSet<&'a syn::Path>) {
@@ -45,15 +78,17 @@ impl Class {
         input: syn::parse::ParseStream,
         docs: Vec<syn::Attribute>,
         has_class_factory: bool,
+        factory: super::class_factory::Factory,
     ) -> syn::Result<Self> {
         let mut interfaces: Vec<Interface> = Vec::new();
         let visibility = input.parse::<syn::Visibility>()?;
 
         let _ = input.parse::<keywords::class>()?;
         let name = input.parse::<Ident>()?;
+        let mut generics = input.parse::<syn::Generics>()?;
         let _ = input.parse::<syn::Token!(:)>()?;
 
-        while !input.peek(syn::token::Brace) {
+        while !input.peek(syn::token::Brace) && !input.peek(syn::Token!(where)) {
             let path = input.parse::<syn::Path>()?;
             let interface = Interface {
                 path: path.clone(),
@@ -74,10 +109,36 @@ impl Class {
                 current = current.parent.as_mut().unwrap().as_mut();
             }
 
-            if !input.peek(syn::token::Brace) {
+            if !input.peek(syn::token::Brace) && !input.peek(syn::Token!(where)) {
                 let _ = input.parse::<syn::Token!(,)>()?;
             }
         }
+        generics.where_clause = input.parse::<Option<syn::WhereClause>>()?;
+        if has_class_factory && !generics.params.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &generics,
+                "generic classes cannot have a class factory, add `#[no_class_factory]`",
+            ));
+        }
+        if let Some(lifetime) = generics.lifetimes().next() {
+            return Err(syn::Error::new_spanned(
+                lifetime,
+                "classes cannot have lifetime parameters as references to them can outlive any borrow",
+            ));
+        }
+        // Interfaces queried from an instance have no lifetime, so the class may not borrow
+        let params = generics
+            .type_params()
+            .map(|param| param.ident.clone())
+            .collect::<Vec<_>>();
+        if !params.is_empty() {
+            let where_clause = generics.make_where_clause();
+            for param in params {
+                where_clause
+                    .predicates
+                    .push(syn::parse_quote!(#param: 'static));
+            }
+        }
         let fields;
         syn::braced!(fields in input);
         let fields =
@@ -89,7 +150,9 @@ impl Class {
 
         Ok(Class {
             name,
+            generics,
             has_class_factory,
+            factory,
             docs,
             visibility,
             interfaces,
@@ -111,6 +174,8 @@ impl Class {
     pub fn to_struct_tokens(&self) -> TokenStream {
         let name = &self.name;
         let vis = &self.visibility;
+        let generics = &self.generics;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
 
         let interfaces = &self.interfaces;
         let interface_fields = interfaces.iter().enumerate().map(|(index, interface)| {
@@ -124,7 +189,11 @@ impl Class {
 
         let user_fields = &self.fields;
         let docs = &self.docs;
-        let methods = self.methods.values().flat_map(|ms| ms);
+        let methods = self
+            .methods
+            .values()
+            .flat_map(|ms| ms)
+            .map(strip_array_attributes);
 
         let iunknown = super::iunknown_impl::IUnknown::new();
         let add_ref = iunknown.to_add_ref_tokens();
@@ -137,33 +206,93 @@ impl Class {
                 let _ = ::std::boxed::Box::from_raw(self.#field_ident.as_ptr());
             }
         });
+        let track_drop = super::diagnostics::drop();
+        let unlock_module = if self.locks_module() {
+            quote! {
+                // The lock was taken when the object was allocated
+                let _ = ::com::production::module::unlock();
+            }
+        } else {
+            TokenStream::new()
+        };
 
         quote! {
             use super::*;
             #(#docs)*
             #[repr(C)]
-            #vis struct #name {
+            #vis struct #name #generics #where_clause {
                 #(#interface_fields,)*
-                #ref_count_ident: ::std::cell::Cell<u32>,
+                #ref_count_ident: ::std::sync::atomic::AtomicU32,
                 #(#user_fields),*
             }
-            impl #name {
+            impl #impl_generics #name #ty_generics #where_clause {
                 #constructor
                 #(#methods)*
                 #add_ref
                 #query_interface
                 #query
             }
-            impl ::std::ops::Drop for #name {
+            impl #impl_generics ::std::ops::Drop for #name #ty_generics #where_clause {
                 fn drop(&mut self) {
+                    #track_drop
                     unsafe {
                         #(#interface_drops)*
                     }
+                    #unlock_module
                 }
             }
         }
     }
 
+    /// The vtables of all implemented interfaces and the offsets of the interface pointers
+    pub fn to_vtable_tokens(&self) -> TokenStream {
+        let offsets = self.interfaces.iter().enumerate().map(|(index, _)| {
+            let offset_ident = Interface::offset_ident(self, index);
+            quote! {
+                #[doc(hidden)]
+                #[allow(non_camel_case_types)]
+                struct #offset_ident;
+                impl ::com::production::VTableOffset for #offset_ident {
+                    const OFFSET: usize = #index;
+                }
+            }
+        });
+        let iunknown = Interface::to_iunknown_vtable_impl_tokens(self);
+        let vtables = self
+            .methods
+            .iter()
+            .map(|(path, methods)| Interface::to_vtable_impl_tokens(self, path, methods));
+        let assertions = self
+            .interfaces
+            .iter()
+            .map(|i| i.to_parent_assertion_tokens());
+        // Agile interfaces require the fields to be thread safe
+        let class_fields = self.interfaces.iter().flat_map(|i| {
+            let interface = &i.path;
+            self.fields.iter().map(move |f| {
+                let ty = &f.ty;
+                quote::quote_spanned! {ty.span()=>
+                    ::com::production::assert_class_field::<#ty, #interface>();
+                }
+            })
+        });
+        // Declared with the class' generics, so that the fields can use them
+        let (impl_generics, _, where_clause) = self.generics.split_for_impl();
+
+        quote! {
+            #(#offsets)*
+            #iunknown
+            #(#vtables)*
+            const _: () = {
+                #[allow(dead_code)]
+                fn assertions #impl_generics () #where_clause {
+                    #(#assertions)*
+                    #(#class_fields)*
+                }
+            };
+        }
+    }
+
     pub fn to_class_trait_impl_tokens(&self) -> TokenStream {
         let name = &self.name;
         let factory = if self.has_class_factory {
@@ -173,14 +302,23 @@ impl Class {
             quote! { () }
         };
         let ref_count_ident = crate::utils::ref_count_ident();
+        let track_release = super::diagnostics::release();
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
 
         quote! {
-            unsafe impl com::production::Class for #name {
+            unsafe impl #impl_generics com::production::Class for #name #ty_generics #where_clause {
                 type Factory = #factory;
 
                 fn dec_ref_count(&self) -> u32 {
-                    let count = self.#ref_count_ident.get().checked_sub(1).expect("Underflow of reference count");
-                    self.#ref_count_ident.set(count);
+                    let count = self.#ref_count_ident
+                        .fetch_sub(1, ::std::sync::atomic::Ordering::Release)
+                        .checked_sub(1)
+                        .expect("Underflow of reference count");
+                    if count == 0 {
+                        // Synchronize with the releases on other threads before the drop
+                        ::std::sync::atomic::fence(::std::sync::atomic::Ordering::Acquire);
+                    }
+                    #track_release
                     count
                 }
             }
@@ -196,18 +334,46 @@ impl syn::parse::Parse for Class {
             let attributes = input.call(syn::Attribute::parse_outer)?;
             let mut docs = Vec::with_capacity(attributes.len());
             let mut has_class_factory = true;
+            let mut factory = None;
+            let mut singleton = None;
             for attr in attributes {
                 if attr.path.is_ident("doc") {
                     docs.push(attr)
                 } else if attr.path.is_ident("no_class_factory") {
                     has_class_factory = false;
+                } else if attr.path.is_ident("factory") && factory.is_none() {
+                    factory = Some((
+                        attr.parse_args::<super::class_factory::Factory>()?,
+                        attr.path.span(),
+                    ));
+                } else if attr.path.is_ident("singleton") && singleton.is_none() {
+                    singleton = Some(attr.path.span());
                 } else {
                     return Err(syn::Error::new(attr.path.span(), "Unrecognized attribute"));
                 }
             }
 
             if !input.peek(syn::Token!(impl)) {
-                class = Some(Self::parse_class(input, docs, has_class_factory)?);
+                let mut factory = match factory {
+                    Some((_, span)) if !has_class_factory => {
+                        return Err(syn::Error::new(
+                            span,
+                            "`#[factory]` cannot be combined with `#[no_class_factory]`",
+                        ))
+                    }
+                    Some((factory, _)) => factory,
+                    None => super::class_factory::Factory::default(),
+                };
+                if let Some(span) = singleton {
+                    if !has_class_factory {
+                        return Err(syn::Error::new(
+                            span,
+                            "`#[singleton]` cannot be combined with `#[no_class_factory]`",
+                        ));
+                    }
+                    factory.singleton = true;
+                }
+                class = Some(Self::parse_class(input, docs, has_class_factory, factory)?);
             } else {
                 let item = input.parse::<syn::ItemImpl>()?;
                 // TODO: ensure that class idents line up
@@ -231,6 +397,11 @@ impl syn::parse::Parse for Class {
                         )),
                     })
                     .collect::<syn::Result<Vec<_>>>()?;
+                for m in &ms {
+                    for p in typed_params(m) {
+                        SizedArray::parse(&p.attrs, &p.ty)?;
+                    }
+                }
 
                 if let Some(_) = methods.insert(interface.clone(), ms) {
                     return Err(syn::Error::new(
@@ -242,16 +413,13 @@ impl syn::parse::Parse for Class {
         }
         let mut class = match class {
             Some(c) => {
-                let mut interface_paths = c.interfaces_paths();
-                for i in methods.keys() {
-                    if !interface_paths.remove(i) {
-                        return Err(syn::Error::new(
-                            i.span().clone(),
-                            "impl for a non-declared interface",
-                        ));
-                    }
-                }
-                if let Some(i) = interface_paths.into_iter().next() {
+                // Impls for undeclared interfaces are allowed as they may be parents of the
+                // declared ones
+                let interface_paths = c.interfaces_paths();
+                if let Some(i) = interface_paths
+                    .into_iter()
+                    .find(|i| !methods.contains_key(*i))
+                {
                     return Err(syn::Error::new(
                         i.span().clone(),
                         "impl for interface is missing",
@@ -271,6 +439,30 @@ impl syn::parse::Parse for Class {
     }
 }
 
+/// The type implementing `::com::Thunk` for the method `name`
+fn thunk_marker_ident(name: &Ident) -> Ident {
+    quote::format_ident!("__{}Thunk", crate::utils::snake_to_camel(&name.to_string()))
+}
+
+/// The arguments of an interface method implementation other than `self`
+fn typed_params(method: &syn::ImplItemMethod) -> impl Iterator<Item = &syn::PatType> {
+    method.sig.inputs.iter().filter_map(|p| match p {
+        syn::FnArg::Receiver(_) => None,
+        syn::FnArg::Typed(p) => Some(p),
+    })
+}
+
+/// The method without `#[size_is]` and `#[length_is]`, which only concern its thunk
+fn strip_array_attributes(method: &syn::ImplItemMethod) -> syn::ImplItemMethod {
+    let mut method = method.clone();
+    for p in method.sig.inputs.iter_mut() {
+        if let syn::FnArg::Typed(p) = p {
+            p.attrs.retain(|a| !SizedArray::is_attribute(a));
+        }
+    }
+    method
+}
+
 mod keywords {
     syn::custom_keyword!(class);
     syn::custom_keyword!(factory);
@@ -282,86 +474,125 @@ pub struct Interface {
 }
 
 impl Interface {
-    /// Creates an intialized VTable for the interface
-    pub fn to_initialized_vtable_tokens(&self, class: &Class, offset: usize) -> TokenStream {
-        let class_name = &class.name;
-        let vtable_ident = self.vtable_ident();
-        let vtable_type = self.to_vtable_type_tokens();
-        let parent = match self.parent.as_ref() {
-            Some(p) => p.to_initialized_vtable_tokens(class, offset),
-            None => Self::iunknown_tokens(class, offset),
+    /// The marker type for the offset of the interface pointer at `index`
+    pub fn offset_ident(class: &Class, index: usize) -> Ident {
+        quote::format_ident!("__{}Offset{}", class.name, index)
+    }
+
+    /// Checks that explicitly given parents match `Interface::Super`
+    fn to_parent_assertion_tokens(&self) -> TokenStream {
+        let parent = match &self.parent {
+            Some(p) => p,
+            None => return TokenStream::new(),
         };
-        let fields = class.methods.get(&self.path).unwrap().iter().map(|m| {
+        let path = &self.path;
+        let parent_path = &parent.path;
+        let assertion = quote::quote_spanned! {parent_path.span()=>
+            ::com::production::assert_parent::<#path, #parent_path>();
+        };
+        let parent_assertion = parent.to_parent_assertion_tokens();
+        quote! {
+            #assertion
+            #parent_assertion
+        }
+    }
+
+    /// The vtable of the interface built with the class' methods
+    ///
+    /// The vtable of the parent interface is built through its `ClassVTable` impl, which
+    /// `interfaces!` provides for `IUnknown` interfaces.
+    fn to_vtable_impl_tokens(
+        class: &Class,
+        path: &syn::Path,
+        methods: &[syn::ImplItemMethod],
+    ) -> TokenStream {
+        let class_name = &class.name;
+        let (_, ty_generics, where_clause) = class.generics.split_for_impl();
+        let thunk_generics = class.thunk_generics();
+        let (impl_generics, _, _) = thunk_generics.split_for_impl();
+        let turbofish = class.thunk_turbofish();
+        let vtable_ident = quote::format_ident!("{}VTable", path.segments.last().unwrap().ident);
+        let (_, thunk_ty_generics, _) = thunk_generics.split_for_impl();
+        let this_ptr = quote! { ::std::ptr::NonNull<::std::ptr::NonNull<#vtable_ident>> };
+        let thunks = methods.iter().map(|m| {
             let name = &m.sig.ident;
-            let params = m.sig.inputs.iter().filter_map(|p| {
-                match p {
-                    syn::FnArg::Receiver(_) => None,
-                    syn::FnArg::Typed(p) => Some(p),
-                }
-            });
-            let args = m.sig.inputs.iter().filter_map(|p| {
-                match p {
-                    syn::FnArg::Receiver(_) => None,
-                    syn::FnArg::Typed(p) => Some(&p.pat),
-                }
-            });
-            let ret = &m.sig.output;
-            let method = quote! {
-                unsafe extern "stdcall" fn #name(this: ::std::ptr::NonNull<::std::ptr::NonNull<#vtable_ident>>, #(#params),*) #ret {
-                    let this = this.as_ptr().sub(#offset);
-                    let this = ::std::mem::ManuallyDrop::new(::com::production::ClassAllocation::from_raw(this as *mut _ as *mut #class_name));
-                    #class_name::#name(&this, #(#args),*)
+            let marker = thunk_marker_ident(name);
+            let mut slices = Vec::new();
+            let tys = typed_params(m).map(|p| {
+                let pat = &p.pat;
+                let ty = &p.ty;
+                // Validated while parsing
+                let array = match SizedArray::parse(&p.attrs, ty) {
+                    Ok(Some(array)) => array,
+                    _ => return quote! { #ty },
+                };
+                let elem = &array.elem;
+                let size = &array.size_is;
+                if array.mutable {
+                    slices.push(quote! {
+                        let #pat: #ty = if #pat.is_null() || #size == 0 {
+                            &mut []
+                        } else {
+                            <#elem as ::com::AbiTransferable>::slice_from_mut_abi(#pat, #size as usize)
+                        };
+                    });
+                    quote! { *mut <#elem as ::com::AbiTransferable>::Abi }
+                } else {
+                    slices.push(quote! {
+                        let #pat: #ty = if #pat.is_null() || #size == 0 {
+                            &[]
+                        } else {
+                            <#elem as ::com::AbiTransferable>::slice_from_abi(#pat, #size as usize)
+                        };
+                    });
+                    quote! { *const <#elem as ::com::AbiTransferable>::Abi }
                 }
+            }).collect::<Vec<_>>();
+            let args = typed_params(m).map(|p| &p.pat).collect::<Vec<_>>();
+            let ret = match &m.sig.output {
+                syn::ReturnType::Default => quote! { () },
+                syn::ReturnType::Type(_, ty) => quote! { #ty },
             };
-            let field_name = Ident::new(&crate::utils::snake_to_camel(&name.to_string()), proc_macro2::Span::call_site());
+            // The interface's thunk for the method calls into this with its calling convention
             quote! {
-                #field_name: {
-                    #method
-                    #name
+                struct #marker #impl_generics (::std::marker::PhantomData<(O, fn() -> #class_name #ty_generics)>) #where_clause;
+                impl #impl_generics ::com::Thunk<#this_ptr, (#(#tys,)*), #ret> for #marker #thunk_ty_generics #where_clause {
+                    unsafe fn call(this: #this_ptr, (#(#args,)*): (#(#tys,)*)) -> #ret {
+                        let this = this.as_ptr().sub(<O as ::com::production::VTableOffset>::OFFSET);
+                        let this = ::std::mem::ManuallyDrop::new(::com::production::ClassAllocation::from_raw(this as *mut _ as *mut #class_name #ty_generics));
+                        #(#slices)*
+                        <#class_name #ty_generics>::#name(&this, #(#args),*)
+                    }
                 }
             }
         });
+        let fields = methods.iter().map(|m| {
+            let name = &m.sig.ident;
+            let field_name = Ident::new(
+                &crate::utils::snake_to_camel(&name.to_string()),
+                proc_macro2::Span::call_site(),
+            );
+            let thunk = crate::interface::vtable::thunk_ident(&field_name);
+            let marker = thunk_marker_ident(name);
+            quote! {
+                #field_name: <#vtable_ident>::#thunk::<#marker #turbofish>
+            }
+        });
         quote! {
-            {
-                #vtable_type
-                #vtable_ident {
-                    parent: #parent,
-                    #(#fields),*
+            unsafe impl #impl_generics ::com::ClassVTable<#class_name #ty_generics, O> for #path #where_clause {
+                fn vtable() -> <#path as ::com::Interface>::VTable {
+                    type #vtable_ident = <#path as ::com::Interface>::VTable;
+                    #(#thunks)*
+                    #vtable_ident {
+                        parent: <<#path as ::com::Interface>::Super as ::com::ClassVTable<#class_name #ty_generics, O>>::vtable(),
+                        #(#fields),*
+                    }
                 }
             }
         }
     }
 
-    fn to_vtable_type_tokens(&self) -> TokenStream {
-        let name = &self.path;
-        let vtable_ident = self.vtable_ident();
-        quote! {
-            type #vtable_ident = <#name as ::com::Interface>::VTable;
-        }
-    }
-
-    fn vtable_ident(&self) -> proc_macro2::Ident {
-        let name = &self.path;
-        quote::format_ident!("{}VTable", name.segments.last().unwrap().ident)
-    }
-
-    fn iunknown_tokens(class: &Class, offset: usize) -> TokenStream {
-        let iunknown = super::iunknown_impl::IUnknownAbi::new(class.name.clone(), offset);
-        let add_ref = iunknown.to_add_ref_tokens();
-        let release = iunknown.to_release_tokens();
-        let query_interface = iunknown.to_query_interface_tokens();
-        quote! {
-            {
-                type IUknownVTable = <::com::interfaces::IUnknown as ::com::Interface>::VTable;
-                #add_ref
-                #release
-                #query_interface
-                IUknownVTable {
-                    AddRef: add_ref,
-                    Release: release,
-                    QueryInterface: query_interface,
-                }
-            }
-        }
+    fn to_iunknown_vtable_impl_tokens(class: &Class) -> TokenStream {
+        super::iunknown_impl::IUnknownAbi::new(class).to_thunks_impl_tokens()
     }
 }
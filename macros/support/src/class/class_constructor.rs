@@ -20,6 +20,13 @@ pub fn generate(class: &Class) -> TokenStream {
 
     let interfaces = &class.interfaces;
     let interface_fields = gen_allocate_interface_fields(interfaces);
+    // Objects of classes with a class factory keep the server module in use
+    let track_allocation = super::diagnostics::allocation(name);
+    let lock_module = if class.locks_module() {
+        quote! { ::com::production::module::lock(); }
+    } else {
+        TokenStream::new()
+    };
 
     quote! {
         /// Allocate the class casting it to the supplied interface
@@ -31,10 +38,12 @@ pub fn generate(class: &Class) -> TokenStream {
             #interface_inits
             let instance = #name {
                 #interface_fields
-                #ref_count_ident: ::std::cell::Cell::new(1),
+                #ref_count_ident: ::std::sync::atomic::AtomicU32::new(1),
                 #(#user_fields),*
             };
             let instance = ::std::boxed::Box::pin(instance);
+            #track_allocation
+            #lock_module
             ::com::production::ClassAllocation::new(instance)
         }
     }
@@ -56,10 +65,12 @@ fn gen_vpointer_inits(class: &Class) -> TokenStream {
         .iter()
         .enumerate()
         .map(move |(index,  interface)| {
-            let interface = interface.to_initialized_vtable_tokens(class, index);
+            let path = &interface.path;
+            let offset_ident = Interface::offset_ident(class, index);
             let vptr_field_ident = quote::format_ident!("__{}", index);
             quote! {
-                let #vptr_field_ident = unsafe { ::std::ptr::NonNull::new_unchecked(::std::boxed::Box::into_raw(::std::boxed::Box::new(#interface))) };
+                let #vptr_field_ident = <#path as ::com::ClassVTable<Self, #offset_ident>>::vtable();
+                let #vptr_field_ident = unsafe { ::std::ptr::NonNull::new_unchecked(::std::boxed::Box::into_raw(::std::boxed::Box::new(#vptr_field_ident))) };
             }
         });
 
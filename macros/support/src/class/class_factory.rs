@@ -1,6 +1,55 @@
 use super::Class;
 use proc_macro2::TokenStream;
 use quote::quote;
+use syn::spanned::Spanned;
+
+/// The options of `#[factory(init = path, state = Type)]` and `#[singleton]`
+///
+/// `init` creates the instances instead of `allocate` with default fields. It is called
+/// with a reference to the factory's `state` if there is one and returns
+/// `Result<ClassAllocation<Class>, HRESULT>`.
+///
+/// The factory of a singleton creates one instance that is cached by the module. Cached
+/// objects are shared by every thread that asks for them, so the fields of a singleton and
+/// the state of a factory must be `Send + Sync`.
+#[derive(Default)]
+pub struct Factory {
+    pub init: Option<syn::Path>,
+    pub state: Option<syn::Type>,
+    pub singleton: bool,
+}
+
+impl syn::parse::Parse for Factory {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut factory = Factory::default();
+        while !input.is_empty() {
+            let key = input.parse::<syn::Ident>()?;
+            let _ = input.parse::<syn::Token!(=)>()?;
+            if key == "init" && factory.init.is_none() {
+                factory.init = Some(input.parse()?);
+            } else if key == "state" && factory.state.is_none() {
+                factory.state = Some(input.parse()?);
+            } else {
+                return Err(syn::Error::new(
+                    key.span(),
+                    "expected `init = path` or `state = Type`",
+                ));
+            }
+            if !input.is_empty() {
+                let _ = input.parse::<syn::Token!(,)>()?;
+            }
+        }
+        if factory.init.is_none() {
+            if let Some(state) = &factory.state {
+                return Err(syn::Error::new(
+                    state.span(),
+                    "a factory with state needs an `init` function",
+                ));
+            }
+        }
+        Ok(factory)
+    }
+}
 
 pub fn generate(class: &Class) -> TokenStream {
     if !class.has_class_factory {
@@ -9,14 +58,58 @@ pub fn generate(class: &Class) -> TokenStream {
 
     let class_factory_ident = crate::utils::class_factory_ident(&class.name);
     let class_name = &class.name;
-    let user_fields = class.fields.iter().map(|f| {
-        let ty = &f.ty;
-        quote! { <#ty as ::std::default::Default>::default() }
-    });
+    let instance = match (&class.factory.init, &class.factory.state) {
+        (Some(init), Some(_)) => quote! { #init(&self.state) },
+        (Some(init), None) => quote! { #init() },
+        (None, _) => {
+            let user_fields = class.fields.iter().map(|f| {
+                let ty = &f.ty;
+                quote! { <#ty as ::std::default::Default>::default() }
+            });
+            quote! {
+                ::std::result::Result::<_, ::com::sys::HRESULT>::Ok(#class_name::allocate(#(#user_fields),*))
+            }
+        }
+    };
+    let create_instance = if class.factory.singleton {
+        quote! {
+            let instance = ::com::production::module::singleton::<#class_name, _>(|| {
+                let instance: ::std::result::Result<::com::production::ClassAllocation<#class_name>, ::com::sys::HRESULT> = #instance;
+                instance.map(|instance| instance.query::<::com::interfaces::IUnknown>().unwrap())
+            });
+        }
+    } else {
+        quote! {
+            let instance: ::std::result::Result<::com::production::ClassAllocation<#class_name>, ::com::sys::HRESULT> = #instance;
+        }
+    };
+    let shared = class
+        .factory
+        .state
+        .iter()
+        .chain(
+            class
+                .fields
+                .iter()
+                .map(|f| &f.ty)
+                .filter(|_| class.factory.singleton),
+        )
+        .map(|ty| {
+            quote::quote_spanned! {ty.span()=>
+                ::com::production::assert_thread_safe::<#ty>();
+            }
+        });
+    let state = class.factory.state.iter();
     quote! {
+        const _: fn() = || {
+            #(#shared)*
+        };
+
         ::com::class! {
             #[no_class_factory]
-            pub class #class_factory_ident: ::com::interfaces::IClassFactory {}
+            pub class #class_factory_ident: ::com::interfaces::IClassFactory {
+                #(state: #state)*
+            }
 
             impl ::com::interfaces::IClassFactory for #class_factory_ident {
                 unsafe fn CreateInstance(
@@ -26,15 +119,26 @@ pub fn generate(class: &Class) -> TokenStream {
                     ppv: *mut *mut ::std::ffi::c_void,
                 ) -> ::com::sys::HRESULT {
                     assert!(!riid.is_null(), "iid passed to CreateInstance was null");
-                    if aggr != ::std::ptr::null_mut() {
+                    if !aggr.is_null() {
                         return ::com::sys::CLASS_E_NOAGGREGATION;
                     }
 
-                    let instance = #class_name::allocate(#(#user_fields)*,);
-                    instance.query_interface(riid, ppv)
+                    #create_instance
+                    match instance {
+                        Ok(instance) => instance.query_interface(riid, ppv),
+                        Err(hr) => {
+                            *ppv = ::std::ptr::null_mut();
+                            hr
+                        }
+                    }
                 }
 
-                unsafe fn LockServer(&self, _increment: com::sys::BOOL) -> com::sys::HRESULT {
+                unsafe fn LockServer(&self, increment: com::sys::BOOL) -> com::sys::HRESULT {
+                    if increment != 0 {
+                        ::com::production::module::lock();
+                    } else if let Err(hr) = ::com::production::module::unlock() {
+                        return hr;
+                    }
                     ::com::sys::S_OK
                 }
             }
@@ -0,0 +1,50 @@
+//! Calls into `com::production::diagnostics` when live object tracking is enabled
+use proc_macro2::{Ident, TokenStream};
+use quote::quote;
+
+fn enabled() -> bool {
+    cfg!(feature = "diagnostics")
+}
+
+/// Register the freshly allocated `instance`
+pub fn allocation(name: &Ident) -> TokenStream {
+    if !enabled() {
+        return TokenStream::new();
+    }
+    quote! {
+        ::com::production::diagnostics::track_allocation(
+            concat!(module_path!(), "::", stringify!(#name)),
+            &*instance as *const Self as usize,
+        );
+    }
+}
+
+/// Record that `self` (a pinned box) now has `value` references
+pub fn add_ref() -> TokenStream {
+    if !enabled() {
+        return TokenStream::new();
+    }
+    quote! {
+        ::com::production::diagnostics::track_add_ref(&**self as *const Self as usize, value);
+    }
+}
+
+/// Record that `self` now has `count` references
+pub fn release() -> TokenStream {
+    if !enabled() {
+        return TokenStream::new();
+    }
+    quote! {
+        ::com::production::diagnostics::track_release(self as *const Self as usize, count);
+    }
+}
+
+/// Remove `self` from the live objects
+pub fn drop() -> TokenStream {
+    if !enabled() {
+        return TokenStream::new();
+    }
+    quote! {
+        ::com::production::diagnostics::track_drop(self as *const Self as usize);
+    }
+}
@@ -2,49 +2,75 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use syn::Ident;
 
-use super::class::Interface;
+use super::class::{Class, Interface};
 
+/// The `::com::IUnknownThunks` impl of a class
+///
+/// The impl is generic over the `::com::production::VTableOffset` of the interface pointer
+/// the methods are called through. The vtables of `IUnknown` interfaces call the methods
+/// through thunks with their own calling convention.
 pub struct IUnknownAbi {
     class_name: Ident,
-    offset: usize,
+    class_generics: syn::Generics,
+    thunk_generics: syn::Generics,
 }
 
 impl IUnknownAbi {
-    pub fn new(class_name: Ident, offset: usize) -> Self {
-        Self { class_name, offset }
+    pub fn new(class: &Class) -> Self {
+        Self {
+            class_name: class.name.clone(),
+            class_generics: class.generics.clone(),
+            thunk_generics: class.thunk_generics(),
+        }
     }
 
-    pub fn to_add_ref_tokens(&self) -> TokenStream {
+    pub fn to_thunks_impl_tokens(&self) -> TokenStream {
+        let class_name = &self.class_name;
+        let (_, ty_generics, where_clause) = self.class_generics.split_for_impl();
+        let (impl_generics, _, _) = self.thunk_generics.split_for_impl();
+        let add_ref = self.to_add_ref_tokens();
+        let release = self.to_release_tokens();
+        let query_interface = self.to_query_interface_tokens();
+
+        quote! {
+            unsafe impl #impl_generics ::com::IUnknownThunks<O> for #class_name #ty_generics #where_clause {
+                #query_interface
+                #add_ref
+                #release
+            }
+        }
+    }
+
+    fn to_add_ref_tokens(&self) -> TokenStream {
         let this_ptr = this_ptr_type();
         let munge = self.borrowed_pointer_munging();
 
         quote! {
-            unsafe extern "stdcall" fn add_ref(this: #this_ptr) -> u32 {
+            unsafe fn add_ref(this: #this_ptr) -> u32 {
                 #munge
                 munged.add_ref()
             }
         }
     }
 
-    pub fn to_release_tokens(&self) -> TokenStream {
+    fn to_release_tokens(&self) -> TokenStream {
         let this_ptr = this_ptr_type();
         let munge = self.owned_pointer_munging();
-        let ref_count_ident = crate::utils::ref_count_ident();
 
         quote! {
-            unsafe extern "stdcall" fn release(this: #this_ptr) -> u32 {
+            unsafe fn release(this: #this_ptr) -> u32 {
                 #munge
-                munged.#ref_count_ident.get().checked_sub(1).expect("Underflow of reference count")
+                ::com::production::ClassAllocation::release(munged)
             }
         }
     }
 
-    pub fn to_query_interface_tokens(&self) -> TokenStream {
+    fn to_query_interface_tokens(&self) -> TokenStream {
         let this_ptr = this_ptr_type();
         let munge = self.borrowed_pointer_munging();
 
         quote! {
-            unsafe extern "stdcall" fn query_interface(
+            unsafe fn query_interface(
                 this: #this_ptr,
                 riid: *const ::com::sys::IID,
                 ppv: *mut *mut ::std::ffi::c_void
@@ -56,12 +82,12 @@ impl IUnknownAbi {
     }
 
     fn owned_pointer_munging(&self) -> TokenStream {
-        let offset = self.offset;
         let class_name = &self.class_name;
+        let (_, ty_generics, _) = self.class_generics.split_for_impl();
 
         quote! {
-            let munged = this.as_ptr().sub(#offset);
-            let munged = ::com::production::ClassAllocation::from_raw(munged as *mut _ as *mut #class_name);
+            let munged = this.as_ptr().sub(<O as ::com::production::VTableOffset>::OFFSET);
+            let munged = ::com::production::ClassAllocation::from_raw(munged as *mut _ as *mut #class_name #ty_generics);
         }
     }
 
@@ -84,10 +110,15 @@ impl IUnknown {
 
     pub fn to_add_ref_tokens(&self) -> TokenStream {
         let ref_count_ident = crate::utils::ref_count_ident();
+        let track_add_ref = super::diagnostics::add_ref();
         quote! {
             pub unsafe fn add_ref(self: &::std::pin::Pin<::std::boxed::Box<Self>>) -> u32 {
-                let value = self.#ref_count_ident.get().checked_add(1).expect("Overflow of reference count");
-                self.#ref_count_ident.set(value);
+                // New references are made from existing ones, so no synchronization is needed
+                let value = self.#ref_count_ident
+                    .fetch_add(1, ::std::sync::atomic::Ordering::Relaxed)
+                    .checked_add(1)
+                    .expect("Overflow of reference count");
+                #track_add_ref
                 value
             }
         }
@@ -139,9 +170,9 @@ impl IUnknown {
 
     pub fn to_query_tokens(&self) -> TokenStream {
         quote! {
-            pub fn query<T: ::com::Interface>(self: &::std::pin::Pin<::std::boxed::Box<Self>>) -> Option<T> {
+            pub fn query<__I: ::com::Interface>(self: &::std::pin::Pin<::std::boxed::Box<Self>>) -> Option<__I> {
                 let mut result = None;
-                let hr = unsafe { self.query_interface(&T::IID, &mut result as *mut _ as _) };
+                let hr = unsafe { self.query_interface(&__I::IID, &mut result as *mut _ as _) };
 
                 if ::com::sys::FAILED(hr) {
                     assert!(
@@ -159,6 +190,6 @@ impl IUnknown {
 
 fn this_ptr_type() -> TokenStream {
     quote! {
-        ::std::ptr::NonNull<::std::ptr::NonNull<<::com::interfaces::IUnknown as ::com::Interface>::VTable>>
+        ::std::ptr::NonNull<::std::ptr::NonNull<::std::ffi::c_void>>
     }
 }
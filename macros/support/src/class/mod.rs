@@ -1,6 +1,7 @@
 mod class;
 mod class_constructor;
 mod class_factory;
+mod diagnostics;
 mod iunknown_impl;
 
 pub use class::Class;
@@ -21,6 +21,15 @@ impl IID {
         Ok(Self { parts })
     }
 
+    /// Whether this is the IID of `IAgileObject`, which marks objects as agile
+    pub fn is_agile_object(&self) -> bool {
+        let agile = ["94ea2b94", "e9cc", "49e0", "c0ff", "ee64ca8f5b90"];
+        self.parts
+            .iter()
+            .zip(&agile)
+            .all(|(part, agile)| part.eq_ignore_ascii_case(agile))
+    }
+
     pub fn to_tokens(&self, interface_ident: &Ident) -> HelperTokenStream {
         let iid_ident = ident(interface_ident);
         let data1 = hex_lit(&self.parts[0]);
@@ -11,6 +11,9 @@ pub struct Interface {
     pub name: Ident,
     pub parent: Option<Path>,
     pub methods: Vec<InterfaceMethod>,
+    pub marshal: bool,
+    /// The calling convention of the methods
+    pub abi: syn::LitStr,
     docs: Vec<Attribute>,
 }
 
@@ -76,32 +79,56 @@ impl Interface {
         }
     }
 
+    /// Release the reference through the `IUnknown` the interface derives from
+    ///
+    /// Derived interfaces are dropped as their parent, so that `Release` is called with the
+    /// calling convention of the `IUnknown`.
     fn drop_impl(&self) -> TokenStream {
         let name = &self.name;
+        let release = if self.is_iunknown() {
+            quote! { self.release(); }
+        } else {
+            quote! {
+                ::std::ptr::drop_in_place(self as *mut Self as *mut <Self as ::com::Interface>::Super);
+            }
+        };
 
         quote! {
             impl Drop for #name {
                 fn drop(&mut self) {
-                    unsafe { <Self as ::com::Interface>::as_iunknown(self).release(); }
+                    unsafe { #release }
                 }
             }
         }
     }
 
+    /// Add a reference like `drop_impl` releases it
     fn clone_impl(&self) -> TokenStream {
         let name = &self.name;
-
-        quote! {
-            impl ::std::clone::Clone for #name {
+        let clone = if self.is_iunknown() {
+            quote! {
                 fn clone(&self) -> Self {
                     unsafe {
-                        <Self as ::com::Interface>::as_iunknown(self).add_ref();
+                        self.add_ref();
                     }
                     Self {
                         inner: self.inner
                     }
                 }
             }
+        } else {
+            quote! {
+                fn clone(&self) -> Self {
+                    let parent = <<Self as ::com::Interface>::Super as ::std::clone::Clone>::clone(self);
+                    unsafe { ::std::mem::transmute(parent) }
+                }
+            }
+        };
+
+        quote! {
+            impl ::std::clone::Clone for #name {
+                #clone
+            }
         }
     }
 }
@@ -110,6 +137,8 @@ impl syn::parse::Parse for Interface {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let attributes = input.call(Attribute::parse_outer)?;
         let mut iid = None;
+        let mut marshal = false;
+        let mut abi = None;
         let mut docs = Vec::new();
         for attr in attributes.into_iter() {
             let path = &attr.path;
@@ -120,6 +149,17 @@ impl syn::parse::Parse for Interface {
                 let iid_str: ParenthsizedStr = syn::parse2(tokens.clone())?;
 
                 iid = Some(IID::parse(&iid_str.lit)?);
+            } else if path.is_ident("marshal") && tokens.is_empty() {
+                marshal = true;
+            } else if path.is_ident("abi") && abi.is_none() {
+                let lit = syn::parse2::<ParenthsizedStr>(tokens.clone())?.lit;
+                if !["stdcall", "system", "C"].contains(&lit.value().as_str()) {
+                    return Err(syn::Error::new(
+                        lit.span(),
+                        "the calling convention must be \"stdcall\", \"system\" or \"C\"",
+                    ));
+                }
+                abi = Some(lit);
             } else {
                 return Err(syn::Error::new(
                     path.span().clone(),
@@ -141,6 +181,7 @@ impl syn::parse::Parse for Interface {
             }
         };
         let name = input.parse::<Ident>()?;
+        let abi = abi.unwrap_or_else(|| syn::LitStr::new("system", name.span()));
         let mut parent = None;
         if name.to_string() != "IUnknown" {
             let _ = input.parse::<syn::Token![:]>().map_err(|_| {
@@ -163,6 +204,8 @@ impl syn::parse::Parse for Interface {
             methods,
             name,
             parent,
+            marshal,
+            abi,
             docs,
         })
     }
@@ -200,6 +243,28 @@ pub struct InterfaceMethodArg {
     pub ty: Box<syn::Type>,
     pub pat: Box<syn::Pat>,
     pub pass_through: bool,
+    pub array: Option<SizedArray>,
+}
+
+impl InterfaceMethodArg {
+    /// The name of the argument if it is a plain identifier
+    pub fn name(&self) -> Option<&Ident> {
+        match &*self.pat {
+            syn::Pat::Ident(p) => Some(&p.ident),
+            _ => None,
+        }
+    }
+}
+
+/// A `#[size_is(len)]` argument: a slice passed as a pointer with its length in `len`
+///
+/// `#[length_is(filled)]` additionally names the argument in which the callee reports how
+/// many elements of a `&mut [T]` it filled in.
+pub struct SizedArray {
+    pub elem: syn::Type,
+    pub mutable: bool,
+    pub size_is: Ident,
+    pub length_is: Option<Ident>,
 }
 
 macro_rules! bail {
@@ -224,6 +289,62 @@ macro_rules! expected_token {
     };
 }
 
+impl SizedArray {
+    /// Whether `attr` is one of the attributes of sized arrays
+    pub fn is_attribute(attr: &Attribute) -> bool {
+        attr.path.is_ident("size_is") || attr.path.is_ident("length_is")
+    }
+
+    /// Parse the sized array attributes of an argument of type `ty`
+    pub fn parse(attrs: &[Attribute], ty: &syn::Type) -> syn::Result<Option<SizedArray>> {
+        let mut size_is = None;
+        let mut length_is = None;
+        for attr in attrs {
+            let slot = if attr.path.is_ident("size_is") {
+                &mut size_is
+            } else if attr.path.is_ident("length_is") {
+                &mut length_is
+            } else {
+                continue;
+            };
+            unexpected_token!(slot.as_ref().map(|_| attr), "duplicate attribute");
+            *slot = Some(attr.parse_args::<Ident>()?);
+        }
+        let size_is = match size_is {
+            Some(size_is) => size_is,
+            None => {
+                if let Some(length_is) = length_is {
+                    bail!(length_is, "`#[length_is]` requires `#[size_is]`");
+                }
+                return Ok(None);
+            }
+        };
+        let (elem, mutable) = match ty {
+            syn::Type::Reference(r) => match &*r.elem {
+                syn::Type::Slice(s) => ((*s.elem).clone(), r.mutability.is_some()),
+                _ => {
+                    bail!(ty, "`#[size_is]` arguments must be slices like `&[T]`");
+                }
+            },
+            _ => {
+                bail!(ty, "`#[size_is]` arguments must be slices like `&[T]`");
+            }
+        };
+        if let (Some(length_is), false) = (&length_is, mutable) {
+            bail!(
+                length_is,
+                "`#[length_is]` only applies to `&mut [T]` arguments"
+            );
+        }
+        Ok(Some(SizedArray {
+            elem,
+            mutable,
+            size_is,
+            length_is,
+        }))
+    }
+}
+
 impl syn::parse::Parse for InterfaceMethod {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let docs = input.call(Attribute::parse_outer)?;
@@ -257,13 +378,46 @@ impl syn::parse::Parse for InterfaceMethod {
                 let pass_through = filter.next().is_some();
 
                 unexpected_token!(filter.next(), "function attribute");
+                let array = SizedArray::parse(&p.attrs, &p.ty)?;
+                if pass_through && array.is_some() {
+                    bail!(p, "`#[pass_through]` cannot be combined with `#[size_is]`");
+                }
                 Ok(InterfaceMethodArg {
                     ty: p.ty,
                     pat: p.pat,
                     pass_through,
+                    array,
                 })
             })
             .collect::<Result<Vec<InterfaceMethodArg>, syn::Error>>()?;
+        for arg in &args {
+            let array = match &arg.array {
+                Some(array) => array,
+                None => continue,
+            };
+            let size_is = &array.size_is;
+            match args.iter().find(|a| a.name() == Some(size_is)) {
+                None => {
+                    bail!(size_is, "no argument named `{}`", size_is);
+                }
+                Some(size) if size.array.is_some() => {
+                    bail!(size_is, "the size of an array cannot be an array");
+                }
+                Some(_) => {}
+            }
+            let sizes = args
+                .iter()
+                .filter_map(|a| a.array.as_ref())
+                .filter(|a| a.size_is == *size_is);
+            if sizes.count() > 1 {
+                bail!(size_is, "`{}` is the size of more than one array", size_is);
+            }
+            if let Some(length_is) = &array.length_is {
+                if !args.iter().any(|a| a.name() == Some(length_is)) {
+                    bail!(length_is, "no argument named `{}`", length_is);
+                }
+            }
+        }
 
         let ret = sig.output;
         Ok(InterfaceMethod {
@@ -277,6 +431,38 @@ impl syn::parse::Parse for InterfaceMethod {
 }
 
 impl InterfaceMethod {
+    /// The argument holding the size of `array`
+    pub fn size_of(&self, array: &SizedArray) -> &InterfaceMethodArg {
+        self.arg(&array.size_is)
+    }
+
+    /// The argument named `name`, which has been checked to exist while parsing
+    pub fn arg(&self, name: &Ident) -> &InterfaceMethodArg {
+        self.args.iter().find(|a| a.name() == Some(name)).unwrap()
+    }
+
+    fn returns_hresult(&self) -> bool {
+        match &self.ret {
+            syn::ReturnType::Type(_, ty) => match &**ty {
+                syn::Type::Path(path) => path
+                    .path
+                    .segments
+                    .last()
+                    .into_iter()
+                    .any(|segment| segment.ident == "HRESULT"),
+                _ => false,
+            },
+            syn::ReturnType::Default => false,
+        }
+    }
+
+    fn is_array_size(&self, arg: &InterfaceMethodArg) -> bool {
+        self.args
+            .iter()
+            .filter_map(|a| a.array.as_ref())
+            .any(|a| arg.name() == Some(&a.size_is))
+    }
+
     fn to_tokens(&self) -> TokenStream {
         let inner_method_ident =
             format_ident!("{}", crate::utils::snake_to_camel(&self.name.to_string()));
@@ -295,7 +481,34 @@ impl InterfaceMethod {
         for (index, arg) in self.args.iter().enumerate() {
             let pat = &arg.pat;
             let ty = &arg.ty;
-            if arg.pass_through {
+            if let Some(array) = &arg.array {
+                // The size is taken from the slice instead of being an argument of its own
+                let size = &array.size_is;
+                let size_ty = &self.size_of(array).ty;
+                let elem = &array.elem;
+                let ptr = if array.mutable {
+                    quote! { #pat.as_mut_ptr() as *mut <#elem as ::com::AbiTransferable>::Abi }
+                } else {
+                    quote! { #pat.as_ptr() as *const <#elem as ::com::AbiTransferable>::Abi }
+                };
+                // Slices too long for the size argument are rejected instead of truncated
+                let too_long = if self.returns_hresult() {
+                    quote! { return ::com::sys::E_INVALIDARG }
+                } else {
+                    let message = format!("`{}` is too long for `{}`", quote!(#pat), size);
+                    quote! { panic!(#message) }
+                };
+                args.push(quote! { #pat: #ty });
+                into.push(quote! {
+                    let #size: #size_ty = match ::std::convert::TryInto::try_into(#pat.len()) {
+                        ::std::result::Result::Ok(size) => size,
+                        ::std::result::Result::Err(_) => #too_long,
+                    };
+                    let #pat = #ptr;
+                });
+            } else if self.is_array_size(arg) {
+                // Computed from the length of its array
+            } else if arg.pass_through {
                 args.push(quote! { #pat: #ty });
             } else {
                 let generic = quote::format_ident!("__{}", index);
@@ -14,11 +14,48 @@ pub fn generate(interface: &Interface) -> TokenStream {
         quote! { #interface_ident }
     };
 
+    let class_fields = if interface.iid.is_agile_object() {
+        quote! {
+            unsafe impl ::com::Agile for #interface_ident {}
+            impl<Fields: ::std::marker::Send + ::std::marker::Sync> ::com::ClassFields<Fields> for #interface_ident {}
+        }
+    } else if let Some(parent) = &interface.parent {
+        quote! {
+            impl<Fields> ::com::ClassFields<Fields> for #interface_ident where #parent: ::com::ClassFields<Fields> {}
+        }
+    } else {
+        quote! {
+            impl<Fields> ::com::ClassFields<Fields> for #interface_ident {}
+        }
+    };
+
+    // The vtable of a class starts with its `IUnknown` methods in the convention of the root
+    let class_vtable = if interface.parent.is_none() {
+        let thunk = |field: &str| vtable::thunk_ident(&quote::format_ident!("{}", field));
+        let (query_interface, add_ref, release) =
+            (thunk("QueryInterface"), thunk("AddRef"), thunk("Release"));
+        quote! {
+            unsafe impl<__C: ::com::IUnknownThunks<__O>, __O> ::com::ClassVTable<__C, __O> for #interface_ident {
+                fn vtable() -> #vtable_ident {
+                    #vtable_ident {
+                        QueryInterface: <#vtable_ident>::#query_interface::<::com::QueryInterfaceThunk<__C, __O>>,
+                        AddRef: <#vtable_ident>::#add_ref::<::com::AddRefThunk<__C, __O>>,
+                        Release: <#vtable_ident>::#release::<::com::ReleaseThunk<__C, __O>>,
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     quote! {
         unsafe impl com::Interface for #interface_ident {
             type VTable = #vtable_ident;
             type Super = #parent;
             const IID: com::sys::IID = #iid_ident;
         }
+        #class_fields
+        #class_vtable
     }
 }
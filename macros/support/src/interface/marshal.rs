@@ -0,0 +1,197 @@
+use super::{iid, vptr, vtable, Interface, InterfaceMethod};
+
+use proc_macro2::{Ident, Literal, TokenStream};
+use quote::{format_ident, quote};
+
+/// Generate the proxy and stub of an interface declared with `#[marshal]`
+pub fn generate(interface: &Interface) -> syn::Result<TokenStream> {
+    if !interface.marshal {
+        return Ok(quote! {});
+    }
+    let name = &interface.name;
+    let parent = match &interface.parent {
+        Some(parent) => parent,
+        None => {
+            return Err(syn::Error::new(
+                name.span(),
+                "IUnknown can not be marshaled through `#[marshal]`",
+            ))
+        }
+    };
+    let vtable_ident = vtable::ident(&name.to_string());
+    let vptr_ident = vptr::ident(name);
+    let iid_ident = iid::ident(name);
+    let abi = &interface.abi;
+
+    let mut proxies = Vec::new();
+    let mut fields = Vec::new();
+    let mut stubs = Vec::new();
+    for (index, method) in interface.methods.iter().enumerate() {
+        let field = format_ident!("{}", crate::utils::snake_to_camel(&method.name.to_string()));
+        let proxy = format_ident!("__proxy_{}", method.name);
+        let index = Literal::u32_suffixed(index as u32);
+        let ret = &method.ret;
+        let ret_ty = match &method.ret {
+            syn::ReturnType::Default => quote! { () },
+            syn::ReturnType::Type(_, ty) => quote! { #ty },
+        };
+        let args = (0..method.args.len())
+            .map(|i| format_ident!("__{}", i))
+            .collect::<Vec<_>>();
+        let raw_tys = method
+            .args
+            .iter()
+            .map(vtable::raw_type)
+            .collect::<syn::Result<Vec<_>>>()?;
+        let mut marshal_in = Vec::new();
+        let mut unmarshal_out = Vec::new();
+        let mut unmarshal_in = Vec::new();
+        let mut checks = Vec::new();
+        let mut as_abi = Vec::new();
+        let mut lengths = Vec::new();
+        let mut marshal_out = Vec::new();
+        for ((arg, ident), raw_ty) in method.args.iter().zip(&args).zip(&raw_tys) {
+            let ty = &arg.ty;
+            let array = match &arg.array {
+                Some(array) => array,
+                None => {
+                    marshal_in.push(quote! {
+                        <#ty as ::com::marshal::MarshalArg>::marshal_in(&#ident, __encoder)?;
+                    });
+                    unmarshal_out.push(quote! {
+                        <#ty as ::com::marshal::MarshalArg>::unmarshal_out(&#ident, __decoder)?;
+                    });
+                    unmarshal_in.push(quote! {
+                        let mut #ident = <#ty as ::com::marshal::MarshalArg>::unmarshal_in(__decoder)?;
+                    });
+                    as_abi.push(quote! {
+                        <#ty as ::com::marshal::MarshalArg>::as_abi(&mut #ident)
+                    });
+                    marshal_out.push(quote! {
+                        <#ty as ::com::marshal::MarshalArg>::marshal_out(#ident, __encoder)?;
+                    });
+                    continue;
+                }
+            };
+            // Arrays are sent along with their elements, the size argument is sent as is
+            let elem = &array.elem;
+            let size = arg_ident(method, &array.size_is);
+            marshal_in.push(quote! {
+                ::com::marshal::marshal_array_in(#ident as *const #elem, #size as usize, __encoder)?;
+            });
+            unmarshal_in.push(quote! {
+                let mut #ident = ::com::marshal::unmarshal_array_in::<#elem>(__decoder)?;
+            });
+            checks.push(quote! {
+                if #ident.len() != #size as usize {
+                    return Err(::com::sys::RPC_E_INVALID_DATAPACKET);
+                }
+            });
+            if !array.mutable {
+                as_abi.push(quote! { #ident.as_ptr() as #raw_ty });
+                continue;
+            }
+            as_abi.push(quote! { #ident.as_mut_ptr() as #raw_ty });
+            unmarshal_out.push(quote! {
+                ::com::marshal::unmarshal_array_out(#ident as *mut #elem, #size as usize, __decoder)?;
+            });
+            // The length has to be taken before its argument is consumed by `marshal_out`
+            let length = format_ident!("{}_length", ident);
+            let filled = match &array.length_is {
+                Some(length_is) => {
+                    let length_is = arg_ident(method, length_is);
+                    quote! {
+                        ::com::marshal::ArrayLength::array_length(&#length_is).unwrap_or(#ident.len())
+                    }
+                }
+                None => quote! { #ident.len() },
+            };
+            lengths.push(quote! { let #length = #filled; });
+            marshal_out.push(quote! {
+                ::com::marshal::marshal_array_out(&#ident, #length, __encoder)?;
+            });
+        }
+
+        proxies.push(quote! {
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            unsafe extern #abi fn #proxy(
+                this: ::std::ptr::NonNull<#vptr_ident>,
+                #(#args: #raw_tys),*
+            ) #ret {
+                ::com::marshal::proxy_call(
+                    this.cast(),
+                    &#iid_ident,
+                    #index,
+                    |__encoder| {
+                        #(#marshal_in)*
+                        Ok(())
+                    },
+                    |__decoder| {
+                        #(#unmarshal_out)*
+                        <#ret_ty as ::com::marshal::Marshal>::unmarshal(__decoder)
+                    },
+                )
+            }
+        });
+        fields.push(quote! { #field: #name::#proxy, });
+        stubs.push(quote! {
+            #index => {
+                #(#unmarshal_in)*
+                #(#checks)*
+                let __result = (__this.as_ref().as_ref().#field)(
+                    __this,
+                    #(#as_abi),*
+                );
+                #(#lengths)*
+                #(#marshal_out)*
+                <#ret_ty as ::com::marshal::Marshal>::marshal(&__result, __encoder)
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl #name {
+            #(#proxies)*
+        }
+
+        unsafe impl ::com::marshal::Remote for #name {
+            const PROXY_VTABLE: #vtable_ident = #vtable_ident {
+                parent: <#parent as ::com::marshal::Remote>::PROXY_VTABLE,
+                #(#fields)*
+            };
+
+            fn proxy_vtable() -> &'static #vtable_ident {
+                &<Self as ::com::marshal::Remote>::PROXY_VTABLE
+            }
+
+            fn register_parents() {
+                ::com::marshal::register::<#parent>()
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            unsafe fn stub(
+                this: &Self,
+                __method: u32,
+                __decoder: &mut ::com::marshal::Decoder,
+                __encoder: &mut ::com::marshal::Encoder,
+            ) -> ::std::result::Result<(), ::com::sys::HRESULT> {
+                let __this = <Self as ::com::AbiTransferable>::get_abi(this);
+                match __method {
+                    #(#stubs)*
+                    _ => Err(::com::sys::RPC_E_INVALIDMETHOD),
+                }
+            }
+        }
+    })
+}
+
+/// The identifier of the proxy's and stub's variable for the argument `name`
+fn arg_ident(method: &InterfaceMethod, name: &Ident) -> Ident {
+    let index = method
+        .args
+        .iter()
+        .position(|a| a.name() == Some(name))
+        .unwrap();
+    format_ident!("__{}", index)
+}
@@ -2,10 +2,11 @@ mod iid;
 mod interface;
 mod interface_impl;
 mod interfaces;
+mod marshal;
 mod vptr;
 pub mod vtable;
 
-pub use interface::{Interface, InterfaceMethod};
+pub use interface::{Interface, InterfaceMethod, SizedArray};
 pub use interfaces::Interfaces;
 use proc_macro2::{Ident, TokenStream};
 use syn::Path;
@@ -22,6 +23,7 @@ pub fn expand_interfacess(interfaces: Interfaces) -> TokenStream {
         out.push(vptr::generate(&interface));
         out.push(interface_impl::generate(&interface));
         out.push(interface.to_iid_tokens());
+        out.push(marshal::generate(&interface).unwrap_or_else(|e| e.to_compile_error()));
     }
     out.extend(convert_impls(interfaces.parents));
 
@@ -18,8 +18,11 @@ pub fn generate(interface: &Interface) -> syn::Result<TokenStream> {
         }
         None => quote! {},
     };
-    let methods = gen_vtable_methods(&interface)?;
+    let methods = gen_vtable_methods(interface)?;
+    let thunks = gen_thunks(interface)?;
+    let abi_check = gen_abi_check(interface);
     let vis = &interface.visibility;
+    let abi = &interface.abi;
 
     Ok(quote!(
         #[allow(non_snake_case, missing_docs)]
@@ -28,9 +31,75 @@ pub fn generate(interface: &Interface) -> syn::Result<TokenStream> {
             #parent_field
             #methods
         }
+        impl #vtable_ident {
+            #[doc(hidden)]
+            pub unsafe extern #abi fn __abi() {}
+            #thunks
+        }
+        #abi_check
     ))
 }
 
+/// Fail to compile if the interface's calling convention differs from its parent's
+///
+/// Every vtable has an empty `__abi` function with the interface's convention, whose type
+/// only coerces to a function pointer of the same convention.
+fn gen_abi_check(interface: &Interface) -> TokenStream {
+    let abi = &interface.abi;
+    match &interface.parent {
+        Some(parent) => quote::quote_spanned! {abi.span()=>
+            const _: unsafe extern #abi fn() = <<#parent as ::com::Interface>::VTable>::__abi;
+        },
+        None => quote! {},
+    }
+}
+
+/// The name of the thunk for the vtable entry `field` in the vtable's `impl`
+pub fn thunk_ident(field: &Ident) -> Ident {
+    format_ident!("__thunk_{}", field)
+}
+
+/// Generic functions with the interface's calling convention for the entries of the vtable
+///
+/// Classes implement `::com::Thunk` for a type per method and put the thunk instantiated
+/// with it into their vtables, which gives them the calling convention of the interface.
+fn gen_thunks(interface: &Interface) -> syn::Result<TokenStream> {
+    let vptr_ident = vptr::ident(&interface.name);
+    let abi = &interface.abi;
+    let vis = &interface.visibility;
+    let mut thunks = Vec::new();
+    for method in &interface.methods {
+        let field = format_ident!("{}", crate::utils::snake_to_camel(&method.name.to_string()));
+        let thunk = thunk_ident(&field);
+        let args = (0..method.args.len())
+            .map(|i| format_ident!("__{}", i))
+            .collect::<Vec<_>>();
+        let raw_tys = method
+            .args
+            .iter()
+            .map(raw_type)
+            .collect::<syn::Result<Vec<_>>>()?;
+        let ret = &method.ret;
+        let ret_ty = match &method.ret {
+            syn::ReturnType::Default => quote! { () },
+            syn::ReturnType::Type(_, ty) => quote! { #ty },
+        };
+        thunks.push(quote! {
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            #vis unsafe extern #abi fn #thunk<
+                __T: ::com::Thunk<::std::ptr::NonNull<#vptr_ident>, (#(#raw_tys,)*), #ret_ty>
+            >(
+                this: ::std::ptr::NonNull<#vptr_ident>,
+                #(#args: #raw_tys),*
+            ) #ret {
+                __T::call(this, (#(#args,)*))
+            }
+        });
+    }
+    Ok(quote!(#(#thunks)*))
+}
+
 pub fn ident(interface_name: &str) -> Ident {
     format_ident!("{}VTable", interface_name)
 }
@@ -38,7 +107,7 @@ pub fn ident(interface_name: &str) -> Ident {
 fn gen_vtable_methods(interface: &Interface) -> syn::Result<TokenStream> {
     let mut methods: Vec<TokenStream> = Vec::new();
     for m in interface.methods.iter() {
-        methods.push(gen_vtable_method(&interface.name, m)?);
+        methods.push(gen_vtable_method(interface, m)?);
     }
 
     Ok(quote!(
@@ -46,12 +115,9 @@ fn gen_vtable_methods(interface: &Interface) -> syn::Result<TokenStream> {
     ))
 }
 
-fn gen_vtable_method(
-    interface_ident: &Ident,
-    method: &InterfaceMethod,
-) -> syn::Result<TokenStream> {
+fn gen_vtable_method(interface: &Interface, method: &InterfaceMethod) -> syn::Result<TokenStream> {
     let method_ident = format_ident!("{}", crate::utils::snake_to_camel(&method.name.to_string()));
-    let vtable_function_signature = gen_vtable_function_signature(interface_ident, method)?;
+    let vtable_function_signature = gen_vtable_function_signature(interface, method)?;
 
     Ok(quote!(
         pub #method_ident: #vtable_function_signature,
@@ -59,14 +125,15 @@ fn gen_vtable_method(
 }
 
 fn gen_vtable_function_signature(
-    interface_ident: &Ident,
+    interface: &Interface,
     method: &InterfaceMethod,
 ) -> syn::Result<TokenStream> {
-    let params = gen_raw_params(interface_ident, method)?;
+    let params = gen_raw_params(&interface.name, method)?;
     let return_type = &method.ret;
+    let abi = &interface.abi;
 
     Ok(quote!(
-        unsafe extern "stdcall" fn(#params) #return_type
+        unsafe extern #abi fn(#params) #return_type
     ))
 }
 
@@ -77,19 +144,29 @@ fn gen_raw_params(interface_ident: &Ident, method: &InterfaceMethod) -> syn::Res
     )];
 
     for param in method.args.iter() {
-        params.push(gen_raw_type(param)?);
+        let ty = raw_type(param)?;
+        params.push(quote!(#ty,));
     }
 
     Ok(TokenStream::from_iter(params))
 }
 
-fn gen_raw_type(p: &super::interface::InterfaceMethodArg) -> syn::Result<TokenStream> {
+/// The type of an argument as it appears in the vtable
+pub fn raw_type(p: &super::interface::InterfaceMethodArg) -> syn::Result<TokenStream> {
     let t = &*p.ty;
+    if let Some(array) = &p.array {
+        let elem = &array.elem;
+        return Ok(if array.mutable {
+            quote!(*mut <#elem as ::com::AbiTransferable>::Abi)
+        } else {
+            quote!(*const <#elem as ::com::AbiTransferable>::Abi)
+        });
+    }
     let ty = match t {
         Type::Path(_) | Type::Ptr(_) if !p.pass_through => {
-            return Ok(quote!(<#t as ::com::AbiTransferable>::Abi,))
+            return Ok(quote!(<#t as ::com::AbiTransferable>::Abi))
         }
-        Type::Path(_) | Type::Ptr(_) => return Ok(quote!(#t,)),
+        Type::Path(_) | Type::Ptr(_) => return Ok(quote!(#t)),
         Type::Array(_n) => "array type",
         Type::BareFn(_n) => "barefn type",
         Type::Group(_n) => "group type",
@@ -1,7 +1,10 @@
+pub mod abi_transferable;
 pub mod class;
 pub mod interface;
+pub mod mock;
 mod utils;
 
 pub use class::Class;
 pub use interface::Interface;
 pub use interface::Interfaces;
+pub use mock::Mock;
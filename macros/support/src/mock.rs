@@ -0,0 +1,233 @@
+use proc_macro2::{Ident, TokenStream};
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+
+/// A test double declared with `mock!`
+///
+/// ```rust,ignore
+/// mock! {
+///     pub class MockAnimal: IDomesticAnimal(IAnimal);
+///
+///     impl IDomesticAnimal {
+///         fn train(&self) -> HRESULT;
+///     }
+///
+///     impl IAnimal {
+///         fn eat(&self) -> HRESULT;
+///     }
+/// }
+/// ```
+///
+/// This expands to a `class!` whose methods forward to `com::testing::mock::Expectations`.
+pub struct Mock {
+    docs: Vec<syn::Attribute>,
+    visibility: syn::Visibility,
+    name: Ident,
+    /// The interface list exactly as `class!` expects it
+    interfaces: TokenStream,
+    impls: Vec<(syn::Path, Vec<syn::TraitItemMethod>)>,
+}
+
+struct Method<'a> {
+    sig: &'a syn::Signature,
+    field: Ident,
+    arg_types: Vec<&'a syn::Type>,
+    ret: TokenStream,
+}
+
+impl<'a> Method<'a> {
+    fn new(method: &'a syn::TraitItemMethod) -> syn::Result<Self> {
+        if let Some(body) = &method.default {
+            return Err(syn::Error::new(
+                body.span(),
+                "mock methods are declared without a body",
+            ));
+        }
+        let sig = &method.sig;
+        let mut arg_types = Vec::new();
+        for input in &sig.inputs {
+            match input {
+                syn::FnArg::Receiver(r) if r.reference.is_some() && r.mutability.is_none() => {}
+                syn::FnArg::Receiver(r) => {
+                    return Err(syn::Error::new(r.span(), "mock methods must take `&self`"))
+                }
+                syn::FnArg::Typed(t) => arg_types.push(&*t.ty),
+            }
+        }
+        let ret = match &sig.output {
+            syn::ReturnType::Default => quote! { () },
+            syn::ReturnType::Type(_, ty) => quote! { #ty },
+        };
+        Ok(Method {
+            sig,
+            field: format_ident!("__mock_{}", sig.ident),
+            arg_types,
+            ret,
+        })
+    }
+
+    fn expectations_type(&self) -> TokenStream {
+        let arg_types = &self.arg_types;
+        let ret = &self.ret;
+        quote! { ::com::testing::mock::Expectations<(#(#arg_types,)*), #ret> }
+    }
+
+    fn to_impl_tokens(&self) -> TokenStream {
+        let name = &self.sig.ident;
+        let unsafety = &self.sig.unsafety;
+        let output = &self.sig.output;
+        let field = &self.field;
+        let args = (0..self.arg_types.len())
+            .map(|i| format_ident!("__{}", i))
+            .collect::<Vec<_>>();
+        let params = args.iter().zip(&self.arg_types).map(|(arg, ty)| {
+            quote! { #arg: #ty }
+        });
+        quote! {
+            #unsafety fn #name(&self, #(#params),*) #output {
+                self.#field.call((#(#args,)*))
+            }
+        }
+    }
+}
+
+impl Mock {
+    pub fn to_tokens(&self) -> syn::Result<TokenStream> {
+        let docs = &self.docs;
+        let vis = &self.visibility;
+        let name = &self.name;
+        let interfaces = &self.interfaces;
+
+        let mut methods = Vec::new();
+        let mut impls = Vec::new();
+        for (path, items) in &self.impls {
+            let mut impl_methods = Vec::new();
+            for item in items {
+                let method = Method::new(item)?;
+                if methods
+                    .iter()
+                    .any(|m: &Method| m.sig.ident == method.sig.ident)
+                {
+                    return Err(syn::Error::new(
+                        method.sig.ident.span(),
+                        "mocks cannot have two methods with the same name",
+                    ));
+                }
+                impl_methods.push(method.to_impl_tokens());
+                methods.push(method);
+            }
+            impls.push(quote! {
+                impl #path for #name {
+                    #(#impl_methods)*
+                }
+            });
+        }
+
+        let fields = methods.iter().map(|m| {
+            let field = &m.field;
+            let ty = m.expectations_type();
+            quote! { #field: #ty }
+        });
+        let constructor_args = methods.iter().map(|m| {
+            let method = &m.sig.ident;
+            quote! {
+                ::com::testing::mock::Expectations::new(
+                    concat!(stringify!(#name), "::", stringify!(#method))
+                )
+            }
+        });
+        let expects = methods.iter().map(|m| {
+            let method = &m.sig.ident;
+            let expect = format_ident!("expect_{}", method);
+            let field = &m.field;
+            let arg_types = &m.arg_types;
+            let ret = &m.ret;
+            let doc = format!("Expect a call to `{}`", method);
+            quote! {
+                #[doc = #doc]
+                #vis fn #expect(&self) -> ::std::cell::RefMut<'_, ::com::testing::mock::Expectation<(#(#arg_types,)*), #ret>> {
+                    self.#field.expect()
+                }
+            }
+        });
+        let checkpoints = methods.iter().map(|m| {
+            let field = &m.field;
+            quote! { self.#field.checkpoint(); }
+        });
+
+        Ok(quote! {
+            ::com::class! {
+                #(#docs)*
+                #[no_class_factory]
+                #vis class #name: #interfaces {
+                    #(#fields),*
+                }
+
+                #(#impls)*
+            }
+
+            impl #name {
+                /// Create a mock without any expectations
+                #vis fn new() -> ::com::testing::mock::MockHandle<#name> {
+                    ::com::testing::mock::MockHandle::new(#name::allocate(#(#constructor_args),*))
+                }
+
+                #(#expects)*
+            }
+
+            impl ::com::testing::mock::Mock for #name {
+                fn checkpoint(&self) {
+                    #(#checkpoints)*
+                }
+            }
+        })
+    }
+}
+
+impl syn::parse::Parse for Mock {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let attributes = input.call(syn::Attribute::parse_outer)?;
+        let mut docs = Vec::with_capacity(attributes.len());
+        for attr in attributes {
+            if attr.path.is_ident("doc") {
+                docs.push(attr)
+            } else {
+                return Err(syn::Error::new(attr.path.span(), "Unrecognized attribute"));
+            }
+        }
+        let visibility = input.parse::<syn::Visibility>()?;
+        let _ = input.parse::<keywords::class>()?;
+        let name = input.parse::<Ident>()?;
+        let _ = input.parse::<syn::Token!(:)>()?;
+        let mut interfaces = TokenStream::new();
+        while !input.peek(syn::Token!(;)) {
+            interfaces.extend(std::iter::once(input.parse::<proc_macro2::TokenTree>()?));
+        }
+        let _ = input.parse::<syn::Token!(;)>()?;
+
+        let mut impls = Vec::new();
+        while !input.is_empty() {
+            let _ = input.parse::<syn::Token!(impl)>()?;
+            let path = input.parse::<syn::Path>()?;
+            let content;
+            syn::braced!(content in input);
+            let mut methods = Vec::new();
+            while !content.is_empty() {
+                methods.push(content.parse::<syn::TraitItemMethod>()?);
+            }
+            impls.push((path, methods));
+        }
+
+        Ok(Mock {
+            docs,
+            visibility,
+            name,
+            interfaces,
+            impls,
+        })
+    }
+}
+
+mod keywords {
+    syn::custom_keyword!(class);
+}
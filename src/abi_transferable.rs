@@ -1,5 +1,15 @@
 /// Types that are safe to transfer over a WinRT API boundary.
 ///
+/// `#[repr(C)]` structs that are `Copy` can derive this trait to be passed by value:
+///
+/// ```rust
+/// #[repr(C)]
+/// #[derive(Clone, Copy, com::AbiTransferable)]
+/// pub struct Food {
+///     pub deliciousness: u32,
+/// }
+/// ```
+///
 /// # Safety
 /// Implementing types only have associated `Abi` types that are
 /// safe to transfer over a WinRT boundary. Implementing types
@@ -82,6 +92,8 @@ primitive_transferable_type! {
     u32,
     i64,
     u64,
+    isize,
+    usize,
     f32,
     f64,
     crate::sys::GUID
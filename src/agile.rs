@@ -0,0 +1,440 @@
+//! Agile references for handing interface pointers to other threads
+//!
+//! Interface pointers are bound to the apartment they were obtained in and can not be
+//! sent to other threads. An [`AgileRef`] can: it is created from an interface pointer on one
+//! thread and resolved to a usable interface pointer on another.
+//!
+//! Objects implementing [`IAgileObject`] can be called from any apartment and are handed out
+//! directly. `class!` only accepts `IAgileObject` for classes whose fields are `Send + Sync`.
+//! On Windows, other objects are referenced through `RoGetAgileReference` or, where that is
+//! not available, the global interface table. Elsewhere a process-local table is used, which
+//! only hands such objects out on the thread that created the reference.
+//!
+//! [`AgileCall`] builds on agile references to run calls on the thread that owns an object
+//! and return their result as a `Future`.
+mod call;
+
+#[doc(inline)]
+pub use call::{completion, AgileCall, CallFuture, Completer};
+
+use crate::interfaces::{IAgileObject, IUnknown};
+use crate::sys::{HRESULT, IID};
+use crate::Interface;
+
+use std::marker::PhantomData;
+
+/// A reference to a COM object that can be sent to and resolved on other threads
+///
+/// ```rust,no_run
+/// # use com::agile::AgileRef;
+/// # use com::interfaces::IUnknown;
+/// # fn example(object: IUnknown) -> Result<(), com::sys::HRESULT> {
+/// let reference = AgileRef::new(&object)?;
+/// std::thread::spawn(move || {
+///     let object = reference.resolve().unwrap();
+///     // use `object` on this thread
+/// });
+/// # Ok(())
+/// # }
+/// ```
+pub struct AgileRef<I: Interface> {
+    inner: Inner,
+    _marker: PhantomData<fn() -> I>,
+}
+
+// All kinds of references can be resolved and released from any thread
+unsafe impl<I: Interface> Send for AgileRef<I> {}
+unsafe impl<I: Interface> Sync for AgileRef<I> {}
+
+enum Inner {
+    /// The object itself declared that it can be used from any apartment
+    Agile(IUnknown),
+    #[cfg(windows)]
+    Reference(crate::interfaces::IAgileReference),
+    #[cfg(windows)]
+    Global(u32),
+    #[cfg(not(windows))]
+    Local(u64),
+}
+
+impl<I: Interface> AgileRef<I> {
+    /// Create an agile reference to `object`
+    pub fn new(object: &I) -> Result<AgileRef<I>, HRESULT> {
+        let unknown = object.as_iunknown();
+        let inner = if unknown.get_interface::<IAgileObject>().is_some() {
+            Inner::Agile(unknown.clone())
+        } else {
+            reference(unknown, &I::IID)?
+        };
+        Ok(AgileRef {
+            inner,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Get an interface pointer to the object that can be used on the current thread
+    pub fn resolve(&self) -> Result<I, HRESULT> {
+        let unknown = match &self.inner {
+            Inner::Agile(unknown) => unknown.clone(),
+            #[cfg(windows)]
+            Inner::Reference(reference) => system::resolve_reference(reference, &I::IID)?,
+            #[cfg(windows)]
+            Inner::Global(cookie) => system::resolve_global(*cookie, &I::IID)?,
+            #[cfg(not(windows))]
+            Inner::Local(key) => local::resolve(*key)?,
+        };
+        unknown
+            .get_interface::<I>()
+            .ok_or(crate::sys::E_NOINTERFACE)
+    }
+
+    /// Whether the object can be used from any apartment without going through a proxy
+    pub fn is_agile(&self) -> bool {
+        if let Inner::Agile(_) = self.inner {
+            return true;
+        }
+        false
+    }
+}
+
+impl<I: Interface> Drop for AgileRef<I> {
+    fn drop(&mut self) {
+        match &self.inner {
+            Inner::Agile(_) => {}
+            #[cfg(windows)]
+            Inner::Reference(_) => {}
+            #[cfg(windows)]
+            Inner::Global(cookie) => system::revoke_global(*cookie),
+            #[cfg(not(windows))]
+            Inner::Local(key) => {
+                let _ = local::revoke(*key);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn reference(object: &IUnknown, iid: &IID) -> Result<Inner, HRESULT> {
+    if let Some(reference) = system::agile_reference(object, iid) {
+        return Ok(Inner::Reference(reference?));
+    }
+    system::register_global(object, iid).map(Inner::Global)
+}
+
+#[cfg(not(windows))]
+fn reference(object: &IUnknown, _iid: &IID) -> Result<Inner, HRESULT> {
+    Ok(Inner::Local(local::register(object)))
+}
+
+#[cfg(windows)]
+mod system {
+    use crate::interfaces::{IAgileReference, IGlobalInterfaceTable, IUnknown};
+    use crate::sys::{GetModuleHandleA, GetProcAddress, CLSID, FAILED, GUID, HRESULT, IID};
+    use crate::AbiTransferable;
+
+    use std::ffi::c_void;
+
+    const CLSID_STD_GLOBAL_INTERFACE_TABLE: CLSID = GUID {
+        data1: 0x0000_0323,
+        data2: 0x0000,
+        data3: 0x0000,
+        data4: [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+    };
+
+    const AGILEREFERENCE_DEFAULT: u32 = 0;
+
+    type RoGetAgileReference = unsafe extern "system" fn(
+        options: u32,
+        riid: *const IID,
+        p_unk: *mut c_void,
+        pp_agile_reference: *mut Option<IAgileReference>,
+    ) -> HRESULT;
+
+    /// Create an agile reference through `RoGetAgileReference`
+    ///
+    /// Returns `None` if the function is not available (before Windows 8.1).
+    pub fn agile_reference(
+        object: &IUnknown,
+        iid: &IID,
+    ) -> Option<Result<IAgileReference, HRESULT>> {
+        let function = unsafe {
+            let module = GetModuleHandleA("combase.dll\0".as_ptr() as *const i8);
+            if module.is_null() {
+                return None;
+            }
+            let function = GetProcAddress(module, "RoGetAgileReference\0".as_ptr() as *const i8);
+            if function.is_null() {
+                return None;
+            }
+            std::mem::transmute::<*mut c_void, RoGetAgileReference>(function)
+        };
+        let mut reference = None;
+        let hr = unsafe {
+            function(
+                AGILEREFERENCE_DEFAULT,
+                iid,
+                object.get_abi().as_ptr() as *mut c_void,
+                &mut reference,
+            )
+        };
+        if FAILED(hr) {
+            return Some(Err(hr));
+        }
+        reference.map(Ok)
+    }
+
+    pub fn resolve_reference(reference: &IAgileReference, iid: &IID) -> Result<IUnknown, HRESULT> {
+        let mut object = None::<IUnknown>;
+        let hr = unsafe { reference.resolve(iid, &mut object as *mut _ as *mut *mut c_void) };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(object.unwrap())
+    }
+
+    fn global_table() -> Result<IGlobalInterfaceTable, HRESULT> {
+        crate::runtime::create_instance(&CLSID_STD_GLOBAL_INTERFACE_TABLE)
+    }
+
+    pub fn register_global(object: &IUnknown, iid: &IID) -> Result<u32, HRESULT> {
+        let mut cookie = 0;
+        let hr = unsafe {
+            global_table()?.register_interface_in_global(object.clone(), iid, &mut cookie)
+        };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(cookie)
+    }
+
+    pub fn resolve_global(cookie: u32, iid: &IID) -> Result<IUnknown, HRESULT> {
+        let mut object = None::<IUnknown>;
+        let hr = unsafe {
+            global_table()?.get_interface_from_global(
+                cookie,
+                iid,
+                &mut object as *mut _ as *mut *mut c_void,
+            )
+        };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(object.unwrap())
+    }
+
+    pub fn revoke_global(cookie: u32) {
+        if let Ok(table) = global_table() {
+            unsafe { table.revoke_interface_from_global(cookie) };
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) mod local {
+    use crate::interfaces::IUnknown;
+    use crate::lazy_global::LazyGlobal;
+    use crate::sys::{E_UNEXPECTED, HRESULT, RPC_E_DISCONNECTED, RPC_E_WRONG_THREAD};
+
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Mutex, MutexGuard};
+    use std::thread::ThreadId;
+
+    struct Table {
+        entries: Vec<Entry>,
+        /// Objects whose reference was dropped on another thread; they are released the next
+        /// time their thread uses the table
+        orphans: Vec<Entry>,
+    }
+
+    struct Entry {
+        key: u64,
+        /// `None` once the object's apartment was torn down
+        object: Option<Referenced>,
+        thread: ThreadId,
+    }
+
+    /// A referenced object.
+    ///
+    /// COM interface pointers are not `Send`. The table only ever hands the object out
+    /// (or releases it) on the thread that created the reference.
+    struct Referenced(IUnknown);
+
+    unsafe impl Send for Referenced {}
+
+    static TABLE: LazyGlobal<Mutex<Table>> = LazyGlobal::new();
+    static NEXT_KEY: AtomicU64 = AtomicU64::new(1);
+
+    fn table() -> MutexGuard<'static, Table> {
+        TABLE.lock(|| Table {
+            entries: Vec::new(),
+            orphans: Vec::new(),
+        })
+    }
+
+    /// Take the orphaned objects of the current thread so they can be released
+    fn take_orphans(table: &mut Table) -> Vec<Entry> {
+        let thread = std::thread::current().id();
+        let mut orphans = Vec::new();
+        let mut index = 0;
+        while index < table.orphans.len() {
+            if table.orphans[index].thread == thread {
+                orphans.push(table.orphans.swap_remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        orphans
+    }
+
+    pub fn register(object: &IUnknown) -> u64 {
+        let key = NEXT_KEY.fetch_add(1, Ordering::Relaxed);
+        let orphans = {
+            let mut table = table();
+            table.entries.push(Entry {
+                key,
+                object: Some(Referenced(object.clone())),
+                thread: std::thread::current().id(),
+            });
+            take_orphans(&mut table)
+        };
+        // Release the objects outside of the lock
+        drop(orphans);
+        key
+    }
+
+    pub fn resolve(key: u64) -> Result<IUnknown, HRESULT> {
+        let (object, orphans) = {
+            let mut table = table();
+            let entry = table
+                .entries
+                .iter()
+                .find(|e| e.key == key)
+                .ok_or(E_UNEXPECTED)?;
+            if entry.thread != std::thread::current().id() {
+                return Err(RPC_E_WRONG_THREAD);
+            }
+            let object = entry.object.as_ref().map(|o| o.0.clone());
+            (object, take_orphans(&mut table))
+        };
+        drop(orphans);
+        object.ok_or(RPC_E_DISCONNECTED)
+    }
+
+    pub fn revoke(key: u64) -> Result<(), HRESULT> {
+        let entry = {
+            let mut table = table();
+            let index = table
+                .entries
+                .iter()
+                .position(|e| e.key == key)
+                .ok_or(E_UNEXPECTED)?;
+            let entry = table.entries.swap_remove(index);
+            if entry.object.is_some() && entry.thread != std::thread::current().id() {
+                table.orphans.push(entry);
+                return Ok(());
+            }
+            entry
+        };
+        drop(entry);
+        Ok(())
+    }
+
+    /// Release all objects referenced from the current thread
+    ///
+    /// This is called when the thread's apartment is torn down. Resolving references to the
+    /// objects fails with `RPC_E_DISCONNECTED` afterwards.
+    pub(crate) fn disconnect_thread() {
+        let thread = std::thread::current().id();
+        let (objects, orphans) = {
+            let mut table = table();
+            let objects = table
+                .entries
+                .iter_mut()
+                .filter(|e| e.thread == thread)
+                .filter_map(|e| e.object.take())
+                .collect::<Vec<_>>();
+            (objects, take_orphans(&mut table))
+        };
+        drop(objects);
+        drop(orphans);
+    }
+}
+
+#[cfg(all(test, not(windows), feature = "production"))]
+mod tests {
+    use super::*;
+    use crate::sys::RPC_E_WRONG_THREAD;
+
+    crate::interfaces! {
+        #[uuid("3C0F0E1A-5B43-4B7E-9B3E-6D5F2C7A8E01")]
+        unsafe interface IValue: IUnknown {
+            fn value(&self) -> u32;
+        }
+    }
+
+    crate::class! {
+        class Value: IValue {
+            value: u32,
+        }
+
+        impl IValue for Value {
+            fn value(&self) -> u32 {
+                self.value
+            }
+        }
+    }
+
+    crate::class! {
+        class AgileValue: IValue, IAgileObject {
+            value: u32,
+        }
+
+        impl IValue for AgileValue {
+            fn value(&self) -> u32 {
+                self.value
+            }
+        }
+
+        impl IAgileObject for AgileValue {}
+    }
+
+    #[test]
+    fn agile_objects_resolve_on_other_threads() {
+        let object = AgileValue::allocate(7).query::<IValue>().unwrap();
+        let reference = AgileRef::new(&object).unwrap();
+        assert!(reference.is_agile());
+        let value = std::thread::spawn(move || unsafe { reference.resolve().unwrap().value() });
+        assert_eq!(value.join().unwrap(), 7);
+    }
+
+    #[test]
+    fn other_objects_only_resolve_on_their_thread() {
+        let object = Value::allocate(3).query::<IValue>().unwrap();
+        let reference = AgileRef::new(&object).unwrap();
+        assert!(!reference.is_agile());
+        assert_eq!(unsafe { reference.resolve().unwrap().value() }, 3);
+
+        let reference = std::thread::spawn(move || {
+            assert_eq!(reference.resolve().err(), Some(RPC_E_WRONG_THREAD));
+            reference
+        })
+        .join()
+        .unwrap();
+        assert_eq!(unsafe { reference.resolve().unwrap().value() }, 3);
+
+        // Dropped elsewhere, the object is released once its thread uses the table again
+        assert_eq!(references(&object), 2);
+        std::thread::spawn(move || drop(reference)).join().unwrap();
+        assert_eq!(references(&object), 2);
+        drop(AgileRef::new(&object).unwrap());
+        assert_eq!(references(&object), 1);
+    }
+
+    fn references(object: &IValue) -> u32 {
+        let unknown = object.as_iunknown();
+        unsafe {
+            unknown.add_ref();
+            unknown.release()
+        }
+    }
+}
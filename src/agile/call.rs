@@ -0,0 +1,274 @@
+use super::AgileRef;
+use crate::runtime::{spawn_in_apartment, ApartmentType, Dispatcher};
+use crate::sys::{HRESULT, RPC_E_DISCONNECTED};
+use crate::Interface;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+/// Runs calls on the apartment thread that owns an object
+///
+/// The object is captured on the thread that creates the `AgileCall`. Calls then run on
+/// that thread if it is a single-threaded apartment with a [`Dispatcher`], and on a new
+/// thread in the multithreaded apartment otherwise. Either way the caller gets a
+/// [`CallFuture`] for the result, which can be awaited on any executor.
+///
+/// ```rust,no_run
+/// # use com::agile::AgileCall;
+/// # use com::interfaces::IUnknown;
+/// # async fn example(object: IUnknown) -> Result<(), com::sys::HRESULT> {
+/// let call = AgileCall::new(&object)?;
+/// let refs = call.run(|object| unsafe { object.add_ref() }).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AgileCall<I: Interface> {
+    reference: Arc<AgileRef<I>>,
+    dispatcher: Option<Dispatcher>,
+}
+
+impl<I: Interface> AgileCall<I> {
+    /// Capture `object` and the apartment of the current thread
+    pub fn new(object: &I) -> Result<AgileCall<I>, HRESULT> {
+        Ok(AgileCall {
+            reference: Arc::new(AgileRef::new(object)?),
+            dispatcher: Dispatcher::current(),
+        })
+    }
+
+    /// Run `f` with the object on its apartment's thread
+    ///
+    /// The future fails with the error from resolving the object on that thread, or with
+    /// `RPC_E_DISCONNECTED` if the thread is gone before `f` runs.
+    pub fn run<F, R>(&self, f: F) -> CallFuture<R>
+    where
+        F: FnOnce(&I) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (completer, future) = completion();
+        let reference = self.reference.clone();
+        let call = move || {
+            let result = reference.resolve().map(|object| f(&object));
+            // Let go of the reference before the caller can continue so that the last one
+            // is normally dropped by the caller
+            drop(reference);
+            completer.complete(result);
+        };
+        match &self.dispatcher {
+            // A failed post drops the call, which completes the future with an error
+            Some(dispatcher) => drop(dispatcher.post(call)),
+            None => drop(spawn_in_apartment(ApartmentType::Multithreaded, call)),
+        }
+        future
+    }
+}
+
+impl<I: Interface> Clone for AgileCall<I> {
+    fn clone(&self) -> Self {
+        AgileCall {
+            reference: self.reference.clone(),
+            dispatcher: self.dispatcher.clone(),
+        }
+    }
+}
+
+struct Shared<T> {
+    result: Option<Result<T, HRESULT>>,
+    completed: bool,
+    waker: Option<Waker>,
+}
+
+/// Create a future that is completed through a [`Completer`]
+///
+/// This adapts asynchronous COM interfaces which report their completion through a
+/// callback interface: the class implementing the callback holds the completer and
+/// completes it once it is called.
+pub fn completion<T>() -> (Completer<T>, CallFuture<T>) {
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        completed: false,
+        waker: None,
+    }));
+    (
+        Completer {
+            shared: shared.clone(),
+        },
+        CallFuture { shared },
+    )
+}
+
+fn lock<T>(shared: &Mutex<Shared<T>>) -> MutexGuard<'_, Shared<T>> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Completes a [`CallFuture`]
+///
+/// Only the first completion counts. Dropping the completer without completing it
+/// completes the future with `RPC_E_DISCONNECTED`.
+pub struct Completer<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Completer<T> {
+    /// Complete the future with `result`
+    pub fn complete(&self, result: Result<T, HRESULT>) {
+        let waker = {
+            let mut shared = lock(&self.shared);
+            if shared.completed {
+                return;
+            }
+            shared.completed = true;
+            shared.result = Some(result);
+            shared.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        self.complete(Err(RPC_E_DISCONNECTED));
+    }
+}
+
+/// The result of a call that completes later
+///
+/// Returned from [`AgileCall::run`] and [`completion`].
+pub struct CallFuture<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Future for CallFuture<T> {
+    type Output = Result<T, HRESULT>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = lock(&self.shared);
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None if shared.completed => panic!("CallFuture polled after completion"),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(windows), feature = "production"))]
+mod tests {
+    use super::*;
+    use crate::interfaces::IUnknown;
+    use crate::sys::RPC_E_WRONG_THREAD;
+
+    use std::cell::Cell;
+    use std::sync::mpsc::channel;
+    use std::task::{RawWaker, RawWakerVTable};
+    use std::thread::Thread;
+
+    crate::interfaces! {
+        #[uuid("8F0B5B4C-1E0A-4E5B-9F43-2C6A1D7E9B30")]
+        unsafe interface ICounter: IUnknown {
+            fn increment(&self) -> u32;
+        }
+    }
+
+    crate::class! {
+        class Counter: ICounter {
+            count: Cell<u32>,
+        }
+
+        impl ICounter for Counter {
+            fn increment(&self) -> u32 {
+                self.count.set(self.count.get() + 1);
+                self.count.get()
+            }
+        }
+    }
+
+    /// Poll `future` on the current thread until it completes
+    fn block_on<F: Future>(future: F) -> F::Output {
+        unsafe fn clone(data: *const ()) -> RawWaker {
+            let thread = &*(data as *const Thread);
+            RawWaker::new(
+                Box::into_raw(Box::new(thread.clone())) as *const (),
+                &VTABLE,
+            )
+        }
+        unsafe fn wake(data: *const ()) {
+            Box::from_raw(data as *mut Thread).unpark();
+        }
+        unsafe fn wake_by_ref(data: *const ()) {
+            (*(data as *const Thread)).unpark();
+        }
+        unsafe fn drop(data: *const ()) {
+            std::mem::drop(Box::from_raw(data as *mut Thread));
+        }
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+
+        let thread = Box::new(std::thread::current());
+        let waker =
+            unsafe { Waker::from_raw(RawWaker::new(Box::into_raw(thread) as *const (), &VTABLE)) };
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+
+    #[test]
+    fn calls_run_on_the_owning_apartment() {
+        let (sender, receiver) = channel();
+        let apartment = spawn_in_apartment(ApartmentType::SingleThreaded, move || {
+            let counter = Counter::allocate(Cell::new(0)).query::<ICounter>().unwrap();
+            sender.send(AgileCall::new(&counter).unwrap()).unwrap();
+            let dispatcher = Dispatcher::current().unwrap();
+            dispatcher.run().unwrap();
+            unsafe { counter.increment() }
+        });
+        let call = receiver.recv().unwrap();
+
+        assert_eq!(block_on(call.run(|c| unsafe { c.increment() })), Ok(1));
+        let second = call.run(|c| unsafe { c.increment() });
+        let third = call.run(|c| unsafe { c.increment() });
+        assert_eq!(block_on(third), Ok(3));
+        assert_eq!(block_on(second), Ok(2));
+
+        call.run(|_| Dispatcher::current().unwrap().stop());
+        assert_eq!(apartment.join().unwrap(), Ok(4));
+        assert_eq!(
+            block_on(call.run(|c| unsafe { c.increment() })),
+            Err(RPC_E_DISCONNECTED)
+        );
+    }
+
+    #[test]
+    fn calls_without_a_dispatcher_need_an_agile_object() {
+        let counter = Counter::allocate(Cell::new(0)).query::<ICounter>().unwrap();
+        let call = AgileCall::new(&counter).unwrap();
+        assert_eq!(
+            block_on(call.run(|c| unsafe { c.increment() })),
+            Err(RPC_E_WRONG_THREAD)
+        );
+    }
+
+    #[test]
+    fn completers_adapt_callbacks() {
+        let (completer, future) = completion();
+        std::thread::spawn(move || {
+            completer.complete(Ok(5));
+            completer.complete(Ok(6));
+        });
+        assert_eq!(block_on(future), Ok(5));
+
+        let (completer, future) = completion::<u32>();
+        drop(completer);
+        assert_eq!(block_on(future), Err(RPC_E_DISCONNECTED));
+    }
+}
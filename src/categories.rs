@@ -0,0 +1,124 @@
+//! Component categories
+//!
+//! Hosts find classes with a certain capability through the component categories the
+//! classes implement. Servers declare them in their registration (see
+//! `ClassRegistration::implemented_category`), which writes the `Implemented Categories`
+//! keys below the class's CLSID.
+//!
+//! Classes in the current process can be added to a process-local table with
+//! [`register_class`] so that [`classes_implementing`] finds them without going through the
+//! registry. On Windows, [`registered_classes_implementing`] asks the system's component
+//! categories manager about the classes in the registry.
+use crate::lazy_global::LazyGlobal;
+use crate::sys::{CATID, CLSID};
+
+use std::sync::{Mutex, MutexGuard};
+
+struct Registration {
+    class_id: CLSID,
+    implemented: Vec<CATID>,
+}
+
+static TABLE: LazyGlobal<Mutex<Vec<Registration>>> = LazyGlobal::new();
+
+fn table() -> MutexGuard<'static, Vec<Registration>> {
+    TABLE.lock(Vec::new)
+}
+
+/// Add a class of this process and the categories it implements to the process-local table
+///
+/// Registering a class again replaces its categories.
+pub fn register_class(class_id: &CLSID, implemented: &[CATID]) {
+    let mut table = table();
+    table.retain(|r| r.class_id != *class_id);
+    table.push(Registration {
+        class_id: *class_id,
+        implemented: implemented.to_vec(),
+    });
+}
+
+/// Remove a class from the process-local table
+pub fn revoke_class(class_id: &CLSID) {
+    table().retain(|r| r.class_id != *class_id);
+}
+
+/// The classes in the process-local table that implement `category`, in registration order
+pub fn classes_implementing(category: &CATID) -> Vec<CLSID> {
+    table()
+        .iter()
+        .filter(|r| r.implemented.contains(category))
+        .map(|r| r.class_id)
+        .collect()
+}
+
+/// The classes in the registry that implement `category`
+///
+/// This uses `ICatInformation` of the system's component categories manager.
+#[cfg(windows)]
+pub fn registered_classes_implementing(
+    category: &CATID,
+) -> Result<Vec<CLSID>, crate::sys::HRESULT> {
+    use crate::interfaces::{ICatInformation, IEnumGUID};
+    use crate::sys::{CLSID_STD_COMPONENT_CATEGORIES_MGR, FAILED, GUID, S_OK};
+
+    let information =
+        crate::runtime::create_instance::<ICatInformation>(&CLSID_STD_COMPONENT_CATEGORIES_MGR)?;
+    let mut classes = None::<IEnumGUID>;
+    let hr = unsafe {
+        information.enum_classes_of_categories(
+            1u32,
+            category as *const CATID,
+            // Do not filter by required categories
+            u32::MAX,
+            std::ptr::null(),
+            &mut classes as *mut _,
+        )
+    };
+    if FAILED(hr) {
+        return Err(hr);
+    }
+    let classes = classes.unwrap();
+    let mut result = Vec::new();
+    loop {
+        let mut batch = [GUID::default(); 16];
+        let mut fetched = 0u32;
+        let hr = unsafe { classes.next(batch.len() as u32, batch.as_mut_ptr(), &mut fetched) };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        result.extend_from_slice(&batch[..fetched as usize]);
+        if hr != S_OK {
+            return Ok(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guid(data1: u32) -> CLSID {
+        CLSID {
+            data1,
+            data2: 0x6B2E,
+            data3: 0x4F1C,
+            data4: [0x8A, 0x3D, 0x5C, 0x7E, 0x9B, 0x1F, 0x2D, 0x4A],
+        }
+    }
+
+    #[test]
+    fn classes_are_found_by_category() {
+        let (plugin, renderer) = (guid(0xCA7E_0001), guid(0xCA7E_0002));
+        let (cat, dog) = (guid(0xC1A5_5001), guid(0xC1A5_5002));
+        register_class(&cat, &[plugin, renderer]);
+        register_class(&dog, &[plugin]);
+        assert_eq!(classes_implementing(&plugin), vec![cat, dog]);
+        assert_eq!(classes_implementing(&renderer), vec![cat]);
+
+        register_class(&cat, &[renderer]);
+        revoke_class(&dog);
+        assert_eq!(classes_implementing(&plugin), vec![]);
+        assert_eq!(classes_implementing(&renderer), vec![cat]);
+        revoke_class(&cat);
+    }
+}
@@ -1,5 +1,9 @@
 use crate::interfaces::IUnknown;
-use crate::sys::IID;
+use crate::sys::{HRESULT, IID};
+
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
 
 /// A COM compliant interface pointer
 ///
@@ -8,7 +12,7 @@ use crate::sys::IID;
 /// The struct implementing this trait must provide a valid vtable as the
 /// associated VTable type. A vtable is valid if:
 /// * it is `#[repr(C)]`
-/// * the type only contains `extern "stdcall" fn" definitions
+/// * the type only contains `extern fn` definitions of the interface's calling convention
 ///
 /// The implementor must be a transparrently equivalent to a valid interface pointer
 /// for the interface `T`. An interface pointer as the name suggests points to an
@@ -42,3 +46,93 @@ pub unsafe trait Interface: Sized + 'static {
         unsafe { std::mem::transmute_copy(self) }
     }
 }
+
+/// An interface whose objects can be called from any thread without a proxy
+///
+/// `interfaces!` implements it for `IAgileObject`. `class!` requires the fields of classes
+/// implementing an agile interface, or an interface derived from one, to be `Send + Sync`.
+///
+/// # Safety
+///
+/// Objects implementing the interface must be safe to call from any thread.
+pub unsafe trait Agile: Interface {}
+
+/// The fields that a class implementing the interface may have
+///
+/// `interfaces!` implements it for every interface. [`Agile`] interfaces require the fields
+/// to be `Send + Sync`, and other interfaces whatever their parent requires.
+#[doc(hidden)]
+pub trait ClassFields<Fields> {}
+
+/// The body of a vtable entry implemented by a class
+///
+/// Each interface declares a generic thunk per method that has the interface's calling
+/// convention and calls `call` of its type argument. `class!` implements this trait for
+/// a type per method, so that its vtables always match the interface's convention.
+#[doc(hidden)]
+pub trait Thunk<This, Args, Ret> {
+    /// Call the method through the interface pointer `this`
+    ///
+    /// # Safety
+    ///
+    /// `this` and `args` must be valid arguments of the vtable entry.
+    unsafe fn call(this: This, args: Args) -> Ret;
+}
+
+/// Builds the vtable of the interface for the class `C`, whose interface pointer is at `O`
+///
+/// `class!` implements it for every interface the class has an `impl` for, building the
+/// vtable of the parent through `Interface::Super`, so that the parent chain of an interface
+/// never has to be spelled out. `interfaces!` implements it for `IUnknown` interfaces from
+/// the [`IUnknownThunks`] of the class, in the calling convention of the interface.
+#[doc(hidden)]
+pub unsafe trait ClassVTable<C, O>: Interface {
+    fn vtable() -> Self::VTable;
+}
+
+/// The `IUnknown` methods of a class called through its interface pointer at `O`
+///
+/// `class!` implements it for every class. `this` points to the interface pointer the method
+/// was called through, whichever `IUnknown` the interface derives from.
+#[doc(hidden)]
+pub unsafe trait IUnknownThunks<O> {
+    unsafe fn query_interface(
+        this: NonNull<NonNull<c_void>>,
+        riid: *const IID,
+        ppv: *mut *mut c_void,
+    ) -> HRESULT;
+    unsafe fn add_ref(this: NonNull<NonNull<c_void>>) -> u32;
+    unsafe fn release(this: NonNull<NonNull<c_void>>) -> u32;
+}
+
+/// The [`Thunk`] of `QueryInterface` of the class `C`
+#[doc(hidden)]
+pub struct QueryInterfaceThunk<C, O>(PhantomData<(C, O)>);
+
+impl<C: IUnknownThunks<O>, O, This> Thunk<NonNull<This>, (*const IID, *mut *mut c_void), HRESULT>
+    for QueryInterfaceThunk<C, O>
+{
+    unsafe fn call(this: NonNull<This>, (riid, ppv): (*const IID, *mut *mut c_void)) -> HRESULT {
+        C::query_interface(this.cast(), riid, ppv)
+    }
+}
+
+/// The [`Thunk`] of `AddRef` of the class `C`
+#[doc(hidden)]
+pub struct AddRefThunk<C, O>(PhantomData<(C, O)>);
+
+impl<C: IUnknownThunks<O>, O, This> Thunk<NonNull<This>, (), u32> for AddRefThunk<C, O> {
+    unsafe fn call(this: NonNull<This>, (): ()) -> u32 {
+        C::add_ref(this.cast())
+    }
+}
+
+/// The [`Thunk`] of `Release` of the class `C`
+#[doc(hidden)]
+pub struct ReleaseThunk<C, O>(PhantomData<(C, O)>);
+
+impl<C: IUnknownThunks<O>, O, This> Thunk<NonNull<This>, (), u32> for ReleaseThunk<C, O> {
+    unsafe fn call(this: NonNull<This>, (): ()) -> u32 {
+        C::release(this.cast())
+    }
+}
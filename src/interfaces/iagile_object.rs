@@ -0,0 +1,11 @@
+//! Everything related to the [IAgileObject](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-iagileobject) COM interface
+use crate::interfaces;
+use crate::interfaces::IUnknown;
+
+interfaces! {
+    /// [IAgileObject](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-iagileobject) COM interface
+    ///
+    /// Objects implementing this marker interface can be called from any apartment.
+    #[uuid("94ea2b94-e9cc-49e0-c0ff-ee64ca8f5b90")]
+    pub unsafe interface IAgileObject: IUnknown {}
+}
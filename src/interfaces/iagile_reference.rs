@@ -0,0 +1,14 @@
+//! Everything related to the [IAgileReference](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-iagilereference) COM interface
+use crate::interfaces;
+use crate::interfaces::IUnknown;
+use crate::sys::{HRESULT, IID};
+use std::ffi::c_void;
+
+interfaces! {
+    /// [IAgileReference](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-iagilereference) COM interface
+    #[uuid("c03f6a43-65a4-9818-987e-e0b810d2a6f2")]
+    pub unsafe interface IAgileReference: IUnknown {
+        /// the [Resolve](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-iagilereference-resolve(refiid_void)) COM method
+        pub unsafe fn resolve(&self, riid: *const IID, ppv_object_reference: *mut *mut c_void) -> HRESULT;
+    }
+}
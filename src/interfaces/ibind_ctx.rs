@@ -0,0 +1,33 @@
+//! Everything related to the [IBindCtx](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ibindctx) COM interface
+use crate::interfaces;
+use crate::interfaces::{IRunningObjectTable, IUnknown};
+use crate::strings::PCWSTR;
+use crate::sys::{BIND_OPTS, HRESULT};
+use std::ffi::c_void;
+
+interfaces! {
+    /// [IBindCtx](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ibindctx) COM interface
+    #[uuid("0000000e-0000-0000-C000-000000000046")]
+    pub unsafe interface IBindCtx: IUnknown {
+        /// the [RegisterObjectBound](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-registerobjectbound) COM method
+        pub unsafe fn register_object_bound(&self, punk: IUnknown) -> HRESULT;
+        /// the [RevokeObjectBound](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-revokeobjectbound) COM method
+        pub unsafe fn revoke_object_bound(&self, punk: IUnknown) -> HRESULT;
+        /// the [ReleaseBoundObjects](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-releaseboundobjects) COM method
+        pub unsafe fn release_bound_objects(&self) -> HRESULT;
+        /// the [SetBindOptions](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-setbindoptions) COM method
+        pub unsafe fn set_bind_options(&self, pbindopts: *mut BIND_OPTS) -> HRESULT;
+        /// the [GetBindOptions](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-getbindoptions) COM method
+        pub unsafe fn get_bind_options(&self, pbindopts: *mut BIND_OPTS) -> HRESULT;
+        /// the [GetRunningObjectTable](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-getrunningobjecttable) COM method
+        pub unsafe fn get_running_object_table(&self, pprot: *mut Option<IRunningObjectTable>) -> HRESULT;
+        /// the [RegisterObjectParam](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-registerobjectparam) COM method
+        pub unsafe fn register_object_param(&self, psz_key: PCWSTR, punk: IUnknown) -> HRESULT;
+        /// the [GetObjectParam](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-getobjectparam) COM method
+        pub unsafe fn get_object_param(&self, psz_key: PCWSTR, ppunk: *mut Option<IUnknown>) -> HRESULT;
+        /// the [EnumObjectParam](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-enumobjectparam) COM method
+        pub unsafe fn enum_object_param(&self, ppenum: *mut *mut c_void) -> HRESULT;
+        /// the [RevokeObjectParam](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ibindctx-revokeobjectparam) COM method
+        pub unsafe fn revoke_object_param(&self, psz_key: PCWSTR) -> HRESULT;
+    }
+}
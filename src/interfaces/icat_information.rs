@@ -0,0 +1,52 @@
+//! Everything related to the [ICatInformation](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nn-comcat-icatinformation) COM interface
+use crate::interfaces;
+use crate::interfaces::iunknown::IUnknown;
+use crate::interfaces::IEnumGUID;
+use crate::strings::TaskString;
+use crate::sys::{CATEGORYINFO, CATID, CLSID, HRESULT};
+
+interfaces! {
+    /// [IEnumCATEGORYINFO](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nn-comcat-ienumcategoryinfo) COM interface
+    #[uuid("0002E011-0000-0000-C000-000000000046")]
+    pub unsafe interface IEnumCATEGORYINFO: IUnknown {
+        /// the [Next](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-ienumcategoryinfo-next) COM method
+        pub unsafe fn next(&self, celt: u32, rgelt: *mut CATEGORYINFO, pcelt_fetched: *mut u32) -> HRESULT;
+        /// the [Skip](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-ienumcategoryinfo-skip) COM method
+        pub unsafe fn skip(&self, celt: u32) -> HRESULT;
+        /// the [Reset](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-ienumcategoryinfo-reset) COM method
+        pub unsafe fn reset(&self) -> HRESULT;
+        /// the [Clone](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-ienumcategoryinfo-clone) COM method
+        pub unsafe fn clone_enum(&self, ppenum: *mut Option<IEnumCATEGORYINFO>) -> HRESULT;
+    }
+
+    /// [ICatInformation](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nn-comcat-icatinformation) COM interface
+    #[uuid("0002E013-0000-0000-C000-000000000046")]
+    pub unsafe interface ICatInformation: IUnknown {
+        /// the [EnumCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatinformation-enumcategories) COM method
+        pub unsafe fn enum_categories(&self, lcid: u32, ppenum_category_info: *mut Option<IEnumCATEGORYINFO>) -> HRESULT;
+        /// the [GetCategoryDesc](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatinformation-getcategorydesc) COM method
+        pub unsafe fn get_category_desc(&self, rcatid: *const CATID, lcid: u32, psz_desc: *mut TaskString) -> HRESULT;
+        /// the [EnumClassesOfCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatinformation-enumclassesofcategories) COM method
+        pub unsafe fn enum_classes_of_categories(
+            &self,
+            c_implemented: u32,
+            rgcatid_impl: *const CATID,
+            c_required: u32,
+            rgcatid_req: *const CATID,
+            ppenum_clsid: *mut Option<IEnumGUID>,
+        ) -> HRESULT;
+        /// the [IsClassOfCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatinformation-isclassofcategories) COM method
+        pub unsafe fn is_class_of_categories(
+            &self,
+            rclsid: *const CLSID,
+            c_implemented: u32,
+            rgcatid_impl: *const CATID,
+            c_required: u32,
+            rgcatid_req: *const CATID,
+        ) -> HRESULT;
+        /// the [EnumImplCategoriesOfClass](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatinformation-enumimplcategoriesofclass) COM method
+        pub unsafe fn enum_impl_categories_of_class(&self, rclsid: *const CLSID, ppenum_catid: *mut Option<IEnumGUID>) -> HRESULT;
+        /// the [EnumReqCategoriesOfClass](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatinformation-enumreqcategoriesofclass) COM method
+        pub unsafe fn enum_req_categories_of_class(&self, rclsid: *const CLSID, ppenum_catid: *mut Option<IEnumGUID>) -> HRESULT;
+    }
+}
@@ -0,0 +1,23 @@
+//! Everything related to the [ICatRegister](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nn-comcat-icatregister) COM interface
+use crate::interfaces;
+use crate::interfaces::iunknown::IUnknown;
+use crate::sys::{CATEGORYINFO, CATID, CLSID, HRESULT};
+
+interfaces! {
+    /// [ICatRegister](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nn-comcat-icatregister) COM interface
+    #[uuid("0002E012-0000-0000-C000-000000000046")]
+    pub unsafe interface ICatRegister: IUnknown {
+        /// the [RegisterCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatregister-registercategories) COM method
+        pub unsafe fn register_categories(&self, c_categories: u32, rgcategory_info: *const CATEGORYINFO) -> HRESULT;
+        /// the [UnRegisterCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatregister-unregistercategories) COM method
+        pub unsafe fn unregister_categories(&self, c_categories: u32, rgcatid: *const CATID) -> HRESULT;
+        /// the [RegisterClassImplCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatregister-registerclassimplcategories) COM method
+        pub unsafe fn register_class_impl_categories(&self, rclsid: *const CLSID, c_categories: u32, rgcatid: *const CATID) -> HRESULT;
+        /// the [UnRegisterClassImplCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatregister-unregisterclassimplcategories) COM method
+        pub unsafe fn unregister_class_impl_categories(&self, rclsid: *const CLSID, c_categories: u32, rgcatid: *const CATID) -> HRESULT;
+        /// the [RegisterClassReqCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatregister-registerclassreqcategories) COM method
+        pub unsafe fn register_class_req_categories(&self, rclsid: *const CLSID, c_categories: u32, rgcatid: *const CATID) -> HRESULT;
+        /// the [UnRegisterClassReqCategories](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-icatregister-unregisterclassreqcategories) COM method
+        pub unsafe fn unregister_class_req_categories(&self, rclsid: *const CLSID, c_categories: u32, rgcatid: *const CATID) -> HRESULT;
+    }
+}
@@ -0,0 +1,19 @@
+//! Everything related to the [IEnumGUID](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nn-comcat-ienumguid) COM interface
+use crate::interfaces;
+use crate::interfaces::iunknown::IUnknown;
+use crate::sys::{GUID, HRESULT};
+
+interfaces! {
+    /// [IEnumGUID](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nn-comcat-ienumguid) COM interface
+    #[uuid("0002E000-0000-0000-C000-000000000046")]
+    pub unsafe interface IEnumGUID: IUnknown {
+        /// the [Next](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-ienumguid-next) COM method
+        pub unsafe fn next(&self, celt: u32, rgelt: *mut GUID, pcelt_fetched: *mut u32) -> HRESULT;
+        /// the [Skip](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-ienumguid-skip) COM method
+        pub unsafe fn skip(&self, celt: u32) -> HRESULT;
+        /// the [Reset](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-ienumguid-reset) COM method
+        pub unsafe fn reset(&self) -> HRESULT;
+        /// the [Clone](https://docs.microsoft.com/en-us/windows/win32/api/comcat/nf-comcat-ienumguid-clone) COM method
+        pub unsafe fn clone_enum(&self, ppenum: *mut Option<IEnumGUID>) -> HRESULT;
+    }
+}
@@ -0,0 +1,19 @@
+//! Everything related to the [IEnumSTATSTG](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ienumstatstg) COM interface
+use crate::interfaces;
+use crate::interfaces::iunknown::IUnknown;
+use crate::sys::{HRESULT, STATSTG};
+
+interfaces! {
+    /// [IEnumSTATSTG](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ienumstatstg) COM interface
+    #[uuid("0000000d-0000-0000-C000-000000000046")]
+    pub unsafe interface IEnumSTATSTG: IUnknown {
+        /// the [Next](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ienumstatstg-next) COM method
+        pub unsafe fn next(&self, celt: u32, rgelt: *mut STATSTG, pcelt_fetched: *mut u32) -> HRESULT;
+        /// the [Skip](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ienumstatstg-skip) COM method
+        pub unsafe fn skip(&self, celt: u32) -> HRESULT;
+        /// the [Reset](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ienumstatstg-reset) COM method
+        pub unsafe fn reset(&self) -> HRESULT;
+        /// the [Clone](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ienumstatstg-clone) COM method
+        pub unsafe fn clone_enum(&self, ppenum: *mut Option<IEnumSTATSTG>) -> HRESULT;
+    }
+}
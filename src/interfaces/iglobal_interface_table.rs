@@ -0,0 +1,28 @@
+//! Everything related to the [IGlobalInterfaceTable](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-iglobalinterfacetable) COM interface
+use crate::interfaces;
+use crate::interfaces::IUnknown;
+use crate::sys::{HRESULT, IID};
+use std::ffi::c_void;
+
+interfaces! {
+    /// [IGlobalInterfaceTable](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-iglobalinterfacetable) COM interface
+    #[uuid("00000146-0000-0000-C000-000000000046")]
+    pub unsafe interface IGlobalInterfaceTable: IUnknown {
+        /// the [RegisterInterfaceInGlobal](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-iglobalinterfacetable-registerinterfaceinglobal) COM method
+        pub unsafe fn register_interface_in_global(
+            &self,
+            p_unk: IUnknown,
+            riid: *const IID,
+            pdw_cookie: *mut u32,
+        ) -> HRESULT;
+        /// the [RevokeInterfaceFromGlobal](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-iglobalinterfacetable-revokeinterfacefromglobal) COM method
+        pub unsafe fn revoke_interface_from_global(&self, dw_cookie: u32) -> HRESULT;
+        /// the [GetInterfaceFromGlobal](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-iglobalinterfacetable-getinterfacefromglobal) COM method
+        pub unsafe fn get_interface_from_global(
+            &self,
+            dw_cookie: u32,
+            riid: *const IID,
+            ppv: *mut *mut c_void,
+        ) -> HRESULT;
+    }
+}
@@ -0,0 +1,114 @@
+//! Everything related to the [IMoniker](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-imoniker)
+//! and [IEnumMoniker](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ienummoniker) COM interfaces
+use crate::interfaces;
+use crate::interfaces::{IBindCtx, IPersistStream, IUnknown};
+use crate::strings::{TaskString, PCWSTR};
+use crate::sys::{BOOL, FILETIME, HRESULT, IID};
+use std::ffi::c_void;
+
+interfaces! {
+    /// [IMoniker](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-imoniker) COM interface
+    #[uuid("0000000f-0000-0000-C000-000000000046")]
+    pub unsafe interface IMoniker: IPersistStream {
+        /// the [BindToObject](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-bindtoobject) COM method
+        pub unsafe fn bind_to_object(
+            &self,
+            pbc: IBindCtx,
+            pmk_to_left: Option<IMoniker>,
+            riid_result: *const IID,
+            ppv_result: *mut *mut c_void,
+        ) -> HRESULT;
+        /// the [BindToStorage](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-bindtostorage) COM method
+        pub unsafe fn bind_to_storage(
+            &self,
+            pbc: IBindCtx,
+            pmk_to_left: Option<IMoniker>,
+            riid: *const IID,
+            ppv_obj: *mut *mut c_void,
+        ) -> HRESULT;
+        /// the [Reduce](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-reduce) COM method
+        pub unsafe fn reduce(
+            &self,
+            pbc: IBindCtx,
+            dw_reduce_how_far: u32,
+            ppmk_to_left: *mut Option<IMoniker>,
+            ppmk_reduced: *mut Option<IMoniker>,
+        ) -> HRESULT;
+        /// the [ComposeWith](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-composewith) COM method
+        pub unsafe fn compose_with(
+            &self,
+            pmk_right: IMoniker,
+            f_only_if_not_generic: BOOL,
+            ppmk_composite: *mut Option<IMoniker>,
+        ) -> HRESULT;
+        /// the [Enum](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-enum) COM method
+        pub unsafe fn enum_moniker(
+            &self,
+            f_forward: BOOL,
+            ppenum_moniker: *mut Option<IEnumMoniker>,
+        ) -> HRESULT;
+        /// the [IsEqual](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-isequal) COM method
+        pub unsafe fn is_equal(&self, pmk_other_moniker: IMoniker) -> HRESULT;
+        /// the [Hash](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-hash) COM method
+        pub unsafe fn hash(&self, pdw_hash: *mut u32) -> HRESULT;
+        /// the [IsRunning](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-isrunning) COM method
+        pub unsafe fn is_running(
+            &self,
+            pbc: IBindCtx,
+            pmk_to_left: Option<IMoniker>,
+            pmk_newly_running: Option<IMoniker>,
+        ) -> HRESULT;
+        /// the [GetTimeOfLastChange](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-gettimeoflastchange) COM method
+        pub unsafe fn get_time_of_last_change(
+            &self,
+            pbc: IBindCtx,
+            pmk_to_left: Option<IMoniker>,
+            p_file_time: *mut FILETIME,
+        ) -> HRESULT;
+        /// the [Inverse](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-inverse) COM method
+        pub unsafe fn inverse(&self, ppmk: *mut Option<IMoniker>) -> HRESULT;
+        /// the [CommonPrefixWith](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-commonprefixwith) COM method
+        pub unsafe fn common_prefix_with(
+            &self,
+            pmk_other: IMoniker,
+            ppmk_prefix: *mut Option<IMoniker>,
+        ) -> HRESULT;
+        /// the [RelativePathTo](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-relativepathto) COM method
+        pub unsafe fn relative_path_to(
+            &self,
+            pmk_other: IMoniker,
+            ppmk_rel_path: *mut Option<IMoniker>,
+        ) -> HRESULT;
+        /// the [GetDisplayName](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-getdisplayname) COM method
+        pub unsafe fn get_display_name(
+            &self,
+            pbc: IBindCtx,
+            pmk_to_left: Option<IMoniker>,
+            ppsz_display_name: *mut TaskString,
+        ) -> HRESULT;
+        /// the [ParseDisplayName](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-parsedisplayname) COM method
+        pub unsafe fn parse_display_name(
+            &self,
+            pbc: IBindCtx,
+            pmk_to_left: Option<IMoniker>,
+            psz_display_name: PCWSTR,
+            pch_eaten: *mut u32,
+            ppmk_out: *mut Option<IMoniker>,
+        ) -> HRESULT;
+        /// the [IsSystemMoniker](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-imoniker-issystemmoniker) COM method
+        pub unsafe fn is_system_moniker(&self, pdw_mksys: *mut u32) -> HRESULT;
+    }
+
+    /// [IEnumMoniker](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ienummoniker) COM interface
+    #[uuid("00000102-0000-0000-C000-000000000046")]
+    pub unsafe interface IEnumMoniker: IUnknown {
+        /// the [Next](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ienummoniker-next) COM method
+        pub unsafe fn next(&self, celt: u32, rgelt: *mut Option<IMoniker>, pcelt_fetched: *mut u32) -> HRESULT;
+        /// the [Skip](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ienummoniker-skip) COM method
+        pub unsafe fn skip(&self, celt: u32) -> HRESULT;
+        /// the [Reset](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ienummoniker-reset) COM method
+        pub unsafe fn reset(&self) -> HRESULT;
+        /// the [Clone](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ienummoniker-clone) COM method
+        pub unsafe fn clone_enum(&self, ppenum: *mut Option<IEnumMoniker>) -> HRESULT;
+    }
+}
@@ -0,0 +1,27 @@
+//! Everything related to the [IPersist](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ipersist)
+//! and [IPersistStream](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ipersiststream) COM interfaces
+use crate::interfaces;
+use crate::interfaces::{IStream, IUnknown};
+use crate::sys::{BOOL, CLSID, HRESULT};
+
+interfaces! {
+    /// [IPersist](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ipersist) COM interface
+    #[uuid("0000010c-0000-0000-C000-000000000046")]
+    pub unsafe interface IPersist: IUnknown {
+        /// the [GetClassID](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ipersist-getclassid) COM method
+        pub unsafe fn get_class_id(&self, p_class_id: *mut CLSID) -> HRESULT;
+    }
+
+    /// [IPersistStream](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-ipersiststream) COM interface
+    #[uuid("00000109-0000-0000-C000-000000000046")]
+    pub unsafe interface IPersistStream: IPersist {
+        /// the [IsDirty](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ipersiststream-isdirty) COM method
+        pub unsafe fn is_dirty(&self) -> HRESULT;
+        /// the [Load](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ipersiststream-load) COM method
+        pub unsafe fn load(&self, p_stm: IStream) -> HRESULT;
+        /// the [Save](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ipersiststream-save) COM method
+        pub unsafe fn save(&self, p_stm: IStream, f_clear_dirty: BOOL) -> HRESULT;
+        /// the [GetSizeMax](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-ipersiststream-getsizemax) COM method
+        pub unsafe fn get_size_max(&self, pcb_size: *mut u64) -> HRESULT;
+    }
+}
@@ -0,0 +1,39 @@
+//! Everything related to the [IRunningObjectTable](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-irunningobjecttable) COM interface
+use crate::interfaces;
+use crate::interfaces::{IEnumMoniker, IMoniker, IUnknown};
+use crate::sys::{FILETIME, HRESULT};
+
+interfaces! {
+    /// [IRunningObjectTable](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-irunningobjecttable) COM interface
+    #[uuid("00000010-0000-0000-C000-000000000046")]
+    pub unsafe interface IRunningObjectTable: IUnknown {
+        /// the [Register](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-irunningobjecttable-register) COM method
+        pub unsafe fn register(
+            &self,
+            grf_flags: u32,
+            punk_object: IUnknown,
+            pmk_object_name: IMoniker,
+            pdw_register: *mut u32,
+        ) -> HRESULT;
+        /// the [Revoke](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-irunningobjecttable-revoke) COM method
+        pub unsafe fn revoke(&self, dw_register: u32) -> HRESULT;
+        /// the [IsRunning](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-irunningobjecttable-isrunning) COM method
+        pub unsafe fn is_running(&self, pmk_object_name: IMoniker) -> HRESULT;
+        /// the [GetObject](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-irunningobjecttable-getobject) COM method
+        pub unsafe fn get_object(
+            &self,
+            pmk_object_name: IMoniker,
+            ppunk_object: *mut Option<IUnknown>,
+        ) -> HRESULT;
+        /// the [NoteChangeTime](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-irunningobjecttable-notechangetime) COM method
+        pub unsafe fn note_change_time(&self, dw_register: u32, pfiletime: *const FILETIME) -> HRESULT;
+        /// the [GetTimeOfLastChange](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-irunningobjecttable-gettimeoflastchange) COM method
+        pub unsafe fn get_time_of_last_change(
+            &self,
+            pmk_object_name: IMoniker,
+            pfiletime: *mut FILETIME,
+        ) -> HRESULT;
+        /// the [EnumRunning](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-irunningobjecttable-enumrunning) COM method
+        pub unsafe fn enum_running(&self, ppenum_moniker: *mut Option<IEnumMoniker>) -> HRESULT;
+    }
+}
@@ -0,0 +1,99 @@
+//! Everything related to the [IStorage](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-istorage) COM interface
+use crate::interfaces;
+use crate::interfaces::{IEnumSTATSTG, IStream, IUnknown};
+use crate::sys::{CLSID, FILETIME, HRESULT, IID, STATSTG};
+use std::ffi::c_void;
+
+interfaces! {
+    /// [IStorage](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-istorage) COM interface
+    #[uuid("0000000b-0000-0000-C000-000000000046")]
+    pub unsafe interface IStorage: IUnknown {
+        /// the [CreateStream](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-createstream) COM method
+        pub unsafe fn create_stream(
+            &self,
+            pwcs_name: *const u16,
+            grf_mode: u32,
+            reserved1: u32,
+            reserved2: u32,
+            ppstm: *mut Option<IStream>,
+        ) -> HRESULT;
+        /// the [OpenStream](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-openstream) COM method
+        pub unsafe fn open_stream(
+            &self,
+            pwcs_name: *const u16,
+            reserved1: *mut c_void,
+            grf_mode: u32,
+            reserved2: u32,
+            ppstm: *mut Option<IStream>,
+        ) -> HRESULT;
+        /// the [CreateStorage](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-createstorage) COM method
+        pub unsafe fn create_storage(
+            &self,
+            pwcs_name: *const u16,
+            grf_mode: u32,
+            reserved1: u32,
+            reserved2: u32,
+            ppstg: *mut Option<IStorage>,
+        ) -> HRESULT;
+        /// the [OpenStorage](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-openstorage) COM method
+        pub unsafe fn open_storage(
+            &self,
+            pwcs_name: *const u16,
+            pstg_priority: Option<IStorage>,
+            grf_mode: u32,
+            snb_exclude: *const *const u16,
+            reserved: u32,
+            ppstg: *mut Option<IStorage>,
+        ) -> HRESULT;
+        /// the [CopyTo](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-copyto) COM method
+        pub unsafe fn copy_to(
+            &self,
+            ciid_exclude: u32,
+            rgiid_exclude: *const IID,
+            snb_exclude: *const *const u16,
+            pstg_dest: IStorage,
+        ) -> HRESULT;
+        /// the [MoveElementTo](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-moveelementto) COM method
+        pub unsafe fn move_element_to(
+            &self,
+            pwcs_name: *const u16,
+            pstg_dest: IStorage,
+            pwcs_new_name: *const u16,
+            grf_flags: u32,
+        ) -> HRESULT;
+        /// the [Commit](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-commit) COM method
+        pub unsafe fn commit(&self, grf_commit_flags: u32) -> HRESULT;
+        /// the [Revert](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-revert) COM method
+        pub unsafe fn revert(&self) -> HRESULT;
+        /// the [EnumElements](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-enumelements) COM method
+        pub unsafe fn enum_elements(
+            &self,
+            reserved1: u32,
+            reserved2: *mut c_void,
+            reserved3: u32,
+            ppenum: *mut Option<IEnumSTATSTG>,
+        ) -> HRESULT;
+        /// the [DestroyElement](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-destroyelement) COM method
+        pub unsafe fn destroy_element(&self, pwcs_name: *const u16) -> HRESULT;
+        /// the [RenameElement](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-renameelement) COM method
+        pub unsafe fn rename_element(
+            &self,
+            pwcs_old_name: *const u16,
+            pwcs_new_name: *const u16,
+        ) -> HRESULT;
+        /// the [SetElementTimes](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-setelementtimes) COM method
+        pub unsafe fn set_element_times(
+            &self,
+            pwcs_name: *const u16,
+            pctime: *const FILETIME,
+            patime: *const FILETIME,
+            pmtime: *const FILETIME,
+        ) -> HRESULT;
+        /// the [SetClass](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-setclass) COM method
+        pub unsafe fn set_class(&self, clsid: *const CLSID) -> HRESULT;
+        /// the [SetStateBits](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-setstatebits) COM method
+        pub unsafe fn set_state_bits(&self, grf_state_bits: u32, grf_mask: u32) -> HRESULT;
+        /// the [Stat](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istorage-stat) COM method
+        pub unsafe fn stat(&self, pstatstg: *mut STATSTG, grf_stat_flag: u32) -> HRESULT;
+    }
+}
@@ -0,0 +1,46 @@
+//! Everything related to the [ISequentialStream](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-isequentialstream)
+//! and [IStream](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-istream) COM interfaces
+use crate::interfaces;
+use crate::interfaces::iunknown::IUnknown;
+use crate::sys::{HRESULT, STATSTG};
+use std::ffi::c_void;
+
+interfaces! {
+    /// [ISequentialStream](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-isequentialstream) COM interface
+    #[uuid("0c733a30-2a1c-11ce-ade5-00aa0044773d")]
+    pub unsafe interface ISequentialStream: IUnknown {
+        /// the [Read](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-isequentialstream-read) COM method
+        pub unsafe fn read(&self, pv: *mut c_void, cb: u32, pcb_read: *mut u32) -> HRESULT;
+        /// the [Write](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-isequentialstream-write) COM method
+        pub unsafe fn write(&self, pv: *const c_void, cb: u32, pcb_written: *mut u32) -> HRESULT;
+    }
+
+    /// [IStream](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nn-objidl-istream) COM interface
+    #[uuid("0000000c-0000-0000-C000-000000000046")]
+    pub unsafe interface IStream: ISequentialStream {
+        /// the [Seek](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-seek) COM method
+        pub unsafe fn seek(&self, dlib_move: i64, dw_origin: u32, plib_new_position: *mut u64) -> HRESULT;
+        /// the [SetSize](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-setsize) COM method
+        pub unsafe fn set_size(&self, lib_new_size: u64) -> HRESULT;
+        /// the [CopyTo](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-copyto) COM method
+        pub unsafe fn copy_to(
+            &self,
+            pstm: IStream,
+            cb: u64,
+            pcb_read: *mut u64,
+            pcb_written: *mut u64,
+        ) -> HRESULT;
+        /// the [Commit](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-commit) COM method
+        pub unsafe fn commit(&self, grf_commit_flags: u32) -> HRESULT;
+        /// the [Revert](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-revert) COM method
+        pub unsafe fn revert(&self) -> HRESULT;
+        /// the [LockRegion](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-lockregion) COM method
+        pub unsafe fn lock_region(&self, lib_offset: u64, cb: u64, dw_lock_type: u32) -> HRESULT;
+        /// the [UnlockRegion](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-unlockregion) COM method
+        pub unsafe fn unlock_region(&self, lib_offset: u64, cb: u64, dw_lock_type: u32) -> HRESULT;
+        /// the [Stat](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-stat) COM method
+        pub unsafe fn stat(&self, pstatstg: *mut STATSTG, grf_stat_flag: u32) -> HRESULT;
+        /// the [Clone](https://docs.microsoft.com/en-us/windows/win32/api/objidl/nf-objidl-istream-clone) COM method
+        pub unsafe fn clone_stream(&self, ppstm: *mut Option<IStream>) -> HRESULT;
+    }
+}
@@ -1,9 +1,48 @@
 //! Common COM interfaces including IUknown and IClassFactory
 
+pub mod iagile_object;
+pub mod iagile_reference;
+pub mod ibind_ctx;
+pub mod icat_information;
+pub mod icat_register;
 pub mod iclass_factory;
+pub mod ienum_guid;
+pub mod ienum_statstg;
+pub mod iglobal_interface_table;
+pub mod imoniker;
+pub mod ipersist;
+pub mod irunning_object_table;
+pub mod istorage;
+pub mod istream;
 pub mod iunknown;
 
+#[doc(inline)]
+pub use iagile_object::IAgileObject;
+#[doc(inline)]
+pub use iagile_reference::IAgileReference;
+#[doc(inline)]
+pub use ibind_ctx::IBindCtx;
+#[doc(inline)]
+pub use icat_information::{ICatInformation, IEnumCATEGORYINFO};
+#[doc(inline)]
+pub use icat_register::ICatRegister;
 #[doc(inline)]
 pub use iclass_factory::IClassFactory;
 #[doc(inline)]
+pub use ienum_guid::IEnumGUID;
+#[doc(inline)]
+pub use ienum_statstg::IEnumSTATSTG;
+#[doc(inline)]
+pub use iglobal_interface_table::IGlobalInterfaceTable;
+#[doc(inline)]
+pub use imoniker::{IEnumMoniker, IMoniker};
+#[doc(inline)]
+pub use ipersist::{IPersist, IPersistStream};
+#[doc(inline)]
+pub use irunning_object_table::IRunningObjectTable;
+#[doc(inline)]
+pub use istorage::IStorage;
+#[doc(inline)]
+pub use istream::{ISequentialStream, IStream};
+#[doc(inline)]
 pub use iunknown::IUnknown;
@@ -0,0 +1,43 @@
+//! Process-wide state that is created on first use
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, MutexGuard, Once};
+
+/// A global value created by the first caller of [`get`](LazyGlobal::get)
+///
+/// The value is leaked and lives until the process ends, so that it can still be used while
+/// other globals are torn down, e.g. when a DLL is detached.
+pub(crate) struct LazyGlobal<T> {
+    value: AtomicPtr<T>,
+    init: Once,
+}
+
+impl<T: Send + Sync> LazyGlobal<T> {
+    /// A global that has not been created yet
+    pub(crate) const fn new() -> LazyGlobal<T> {
+        LazyGlobal {
+            value: AtomicPtr::new(std::ptr::null_mut()),
+            init: Once::new(),
+        }
+    }
+
+    /// The value, created with `init` if this is the first use
+    pub(crate) fn get(&'static self, init: impl FnOnce() -> T) -> &'static T {
+        self.init.call_once(|| {
+            let value = Box::new(init());
+            self.value.store(Box::into_raw(value), Ordering::Release);
+        });
+        // SAFETY: The value was stored by `call_once` and is never freed
+        unsafe { &*self.value.load(Ordering::Acquire) }
+    }
+}
+
+impl<T: Send> LazyGlobal<Mutex<T>> {
+    /// Lock the value, created with `init` if this is the first use
+    ///
+    /// A panic while the lock was held does not poison the value for everyone else.
+    pub(crate) fn lock(&'static self, init: impl FnOnce() -> T) -> MutexGuard<'static, T> {
+        self.get(|| Mutex::new(init()))
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
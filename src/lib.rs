@@ -15,15 +15,29 @@
 //! # fn main() {}
 //! ```
 //!
+//! Methods use the `system` calling convention, which is `stdcall` on 32-bit Windows,
+//! unless the interface is annotated with `#[abi("stdcall")]` or `#[abi("C")]`. An
+//! interface must use the same convention as its parent, and classes implementing the
+//! interface pick up its convention automatically.
+//!
 
 #![deny(missing_docs)]
 
 mod abi_transferable;
+pub mod agile;
+pub mod categories;
 mod interface;
 pub mod interfaces;
+mod lazy_global;
+pub mod marshal;
+pub mod moniker;
 mod param;
+pub mod rot;
 pub mod runtime;
+pub mod storage;
+pub mod strings;
 pub mod sys;
+pub mod testing;
 
 #[cfg(feature = "production")]
 /// Functionality for producing COM classes
@@ -31,8 +45,14 @@ pub mod production;
 
 #[doc(inline)]
 pub use abi_transferable::AbiTransferable;
+#[doc(hidden)]
+pub use interface::ClassFields;
+#[doc(hidden)]
+pub use interface::Thunk;
+#[doc(hidden)]
+pub use interface::{AddRefThunk, ClassVTable, IUnknownThunks, QueryInterfaceThunk, ReleaseThunk};
 #[doc(inline)]
-pub use interface::Interface;
+pub use interface::{Agile, Interface};
 #[doc(inline)]
 pub use param::Param;
 #[doc(inline)]
@@ -40,9 +60,14 @@ pub use sys::{CLSID, IID};
 
 pub use com_macros::interfaces;
 
+pub use com_macros::AbiTransferable;
+
 #[cfg(feature = "production")]
 pub use com_macros::class;
 
+#[cfg(feature = "production")]
+pub use com_macros::mock;
+
 // this allows for the crate to refer to itself as `com` to keep macros consistent
 // whether they are used by some other crate or internally
 #[doc(hidden)]
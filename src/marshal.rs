@@ -0,0 +1,410 @@
+//! Standard marshaling of interface pointers between processes
+//!
+//! Interfaces declared with the `#[marshal]` attribute in [`interfaces!`](crate::interfaces)
+//! get a generated proxy (used on the client side in place of the real object) and a stub
+//! (which unpacks calls on the server side and invokes the real object). Calls are serialized
+//! into messages and sent over a [`Transport`] such as a Unix domain socket or a pair of pipes.
+//!
+//! ```rust,no_run
+//! # #[cfg(unix)]
+//! # fn main() -> Result<(), com::sys::HRESULT> {
+//! com::interfaces! {
+//!     #[uuid("1B7B2FE2-94B6-4B4E-9E9F-6F1A0C1D3C44")]
+//!     #[marshal]
+//!     pub unsafe interface ICounter: com::interfaces::IUnknown {
+//!         pub unsafe fn add(&self, value: u32, total: *mut u32) -> com::sys::HRESULT;
+//!     }
+//! }
+//!
+//! let stream = std::os::unix::net::UnixStream::connect("/tmp/counter.sock").unwrap();
+//! let connection = com::marshal::Connection::new(com::marshal::StreamTransport::unix(stream).unwrap());
+//! let counter = connection.root::<ICounter>()?;
+//! let mut total = 0;
+//! unsafe { counter.add(1u32, &mut total) };
+//! # Ok(())
+//! # }
+//! # #[cfg(not(unix))]
+//! # fn main() {}
+//! ```
+//!
+//! Interface pointers passed as arguments are marshaled by reference: the receiving side gets
+//! a proxy and calls on it travel back to the original object. Each side keeps the objects it
+//! handed out alive until the other side releases the last proxy to them.
+//!
+//! Arrays declared with `#[size_is(len)]` are sent along with their elements. For a
+//! `&mut [T]`, only the first `#[length_is(filled)]` elements travel back to the caller.
+//!
+//! Calls are synchronous. While waiting for a reply, calls coming in from the other side
+//! (for example a server calling back into a client's callback interface) are dispatched
+//! on the waiting thread.
+
+mod connection;
+mod proxy;
+mod transport;
+mod wire;
+
+#[doc(inline)]
+pub use connection::Connection;
+#[doc(hidden)]
+pub use proxy::proxy_call;
+#[doc(inline)]
+pub use transport::{StreamTransport, Transport, MAX_MESSAGE_LEN};
+#[doc(hidden)]
+pub use wire::{
+    marshal_array_in, marshal_array_out, unmarshal_array_in, unmarshal_array_out, ArrayLength,
+};
+#[doc(inline)]
+pub use wire::{Decoder, Encoder, Marshal, MarshalArg, MarshalReturn};
+
+use crate::interfaces::iunknown::{IUnknown, IUnknownVTable};
+use crate::lazy_global::LazyGlobal;
+use crate::sys::{HRESULT, IID, RPC_E_INVALIDMETHOD};
+use crate::Interface;
+
+use std::ffi::c_void;
+use std::sync::{Mutex, MutexGuard};
+
+/// An interface which can be called across process boundaries
+///
+/// This is implemented by [`interfaces!`](crate::interfaces) for interfaces with the
+/// `#[marshal]` attribute and should not normally be implemented by hand.
+///
+/// # Safety
+///
+/// `PROXY_VTABLE` must only contain functions that forward to [`proxy_call`] and
+/// `stub` must decode arguments in the same order the proxy encodes them.
+pub unsafe trait Remote: Interface {
+    /// The vtable of proxies for this interface
+    const PROXY_VTABLE: Self::VTable;
+
+    /// A static reference to [`Remote::PROXY_VTABLE`]
+    fn proxy_vtable() -> &'static Self::VTable;
+
+    /// Register the parent interfaces of this interface
+    fn register_parents();
+
+    /// Decode the arguments of the method with index `method`, call it on `this`
+    /// and encode the results
+    ///
+    /// Methods are numbered in declaration order, not counting the methods of
+    /// parent interfaces.
+    ///
+    /// # Safety
+    ///
+    /// `this` must be a valid interface pointer.
+    unsafe fn stub(
+        this: &Self,
+        method: u32,
+        decoder: &mut Decoder,
+        encoder: &mut Encoder,
+    ) -> Result<(), HRESULT>;
+}
+
+unsafe impl Remote for IUnknown {
+    const PROXY_VTABLE: IUnknownVTable = IUnknownVTable {
+        QueryInterface: proxy::query_interface,
+        AddRef: proxy::add_ref,
+        Release: proxy::release,
+    };
+
+    fn proxy_vtable() -> &'static IUnknownVTable {
+        &<Self as Remote>::PROXY_VTABLE
+    }
+
+    fn register_parents() {}
+
+    unsafe fn stub(
+        _this: &Self,
+        _method: u32,
+        _decoder: &mut Decoder,
+        _encoder: &mut Encoder,
+    ) -> Result<(), HRESULT> {
+        // `IUnknown` methods are handled by the connection itself
+        Err(RPC_E_INVALIDMETHOD)
+    }
+}
+
+type StubFn = unsafe fn(&IUnknown, u32, &mut Decoder, &mut Encoder) -> Result<(), HRESULT>;
+
+#[derive(Copy, Clone)]
+struct Registration {
+    iid: IID,
+    proxy_vtable: usize,
+    stub: StubFn,
+}
+
+static REGISTRY: LazyGlobal<Mutex<Vec<Registration>>> = LazyGlobal::new();
+
+fn registry() -> MutexGuard<'static, Vec<Registration>> {
+    REGISTRY.lock(Vec::new)
+}
+
+fn registration(iid: &IID) -> Option<Registration> {
+    registry().iter().find(|r| r.iid == *iid).copied()
+}
+
+unsafe fn stub<I: Remote>(
+    this: &IUnknown,
+    method: u32,
+    decoder: &mut Decoder,
+    encoder: &mut Encoder,
+) -> Result<(), HRESULT> {
+    I::stub(
+        &*(this as *const IUnknown as *const I),
+        method,
+        decoder,
+        encoder,
+    )
+}
+
+/// Register the proxy and stub of the interface `I` and its parents
+///
+/// Interfaces that appear in method signatures or are requested through
+/// [`Connection::root`] are registered automatically. Interfaces that are only ever
+/// reached through `QueryInterface` must be registered on both sides of a connection.
+pub fn register<I: Remote>() {
+    {
+        let mut registry = registry();
+        if registry.iter().any(|r| r.iid == I::IID) {
+            return;
+        }
+        registry.push(Registration {
+            iid: I::IID,
+            proxy_vtable: I::proxy_vtable() as *const _ as *const c_void as usize,
+            stub: stub::<I>,
+        });
+    }
+    I::register_parents();
+}
+
+#[cfg(all(test, unix, feature = "production"))]
+mod tests {
+    use super::*;
+    use crate::sys::S_OK;
+    use crate::{class, interfaces, AbiTransferable};
+
+    use std::cell::{Cell, RefCell};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::process::{Command, Stdio};
+    use std::ptr::NonNull;
+
+    const SOCKET_VAR: &str = "COM_MARSHAL_TEST_SOCKET";
+
+    interfaces! {
+        #[uuid("3C1A9E0B-5F5D-4A36-8E55-0B8C7D2E9F11")]
+        #[marshal]
+        pub unsafe interface ICounter: IUnknown {
+            pub unsafe fn add(&self, value: u32, total: *mut u32) -> HRESULT;
+            pub unsafe fn set_observer(&self, observer: Option<IObserver>) -> HRESULT;
+            pub unsafe fn create_counter(&self, counter: *mut Option<ICounter>) -> HRESULT;
+            pub unsafe fn exported_objects(&self, count: *mut u32) -> HRESULT;
+            pub unsafe fn add_all(
+                &self,
+                #[size_is(count)] values: &[u32],
+                count: u32,
+                total: *mut u32,
+            ) -> HRESULT;
+            pub unsafe fn digits(
+                &self,
+                #[size_is(capacity)]
+                #[length_is(filled)]
+                digits: &mut [u8],
+                capacity: u32,
+                filled: *mut u32,
+            ) -> HRESULT;
+        }
+
+        #[uuid("9D4F1B62-3E0A-47C1-B7D8-5A2E6C0F1B33")]
+        #[marshal]
+        pub unsafe interface IObserver: IUnknown {
+            pub unsafe fn notify(&self, total: u32) -> HRESULT;
+        }
+    }
+
+    class! {
+        #[no_class_factory]
+        class Counter: ICounter {
+            total: Cell<u32>,
+            observer: RefCell<Option<IObserver>>,
+            connection: Connection,
+        }
+
+        impl ICounter for Counter {
+            unsafe fn add(&self, value: u32, total: *mut u32) -> HRESULT {
+                self.total.set(self.total.get() + value);
+                *total = self.total.get();
+                if let Some(observer) = &*self.observer.borrow() {
+                    return observer.notify(self.total.get());
+                }
+                S_OK
+            }
+
+            unsafe fn set_observer(
+                &self,
+                observer: *mut NonNull<<IObserver as Interface>::VTable>,
+            ) -> HRESULT {
+                *self.observer.borrow_mut() = Option::<IObserver>::from_abi(&observer).clone();
+                S_OK
+            }
+
+            unsafe fn create_counter(&self, counter: *mut Option<ICounter>) -> HRESULT {
+                let instance =
+                    Counter::allocate(Cell::new(0), RefCell::new(None), self.connection.clone());
+                *counter = instance.query::<ICounter>();
+                S_OK
+            }
+
+            unsafe fn exported_objects(&self, count: *mut u32) -> HRESULT {
+                *count = self.connection.exported_objects() as u32;
+                S_OK
+            }
+
+            unsafe fn add_all(
+                &self,
+                #[size_is(count)] values: &[u32],
+                count: u32,
+                total: *mut u32,
+            ) -> HRESULT {
+                assert_eq!(values.len(), count as usize);
+                self.add(values.iter().sum(), total)
+            }
+
+            unsafe fn digits(
+                &self,
+                #[size_is(capacity)] digits: &mut [u8],
+                capacity: u32,
+                filled: *mut u32,
+            ) -> HRESULT {
+                let text = self.total.get().to_string();
+                let len = text.len().min(capacity as usize);
+                digits[..len].copy_from_slice(&text.as_bytes()[..len]);
+                *filled = len as u32;
+                S_OK
+            }
+        }
+    }
+
+    class! {
+        #[no_class_factory]
+        class Observer: IObserver {
+            seen: RefCell<Vec<u32>>,
+        }
+
+        impl IObserver for Observer {
+            unsafe fn notify(&self, total: u32) -> HRESULT {
+                self.seen.borrow_mut().push(total);
+                S_OK
+            }
+        }
+    }
+
+    /// The server side of `calls_cross_process_boundaries`, run in a child process
+    #[test]
+    #[ignore]
+    fn counter_server() {
+        let path = match std::env::var(SOCKET_VAR) {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let connection =
+            Connection::new(StreamTransport::unix(UnixStream::connect(path).unwrap()).unwrap());
+        let counter = Counter::allocate(Cell::new(0), RefCell::new(None), connection.clone());
+        connection.set_root(&counter.query::<ICounter>().unwrap());
+        drop(counter);
+        connection.serve().unwrap();
+    }
+
+    #[test]
+    fn calls_cross_process_boundaries() {
+        let path = std::env::temp_dir().join(format!("com-marshal-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut server = Command::new(std::env::current_exe().unwrap())
+            .arg("--ignored")
+            .arg("--exact")
+            .arg("marshal::tests::counter_server")
+            .env(SOCKET_VAR, &path)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let connection = Connection::new(StreamTransport::unix(stream).unwrap());
+        let counter = connection.root::<ICounter>().unwrap();
+        let mut total = 0;
+        unsafe {
+            assert_eq!(counter.add(2u32, &mut total), S_OK);
+            assert_eq!(total, 2);
+
+            // Interface arguments are marshaled by reference, so the server can call back
+            let observer = Observer::allocate(RefCell::new(Vec::new()));
+            let observer_interface = observer.query::<IObserver>().unwrap();
+            assert_eq!(counter.set_observer(Some(observer_interface.clone())), S_OK);
+            assert_eq!(counter.add(3u32, &mut total), S_OK);
+            assert_eq!(total, 5);
+            assert_eq!(*observer.seen.borrow(), [5]);
+            assert_eq!(connection.exported_objects(), 1);
+
+            // Sized arrays travel with their elements, only the filled part comes back
+            assert_eq!(counter.add_all(&[1, 2, 3], &mut total), S_OK);
+            assert_eq!(total, 11);
+            let mut digits = [0u8; 4];
+            let mut filled = 0;
+            assert_eq!(counter.digits(&mut digits, &mut filled), S_OK);
+            assert_eq!((filled, &digits), (2, b"11\0\0"));
+
+            // Dropping the last proxy releases the remote object
+            let mut count = 0;
+            let mut created = None;
+            assert_eq!(counter.create_counter(&mut created), S_OK);
+            let created = created.unwrap();
+            assert_eq!(created.add(7u32, &mut total), S_OK);
+            assert_eq!(total, 7);
+            assert_eq!(counter.exported_objects(&mut count), S_OK);
+            assert_eq!(count, 2);
+            drop(created);
+            assert_eq!(counter.exported_objects(&mut count), S_OK);
+            assert_eq!(count, 1);
+
+            // Objects keep their identity across the connection
+            let unknown = counter.get_interface::<IUnknown>().unwrap();
+            assert_eq!(
+                unknown.get_interface::<ICounter>().unwrap().as_raw(),
+                counter.as_raw()
+            );
+            assert!(counter.get_interface::<IObserver>().is_none());
+
+            assert_eq!(counter.set_observer(None::<IObserver>), S_OK);
+            assert_eq!(connection.exported_objects(), 0);
+        }
+        drop(counter);
+        drop(connection);
+        assert!(server.wait().unwrap().success());
+    }
+
+    #[test]
+    fn proxy_calls_fail_once_disconnected() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let client = Connection::new(StreamTransport::unix(client).unwrap());
+        let server = Connection::new(StreamTransport::unix(server).unwrap());
+        // Without a server loop the only way to get a proxy is to hand the reference over directly
+        let counter = Counter::allocate(Cell::new(0), RefCell::new(None), server.clone());
+        let counter = counter.query::<ICounter>().unwrap();
+        let mut encoder = Encoder::new(&server);
+        encoder.write_interface(Some(&counter)).unwrap();
+        let message = encoder.into_bytes();
+        let proxy = Decoder::new(&client, &message)
+            .read_interface::<ICounter>()
+            .unwrap()
+            .unwrap();
+        server.disconnect();
+
+        let mut total = 0;
+        assert_eq!(
+            unsafe { proxy.add(1u32, &mut total) },
+            crate::sys::RPC_E_DISCONNECTED
+        );
+        assert!(!client.is_connected());
+    }
+}
@@ -0,0 +1,446 @@
+use super::proxy;
+use super::wire::{Decoder, Encoder, Marshal, Reference};
+use super::{register, registration, Remote, Transport};
+use crate::interfaces::IUnknown;
+use crate::sys::{
+    E_NOINTERFACE, FAILED, GUID, HRESULT, IID, RPC_E_DISCONNECTED, RPC_E_INVALIDMETHOD,
+    RPC_E_INVALID_DATAPACKET, S_OK,
+};
+use crate::Interface;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::io;
+use std::rc::Rc;
+
+const CALL: u8 = 0;
+const REPLY: u8 = 1;
+
+/// The kind, call id and status at the start of a reply
+const REPLY_HEADER_LEN: usize = 9;
+
+/// The object id of the root object
+const ROOT: u64 = 0;
+
+/// Methods of `IUnknown` are handled by the connection
+const QUERY_INTERFACE: u32 = 0;
+const RELEASE: u32 = 2;
+
+/// One end of a connection between two processes
+///
+/// Each end exports the objects it hands out to the other side and holds proxies for the
+/// objects it received. Cloning a connection returns another handle to the same connection.
+#[derive(Clone)]
+pub struct Connection {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    /// Dropped when the connection closes so that the other side notices
+    transport: RefCell<Option<Box<dyn Transport>>>,
+    connected: Cell<bool>,
+    next_call: Cell<u32>,
+    exports: RefCell<Exports>,
+    imports: RefCell<HashMap<u64, *const proxy::ProxyManager>>,
+}
+
+#[derive(Default)]
+struct Exports {
+    objects: HashMap<u64, Export>,
+    next_id: u64,
+}
+
+struct Export {
+    object: IUnknown,
+    /// References held by proxies on the other side
+    refs: u32,
+    /// Pinned objects stay exported until the connection closes
+    pinned: bool,
+}
+
+impl Connection {
+    /// Create a connection over `transport`
+    pub fn new<T: Transport + 'static>(transport: T) -> Connection {
+        Connection {
+            inner: Rc::new(Inner {
+                transport: RefCell::new(Some(Box::new(transport))),
+                connected: Cell::new(true),
+                next_call: Cell::new(0),
+                exports: RefCell::new(Exports {
+                    objects: HashMap::new(),
+                    next_id: ROOT + 1,
+                }),
+                imports: RefCell::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Make `object` available to the other side through [`Connection::root`]
+    ///
+    /// The root object stays alive until the connection is closed.
+    pub fn set_root<I: Remote>(&self, object: &I) {
+        register::<I>();
+        let object = identity(object.as_iunknown());
+        let previous = self.inner.exports.borrow_mut().objects.insert(
+            ROOT,
+            Export {
+                object,
+                refs: 0,
+                pinned: true,
+            },
+        );
+        drop(previous);
+    }
+
+    /// Get a proxy for the root object of the other side
+    pub fn root<I: Remote>(&self) -> Result<I, HRESULT> {
+        register::<I>();
+        let unknown = self.remote_query_interface(ROOT, &I::IID)?;
+        let mut interface = None::<I>;
+        unsafe {
+            *(&mut interface as *mut Option<I> as *mut Option<IUnknown>) = Some(unknown);
+        }
+        Ok(interface.unwrap())
+    }
+
+    /// Dispatch calls from the other side until it closes the connection
+    pub fn serve(&self) -> Result<(), HRESULT> {
+        loop {
+            let message = match self.recv() {
+                Ok(Some(message)) => message,
+                Ok(None) => {
+                    self.disconnect();
+                    return Ok(());
+                }
+                Err(_) => {
+                    self.disconnect();
+                    return Err(RPC_E_DISCONNECTED);
+                }
+            };
+            if message.first() != Some(&CALL) {
+                self.disconnect();
+                return Err(RPC_E_INVALID_DATAPACKET);
+            }
+            self.dispatch(&message)?;
+        }
+    }
+
+    /// Whether the other side can still be reached
+    pub fn is_connected(&self) -> bool {
+        self.inner.connected.get()
+    }
+
+    /// The number of objects currently exported to the other side, including the root object
+    pub fn exported_objects(&self) -> usize {
+        self.inner.exports.borrow().objects.len()
+    }
+
+    /// Close the connection
+    ///
+    /// All exported objects are released and calls through proxies of the other side's
+    /// objects fail with `RPC_E_DISCONNECTED` from now on.
+    pub fn disconnect(&self) {
+        self.inner.connected.set(false);
+        let transport = self.inner.transport.borrow_mut().take();
+        drop(transport);
+        let exports = std::mem::take(&mut *self.inner.exports.borrow_mut());
+        // Releasing the objects may release proxies, so this happens outside of the borrow
+        drop(exports);
+    }
+
+    pub(super) fn ptr_eq(&self, other: &Connection) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    pub(super) fn imported(&self, object: u64) -> Option<*const proxy::ProxyManager> {
+        self.inner.imports.borrow().get(&object).copied()
+    }
+
+    pub(super) fn add_import(&self, object: u64, manager: *const proxy::ProxyManager) {
+        self.inner.imports.borrow_mut().insert(object, manager);
+    }
+
+    pub(super) fn remove_import(&self, object: u64) {
+        self.inner.imports.borrow_mut().remove(&object);
+    }
+
+    /// Export `unknown` (or find where it came from) so it can be sent to the other side
+    pub(super) fn marshal_reference(&self, unknown: Option<&IUnknown>) -> Reference {
+        let unknown = match unknown {
+            Some(unknown) => unknown,
+            None => return Reference::Null,
+        };
+        if let Some((connection, object)) = proxy::identify(unknown) {
+            if connection.ptr_eq(self) {
+                return Reference::Receiver(object);
+            }
+        }
+        let object = identity(unknown);
+        let mut exports = self.inner.exports.borrow_mut();
+        let existing = exports
+            .objects
+            .iter_mut()
+            .find(|(_, export)| export.object.as_raw() == object.as_raw());
+        if let Some((id, export)) = existing {
+            export.refs += 1;
+            return Reference::Sender(*id);
+        }
+        let id = exports.next_id;
+        exports.next_id += 1;
+        exports.objects.insert(
+            id,
+            Export {
+                object,
+                refs: 1,
+                pinned: false,
+            },
+        );
+        Reference::Sender(id)
+    }
+
+    /// Resolve a reference received from the other side as the interface `iid`
+    pub(super) fn unmarshal_reference(
+        &self,
+        reference: Reference,
+        iid: &IID,
+    ) -> Result<Option<IUnknown>, HRESULT> {
+        match reference {
+            Reference::Null => Ok(None),
+            Reference::Sender(object) => unsafe { proxy::import(self, object, iid).map(Some) },
+            Reference::Receiver(object) => {
+                let object = self.exported(object)?;
+                query(&object, iid).map(Some).ok_or(E_NOINTERFACE)
+            }
+        }
+    }
+
+    /// Ask the other side for the interface `iid` of one of its objects
+    pub(super) fn remote_query_interface(
+        &self,
+        object: u64,
+        iid: &IID,
+    ) -> Result<IUnknown, HRESULT> {
+        let mut encoder = Encoder::new(self);
+        iid.marshal(&mut encoder)?;
+        let reply = self.call(
+            object,
+            &IUnknown::IID,
+            QUERY_INTERFACE,
+            encoder.into_bytes(),
+        )?;
+        let mut decoder = Decoder::new(self, &reply);
+        let hr = HRESULT::unmarshal(&mut decoder)?;
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        decoder.read_unknown(iid)?.ok_or(RPC_E_INVALID_DATAPACKET)
+    }
+
+    /// Give back `count` references to one of the other side's objects
+    pub(super) fn remote_release(&self, object: u64, count: u32) -> Result<(), HRESULT> {
+        let mut encoder = Encoder::new(self);
+        count.marshal(&mut encoder)?;
+        self.call(object, &IUnknown::IID, RELEASE, encoder.into_bytes())
+            .map(|_| ())
+    }
+
+    /// Call a method on one of the other side's objects and wait for the reply
+    pub(super) fn call(
+        &self,
+        object: u64,
+        iid: &IID,
+        method: u32,
+        arguments: Vec<u8>,
+    ) -> Result<Vec<u8>, HRESULT> {
+        if !self.is_connected() {
+            return Err(RPC_E_DISCONNECTED);
+        }
+        let call_id = self.inner.next_call.get();
+        self.inner.next_call.set(call_id.wrapping_add(1));
+
+        let mut encoder = Encoder::new(self);
+        encoder.write_bytes(&[CALL]);
+        call_id.marshal(&mut encoder)?;
+        object.marshal(&mut encoder)?;
+        iid.marshal(&mut encoder)?;
+        method.marshal(&mut encoder)?;
+        encoder.write_bytes(&arguments);
+        self.send(&encoder.into_bytes())?;
+
+        loop {
+            let message = self.receive()?;
+            match message[0] {
+                // The other side calls back into us while handling our call
+                CALL => self.dispatch(&message)?,
+                REPLY if message.len() < REPLY_HEADER_LEN => {
+                    self.disconnect();
+                    return Err(RPC_E_INVALID_DATAPACKET);
+                }
+                REPLY => {
+                    let mut decoder = Decoder::new(self, &message[1..]);
+                    let reply_id = u32::unmarshal(&mut decoder)?;
+                    let status = HRESULT::unmarshal(&mut decoder)?;
+                    if reply_id != call_id {
+                        self.disconnect();
+                        return Err(RPC_E_INVALID_DATAPACKET);
+                    }
+                    if FAILED(status) {
+                        return Err(status);
+                    }
+                    return Ok(message[REPLY_HEADER_LEN..].to_vec());
+                }
+                _ => {
+                    self.disconnect();
+                    return Err(RPC_E_INVALID_DATAPACKET);
+                }
+            }
+        }
+    }
+
+    fn send(&self, message: &[u8]) -> Result<(), HRESULT> {
+        let sent = match &*self.inner.transport.borrow() {
+            Some(transport) => transport.send(message).is_ok(),
+            None => false,
+        };
+        if sent {
+            Ok(())
+        } else {
+            self.disconnect();
+            Err(RPC_E_DISCONNECTED)
+        }
+    }
+
+    fn recv(&self) -> io::Result<Option<Vec<u8>>> {
+        match &*self.inner.transport.borrow() {
+            Some(transport) => transport.recv(),
+            None => Ok(None),
+        }
+    }
+
+    fn receive(&self) -> Result<Vec<u8>, HRESULT> {
+        match self.recv() {
+            Ok(Some(message)) if !message.is_empty() => Ok(message),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                self.disconnect();
+                Err(RPC_E_INVALID_DATAPACKET)
+            }
+            _ => {
+                self.disconnect();
+                Err(RPC_E_DISCONNECTED)
+            }
+        }
+    }
+
+    /// Handle a call from the other side and send the reply
+    fn dispatch(&self, message: &[u8]) -> Result<(), HRESULT> {
+        let mut decoder = Decoder::new(self, &message[1..]);
+        let header = (|| -> Result<_, HRESULT> {
+            Ok((
+                u32::unmarshal(&mut decoder)?,
+                u64::unmarshal(&mut decoder)?,
+                GUID::unmarshal(&mut decoder)?,
+                u32::unmarshal(&mut decoder)?,
+            ))
+        })();
+        let (call_id, object, iid, method) = match header {
+            Ok(header) => header,
+            Err(hr) => {
+                self.disconnect();
+                return Err(hr);
+            }
+        };
+
+        let mut results = Encoder::new(self);
+        let status = match self.invoke(object, &iid, method, &mut decoder, &mut results) {
+            Ok(()) => S_OK,
+            Err(hr) => hr,
+        };
+
+        let mut reply = Encoder::new(self);
+        reply.write_bytes(&[REPLY]);
+        call_id.marshal(&mut reply)?;
+        status.marshal(&mut reply)?;
+        if status == S_OK {
+            reply.write_bytes(&results.into_bytes());
+        }
+        self.send(&reply.into_bytes())
+    }
+
+    fn invoke(
+        &self,
+        object: u64,
+        iid: &IID,
+        method: u32,
+        decoder: &mut Decoder,
+        encoder: &mut Encoder,
+    ) -> Result<(), HRESULT> {
+        if *iid == IUnknown::IID {
+            return match method {
+                QUERY_INTERFACE => {
+                    let iid = GUID::unmarshal(decoder)?;
+                    match query(&self.exported(object)?, &iid) {
+                        Some(interface) => {
+                            S_OK.marshal(encoder)?;
+                            encoder.write_unknown(Some(&interface))
+                        }
+                        None => E_NOINTERFACE.marshal(encoder),
+                    }
+                }
+                RELEASE => {
+                    let count = u32::unmarshal(decoder)?;
+                    self.release_export(object, count);
+                    Ok(())
+                }
+                _ => Err(RPC_E_INVALIDMETHOD),
+            };
+        }
+        let registration = registration(iid).ok_or(E_NOINTERFACE)?;
+        let interface = query(&self.exported(object)?, iid).ok_or(E_NOINTERFACE)?;
+        unsafe { (registration.stub)(&interface, method, decoder, encoder) }
+    }
+
+    fn exported(&self, object: u64) -> Result<IUnknown, HRESULT> {
+        self.inner
+            .exports
+            .borrow()
+            .objects
+            .get(&object)
+            .map(|export| export.object.clone())
+            .ok_or(RPC_E_DISCONNECTED)
+    }
+
+    fn release_export(&self, object: u64, count: u32) {
+        let released = {
+            let mut exports = self.inner.exports.borrow_mut();
+            match exports.objects.get_mut(&object) {
+                Some(export) => {
+                    export.refs = export.refs.saturating_sub(count);
+                    if export.refs == 0 && !export.pinned {
+                        exports.objects.remove(&object)
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            }
+        };
+        // Releasing the object may call back into the other side
+        drop(released);
+    }
+}
+
+/// The controlling `IUnknown` of an object
+fn identity(unknown: &IUnknown) -> IUnknown {
+    query(unknown, &IUnknown::IID).unwrap_or_else(|| unknown.clone())
+}
+
+/// Query `unknown` for the interface `iid`, returned as an `IUnknown` pointer
+fn query(unknown: &IUnknown, iid: &IID) -> Option<IUnknown> {
+    let mut interface = None::<IUnknown>;
+    let hr = unsafe { unknown.query_interface(iid, &mut interface as *mut _ as *mut *mut c_void) };
+    if FAILED(hr) {
+        return None;
+    }
+    interface
+}
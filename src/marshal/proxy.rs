@@ -0,0 +1,228 @@
+use super::connection::Connection;
+use super::wire::{Decoder, Encoder, MarshalReturn};
+use super::{registration, Remote};
+use crate::interfaces::iunknown::{IUnknown, IUnknownVPtr};
+use crate::sys::{E_NOINTERFACE, E_POINTER, FAILED, GUID, HRESULT, IID, S_OK};
+use crate::{AbiTransferable, Interface};
+
+use std::cell::{Cell, RefCell};
+use std::ffi::c_void;
+use std::ptr::NonNull;
+
+/// Proxies answer `QueryInterface` for this IID with themselves so that interface
+/// pointers coming back to the side that exported them can be recognized
+const IID_PROXY: IID = GUID {
+    data1: 0x5C1E_7A52,
+    data2: 0x6B0D,
+    data3: 0x4C3A,
+    data4: [0x9F, 0x4E, 0x0B, 0x2D, 0x71, 0xA8, 0x3E, 0x16],
+};
+
+/// The object behind an interface pointer handed out for a remote object
+#[repr(C)]
+struct InterfaceProxy {
+    vtable: *const c_void,
+    manager: *const ProxyManager,
+    iid: IID,
+}
+
+/// All proxies for one remote object
+///
+/// Interface proxies of the same object share a reference count so that the object
+/// has a single identity on this side of the connection.
+pub(super) struct ProxyManager {
+    connection: Connection,
+    object: u64,
+    refs: Cell<u32>,
+    /// References to the remote object received from the other side
+    remote_refs: Cell<u32>,
+    /// Boxed so that the proxies keep their address when the vector grows
+    #[allow(clippy::vec_box)]
+    interfaces: RefCell<Vec<Box<InterfaceProxy>>>,
+}
+
+impl ProxyManager {
+    /// Get the proxy implementing `iid`, creating it if necessary
+    fn interface(&self, iid: &IID) -> Result<NonNull<c_void>, HRESULT> {
+        if let Some(proxy) = self.interfaces.borrow().iter().find(|p| p.iid == *iid) {
+            return Ok(NonNull::from(&**proxy).cast());
+        }
+        let vtable = if *iid == IUnknown::IID {
+            IUnknown::proxy_vtable() as *const _ as *const c_void
+        } else {
+            registration(iid).ok_or(E_NOINTERFACE)?.proxy_vtable as *const c_void
+        };
+        let proxy = Box::new(InterfaceProxy {
+            vtable,
+            manager: self,
+            iid: *iid,
+        });
+        let pointer = NonNull::from(&*proxy).cast();
+        self.interfaces.borrow_mut().push(proxy);
+        Ok(pointer)
+    }
+
+    fn add_ref(&self) -> u32 {
+        let refs = self.refs.get() + 1;
+        self.refs.set(refs);
+        refs
+    }
+
+    unsafe fn release(manager: *const ProxyManager) -> u32 {
+        let refs = (*manager).refs.get() - 1;
+        (*manager).refs.set(refs);
+        if refs == 0 {
+            Self::destroy(manager);
+        }
+        refs
+    }
+
+    /// Free the proxies and give the remote references back to the other side
+    unsafe fn destroy(manager: *const ProxyManager) {
+        let manager = Box::from_raw(manager as *mut ProxyManager);
+        manager.connection.remove_import(manager.object);
+        let remote_refs = manager.remote_refs.get();
+        if remote_refs > 0 {
+            // There is nothing to do if the other side is gone already
+            let _ = manager
+                .connection
+                .remote_release(manager.object, remote_refs);
+        }
+    }
+}
+
+unsafe fn manager<'a>(this: NonNull<c_void>) -> &'a ProxyManager {
+    &*(*this.cast::<InterfaceProxy>().as_ptr()).manager
+}
+
+/// Get a proxy for the object `object` exported by the other side of `connection`
+///
+/// This takes over one remote reference to the object and returns the interface `iid`
+/// as an owned `IUnknown` pointer.
+pub(super) unsafe fn import(
+    connection: &Connection,
+    object: u64,
+    iid: &IID,
+) -> Result<IUnknown, HRESULT> {
+    let manager = match connection.imported(object) {
+        Some(manager) => manager,
+        None => {
+            let manager = Box::into_raw(Box::new(ProxyManager {
+                connection: connection.clone(),
+                object,
+                refs: Cell::new(0),
+                remote_refs: Cell::new(0),
+                interfaces: RefCell::new(Vec::new()),
+            }));
+            connection.add_import(object, manager);
+            manager
+        }
+    };
+    (*manager).remote_refs.set((*manager).remote_refs.get() + 1);
+    match (*manager).interface(iid) {
+        Ok(proxy) => {
+            (*manager).add_ref();
+            Ok(std::mem::transmute::<NonNull<c_void>, IUnknown>(proxy))
+        }
+        Err(hr) => {
+            if (*manager).refs.get() == 0 {
+                ProxyManager::destroy(manager);
+            }
+            Err(hr)
+        }
+    }
+}
+
+/// If `unknown` is a proxy, return its connection and the id of the remote object
+pub(super) fn identify(unknown: &IUnknown) -> Option<(Connection, u64)> {
+    let mut proxy = std::ptr::null_mut::<c_void>();
+    let hr = unsafe { unknown.query_interface(&IID_PROXY, &mut proxy) };
+    if FAILED(hr) {
+        return None;
+    }
+    let proxy = NonNull::new(proxy)?;
+    unsafe {
+        let manager = manager(proxy);
+        let identity = (manager.connection.clone(), manager.object);
+        ProxyManager::release(manager);
+        Some(identity)
+    }
+}
+
+/// Make a call through a proxy
+///
+/// This is used by the proxies generated by `interfaces!` and should not be called directly.
+///
+/// # Safety
+///
+/// `this` must point to a proxy created by this module.
+pub unsafe fn proxy_call<R: MarshalReturn>(
+    this: NonNull<c_void>,
+    iid: &IID,
+    method: u32,
+    marshal_in: impl FnOnce(&mut Encoder) -> Result<(), HRESULT>,
+    unmarshal_out: impl FnOnce(&mut Decoder) -> Result<R, HRESULT>,
+) -> R {
+    let manager = manager(this);
+    let connection = &manager.connection;
+    let result = (|| -> Result<R, HRESULT> {
+        let mut encoder = Encoder::new(connection);
+        marshal_in(&mut encoder)?;
+        let reply = connection.call(manager.object, iid, method, encoder.into_bytes())?;
+        unmarshal_out(&mut Decoder::new(connection, &reply))
+    })();
+    result.unwrap_or_else(R::failure)
+}
+
+pub(super) unsafe extern "system" fn query_interface(
+    this: NonNull<IUnknownVPtr>,
+    riid: *const IID,
+    ppv: *mut *mut c_void,
+) -> HRESULT {
+    if riid.is_null() || ppv.is_null() {
+        return E_POINTER;
+    }
+    *ppv = std::ptr::null_mut();
+    let this = this.cast::<c_void>();
+    let manager = manager(this);
+    let riid = &*riid;
+    if *riid == IID_PROXY {
+        manager.add_ref();
+        *ppv = this.as_ptr();
+        return S_OK;
+    }
+    let local =
+        *riid == IUnknown::IID || manager.interfaces.borrow().iter().any(|p| p.iid == *riid);
+    if local {
+        return match manager.interface(riid) {
+            Ok(proxy) => {
+                manager.add_ref();
+                *ppv = proxy.as_ptr();
+                S_OK
+            }
+            Err(hr) => hr,
+        };
+    }
+    if registration(riid).is_none() {
+        // We could not make calls through the interface even if the object implements it
+        return E_NOINTERFACE;
+    }
+    match manager
+        .connection
+        .remote_query_interface(manager.object, riid)
+    {
+        Ok(interface) => {
+            *ppv = interface.into_abi().as_ptr() as *mut c_void;
+            S_OK
+        }
+        Err(hr) => hr,
+    }
+}
+
+pub(super) unsafe extern "system" fn add_ref(this: NonNull<IUnknownVPtr>) -> u32 {
+    manager(this.cast()).add_ref()
+}
+
+pub(super) unsafe extern "system" fn release(this: NonNull<IUnknownVPtr>) -> u32 {
+    ProxyManager::release(manager(this.cast()))
+}
@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+
+/// The largest message a [`StreamTransport`] sends or receives
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// A channel that carries the messages of a [`Connection`](super::Connection)
+///
+/// Transports deliver whole messages in order. They do not need to be thread safe as
+/// a connection is only ever used from the thread that created it.
+pub trait Transport {
+    /// Send one message
+    fn send(&self, message: &[u8]) -> io::Result<()>;
+    /// Receive the next message or `None` once the other side has closed the transport
+    ///
+    /// Malformed messages are reported as [`io::ErrorKind::InvalidData`], which fails the
+    /// call waiting for them with `RPC_E_INVALID_DATAPACKET`.
+    fn recv(&self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// A [`Transport`] over a pair of byte streams
+///
+/// Messages are framed with a little endian `u32` length prefix. This works with anything
+/// that implements [`Read`] and [`Write`], such as a Unix domain socket, the stdin and stdout
+/// pipes of a child process or a Windows named pipe opened as a [`File`](std::fs::File).
+///
+/// Messages are limited to [`MAX_MESSAGE_LEN`] bytes. Longer ones fail with
+/// [`io::ErrorKind::InvalidData`] when received, before anything is allocated for them.
+pub struct StreamTransport<R, W> {
+    reader: RefCell<R>,
+    writer: RefCell<W>,
+}
+
+impl<R: Read, W: Write> StreamTransport<R, W> {
+    /// Create a transport reading messages from `reader` and writing them to `writer`
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader: RefCell::new(reader),
+            writer: RefCell::new(writer),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl StreamTransport<std::os::unix::net::UnixStream, std::os::unix::net::UnixStream> {
+    /// Create a transport over a connected Unix domain socket
+    pub fn unix(stream: std::os::unix::net::UnixStream) -> io::Result<Self> {
+        Ok(Self::new(stream.try_clone()?, stream))
+    }
+}
+
+impl<R: Read, W: Write> Transport for StreamTransport<R, W> {
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message is too large",
+            ));
+        }
+        let mut writer = self.writer.borrow_mut();
+        writer.write_all(&(message.len() as u32).to_le_bytes())?;
+        writer.write_all(message)?;
+        writer.flush()
+    }
+
+    fn recv(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut reader = self.reader.borrow_mut();
+        let mut header = [0; 4];
+        let mut read = 0;
+        while read < header.len() {
+            match reader.read(&mut header[read..]) {
+                // The other side closed the stream between two messages
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let len = u32::from_le_bytes(header) as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message is too large",
+            ));
+        }
+        let mut message = vec![0; len];
+        reader.read_exact(&mut message)?;
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_messages_are_rejected() {
+        let mut stream = Vec::new();
+        StreamTransport::new(io::empty(), &mut stream)
+            .send(b"hello")
+            .unwrap();
+        stream.extend_from_slice(&(MAX_MESSAGE_LEN as u32 + 1).to_le_bytes());
+
+        let transport = StreamTransport::new(&stream[..], io::sink());
+        assert_eq!(transport.recv().unwrap(), Some(b"hello".to_vec()));
+        let error = transport.recv().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,493 @@
+use super::connection::Connection;
+use super::{register, Remote};
+use crate::interfaces::IUnknown;
+use crate::sys::{GUID, HRESULT, IID, RPC_E_INVALID_DATAPACKET};
+use crate::AbiTransferable;
+
+/// Writes the arguments or results of a call into a message
+pub struct Encoder<'a> {
+    connection: &'a Connection,
+    buffer: Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+    pub(super) fn new(connection: &'a Connection) -> Self {
+        Self {
+            connection,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub(super) fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    /// Append raw bytes to the message
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes)
+    }
+
+    /// Marshal an interface pointer by reference
+    ///
+    /// The other side of the connection receives a proxy to `interface`.
+    pub fn write_interface<I: Remote>(&mut self, interface: Option<&I>) -> Result<(), HRESULT> {
+        register::<I>();
+        self.write_unknown(interface.map(|i| i.as_iunknown()))
+    }
+
+    pub(super) fn write_unknown(&mut self, unknown: Option<&IUnknown>) -> Result<(), HRESULT> {
+        let reference = self.connection.marshal_reference(unknown);
+        reference.write(self);
+        Ok(())
+    }
+}
+
+/// Reads the arguments or results of a call from a message
+pub struct Decoder<'a> {
+    connection: &'a Connection,
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub(super) fn new(connection: &'a Connection, data: &'a [u8]) -> Self {
+        Self { connection, data }
+    }
+
+    /// Take the next `len` bytes from the message
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], HRESULT> {
+        if self.data.len() < len {
+            return Err(RPC_E_INVALID_DATAPACKET);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Unmarshal an interface pointer written by [`Encoder::write_interface`]
+    pub fn read_interface<I: Remote>(&mut self) -> Result<Option<I>, HRESULT> {
+        register::<I>();
+        let unknown = self.read_unknown(&I::IID)?;
+        let mut interface = None::<I>;
+        unsafe {
+            *(&mut interface as *mut Option<I> as *mut Option<IUnknown>) = unknown;
+        }
+        Ok(interface)
+    }
+
+    /// Read an interface reference and resolve it as the interface `iid`
+    ///
+    /// The returned `IUnknown` actually points to an `iid` interface.
+    pub(super) fn read_unknown(&mut self, iid: &IID) -> Result<Option<IUnknown>, HRESULT> {
+        let reference = Reference::read(self)?;
+        self.connection.unmarshal_reference(reference, iid)
+    }
+}
+
+/// How an interface pointer travels over the wire
+#[derive(Copy, Clone)]
+pub(super) enum Reference {
+    /// A null interface pointer
+    Null,
+    /// An object exported by the sending side
+    Sender(u64),
+    /// An object exported by the receiving side, coming back to it
+    Receiver(u64),
+}
+
+impl Reference {
+    fn write(self, encoder: &mut Encoder) {
+        match self {
+            Reference::Null => encoder.write_bytes(&[0]),
+            Reference::Sender(id) => {
+                encoder.write_bytes(&[1]);
+                encoder.write_bytes(&id.to_le_bytes());
+            }
+            Reference::Receiver(id) => {
+                encoder.write_bytes(&[2]);
+                encoder.write_bytes(&id.to_le_bytes());
+            }
+        }
+    }
+
+    fn read(decoder: &mut Decoder) -> Result<Reference, HRESULT> {
+        match u8::unmarshal(decoder)? {
+            0 => Ok(Reference::Null),
+            1 => Ok(Reference::Sender(u64::unmarshal(decoder)?)),
+            2 => Ok(Reference::Receiver(u64::unmarshal(decoder)?)),
+            _ => Err(RPC_E_INVALID_DATAPACKET),
+        }
+    }
+}
+
+/// A value with a wire representation
+pub trait Marshal: Sized {
+    /// Write the value into a message
+    fn marshal(&self, encoder: &mut Encoder) -> Result<(), HRESULT>;
+    /// Read a value written by [`Marshal::marshal`]
+    fn unmarshal(decoder: &mut Decoder) -> Result<Self, HRESULT>;
+}
+
+macro_rules! primitive_marshal {
+    ($($t:ty),+) => {
+        $(impl Marshal for $t {
+            fn marshal(&self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+                encoder.write_bytes(&self.to_le_bytes());
+                Ok(())
+            }
+            fn unmarshal(decoder: &mut Decoder) -> Result<Self, HRESULT> {
+                let mut bytes = [0; std::mem::size_of::<$t>()];
+                bytes.copy_from_slice(decoder.read_bytes(std::mem::size_of::<$t>())?);
+                Ok(<$t>::from_le_bytes(bytes))
+            }
+        })*
+    };
+}
+
+primitive_marshal! { i8, u8, i16, u16, i32, u32, i64, u64 }
+
+impl Marshal for f32 {
+    fn marshal(&self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        self.to_bits().marshal(encoder)
+    }
+    fn unmarshal(decoder: &mut Decoder) -> Result<Self, HRESULT> {
+        u32::unmarshal(decoder).map(f32::from_bits)
+    }
+}
+
+impl Marshal for f64 {
+    fn marshal(&self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        self.to_bits().marshal(encoder)
+    }
+    fn unmarshal(decoder: &mut Decoder) -> Result<Self, HRESULT> {
+        u64::unmarshal(decoder).map(f64::from_bits)
+    }
+}
+
+impl Marshal for bool {
+    fn marshal(&self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        (*self as u8).marshal(encoder)
+    }
+    fn unmarshal(decoder: &mut Decoder) -> Result<Self, HRESULT> {
+        match u8::unmarshal(decoder)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(RPC_E_INVALID_DATAPACKET),
+        }
+    }
+}
+
+impl Marshal for () {
+    fn marshal(&self, _encoder: &mut Encoder) -> Result<(), HRESULT> {
+        Ok(())
+    }
+    fn unmarshal(_decoder: &mut Decoder) -> Result<Self, HRESULT> {
+        Ok(())
+    }
+}
+
+impl Marshal for GUID {
+    fn marshal(&self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        self.data1.marshal(encoder)?;
+        self.data2.marshal(encoder)?;
+        self.data3.marshal(encoder)?;
+        encoder.write_bytes(&self.data4);
+        Ok(())
+    }
+    fn unmarshal(decoder: &mut Decoder) -> Result<Self, HRESULT> {
+        let mut guid = GUID {
+            data1: u32::unmarshal(decoder)?,
+            data2: u16::unmarshal(decoder)?,
+            data3: u16::unmarshal(decoder)?,
+            data4: [0; 8],
+        };
+        guid.data4.copy_from_slice(decoder.read_bytes(8)?);
+        Ok(guid)
+    }
+}
+
+impl<T: Remote> Marshal for Option<T> {
+    fn marshal(&self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        encoder.write_interface(self.as_ref())
+    }
+    fn unmarshal(decoder: &mut Decoder) -> Result<Self, HRESULT> {
+        decoder.read_interface()
+    }
+}
+
+/// A method return type with a wire representation
+pub trait MarshalReturn: Marshal {
+    /// The value a proxy returns when the call could not be made
+    fn failure(hr: HRESULT) -> Self;
+}
+
+impl MarshalReturn for HRESULT {
+    fn failure(hr: HRESULT) -> Self {
+        hr
+    }
+}
+
+impl MarshalReturn for u32 {
+    fn failure(_hr: HRESULT) -> Self {
+        0
+    }
+}
+
+impl MarshalReturn for () {
+    fn failure(_hr: HRESULT) -> Self {}
+}
+
+/// A method argument type with a wire representation
+///
+/// The proxy calls `marshal_in` before and `unmarshal_out` after the call with the
+/// argument it was given. The stub rebuilds the argument with `unmarshal_in`, passes
+/// `as_abi` of it to the real object and sends the results back with `marshal_out`.
+///
+/// Values are passed by value, `*const T` is an input pointer and `*mut T` is an output
+/// pointer. Null pointers are preserved.
+pub trait MarshalArg: AbiTransferable {
+    /// The stub's local copy of the argument
+    type Local;
+
+    /// Write the input part of the argument
+    ///
+    /// # Safety
+    ///
+    /// `abi` must be a valid argument of this type.
+    unsafe fn marshal_in(abi: &Self::Abi, encoder: &mut Encoder) -> Result<(), HRESULT>;
+    /// Read the output part of the argument and store it
+    ///
+    /// # Safety
+    ///
+    /// `abi` must be a valid argument of this type.
+    unsafe fn unmarshal_out(abi: &Self::Abi, decoder: &mut Decoder) -> Result<(), HRESULT>;
+    /// Read the input part of the argument on the stub side
+    fn unmarshal_in(decoder: &mut Decoder) -> Result<Self::Local, HRESULT>;
+    /// The argument to pass to the real object
+    fn as_abi(local: &mut Self::Local) -> Self::Abi;
+    /// Write the output part of the argument on the stub side
+    fn marshal_out(local: Self::Local, encoder: &mut Encoder) -> Result<(), HRESULT>;
+}
+
+macro_rules! value_marshal_arg {
+    ($($t:ty),+) => {
+        $(impl MarshalArg for $t {
+            type Local = Self;
+            unsafe fn marshal_in(abi: &Self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+                abi.marshal(encoder)
+            }
+            unsafe fn unmarshal_out(_abi: &Self, _decoder: &mut Decoder) -> Result<(), HRESULT> {
+                Ok(())
+            }
+            fn unmarshal_in(decoder: &mut Decoder) -> Result<Self, HRESULT> {
+                Self::unmarshal(decoder)
+            }
+            fn as_abi(local: &mut Self) -> Self {
+                *local
+            }
+            fn marshal_out(_local: Self, _encoder: &mut Encoder) -> Result<(), HRESULT> {
+                Ok(())
+            }
+        })*
+    };
+}
+
+value_marshal_arg! { bool, i8, u8, i16, u16, i32, u32, i64, u64, f32, f64, GUID }
+
+impl<T: Marshal> MarshalArg for *const T {
+    type Local = Option<T>;
+
+    unsafe fn marshal_in(abi: &Self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        match abi.as_ref() {
+            Some(value) => {
+                true.marshal(encoder)?;
+                value.marshal(encoder)
+            }
+            None => false.marshal(encoder),
+        }
+    }
+    unsafe fn unmarshal_out(_abi: &Self, _decoder: &mut Decoder) -> Result<(), HRESULT> {
+        Ok(())
+    }
+    fn unmarshal_in(decoder: &mut Decoder) -> Result<Option<T>, HRESULT> {
+        if bool::unmarshal(decoder)? {
+            T::unmarshal(decoder).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+    fn as_abi(local: &mut Option<T>) -> Self {
+        local
+            .as_ref()
+            .map_or(std::ptr::null(), |value| value as *const T)
+    }
+    fn marshal_out(_local: Option<T>, _encoder: &mut Encoder) -> Result<(), HRESULT> {
+        Ok(())
+    }
+}
+
+impl<T: Marshal + Default> MarshalArg for *mut T {
+    type Local = Option<T>;
+
+    unsafe fn marshal_in(abi: &Self, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        (!abi.is_null()).marshal(encoder)
+    }
+    unsafe fn unmarshal_out(abi: &Self, decoder: &mut Decoder) -> Result<(), HRESULT> {
+        if !abi.is_null() {
+            // Output pointers are uninitialized on the way in
+            abi.write(T::unmarshal(decoder)?);
+        }
+        Ok(())
+    }
+    fn unmarshal_in(decoder: &mut Decoder) -> Result<Option<T>, HRESULT> {
+        Ok(if bool::unmarshal(decoder)? {
+            Some(T::default())
+        } else {
+            None
+        })
+    }
+    fn as_abi(local: &mut Option<T>) -> Self {
+        local
+            .as_mut()
+            .map_or(std::ptr::null_mut(), |value| value as *mut T)
+    }
+    fn marshal_out(local: Option<T>, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        match local {
+            Some(value) => value.marshal(encoder),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Remote> MarshalArg for T {
+    type Local = T;
+
+    unsafe fn marshal_in(abi: &Self::Abi, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        encoder.write_interface(Some(T::from_abi(abi)))
+    }
+    unsafe fn unmarshal_out(_abi: &Self::Abi, _decoder: &mut Decoder) -> Result<(), HRESULT> {
+        Ok(())
+    }
+    fn unmarshal_in(decoder: &mut Decoder) -> Result<T, HRESULT> {
+        decoder.read_interface()?.ok_or(RPC_E_INVALID_DATAPACKET)
+    }
+    fn as_abi(local: &mut T) -> Self::Abi {
+        local.get_abi()
+    }
+    fn marshal_out(_local: T, _encoder: &mut Encoder) -> Result<(), HRESULT> {
+        Ok(())
+    }
+}
+
+impl<T: Remote> MarshalArg for Option<T> {
+    type Local = Option<T>;
+
+    unsafe fn marshal_in(abi: &Self::Abi, encoder: &mut Encoder) -> Result<(), HRESULT> {
+        encoder.write_interface(Option::<T>::from_abi(abi).as_ref())
+    }
+    unsafe fn unmarshal_out(_abi: &Self::Abi, _decoder: &mut Decoder) -> Result<(), HRESULT> {
+        Ok(())
+    }
+    fn unmarshal_in(decoder: &mut Decoder) -> Result<Option<T>, HRESULT> {
+        decoder.read_interface()
+    }
+    fn as_abi(local: &mut Option<T>) -> Self::Abi {
+        local.get_abi()
+    }
+    fn marshal_out(_local: Option<T>, _encoder: &mut Encoder) -> Result<(), HRESULT> {
+        Ok(())
+    }
+}
+
+/// Write the elements of a `#[size_is]` array argument
+///
+/// # Safety
+///
+/// `abi` must be null or point to `len` elements.
+#[doc(hidden)]
+pub unsafe fn marshal_array_in<T: Marshal>(
+    abi: *const T,
+    len: usize,
+    encoder: &mut Encoder,
+) -> Result<(), HRESULT> {
+    let values = if abi.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(abi, len)
+    };
+    marshal_array_out(values, len, encoder)
+}
+
+/// Read the elements written by [`marshal_array_in`] on the stub side
+#[doc(hidden)]
+pub fn unmarshal_array_in<T: Marshal>(decoder: &mut Decoder) -> Result<Vec<T>, HRESULT> {
+    let len = u32::unmarshal(decoder)? as usize;
+    // The length comes from the other side, so it does not size the allocation up front
+    let mut values = Vec::with_capacity(len.min(decoder.data.len()));
+    for _ in 0..len {
+        values.push(T::unmarshal(decoder)?);
+    }
+    Ok(values)
+}
+
+/// Write the first `len` elements of an array the object filled in on the stub side
+#[doc(hidden)]
+pub fn marshal_array_out<T: Marshal>(
+    values: &[T],
+    len: usize,
+    encoder: &mut Encoder,
+) -> Result<(), HRESULT> {
+    let values = &values[..len.min(values.len())];
+    (values.len() as u32).marshal(encoder)?;
+    for value in values {
+        value.marshal(encoder)?;
+    }
+    Ok(())
+}
+
+/// Read the elements written by [`marshal_array_out`] into the caller's array
+///
+/// # Safety
+///
+/// `abi` must be null or point to `capacity` elements. The elements are overwritten without
+/// being dropped, so they may be uninitialized.
+#[doc(hidden)]
+pub unsafe fn unmarshal_array_out<T: Marshal>(
+    abi: *mut T,
+    capacity: usize,
+    decoder: &mut Decoder,
+) -> Result<(), HRESULT> {
+    let values = unmarshal_array_in::<T>(decoder)?;
+    if values.len() > capacity || (abi.is_null() && !values.is_empty()) {
+        return Err(RPC_E_INVALID_DATAPACKET);
+    }
+    for (index, value) in values.into_iter().enumerate() {
+        // Output arrays are uninitialized on the way in
+        abi.add(index).write(value);
+    }
+    Ok(())
+}
+
+/// The stub's local copy of a `#[length_is]` argument
+#[doc(hidden)]
+pub trait ArrayLength {
+    /// The number of elements the object filled in, if it said so
+    fn array_length(&self) -> Option<usize>;
+}
+
+macro_rules! array_length {
+    ($($t:ty),+) => {
+        $(impl ArrayLength for $t {
+            fn array_length(&self) -> Option<usize> {
+                Some(*self as usize)
+            }
+        }
+
+        impl ArrayLength for Option<$t> {
+            fn array_length(&self) -> Option<usize> {
+                self.map(|len| len as usize)
+            }
+        })*
+    };
+}
+
+array_length! { u8, u16, u32, u64, i32 }
@@ -0,0 +1,190 @@
+//! Monikers: objects named by display names
+//!
+//! A [`Moniker`] is parsed from a display name and can be bound to get the object it names.
+//! The following display names are understood:
+//!
+//! * `clsid:{GUID}:` - the class object of the class with the given class id
+//! * `new:{GUID}` - a new instance of the class with the given class id
+//! * `!item` - an object registered in the [running object table](crate::rot) as `!item`
+//! * anything else - an object registered in the running object table under that file name
+//!
+//! Item names can be appended to any moniker to form a composite moniker (e.g. `C:\doc.txt!sheet`).
+use crate::runtime;
+use crate::sys::{HRESULT, MK_E_SYNTAX};
+use crate::{rot, Interface, CLSID};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed moniker
+#[derive(Clone, Debug, PartialEq)]
+pub enum Moniker {
+    /// The class object of a class (`clsid:{GUID}:`)
+    Class(CLSID),
+    /// A new instance of a class (`new:{GUID}`)
+    New(CLSID),
+    /// A running object named by an item (`!item`)
+    Item(String),
+    /// A running object named by a file name
+    File(String),
+    /// A moniker made up of several monikers from left to right
+    Composite(Vec<Moniker>),
+}
+
+impl Moniker {
+    /// Parse a moniker from its display name
+    pub fn parse(display_name: &str) -> Result<Moniker, HRESULT> {
+        let mut parts = display_name.split('!');
+        let first = parts.next().unwrap_or_default();
+        let mut monikers = Vec::new();
+        if !first.is_empty() {
+            monikers.push(parse_first(first)?);
+        }
+        for item in parts {
+            if item.is_empty() {
+                return Err(MK_E_SYNTAX);
+            }
+            monikers.push(Moniker::Item(item.to_owned()));
+        }
+        match monikers.len() {
+            0 => Err(MK_E_SYNTAX),
+            1 => Ok(monikers.remove(0)),
+            _ => Ok(Moniker::Composite(monikers)),
+        }
+    }
+
+    /// The display name of the moniker
+    pub fn display_name(&self) -> String {
+        self.to_string()
+    }
+
+    /// Bind the moniker to the object it names
+    ///
+    /// Class monikers return the class object through [`runtime::get_class_object`], new
+    /// monikers create an instance through [`runtime::create_instance`]. All other monikers
+    /// are looked up in the [running object table](crate::rot) by display name.
+    pub fn bind<I: Interface>(&self) -> Result<I, HRESULT> {
+        match self {
+            Moniker::Class(clsid) => runtime::get_class_object(clsid),
+            Moniker::New(clsid) => runtime::create_instance(clsid),
+            _ => rot::get_object(&self.display_name()),
+        }
+    }
+
+    /// Whether the object named by the moniker is running
+    ///
+    /// Class and new monikers are never considered running.
+    pub fn is_running(&self) -> bool {
+        match self {
+            Moniker::Class(_) | Moniker::New(_) => false,
+            _ => rot::is_running(&self.display_name()),
+        }
+    }
+}
+
+fn parse_first(name: &str) -> Result<Moniker, HRESULT> {
+    if let Some(rest) = strip_prefix_ignore_case(name, "clsid:") {
+        return parse_clsid(rest.trim_end_matches(':')).map(Moniker::Class);
+    }
+    if let Some(rest) = strip_prefix_ignore_case(name, "new:") {
+        return parse_clsid(rest).map(Moniker::New);
+    }
+    Ok(Moniker::File(name.to_owned()))
+}
+
+fn parse_clsid(s: &str) -> Result<CLSID, HRESULT> {
+    s.parse().map_err(|_| MK_E_SYNTAX)
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.len() >= prefix.len()
+        && s.is_char_boundary(prefix.len())
+        && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+    {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+impl FromStr for Moniker {
+    type Err = HRESULT;
+
+    fn from_str(s: &str) -> Result<Moniker, HRESULT> {
+        Moniker::parse(s)
+    }
+}
+
+impl fmt::Display for Moniker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Moniker::Class(clsid) => write!(f, "clsid:{:?}:", clsid),
+            Moniker::New(clsid) => write!(f, "new:{:?}", clsid),
+            Moniker::Item(item) => write!(f, "!{}", item),
+            Moniker::File(path) => f.write_str(path),
+            Moniker::Composite(monikers) => monikers.iter().try_for_each(|m| m.fmt(f)),
+        }
+    }
+}
+
+/// Parse `display_name` and bind the resulting moniker
+///
+/// Returns `MK_E_SYNTAX` if the display name cannot be parsed and `MK_E_UNAVAILABLE`
+/// if the object it names is not running.
+pub fn bind<I: Interface>(display_name: &str) -> Result<I, HRESULT> {
+    Moniker::parse(display_name)?.bind()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::GUID;
+
+    const CLSID: GUID = GUID {
+        data1: 0xC5F4_5CBC,
+        data2: 0x4439,
+        data3: 0x418C,
+        data4: [0xA9, 0xF9, 0x05, 0xAC, 0x67, 0x52, 0x5E, 0x43],
+    };
+
+    #[test]
+    fn parses_class_and_new_monikers() {
+        assert_eq!(
+            Moniker::parse("clsid:{C5F45CBC-4439-418C-A9F9-05AC67525E43}:"),
+            Ok(Moniker::Class(CLSID))
+        );
+        assert_eq!(
+            Moniker::parse("CLSID:c5f45cbc-4439-418c-a9f9-05ac67525e43"),
+            Ok(Moniker::Class(CLSID))
+        );
+        assert_eq!(
+            Moniker::parse("new:{C5F45CBC-4439-418C-A9F9-05AC67525E43}"),
+            Ok(Moniker::New(CLSID))
+        );
+        assert_eq!(Moniker::parse("new:{not-a-guid}"), Err(MK_E_SYNTAX));
+    }
+
+    #[test]
+    fn parses_item_file_and_composite_monikers() {
+        assert_eq!(
+            Moniker::parse("!sheet"),
+            Ok(Moniker::Item("sheet".to_owned()))
+        );
+        assert_eq!(
+            Moniker::parse(r"C:\doc.txt"),
+            Ok(Moniker::File(r"C:\doc.txt".to_owned()))
+        );
+        let composite = Moniker::parse(r"C:\doc.txt!sheet!cell").unwrap();
+        assert_eq!(
+            composite,
+            Moniker::Composite(vec![
+                Moniker::File(r"C:\doc.txt".to_owned()),
+                Moniker::Item("sheet".to_owned()),
+                Moniker::Item("cell".to_owned()),
+            ])
+        );
+        assert_eq!(composite.display_name(), r"C:\doc.txt!sheet!cell");
+        assert_eq!(Moniker::parse(""), Err(MK_E_SYNTAX));
+        assert_eq!(Moniker::parse("a!!b"), Err(MK_E_SYNTAX));
+    }
+}
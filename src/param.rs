@@ -6,6 +6,11 @@ pub enum Param<'a, T> {
     Borrowed(&'a T),
     /// The owned version of the param
     Owned(T),
+    /// A value pointing into a UTF-16 buffer converted from another type, such as a
+    /// [`PCWSTR`](crate::strings::PCWSTR) made from a `&str`
+    ///
+    /// The param keeps the buffer alive for as long as the value is used.
+    Converted(T, Vec<u16>),
 }
 
 impl<'a, T: AbiTransferable> Param<'a, T> {
@@ -14,6 +19,7 @@ impl<'a, T: AbiTransferable> Param<'a, T> {
         match self {
             Param::Borrowed(value) => value.get_abi(),
             Param::Owned(value) => value.get_abi(),
+            Param::Converted(value, _) => value.get_abi(),
         }
     }
 }
@@ -1,6 +1,16 @@
 mod class;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+pub mod local_server;
+pub mod manifest;
+pub mod module;
 #[doc(hidden)]
 pub mod registration;
+pub mod registry;
 
+#[doc(hidden)]
+pub use class::{assert_class_field, assert_parent, assert_thread_safe, ClassField, VTableOffset};
 #[doc(inline)]
 pub use class::{Class, ClassAllocation};
+#[doc(inline)]
+pub use registration::{ClassRegistration, ComponentCategory};
@@ -1,3 +1,5 @@
+use crate::{ClassFields, Interface};
+
 /// A COM compliant class
 ///
 /// # Safety
@@ -44,6 +46,18 @@ impl<T: Class> ClassAllocation<T> {
         let inner = std::mem::ManuallyDrop::new(Box::from_raw(raw).into());
         Self { inner }
     }
+
+    /// Release the reference owned by the allocation and return the remaining count
+    #[doc(hidden)]
+    pub fn release(self) -> u32 {
+        let mut this = std::mem::ManuallyDrop::new(self);
+        let count = this.inner.dec_ref_count();
+        if count == 0 {
+            // SAFETY: This was the last reference
+            unsafe { std::mem::ManuallyDrop::drop(&mut this.inner) };
+        }
+        count
+    }
 }
 
 impl<T: Class> std::ops::Deref for ClassAllocation<T> {
@@ -73,3 +87,27 @@ impl<T: Class> std::fmt::Debug for ClassAllocation<T> {
             .finish()
     }
 }
+
+/// The position of an interface pointer within a class, counted in pointers
+#[doc(hidden)]
+pub trait VTableOffset {
+    const OFFSET: usize;
+}
+
+/// Fails to compile unless `P` is the parent of `I`
+#[doc(hidden)]
+pub fn assert_parent<I: Interface<Super = P>, P: Interface>() {}
+
+/// Fails to compile unless `T` can be shared by the threads using a cached object
+#[doc(hidden)]
+pub fn assert_thread_safe<T: Send + Sync>() {}
+
+/// A type that classes implementing `I` may have fields of
+#[doc(hidden)]
+pub trait ClassField<I> {}
+
+impl<F, I: ClassFields<F>> ClassField<I> for F {}
+
+/// Fails to compile unless a class implementing `I` may have a field of type `F`
+#[doc(hidden)]
+pub fn assert_class_field<F: ClassField<I>, I>() {}
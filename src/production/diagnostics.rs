@@ -0,0 +1,344 @@
+//! Live object tracking for finding leaked COM objects
+//!
+//! With the `diagnostics` feature, every object allocated by a [`class!`](crate::class) type
+//! is registered here until its final release. [`live_objects`] lists the objects that are
+//! currently alive and [`leak_report`] formats them for humans.
+//!
+//! To find an unbalanced `AddRef` or `Release`, install a backtrace capture with
+//! [`set_backtrace_capture`]. Every reference count change is then recorded together with
+//! the captured backtrace.
+//!
+//! ```rust,no_run
+//! use com::production::diagnostics;
+//!
+//! fn capture() -> String {
+//!     // e.g. a `std::backtrace::Backtrace` or one from the `backtrace` crate
+//! #   String::new()
+//! }
+//!
+//! diagnostics::set_backtrace_capture(Some(capture));
+//! let _leaks = diagnostics::LeakCheck::new();
+//! // ... leaked objects are reported when `_leaks` goes out of scope
+//! ```
+use crate::lazy_global::LazyGlobal;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::thread::ThreadId;
+
+/// An object that has been allocated but not yet finally released
+#[derive(Clone, Debug)]
+pub struct LiveObject {
+    /// The path of the class
+    pub type_name: &'static str,
+    /// The address of the object
+    pub address: usize,
+    /// The current reference count
+    pub refs: u32,
+    /// The reference count changes, recorded while a backtrace capture is installed
+    pub history: Vec<RefCountChange>,
+}
+
+/// A change of an object's reference count
+#[derive(Clone, Debug)]
+pub struct RefCountChange {
+    /// Whether the count was incremented (`AddRef`) or decremented (`Release`)
+    pub kind: RefCountChangeKind,
+    /// The reference count after the change
+    pub refs: u32,
+    /// The thread that changed the count
+    pub thread: ThreadId,
+    /// The backtrace returned by the installed capture
+    pub backtrace: String,
+}
+
+/// The kind of a [`RefCountChange`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RefCountChangeKind {
+    /// The object was allocated with a single reference
+    Allocate,
+    /// A reference was added
+    AddRef,
+    /// A reference was released
+    Release,
+}
+
+struct Registry {
+    objects: HashMap<usize, Tracked>,
+    next_serial: u64,
+    capture: Option<fn() -> String>,
+    /// The threads running the capture
+    capturing: Vec<ThreadId>,
+}
+
+struct Tracked {
+    serial: u64,
+    object: LiveObject,
+}
+
+static REGISTRY: LazyGlobal<Mutex<Registry>> = LazyGlobal::new();
+
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock(|| Registry {
+        objects: HashMap::new(),
+        next_serial: 0,
+        capture: None,
+        capturing: Vec::new(),
+    })
+}
+
+/// Record reference count changes with backtraces returned by `capture`
+///
+/// Passing `None` stops recording. Changes recorded before stay in the history of the
+/// objects that are still alive.
+pub fn set_backtrace_capture(capture: Option<fn() -> String>) {
+    registry().capture = capture;
+}
+
+/// The objects that are currently alive, in the order they were allocated
+pub fn live_objects() -> Vec<LiveObject> {
+    let registry = registry();
+    let mut objects = registry.objects.values().collect::<Vec<_>>();
+    objects.sort_by_key(|t| t.serial);
+    objects.into_iter().map(|t| t.object.clone()).collect()
+}
+
+/// A human readable list of the objects that are currently alive
+///
+/// Returns `None` if there are none.
+pub fn leak_report() -> Option<String> {
+    let objects = live_objects();
+    if objects.is_empty() {
+        return None;
+    }
+    let mut report = format!("{} live COM object(s):\n", objects.len());
+    for object in &objects {
+        let _ = writeln!(
+            report,
+            "  {} at {:#x} with {} reference(s)",
+            object.type_name, object.address, object.refs
+        );
+        for change in &object.history {
+            let _ = writeln!(
+                report,
+                "    {:?} to {} on {:?}\n      {}",
+                change.kind,
+                change.refs,
+                change.thread,
+                change.backtrace.replace('\n', "\n      ")
+            );
+        }
+    }
+    Some(report)
+}
+
+/// Prints the objects leaked since it was created to stderr when dropped
+///
+/// Create one at the start of `main` (or a test) to get a report of everything still alive
+/// when it returns. Objects that were already alive when the check was created are ignored.
+pub struct LeakCheck {
+    serial: u64,
+}
+
+impl LeakCheck {
+    /// Start checking for objects allocated from now on
+    pub fn new() -> LeakCheck {
+        LeakCheck {
+            serial: registry().next_serial,
+        }
+    }
+
+    /// The objects allocated since the check was created that are still alive
+    pub fn leaks(&self) -> Vec<LiveObject> {
+        let registry = registry();
+        let mut objects = registry
+            .objects
+            .values()
+            .filter(|t| t.serial >= self.serial)
+            .collect::<Vec<_>>();
+        objects.sort_by_key(|t| t.serial);
+        objects.into_iter().map(|t| t.object.clone()).collect()
+    }
+}
+
+impl Default for LeakCheck {
+    fn default() -> LeakCheck {
+        LeakCheck::new()
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        let leaks = self.leaks();
+        if leaks.is_empty() {
+            return;
+        }
+        eprintln!("{} leaked COM object(s):", leaks.len());
+        for object in leaks {
+            eprintln!(
+                "  {} at {:#x} with {} reference(s)",
+                object.type_name, object.address, object.refs
+            );
+        }
+    }
+}
+
+/// Record a reference count change of the object at `address`
+///
+/// The backtrace is captured without holding the registry lock, so that other threads are
+/// not held up by it and a capture that uses `class!` objects does not deadlock. Changes made
+/// by the capture itself are recorded without a backtrace.
+fn record(address: usize, kind: RefCountChangeKind, refs: u32) {
+    let thread = std::thread::current().id();
+    let (capture, serial, index) = {
+        let mut guard = registry();
+        let registry = &mut *guard;
+        let tracked = match registry.objects.get_mut(&address) {
+            Some(tracked) => tracked,
+            None => return,
+        };
+        tracked.object.refs = refs;
+        let capture = match registry.capture {
+            Some(capture) => capture,
+            None => return,
+        };
+        // The change is added right away to keep the history in order
+        tracked.object.history.push(RefCountChange {
+            kind,
+            refs,
+            thread,
+            backtrace: String::new(),
+        });
+        if registry.capturing.contains(&thread) {
+            return;
+        }
+        registry.capturing.push(thread);
+        (capture, tracked.serial, tracked.object.history.len() - 1)
+    };
+    let backtrace = capture();
+
+    let mut registry = registry();
+    registry.capturing.retain(|t| *t != thread);
+    // The object may have been dropped, and its address reused, in the meantime
+    if let Some(tracked) = registry.objects.get_mut(&address) {
+        if tracked.serial == serial {
+            tracked.object.history[index].backtrace = backtrace;
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn track_allocation(type_name: &'static str, address: usize) {
+    let mut registry = registry();
+    let serial = registry.next_serial;
+    registry.next_serial += 1;
+    registry.objects.insert(
+        address,
+        Tracked {
+            serial,
+            object: LiveObject {
+                type_name,
+                address,
+                refs: 1,
+                history: Vec::new(),
+            },
+        },
+    );
+    drop(registry);
+    record(address, RefCountChangeKind::Allocate, 1);
+}
+
+#[doc(hidden)]
+pub fn track_add_ref(address: usize, refs: u32) {
+    record(address, RefCountChangeKind::AddRef, refs);
+}
+
+#[doc(hidden)]
+pub fn track_release(address: usize, refs: u32) {
+    record(address, RefCountChangeKind::Release, refs);
+}
+
+#[doc(hidden)]
+pub fn track_drop(address: usize) {
+    registry().objects.remove(&address);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::IUnknown;
+
+    crate::interfaces! {
+        #[uuid("A9E4C0B1-7F52-4E0C-8B6D-3D1F0A2C5E77")]
+        unsafe interface ITracked: IUnknown {
+            fn noop(&self);
+        }
+    }
+
+    crate::class! {
+        class Tracked: ITracked {
+            value: u32,
+        }
+
+        impl ITracked for Tracked {
+            fn noop(&self) {}
+        }
+    }
+
+    crate::class! {
+        class Capture: ITracked {}
+
+        impl ITracked for Capture {
+            fn noop(&self) {}
+        }
+    }
+
+    /// Other tests allocate objects at the same time
+    fn tracked(check: &LeakCheck) -> Vec<LiveObject> {
+        let mut leaks = check.leaks();
+        leaks.retain(|o| o.type_name.ends_with("diagnostics::tests::Tracked"));
+        leaks
+    }
+
+    #[test]
+    fn objects_are_tracked_until_their_final_release() {
+        let check = LeakCheck::new();
+        let object = Tracked::allocate(1);
+        let interface = object.query::<ITracked>().unwrap();
+        let clone = interface.clone();
+        let leaks = tracked(&check);
+        assert_eq!(leaks.len(), 1);
+        assert!(leaks[0].type_name.ends_with("::Tracked"));
+        assert_eq!(leaks[0].address, &**object as *const Tracked as usize);
+        assert_eq!(leaks[0].refs, 3);
+        assert!(leak_report().unwrap().contains("::Tracked at"));
+
+        drop(object);
+        drop(interface);
+        assert_eq!(tracked(&check)[0].refs, 1);
+        drop(clone);
+        assert!(tracked(&check).is_empty());
+    }
+
+    #[test]
+    fn captures_may_use_class_objects() {
+        fn capture() -> String {
+            let object = Capture::allocate().query::<ITracked>().unwrap();
+            unsafe { object.noop() };
+            "captured".to_owned()
+        }
+
+        let check = LeakCheck::new();
+        set_backtrace_capture(Some(capture));
+        let object = Tracked::allocate(1);
+        let interface = object.query::<ITracked>().unwrap();
+        set_backtrace_capture(None);
+        let leaks = tracked(&check);
+        let history = &leaks[0].history;
+        assert_eq!(history[0].kind, RefCountChangeKind::Allocate);
+        assert_eq!(history[1].kind, RefCountChangeKind::AddRef);
+        assert!(history.iter().all(|change| change.backtrace == "captured"));
+        drop((object, interface));
+    }
+}
@@ -0,0 +1,488 @@
+//! Hosting COM classes in an executable (a local server)
+//!
+//! A local server makes class objects available to clients in other processes and keeps
+//! running while any of its objects are alive or while clients hold server locks (see
+//! [`module`](super::module)). These are counted as references to the server process with
+//! `CoAddRefServerProcess`. Releasing the last one with `CoReleaseServerProcess` suspends the
+//! class objects in the same step, so that no activation can reach the server while it
+//! revokes them and shuts down.
+//!
+//! The server runs in a single-threaded apartment, so calls into its objects are delivered
+//! one at a time through the message loop of its [`Dispatcher`].
+//!
+//! Most servers only need [`local_server_module!`](crate::local_server_module) which generates
+//! `main` including handling of the `/RegServer` and `/UnregServer` command lines and their
+//! per-user variants `/RegServerPerUser` and `/UnregServerPerUser`.
+use super::module::{self, ServerProcess};
+use super::registry::Scope;
+use crate::interfaces::IClassFactory;
+use crate::runtime::{self, ApartmentRuntime, ApartmentType, Dispatcher};
+use crate::sys::{
+    CoAddRefServerProcess, CoRegisterClassObject, CoReleaseServerProcess, CoResumeClassObjects,
+    CoRevokeClassObject, CoSuspendClassObjects, CLASS_E_CLASSNOTAVAILABLE, CLSCTX_LOCAL_SERVER,
+    CLSID, E_NOINTERFACE, FAILED, HRESULT, REGCLS_MULTIPLEUSE, REGCLS_SUSPENDED,
+};
+use crate::Interface;
+
+use std::ffi::c_void;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Makes class objects available to clients
+///
+/// [`SystemHost`] registers them with the COM runtime. [`InProcessHost`] stands in for it in
+/// tests where clients live in the same process.
+pub trait ClassHost {
+    /// Make `factory` available for `class_id`
+    fn register(&mut self, class_id: &CLSID, factory: IClassFactory) -> Result<(), HRESULT>;
+
+    /// Start handing out the registered class objects
+    fn resume(&mut self) -> Result<(), HRESULT> {
+        Ok(())
+    }
+
+    /// Stop handing out class objects and release them
+    fn revoke(&mut self);
+
+    /// The reference count of the server process
+    fn references(&self) -> Arc<dyn ProcessReferences>;
+}
+
+/// The reference count of a server process
+pub trait ProcessReferences: Send + Sync {
+    /// Count a reference to the process like `CoAddRefServerProcess`
+    fn add_ref(&self) -> u32;
+
+    /// Release a reference like `CoReleaseServerProcess` and return how many are left
+    ///
+    /// Releasing the last reference suspends the class objects in the same step.
+    fn release(&self) -> u32;
+}
+
+/// Registers class objects with the COM runtime so that clients in other processes can
+/// create objects through them
+#[derive(Default)]
+pub struct SystemHost {
+    cookies: Vec<u32>,
+}
+
+impl ClassHost for SystemHost {
+    fn register(&mut self, class_id: &CLSID, factory: IClassFactory) -> Result<(), HRESULT> {
+        let mut cookie = 0;
+        let hr = unsafe {
+            CoRegisterClassObject(
+                class_id as *const CLSID,
+                factory.as_raw().as_ptr() as *mut c_void,
+                CLSCTX_LOCAL_SERVER,
+                REGCLS_MULTIPLEUSE | REGCLS_SUSPENDED,
+                &mut cookie,
+            )
+        };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        self.cookies.push(cookie);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), HRESULT> {
+        match unsafe { CoResumeClassObjects() } {
+            hr if FAILED(hr) => Err(hr),
+            _ => Ok(()),
+        }
+    }
+
+    fn revoke(&mut self) {
+        unsafe {
+            CoSuspendClassObjects();
+            for cookie in self.cookies.drain(..) {
+                CoRevokeClassObject(cookie);
+            }
+        }
+    }
+
+    fn references(&self) -> Arc<dyn ProcessReferences> {
+        Arc::new(SystemReferences)
+    }
+}
+
+/// The reference count of the process kept by the COM runtime
+struct SystemReferences;
+
+impl ProcessReferences for SystemReferences {
+    fn add_ref(&self) -> u32 {
+        unsafe { CoAddRefServerProcess() }
+    }
+
+    fn release(&self) -> u32 {
+        unsafe { CoReleaseServerProcess() }
+    }
+}
+
+/// Hands out class objects to clients in the same process
+///
+/// This stands in for the COM runtime in tests of a local server.
+#[derive(Default)]
+pub struct InProcessHost {
+    classes: Vec<(CLSID, IClassFactory)>,
+    references: Arc<InProcessReferences>,
+}
+
+/// Suspends the class objects of an [`InProcessHost`] once the last reference is released
+#[derive(Default)]
+struct InProcessReferences {
+    /// The reference count and whether the class objects are handed out
+    state: Mutex<(u32, bool)>,
+}
+
+impl InProcessReferences {
+    fn state(&self) -> std::sync::MutexGuard<'_, (u32, bool)> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ProcessReferences for InProcessReferences {
+    fn add_ref(&self) -> u32 {
+        let mut state = self.state();
+        state.0 += 1;
+        state.0
+    }
+
+    fn release(&self) -> u32 {
+        let mut state = self.state();
+        state.0 = state.0.saturating_sub(1);
+        if state.0 == 0 {
+            state.1 = false;
+        }
+        state.0
+    }
+}
+
+impl InProcessHost {
+    /// Get the class object registered for `class_id`
+    pub fn get_class_object<I: Interface>(&self, class_id: &CLSID) -> Result<I, HRESULT> {
+        if !self.references.state().1 {
+            return Err(CLASS_E_CLASSNOTAVAILABLE);
+        }
+        let (_, factory) = self
+            .classes
+            .iter()
+            .find(|(id, _)| id == class_id)
+            .ok_or(CLASS_E_CLASSNOTAVAILABLE)?;
+        factory
+            .as_iunknown()
+            .get_interface::<I>()
+            .ok_or(E_NOINTERFACE)
+    }
+}
+
+impl ClassHost for InProcessHost {
+    fn register(&mut self, class_id: &CLSID, factory: IClassFactory) -> Result<(), HRESULT> {
+        self.classes.push((*class_id, factory));
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), HRESULT> {
+        self.references.state().1 = true;
+        Ok(())
+    }
+
+    fn revoke(&mut self) {
+        self.references.state().1 = false;
+        self.classes.clear();
+    }
+
+    fn references(&self) -> Arc<dyn ProcessReferences> {
+        self.references.clone()
+    }
+}
+
+/// The classes served by a local server
+#[derive(Default)]
+pub struct LocalServer {
+    classes: Vec<(CLSID, fn() -> IClassFactory)>,
+}
+
+impl LocalServer {
+    /// Create a server without any classes
+    pub fn new() -> LocalServer {
+        LocalServer::default()
+    }
+
+    /// Serve `class_id` with class objects created by `factory`
+    pub fn class(mut self, class_id: CLSID, factory: fn() -> IClassFactory) -> LocalServer {
+        self.classes.push((class_id, factory));
+        self
+    }
+
+    /// Register the class objects with `host`
+    ///
+    /// The returned server shuts down once the module is no longer in use.
+    pub fn start<H: ClassHost>(&self, host: H) -> Result<RunningServer<H>, HRESULT> {
+        self.start_with(host, None)
+    }
+
+    fn start_with<H: ClassHost>(
+        &self,
+        mut host: H,
+        dispatcher: Option<Dispatcher>,
+    ) -> Result<RunningServer<H>, HRESULT> {
+        for (class_id, factory) in &self.classes {
+            if let Err(hr) = host.register(class_id, factory()) {
+                host.revoke();
+                return Err(hr);
+            }
+        }
+        let process = Arc::new(Process {
+            references: host.references(),
+            stopped: Mutex::new(false),
+            condvar: Condvar::new(),
+            dispatcher,
+        });
+        module::serve(process.clone());
+        if let Err(hr) = host.resume() {
+            module::stop_serving();
+            host.revoke();
+            return Err(hr);
+        }
+        Ok(RunningServer { host, process })
+    }
+
+    /// Serve the classes to other processes until the module is no longer in use
+    ///
+    /// This initializes a single-threaded apartment for the calling thread and runs its
+    /// message loop while the server is in use.
+    pub fn run(&self) -> Result<(), HRESULT> {
+        let _runtime = ApartmentRuntime::new(ApartmentType::SingleThreaded)?;
+        let installed = runtime::install_dispatcher();
+        let dispatcher = Dispatcher::current().expect("the dispatcher was just installed");
+        let result = self
+            .start_with(SystemHost::default(), Some(dispatcher.clone()))
+            .and_then(|server| {
+                let result = dispatcher.run();
+                server.shutdown();
+                result
+            });
+        module::release_cached_objects();
+        drop(installed);
+        result
+    }
+}
+
+/// Shuts a running server down once the last reference to the process is released
+struct Process {
+    references: Arc<dyn ProcessReferences>,
+    stopped: Mutex<bool>,
+    condvar: Condvar,
+    /// The message loop of the server's apartment
+    dispatcher: Option<Dispatcher>,
+}
+
+impl ServerProcess for Process {
+    fn add_ref(&self) {
+        self.references.add_ref();
+    }
+
+    fn release(&self) {
+        if self.references.release() == 0 {
+            *self.stopped.lock().unwrap_or_else(|e| e.into_inner()) = true;
+            self.condvar.notify_all();
+            if let Some(dispatcher) = &self.dispatcher {
+                dispatcher.stop();
+            }
+        }
+    }
+}
+
+/// A local server whose class objects are available to clients
+pub struct RunningServer<H: ClassHost> {
+    host: H,
+    process: Arc<Process>,
+}
+
+impl<H: ClassHost> RunningServer<H> {
+    /// The host the class objects were registered with
+    pub fn host(&self) -> &H {
+        &self.host
+    }
+
+    /// Block until the last object or server lock goes away and revoke the class objects
+    pub fn wait(self) -> H {
+        let mut stopped = self
+            .process
+            .stopped
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        while !*stopped {
+            stopped = self
+                .process
+                .condvar
+                .wait(stopped)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        drop(stopped);
+        self.shutdown()
+    }
+
+    /// Revoke the class objects right away
+    pub fn shutdown(mut self) -> H {
+        module::stop_serving();
+        self.host.revoke();
+        self.host
+    }
+}
+
+/// The command line of a local server
+#[doc(hidden)]
+pub enum Command {
+    Register(Scope),
+    Unregister(Scope),
+    Run,
+}
+
+#[doc(hidden)]
+pub fn command<I: Iterator<Item = String>>(mut args: I) -> Command {
+    // COM starts servers with `-Embedding`, which is handled like no arguments at all
+    let arg = args.nth(1).unwrap_or_default().to_ascii_lowercase();
+    match arg.trim_start_matches(&['/', '-'][..]) {
+        "regserver" => Command::Register(Scope::Machine),
+        "unregserver" => Command::Unregister(Scope::Machine),
+        "regserverperuser" => Command::Register(Scope::User),
+        "unregserverperuser" => Command::Unregister(Scope::User),
+        _ => Command::Run,
+    }
+}
+
+/// A macro for declaring a COM local server
+///
+/// This implements `main` on behalf of the user. Started with `/RegServer` or
+/// `/UnregServer` it (un)registers the classes for the machine, with `/RegServerPerUser` or
+/// `/UnregServerPerUser` for the current user. Otherwise it serves them until they are no
+/// longer in use.
+///
+/// Classes are declared like for [`inproc_dll_module!`](crate::inproc_dll_module), including
+/// their optional registry entries and factory state. Their ProgIDs and categories are made
+/// known within the process before the server starts.
+#[macro_export]
+macro_rules! local_server_module {
+    ($(($class_id:ident, $class_type:ty $(, $key:ident = $value:expr)*)),+ $(,)?) => {
+        fn main() {
+            use ::com::production::local_server::{self, Command, LocalServer};
+            use ::com::production::registration::{self, ClassRegistration};
+
+            let file_path = ::std::env::current_exe()
+                .expect("could not determine the path of the server")
+                .display()
+                .to_string();
+            let classes = vec![$(
+                ::com::__class_registration!(
+                    ClassRegistration::new($class_id, stringify!($class_type))
+                    $(, $key = $value)*
+                )
+            ),+];
+            let keys = classes
+                .iter()
+                .flat_map(|class| class.local_server_keys(&file_path))
+                .collect::<Vec<_>>();
+            let hr = match local_server::command(::std::env::args()) {
+                Command::Register(scope) => registration::dll_register_server(&keys, scope),
+                Command::Unregister(scope) => registration::dll_unregister_server(&keys, scope),
+                Command::Run => {
+                    for class in &classes {
+                        class.register_in_process();
+                    }
+                    let server = LocalServer::new()
+                        $(.class($class_id, || {
+                            ::com::__class_factory!($class_type $(, $key = $value)*)
+                                .query::<::com::interfaces::IClassFactory>()
+                                .unwrap()
+                        }))+;
+                    match server.run() {
+                        Ok(()) => ::com::sys::S_OK,
+                        Err(hr) => hr,
+                    }
+                }
+            };
+            if ::com::sys::FAILED(hr) {
+                ::std::process::exit(hr);
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::IUnknown;
+    use crate::sys::S_OK;
+
+    crate::interfaces! {
+        #[uuid("5D1D5D27-8A4E-4F2B-A2B8-8D5B8B1C3E61")]
+        unsafe interface IPing: IUnknown {
+            fn ping(&self) -> HRESULT;
+        }
+    }
+
+    crate::class! {
+        class Ping: IPing {
+            pings: std::cell::Cell<u32>,
+        }
+
+        impl IPing for Ping {
+            fn ping(&self) -> HRESULT {
+                self.pings.set(self.pings.get() + 1);
+                S_OK
+            }
+        }
+    }
+
+    const CLSID_PING: CLSID = CLSID {
+        data1: 0x5D1D_5D28,
+        data2: 0x8A4E,
+        data3: 0x4F2B,
+        data4: [0xA2, 0xB8, 0x8D, 0x5B, 0x8B, 0x1C, 0x3E, 0x61],
+    };
+
+    fn ping_factory() -> IClassFactory {
+        PingClassFactory::allocate().query().unwrap()
+    }
+
+    #[test]
+    fn shuts_down_once_objects_and_locks_are_gone() {
+        let server = LocalServer::new()
+            .class(CLSID_PING, ping_factory)
+            .start(InProcessHost::default())
+            .unwrap();
+        let factory = server
+            .host()
+            .get_class_object::<IClassFactory>(&CLSID_PING)
+            .unwrap();
+        assert_eq!(unsafe { factory.lock_server(1) }, S_OK);
+        let ping = factory.get_instance::<IPing>().unwrap();
+        assert_eq!(unsafe { ping.ping() }, S_OK);
+        drop(ping);
+        assert_eq!(unsafe { factory.lock_server(0) }, S_OK);
+        drop(factory);
+
+        let host = server.wait();
+        assert_eq!(
+            host.get_class_object::<IClassFactory>(&CLSID_PING).err(),
+            Some(CLASS_E_CLASSNOTAVAILABLE)
+        );
+    }
+
+    #[test]
+    fn releasing_the_last_reference_suspends_the_class_objects() {
+        let mut host = InProcessHost::default();
+        host.register(&CLSID_PING, ping_factory()).unwrap();
+        host.resume().unwrap();
+        let references = host.references();
+        assert_eq!(references.add_ref(), 1);
+        assert!(host.get_class_object::<IClassFactory>(&CLSID_PING).is_ok());
+
+        assert_eq!(references.release(), 0);
+        assert_eq!(
+            host.get_class_object::<IClassFactory>(&CLSID_PING).err(),
+            Some(CLASS_E_CLASSNOTAVAILABLE)
+        );
+    }
+}
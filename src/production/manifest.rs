@@ -0,0 +1,254 @@
+//! Manifests for registration-free COM
+//!
+//! Instead of writing to the registry, a server deployed side by side with its clients
+//! describes its classes in an assembly manifest. [`Manifest`] generates one from the same
+//! [`ClassRegistration`]s that [`inproc_dll_module!`](crate::inproc_dll_module) registers,
+//! which the macro makes available as `class_registrations()`:
+//!
+//! ```rust,ignore
+//! let manifest = Manifest::new("Animals.Server", "1.0.0.0")
+//!     .file(ManifestFile::new("server.dll").classes(class_registrations()));
+//! std::fs::write("Animals.Server.manifest", manifest.to_string())?;
+//! ```
+use super::registration::{guid_to_string, ClassRegistration};
+use crate::sys::GUID;
+
+use std::fmt::{self, Display, Formatter, Write};
+
+/// An assembly manifest listing the COM classes of its files
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    name: String,
+    version: String,
+    processor_architecture: Option<String>,
+    files: Vec<ManifestFile>,
+}
+
+impl Manifest {
+    /// A manifest for the assembly `name` with a four part `version` such as `1.0.0.0`
+    pub fn new(name: &str, version: &str) -> Manifest {
+        Manifest {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            processor_architecture: None,
+            files: Vec::new(),
+        }
+    }
+
+    /// The processor architecture of the assembly, e.g. `amd64` or `x86`
+    pub fn processor_architecture(mut self, processor_architecture: &str) -> Manifest {
+        self.processor_architecture = Some(processor_architecture.to_owned());
+        self
+    }
+
+    /// Add a file of the assembly
+    pub fn file(mut self, file: ManifestFile) -> Manifest {
+        self.files.push(file);
+        self
+    }
+}
+
+impl Display for Manifest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#
+        )?;
+        writeln!(
+            f,
+            r#"<assembly xmlns="urn:schemas-microsoft-com:asm.v1" manifestVersion="1.0">"#
+        )?;
+        write!(
+            f,
+            r#"  <assemblyIdentity type="win32" name="{}" version="{}""#,
+            Escaped(&self.name),
+            Escaped(&self.version)
+        )?;
+        if let Some(processor_architecture) = &self.processor_architecture {
+            write!(
+                f,
+                r#" processorArchitecture="{}""#,
+                Escaped(processor_architecture)
+            )?;
+        }
+        writeln!(f, " />")?;
+        for file in &self.files {
+            write!(f, "{}", file)?;
+        }
+        writeln!(f, "</assembly>")
+    }
+}
+
+/// A file of an assembly and the COM classes and type libraries in it
+#[derive(Clone, Debug)]
+pub struct ManifestFile {
+    name: String,
+    type_libs: Vec<TypeLib>,
+    classes: Vec<ClassRegistration>,
+}
+
+#[derive(Clone, Debug)]
+struct TypeLib {
+    libid: String,
+    version: String,
+    help_dir: String,
+}
+
+impl ManifestFile {
+    /// The file `name` relative to the manifest
+    pub fn new(name: &str) -> ManifestFile {
+        ManifestFile {
+            name: name.to_owned(),
+            type_libs: Vec::new(),
+            classes: Vec::new(),
+        }
+    }
+
+    /// Add a type library embedded in the file
+    pub fn type_lib(mut self, libid: &GUID, version: &str, help_dir: &str) -> ManifestFile {
+        self.type_libs.push(TypeLib {
+            libid: guid_to_string(libid),
+            version: version.to_owned(),
+            help_dir: help_dir.to_owned(),
+        });
+        self
+    }
+
+    /// Add a class served by the file
+    pub fn class(mut self, class: ClassRegistration) -> ManifestFile {
+        self.classes.push(class);
+        self
+    }
+
+    /// Add several classes served by the file
+    pub fn classes<I: IntoIterator<Item = ClassRegistration>>(
+        mut self,
+        classes: I,
+    ) -> ManifestFile {
+        self.classes.extend(classes);
+        self
+    }
+}
+
+impl Display for ManifestFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, r#"  <file name="{}">"#, Escaped(&self.name))?;
+        for type_lib in &self.type_libs {
+            writeln!(
+                f,
+                r#"    <typelib tlbid="{}" version="{}" helpdir="{}" />"#,
+                type_lib.libid,
+                Escaped(&type_lib.version),
+                Escaped(&type_lib.help_dir)
+            )?;
+        }
+        for class in &self.classes {
+            write!(
+                f,
+                r#"    <comClass clsid="{}" threadingModel="{}""#,
+                guid_to_string(&class.class_id),
+                Escaped(&class.threading_model)
+            )?;
+            if let Some(prog_id) = &class.prog_id {
+                write!(f, r#" progid="{}""#, Escaped(prog_id))?;
+            }
+            if let Some(type_lib) = &class.type_lib {
+                write!(f, r#" tlbid="{}""#, type_lib)?;
+            }
+            write!(f, r#" description="{}""#, Escaped(&class.name))?;
+            // Without a versioned ProgID the version independent one is the class's only one
+            match (&class.prog_id, &class.version_independent_prog_id) {
+                (Some(_), Some(prog_id)) => {
+                    writeln!(f, ">")?;
+                    writeln!(f, "      <progid>{}</progid>", Escaped(prog_id))?;
+                    writeln!(f, "    </comClass>")?;
+                }
+                (None, Some(prog_id)) => {
+                    writeln!(f, r#" progid="{}" />"#, Escaped(prog_id))?;
+                }
+                (_, None) => writeln!(f, " />")?,
+            }
+        }
+        writeln!(f, "  </file>")
+    }
+}
+
+/// Text escaped for XML attributes and elements
+struct Escaped<'a>(&'a str);
+
+impl Display for Escaped<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&apos;")?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBID_ANIMALS: GUID = GUID {
+        data1: 0x1B3C_5D7E,
+        data2: 0x2A4B,
+        data3: 0x4C6D,
+        data4: [0x8E, 0x9F, 0x0A, 0x1B, 0x2C, 0x3D, 0x4E, 0x5F],
+    };
+
+    const CLSID_CAT: GUID = GUID {
+        data1: 0xC5F4_5F1A,
+        data2: 0x3E2B,
+        data3: 0x4C6D,
+        data4: [0x9A, 0x1E, 0x6F, 0x2B, 0x8C, 0x3D, 0x4E, 0x5F],
+    };
+
+    const CLSID_DOG: GUID = GUID {
+        data1: 0xC5F4_5F1B,
+        data2: 0x3E2B,
+        data3: 0x4C6D,
+        data4: [0x9A, 0x1E, 0x6F, 0x2B, 0x8C, 0x3D, 0x4E, 0x5F],
+    };
+
+    #[test]
+    fn manifests_list_classes_and_type_libraries() {
+        let manifest = Manifest::new("Animals.Server", "1.0.0.0")
+            .processor_architecture("amd64")
+            .file(
+                ManifestFile::new("server.dll")
+                    .type_lib(&LIBID_ANIMALS, "1.0", "")
+                    .class(
+                        ClassRegistration::new(CLSID_CAT, "Cat")
+                            .prog_id("Animals.Cat.1")
+                            .version_independent_prog_id("Animals.Cat")
+                            .type_lib(&LIBID_ANIMALS),
+                    )
+                    .class(
+                        ClassRegistration::new(CLSID_DOG, "Dog<'_, \"Good\">")
+                            .threading_model("Both"),
+                    ),
+            );
+        assert_eq!(
+            manifest.to_string(),
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<assembly xmlns="urn:schemas-microsoft-com:asm.v1" manifestVersion="1.0">
+  <assemblyIdentity type="win32" name="Animals.Server" version="1.0.0.0" processorArchitecture="amd64" />
+  <file name="server.dll">
+    <typelib tlbid="{1B3C5D7E-2A4B-4C6D-8E9F-0A1B2C3D4E5F}" version="1.0" helpdir="" />
+    <comClass clsid="{C5F45F1A-3E2B-4C6D-9A1E-6F2B8C3D4E5F}" threadingModel="Apartment" progid="Animals.Cat.1" tlbid="{1B3C5D7E-2A4B-4C6D-8E9F-0A1B2C3D4E5F}" description="Cat">
+      <progid>Animals.Cat</progid>
+    </comClass>
+    <comClass clsid="{C5F45F1B-3E2B-4C6D-9A1E-6F2B8C3D4E5F}" threadingModel="Both" description="Dog&lt;&apos;_, &quot;Good&quot;&gt;" />
+  </file>
+</assembly>
+"#
+        );
+    }
+}
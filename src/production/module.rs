@@ -0,0 +1,288 @@
+//! The lifetime of a COM server module
+//!
+//! A server is in use while objects of its classes are alive or while clients hold
+//! locks through `IClassFactory::LockServer`. Objects of classes declared with a class
+//! factory lock the module when they are allocated and unlock it on their final release.
+//! Class factories themselves do not keep the module in use.
+//!
+//! While a local server runs, the locks are also counted as references to the server
+//! process, which shuts down once the last one goes away.
+//!
+//! The module also caches class objects per CLSID and the instances of `#[singleton]`
+//! classes. They are created on first use and released by [`release_cached_objects`] when
+//! the module shuts down. Cached objects do not keep the module in use.
+//!
+//! Cached objects are handed to every thread that asks for them. Reference counts of
+//! `class!` objects are atomic, and `class!` requires the fields of `#[singleton]` classes
+//! and the state of factories to be `Send + Sync`.
+
+use crate::interfaces::IUnknown;
+use crate::lazy_global::LazyGlobal;
+use crate::sys::{CLSID, E_UNEXPECTED, HRESULT, S_FALSE, S_OK};
+use crate::Interface;
+
+use std::any::TypeId;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// The reference count of a running local server process
+pub(crate) trait ServerProcess: Send + Sync {
+    /// Count a reference to the process
+    fn add_ref(&self);
+
+    /// Release a reference and shut the server down if it was the last one
+    fn release(&self);
+}
+
+struct State {
+    locks: usize,
+    process: Option<Arc<dyn ServerProcess>>,
+}
+
+static STATE: LazyGlobal<Mutex<State>> = LazyGlobal::new();
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock(|| State {
+        locks: 0,
+        process: None,
+    })
+}
+
+/// Count the locks as references to `process` until [`stop_serving`] is called
+///
+/// Locks that are already held are counted right away.
+pub(crate) fn serve(process: Arc<dyn ServerProcess>) {
+    let mut state = state();
+    for _ in 0..state.locks {
+        process.add_ref();
+    }
+    state.process = Some(process);
+}
+
+/// Stop counting the locks as references to the server process
+pub(crate) fn stop_serving() {
+    state().process = None;
+}
+
+/// Keep the module in use and return the new lock count
+pub fn lock() -> usize {
+    let mut state = state();
+    state.locks += 1;
+    if let Some(process) = &state.process {
+        process.add_ref();
+    }
+    state.locks
+}
+
+/// Release a lock taken with [`lock`] and return the new lock count
+///
+/// Fails with `E_UNEXPECTED` if no lock is held, e.g. because a client called
+/// `IClassFactory::LockServer(FALSE)` once too often.
+pub fn unlock() -> Result<usize, HRESULT> {
+    let mut state = state();
+    state.locks = state.locks.checked_sub(1).ok_or(E_UNEXPECTED)?;
+    if let Some(process) = &state.process {
+        process.release();
+    }
+    Ok(state.locks)
+}
+
+/// The number of live objects and server locks keeping the module in use
+pub fn lock_count() -> usize {
+    state().locks
+}
+
+/// The implementation of `DllCanUnloadNow`
+///
+/// Once the module is no longer in use, the cached objects are released and `S_OK` is
+/// returned. Otherwise `S_FALSE`.
+#[doc(hidden)]
+pub fn can_unload_now() -> HRESULT {
+    if lock_count() != 0 {
+        return S_FALSE;
+    }
+    release_cached_objects();
+    S_OK
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Key {
+    ClassObject(CLSID),
+    Singleton(TypeId),
+}
+
+enum Slot {
+    /// Another thread is creating the object
+    Creating,
+    /// The owned `IUnknown` pointer of the object
+    Ready(usize),
+}
+
+struct Cache {
+    objects: Mutex<Vec<(Key, Slot)>>,
+    created: Condvar,
+}
+
+static CACHE: LazyGlobal<Cache> = LazyGlobal::new();
+
+fn cache() -> &'static Cache {
+    CACHE.get(|| Cache {
+        objects: Mutex::new(Vec::new()),
+        created: Condvar::new(),
+    })
+}
+
+fn cached_objects() -> MutexGuard<'static, Vec<(Key, Slot)>> {
+    cache().objects.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Get the cached object for `key` or create it with `create`
+///
+/// Only one thread creates the object while the others wait for it.
+fn cached<F>(key: Key, create: F) -> Result<IUnknown, HRESULT>
+where
+    F: FnOnce() -> Result<IUnknown, HRESULT>,
+{
+    let mut objects = cached_objects();
+    loop {
+        match objects.iter().find(|(k, _)| *k == key) {
+            Some((_, Slot::Ready(object))) => {
+                let object = std::mem::ManuallyDrop::new(unsafe {
+                    std::mem::transmute::<usize, IUnknown>(*object)
+                });
+                return Ok((*object).clone());
+            }
+            Some((_, Slot::Creating)) => {
+                objects = cache()
+                    .created
+                    .wait(objects)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+            None => break,
+        }
+    }
+    objects.push((key, Slot::Creating));
+    drop(objects);
+
+    let result = create();
+    let mut objects = cached_objects();
+    let index = objects.iter().position(|(k, _)| *k == key).unwrap();
+    match &result {
+        Ok(object) => {
+            let owned = std::mem::ManuallyDrop::new(object.clone());
+            objects[index].1 = Slot::Ready(owned.as_raw().as_ptr() as usize);
+        }
+        Err(_) => {
+            objects.remove(index);
+        }
+    }
+    cache().created.notify_all();
+    result
+}
+
+/// Get the class object cached for `class_id` or create it with `create`
+pub fn class_object<F>(class_id: &CLSID, create: F) -> Result<IUnknown, HRESULT>
+where
+    F: FnOnce() -> Result<IUnknown, HRESULT>,
+{
+    cached(Key::ClassObject(*class_id), create)
+}
+
+/// Get the instance of the singleton class `T` or create it with `create`
+#[doc(hidden)]
+pub fn singleton<T: 'static, F>(create: F) -> Result<IUnknown, HRESULT>
+where
+    F: FnOnce() -> Result<IUnknown, HRESULT>,
+{
+    cached(Key::Singleton(TypeId::of::<T>()), create)
+}
+
+/// Release the cached class objects and singletons
+///
+/// Objects still referenced elsewhere stay alive until those references are released.
+/// Objects requested afterwards are created anew.
+pub fn release_cached_objects() {
+    let mut released = Vec::new();
+    cached_objects().retain(|(_, slot)| {
+        if let Slot::Ready(object) = slot {
+            released.push(*object);
+            return false;
+        }
+        true
+    });
+    // Released without holding the lock as dropping an object may use the cache
+    for object in released {
+        drop(unsafe { std::mem::transmute::<usize, IUnknown>(object) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::IClassFactory;
+    use crate::sys::E_FAIL;
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    crate::interfaces! {
+        #[uuid("4A7D2B90-3C1E-4F58-B6A2-9E0D5C8F1A37")]
+        unsafe interface ICount: IUnknown {
+            fn next(&self) -> u32;
+        }
+    }
+
+    crate::class! {
+        #[singleton]
+        class Counter: ICount {
+            count: AtomicU32,
+        }
+
+        impl ICount for Counter {
+            fn next(&self) -> u32 {
+                self.count.fetch_add(1, Ordering::Relaxed) + 1
+            }
+        }
+    }
+
+    const CLSID_COUNTER: CLSID = CLSID {
+        data1: 0x4A7D_2B91,
+        data2: 0x3C1E,
+        data3: 0x4F58,
+        data4: [0xB6, 0xA2, 0x9E, 0x0D, 0x5C, 0x8F, 0x1A, 0x37],
+    };
+
+    #[test]
+    fn server_locks_keep_the_module_in_use() {
+        // Other tests allocate objects at the same time, so only the lock held here is known
+        lock();
+        assert!(lock_count() > 0);
+        assert_eq!(can_unload_now(), S_FALSE);
+        assert!(unlock().is_ok());
+    }
+
+    #[test]
+    fn singletons_and_class_objects_are_cached() {
+        let create = || Ok(CounterClassFactory::allocate().query::<IUnknown>().unwrap());
+        let factory = class_object(&CLSID_COUNTER, create).unwrap();
+        let again = class_object(&CLSID_COUNTER, || Err(E_FAIL)).unwrap();
+        assert_eq!(factory.as_raw(), again.as_raw());
+
+        let factory = factory.get_interface::<IClassFactory>().unwrap();
+        let first = factory.get_instance::<ICount>().unwrap();
+        let second = factory.get_instance::<ICount>().unwrap();
+        unsafe {
+            assert_eq!(first.next(), 1);
+            assert_eq!(second.next(), 2);
+        }
+        drop((first, second));
+
+        release_cached_objects();
+        assert_eq!(
+            class_object(&CLSID_COUNTER, || Err(E_FAIL)).err(),
+            Some(E_FAIL)
+        );
+        let fresh = factory.get_instance::<ICount>().unwrap();
+        assert_eq!(unsafe { fresh.next() }, 1);
+        drop(fresh);
+        release_cached_objects();
+    }
+}
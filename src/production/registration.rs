@@ -1,141 +1,106 @@
 //! Helpers for registering COM servers
 
+use super::registry::{RegistryBackend, Scope, WindowsRegistry};
 use crate::sys::{
-    GetModuleFileNameA, RegCloseKey, RegCreateKeyExA, RegDeleteKeyA, RegSetValueExA, CLSID,
-    ERROR_SUCCESS, FAILED, GUID, HKEY, HRESULT, LSTATUS, SELFREG_E_CLASS, S_OK,
+    GetModuleFileNameW, BOOL, CATID, CLSID, E_INVALIDARG, GUID, HRESULT, SELFREG_E_CLASS, S_OK,
 };
 
-use std::convert::TryInto;
 use std::ffi::c_void;
-use std::ffi::CString;
-use std::str;
 
 #[doc(hidden)]
 pub struct RegistryKeyInfo {
-    key_path: CString,
-    key_value_name: CString,
-    key_value_data: CString,
+    key_path: String,
+    key_value_name: String,
+    key_value_data: String,
+    shared: bool,
 }
 
 #[doc(hidden)]
 impl RegistryKeyInfo {
     pub fn new(key_path: &str, key_value_name: &str, key_value_data: &str) -> RegistryKeyInfo {
         RegistryKeyInfo {
-            key_path: CString::new(key_path).unwrap(),
-            key_value_name: CString::new(key_value_name).unwrap(),
-            key_value_data: CString::new(key_value_data).unwrap(),
+            key_path: key_path.to_owned(),
+            key_value_name: key_value_name.to_owned(),
+            key_value_data: key_value_data.to_owned(),
+            shared: false,
         }
     }
-}
 
-#[doc(hidden)]
-pub fn register_keys(registry_keys_to_add: &Vec<RegistryKeyInfo>) -> HRESULT {
-    for key_info in registry_keys_to_add.iter() {
-        let result = add_class_key(&key_info);
-        if result as u32 != ERROR_SUCCESS {
-            return SELFREG_E_CLASS;
+    /// A key that other servers may rely on as well, so it is not removed on unregistration
+    pub fn shared(key_path: &str, key_value_name: &str, key_value_data: &str) -> RegistryKeyInfo {
+        RegistryKeyInfo {
+            shared: true,
+            ..RegistryKeyInfo::new(key_path, key_value_name, key_value_data)
         }
     }
-
-    S_OK
 }
 
 #[doc(hidden)]
-pub fn unregister_keys(registry_keys_to_remove: &Vec<RegistryKeyInfo>) -> HRESULT {
-    let mut hr = S_OK;
-    for key_info in registry_keys_to_remove.iter() {
-        let result = remove_class_key(&key_info);
-        if result as u32 != ERROR_SUCCESS {
-            hr = SELFREG_E_CLASS;
-        }
+pub fn register_keys<R: RegistryBackend + ?Sized>(
+    registry: &mut R,
+    registry_keys_to_add: &[RegistryKeyInfo],
+) -> HRESULT {
+    match set_values(registry, registry_keys_to_add) {
+        Ok(()) => S_OK,
+        Err((_, hr)) => hr,
     }
-
-    hr
 }
 
-const HKEY_CLASSES_ROOT: HKEY = 0x8000_0000 as HKEY;
-const KEY_ALL_ACCESS: u32 = 0x000F_003F;
-const REG_OPTION_NON_VOLATILE: u32 = 0x00000000;
-fn create_class_key(key_info: &RegistryKeyInfo) -> Result<HKEY, LSTATUS> {
-    let mut hk_result = std::ptr::null_mut::<c_void>();
-    let lp_class = std::ptr::null_mut::<u8>();
-    let lp_security_attributes = std::ptr::null_mut::<c_void>();
-    let lpdw_disposition = std::ptr::null_mut::<u32>();
-    let result = unsafe {
-        RegCreateKeyExA(
-            HKEY_CLASSES_ROOT,
-            key_info.key_path.as_ptr(),
-            0,
-            lp_class,
-            REG_OPTION_NON_VOLATILE,
-            KEY_ALL_ACCESS,
-            lp_security_attributes,
-            &mut hk_result as *mut _,
-            lpdw_disposition,
-        )
-    };
-    if result as u32 != ERROR_SUCCESS {
-        return Err(result);
+/// Write the keys in order, returning the index of the first one that could not be written
+fn set_values<R: RegistryBackend + ?Sized>(
+    registry: &mut R,
+    registry_keys_to_add: &[RegistryKeyInfo],
+) -> Result<(), (usize, HRESULT)> {
+    for (index, key_info) in registry_keys_to_add.iter().enumerate() {
+        registry
+            .set_value(
+                &key_info.key_path,
+                &key_info.key_value_name,
+                &key_info.key_value_data,
+            )
+            .map_err(|hr| (index, hr))?;
     }
 
-    Ok(hk_result)
+    Ok(())
 }
 
-const REG_SZ: u32 = 1;
-fn set_class_key(key_handle: HKEY, key_info: &RegistryKeyInfo) -> Result<HKEY, LSTATUS> {
-    let result = unsafe {
-        RegSetValueExA(
-            key_handle,
-            key_info.key_value_name.as_ptr(),
-            0,
-            REG_SZ,
-            key_info.key_value_data.as_ptr() as *const u8,
-            key_info
-                .key_value_data
-                .to_bytes_with_nul()
-                .len()
-                .try_into()
-                .unwrap(),
-        )
-    };
-    if result as u32 != ERROR_SUCCESS {
-        return Err(result);
-    }
-
-    Ok(key_handle)
+#[doc(hidden)]
+pub fn unregister_keys<R: RegistryBackend + ?Sized>(
+    registry: &mut R,
+    registry_keys_to_remove: &[RegistryKeyInfo],
+) -> HRESULT {
+    delete_keys(registry, registry_keys_to_remove.iter())
 }
 
-fn add_class_key(key_info: &RegistryKeyInfo) -> LSTATUS {
-    let key_handle = match create_class_key(key_info) {
-        Ok(key_handle) => key_handle,
-        Err(e) => return e,
-    };
-
-    let key_handle = match set_class_key(key_handle, key_info) {
-        Ok(key_handle) => key_handle,
-        Err(e) => return e,
-    };
-
-    unsafe { RegCloseKey(key_handle) }
-}
+fn delete_keys<'a, R: RegistryBackend + ?Sized, I: Iterator<Item = &'a RegistryKeyInfo>>(
+    registry: &mut R,
+    registry_keys_to_remove: I,
+) -> HRESULT {
+    let mut hr = S_OK;
+    let mut removed = Vec::new();
+    for key_info in registry_keys_to_remove.filter(|key_info| !key_info.shared) {
+        // Keys with several values are listed more than once
+        if removed.contains(&&key_info.key_path) {
+            continue;
+        }
+        removed.push(&key_info.key_path);
+        if registry.delete_key(&key_info.key_path).is_err() {
+            hr = SELFREG_E_CLASS;
+        }
+    }
 
-fn remove_class_key(key_info: &RegistryKeyInfo) -> LSTATUS {
-    unsafe { RegDeleteKeyA(HKEY_CLASSES_ROOT, key_info.key_path.as_ptr()) }
+    hr
 }
 
 #[doc(hidden)]
 pub unsafe fn get_dll_file_path(hmodule: *mut c_void) -> String {
     const MAX_FILE_PATH_LENGTH: usize = 260;
 
-    let mut path = [0u8; MAX_FILE_PATH_LENGTH];
+    let mut path = [0u16; MAX_FILE_PATH_LENGTH];
 
-    let len = GetModuleFileNameA(
-        hmodule,
-        path.as_mut_ptr() as *mut _,
-        MAX_FILE_PATH_LENGTH as _,
-    );
+    let len = GetModuleFileNameW(hmodule, path.as_mut_ptr(), MAX_FILE_PATH_LENGTH as _);
 
-    String::from_utf8(path[..len as usize].to_vec()).unwrap()
+    String::from_utf16(&path[..len as usize]).unwrap()
 }
 
 #[doc(hidden)]
@@ -148,9 +113,250 @@ pub fn class_inproc_key_path(clsid: CLSID) -> String {
     format!("CLSID\\{}\\InprocServer32", guid_to_string(&clsid))
 }
 
-fn guid_to_string(guid: &GUID) -> String {
+#[doc(hidden)]
+pub fn class_local_server_key_path(clsid: CLSID) -> String {
+    format!("CLSID\\{}\\LocalServer32", guid_to_string(&clsid))
+}
+
+/// A component category and its description
+///
+/// Registering a class that implements or requires the category also describes the category
+/// below `Component Categories` like `ICatRegister::RegisterCategories`. The description is
+/// left in place when the class is unregistered as other classes may use the category too.
+#[derive(Clone, Copy, Debug)]
+pub struct ComponentCategory {
+    /// The CATID of the category
+    pub id: CATID,
+    /// The English description of the category
+    pub description: &'static str,
+}
+
+/// The registry entries of a class
+///
+/// Besides the class key and its server key, a class can have a threading model, ProgIDs,
+/// a type library and component categories. The threading model defaults to `Apartment` as objects of `class!`
+/// types must not be called from several threads at once.
+#[derive(Clone, Debug)]
+pub struct ClassRegistration {
+    pub(crate) class_id: CLSID,
+    pub(crate) name: String,
+    pub(crate) threading_model: String,
+    pub(crate) prog_id: Option<String>,
+    pub(crate) version_independent_prog_id: Option<String>,
+    pub(crate) type_lib: Option<String>,
+    pub(crate) version: Option<String>,
+    implemented_categories: Vec<ComponentCategory>,
+    required_categories: Vec<ComponentCategory>,
+}
+
+impl ClassRegistration {
+    /// Register `class_id` with `name` as its description
+    pub fn new(class_id: CLSID, name: &str) -> ClassRegistration {
+        ClassRegistration {
+            class_id,
+            name: name.to_owned(),
+            threading_model: "Apartment".to_owned(),
+            prog_id: None,
+            version_independent_prog_id: None,
+            type_lib: None,
+            version: None,
+            implemented_categories: Vec::new(),
+            required_categories: Vec::new(),
+        }
+    }
+
+    /// The CLSID of the class
+    pub fn class_id(&self) -> &CLSID {
+        &self.class_id
+    }
+
+    /// The `ThreadingModel` of an in-process server, e.g. `Apartment`, `Free` or `Both`
+    pub fn threading_model(mut self, threading_model: &str) -> ClassRegistration {
+        self.threading_model = threading_model.to_owned();
+        self
+    }
+
+    /// The versioned ProgID such as `Animals.Cat.1`
+    pub fn prog_id(mut self, prog_id: &str) -> ClassRegistration {
+        self.prog_id = Some(prog_id.to_owned());
+        self
+    }
+
+    /// The ProgID without a version such as `Animals.Cat`
+    ///
+    /// It points to the versioned ProgID as its current version if there is one.
+    pub fn version_independent_prog_id(mut self, prog_id: &str) -> ClassRegistration {
+        self.version_independent_prog_id = Some(prog_id.to_owned());
+        self
+    }
+
+    /// The LIBID of the type library describing the class
+    pub fn type_lib(mut self, libid: &GUID) -> ClassRegistration {
+        self.type_lib = Some(guid_to_string(libid));
+        self
+    }
+
+    /// The version of the class such as `1.0`
+    pub fn version(mut self, version: &str) -> ClassRegistration {
+        self.version = Some(version.to_owned());
+        self
+    }
+
+    /// A component category that the class implements
+    pub fn implemented_category(mut self, category: &ComponentCategory) -> ClassRegistration {
+        self.implemented_categories.push(*category);
+        self
+    }
+
+    /// A component category that a container must support to host the class
+    pub fn required_category(mut self, category: &ComponentCategory) -> ClassRegistration {
+        self.required_categories.push(*category);
+        self
+    }
+
+    /// The component categories that the class implements
+    pub fn implemented_categories(&self) -> &[ComponentCategory] {
+        &self.implemented_categories
+    }
+
+    /// Make the class's ProgIDs and categories known within this process
+    ///
+    /// Afterwards [`runtime::clsid_from_progid`](crate::runtime::clsid_from_progid) and
+    /// [`categories::classes_implementing`](crate::categories::classes_implementing) find
+    /// the class without a registration in the registry.
+    pub fn register_in_process(&self) {
+        match (&self.prog_id, &self.version_independent_prog_id) {
+            (Some(prog_id), version_independent) => crate::runtime::register_prog_id(
+                prog_id,
+                version_independent.as_ref().map(String::as_str),
+                &self.class_id,
+            ),
+            (None, Some(prog_id)) => {
+                crate::runtime::register_prog_id(prog_id, None, &self.class_id)
+            }
+            (None, None) => {}
+        }
+        if !self.implemented_categories.is_empty() {
+            let implemented = self
+                .implemented_categories
+                .iter()
+                .map(|category| category.id)
+                .collect::<Vec<_>>();
+            crate::categories::register_class(&self.class_id, &implemented);
+        }
+    }
+
+    /// The keys of the class served by the DLL at `file_path`
+    #[doc(hidden)]
+    pub fn inproc_keys(&self, file_path: &str) -> Vec<RegistryKeyInfo> {
+        let server = class_inproc_key_path(self.class_id);
+        let mut keys = vec![
+            RegistryKeyInfo::new(&class_key_path(self.class_id), "", &self.name),
+            RegistryKeyInfo::new(&server, "", file_path),
+            RegistryKeyInfo::new(&server, "ThreadingModel", &self.threading_model),
+        ];
+        keys.extend(self.common_keys());
+        keys
+    }
+
+    /// The keys of the class served by the executable at `file_path`
+    #[doc(hidden)]
+    pub fn local_server_keys(&self, file_path: &str) -> Vec<RegistryKeyInfo> {
+        let mut keys = vec![
+            RegistryKeyInfo::new(&class_key_path(self.class_id), "", &self.name),
+            RegistryKeyInfo::new(
+                &class_local_server_key_path(self.class_id),
+                "",
+                &format!("\"{}\"", file_path),
+            ),
+        ];
+        keys.extend(self.common_keys());
+        keys
+    }
+
+    fn common_keys(&self) -> Vec<RegistryKeyInfo> {
+        let class_key = class_key_path(self.class_id);
+        let class_id = guid_to_string(&self.class_id);
+        let mut keys = Vec::new();
+        if let Some(prog_id) = &self.prog_id {
+            keys.push(RegistryKeyInfo::new(
+                &format!("{}\\ProgID", class_key),
+                "",
+                prog_id,
+            ));
+            keys.push(RegistryKeyInfo::new(prog_id, "", &self.name));
+            keys.push(RegistryKeyInfo::new(
+                &format!("{}\\CLSID", prog_id),
+                "",
+                &class_id,
+            ));
+        }
+        if let Some(prog_id) = &self.version_independent_prog_id {
+            keys.push(RegistryKeyInfo::new(
+                &format!("{}\\VersionIndependentProgID", class_key),
+                "",
+                prog_id,
+            ));
+            keys.push(RegistryKeyInfo::new(prog_id, "", &self.name));
+            keys.push(RegistryKeyInfo::new(
+                &format!("{}\\CLSID", prog_id),
+                "",
+                &class_id,
+            ));
+            if let Some(current) = &self.prog_id {
+                keys.push(RegistryKeyInfo::new(
+                    &format!("{}\\CurVer", prog_id),
+                    "",
+                    current,
+                ));
+            }
+        }
+        for (name, categories) in &[
+            ("Implemented Categories", &self.implemented_categories),
+            ("Required Categories", &self.required_categories),
+        ] {
+            if categories.is_empty() {
+                continue;
+            }
+            // The parent key is listed so that it is removed along with the categories
+            let categories_key = format!("{}\\{}", class_key, name);
+            keys.push(RegistryKeyInfo::new(&categories_key, "", ""));
+            for category in categories.iter() {
+                let category_id = guid_to_string(&category.id);
+                // The description is named by the locale it is in, here US English
+                keys.push(RegistryKeyInfo::shared(
+                    &format!("Component Categories\\{}", category_id),
+                    "409",
+                    category.description,
+                ));
+                keys.push(RegistryKeyInfo::new(
+                    &format!("{}\\{}", categories_key, category_id),
+                    "",
+                    "",
+                ));
+            }
+        }
+        if let Some(type_lib) = &self.type_lib {
+            keys.push(RegistryKeyInfo::new(
+                &format!("{}\\TypeLib", class_key),
+                "",
+                type_lib,
+            ));
+        }
+        if let Some(version) = &self.version {
+            keys.push(RegistryKeyInfo::new(
+                &format!("{}\\Version", class_key),
+                "",
+                version,
+            ));
+        }
+        keys
+    }
+}
+
+pub(crate) fn guid_to_string(guid: &GUID) -> String {
     format!(
-        "{{{:04X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+        "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
         guid.data1,
         guid.data2,
         guid.data3,
@@ -165,95 +371,315 @@ fn guid_to_string(guid: &GUID) -> String {
     )
 }
 
-/// Register the supplied keys with the registry
+/// Register the supplied keys with the registry of `scope`
+#[doc(hidden)]
+#[inline]
+pub fn dll_register_server(relevant_keys: &[RegistryKeyInfo], scope: Scope) -> HRESULT {
+    register_server(&mut WindowsRegistry::new(scope), relevant_keys)
+}
+
+/// Unregister the supplied keys with the registry of `scope`
 #[doc(hidden)]
 #[inline]
-pub fn dll_register_server(relevant_keys: &mut Vec<RegistryKeyInfo>) -> HRESULT {
-    let hr = register_keys(relevant_keys);
-    if FAILED(hr) {
-        dll_unregister_server(relevant_keys);
+pub fn dll_unregister_server(relevant_keys: &[RegistryKeyInfo], scope: Scope) -> HRESULT {
+    unregister_server(&mut WindowsRegistry::new(scope), relevant_keys)
+}
+
+/// (Un)register the supplied keys for `DllInstall`
+///
+/// The command line selects the scope: `user` for the current user and nothing for the
+/// whole machine.
+#[doc(hidden)]
+pub unsafe fn dll_install(
+    install: BOOL,
+    cmd_line: *const u16,
+    relevant_keys: &[RegistryKeyInfo],
+) -> HRESULT {
+    let cmd_line = if cmd_line.is_null() {
+        String::new()
+    } else {
+        let len = (0..).take_while(|&i| *cmd_line.add(i) != 0).count();
+        String::from_utf16_lossy(std::slice::from_raw_parts(cmd_line, len))
+    };
+    let scope = match install_scope(&cmd_line) {
+        Ok(scope) => scope,
+        Err(hr) => return hr,
+    };
+    if install != 0 {
+        dll_register_server(relevant_keys, scope)
+    } else {
+        dll_unregister_server(relevant_keys, scope)
     }
+}
 
-    hr
+fn install_scope(cmd_line: &str) -> Result<Scope, HRESULT> {
+    match cmd_line.trim() {
+        "" => Ok(Scope::Machine),
+        cmd_line if cmd_line.eq_ignore_ascii_case("user") => Ok(Scope::User),
+        _ => Err(E_INVALIDARG),
+    }
 }
 
-/// Unregister the supplied keys with the registry
+/// Register the supplied keys with `registry`, removing them again on failure
+///
+/// Only the keys written before the failure are removed and the error of the failed write
+/// is returned.
 #[doc(hidden)]
-#[inline]
-pub fn dll_unregister_server(relevant_keys: &mut Vec<RegistryKeyInfo>) -> HRESULT {
-    relevant_keys.reverse();
-    unregister_keys(relevant_keys)
+pub fn register_server<R: RegistryBackend + ?Sized>(
+    registry: &mut R,
+    relevant_keys: &[RegistryKeyInfo],
+) -> HRESULT {
+    match set_values(registry, relevant_keys) {
+        Ok(()) => S_OK,
+        Err((failed_index, hr)) => {
+            unregister_server(registry, &relevant_keys[..failed_index]);
+            hr
+        }
+    }
+}
+
+/// Unregister the supplied keys with `registry`
+///
+/// The keys are removed in reverse order, so that subkeys go before their parents.
+#[doc(hidden)]
+pub fn unregister_server<R: RegistryBackend + ?Sized>(
+    registry: &mut R,
+    relevant_keys: &[RegistryKeyInfo],
+) -> HRESULT {
+    delete_keys(registry, relevant_keys.iter().rev())
 }
 
 /// A macro for declaring a COM server to the COM runtime
 ///
-/// This implements the `DllGetClassObject`, `DllRegisterServer`, and `DllUnregisterServer`
-/// functions on behalf of the user.
+/// This implements the `DllGetClassObject`, `DllCanUnloadNow`, `DllRegisterServer`,
+/// `DllUnregisterServer` and `DllInstall` functions on behalf of the user. The DLL can be
+/// unloaded once no objects of its classes are alive and no server locks are held.
+///
+/// `DllRegisterServer` registers the classes for the whole machine. `regsvr32 /n /i:user`
+/// registers them for the current user only through `DllInstall` (and `regsvr32 /u /n
+/// /i:user` removes them again).
+///
+/// Each class is given with its CLSID, optionally followed by the registry entries of a
+/// [`ClassRegistration`](crate::production::ClassRegistration):
+///
+/// ```rust,ignore
+/// com::inproc_dll_module![
+///     (CLSID_CAT_CLASS, BritishShortHairCat,
+///         prog_id = "Animals.Cat.1",
+///         version_independent_prog_id = "Animals.Cat",
+///         threading_model = "Both",
+///         implemented_category = &ANIMAL_PLUGINS),
+///     (CLSID_DOG_CLASS, Dog),
+/// ];
+/// ```
+///
+/// where `ANIMAL_PLUGINS` is a [`ComponentCategory`](crate::production::ComponentCategory).
+/// The ProgIDs and categories are also made known within the process when the first class
+/// object is requested (see [`ClassRegistration::register_in_process`]).
+///
+/// Classes whose factory has state (`#[factory(init = .., state = Type)]`) are given the
+/// state with a `state` key. It is evaluated once, when the class object is first created:
+///
+/// ```rust,ignore
+/// com::inproc_dll_module![
+///     (CLSID_CONFIGURED_CLASS, Configured, state = Arc::new(Config::load())),
+/// ];
+/// ```
+///
+/// The classes are also returned by a generated `pub fn class_registrations()`, e.g. to
+/// describe them in a [`Manifest`](crate::production::manifest::Manifest).
 #[macro_export]
 macro_rules! inproc_dll_module {
-    (($class_id_one:ident, $class_type_one:ty), $(($class_id:ident, $class_type:ty)),*) => {
+    ($(($class_id:ident, $class_type:ty $(, $key:ident = $value:expr)*)),+ $(,)?) => {
         static mut _HMODULE: *mut ::std::ffi::c_void = ::std::ptr::null_mut();
         #[no_mangle]
-        unsafe extern "stdcall" fn DllMain(hinstance: *mut ::std::ffi::c_void, fdw_reason: u32, reserved: *mut ::std::ffi::c_void) -> i32 {
+        unsafe extern "system" fn DllMain(hinstance: *mut ::std::ffi::c_void, fdw_reason: u32, reserved: *mut ::std::ffi::c_void) -> i32 {
+            const DLL_PROCESS_DETACH: u32 = 0;
             const DLL_PROCESS_ATTACH: u32 = 1;
             if fdw_reason == DLL_PROCESS_ATTACH {
                 unsafe { _HMODULE = hinstance; }
             }
+            // When the whole process is terminating (`reserved` is not null) other threads
+            // are already gone and the objects are left alone
+            if fdw_reason == DLL_PROCESS_DETACH && reserved.is_null() {
+                ::com::production::module::release_cached_objects();
+            }
             1
         }
 
         #[no_mangle]
-        unsafe extern "stdcall" fn DllGetClassObject(class_id: *const ::com::sys::CLSID, iid: *const ::com::sys::IID, result: *mut *mut ::std::ffi::c_void) -> ::com::sys::HRESULT {
+        unsafe extern "system" fn DllGetClassObject(class_id: *const ::com::sys::CLSID, iid: *const ::com::sys::IID, result: *mut *mut ::std::ffi::c_void) -> ::com::sys::HRESULT {
             use ::com::interfaces::IUnknown;
             assert!(!class_id.is_null(), "class id passed to DllGetClassObject should never be null");
 
             let class_id = unsafe { &*class_id };
-            if class_id == &$class_id_one {
-                let instance = <$class_type_one as ::com::production::Class>::Factory::allocate();
-                instance.query_interface(&*iid, result)
-            } $(else if class_id == &$class_id {
-                let instance = <$class_type_one as ::com::production::Class>::Factory::allocate();
-                instance.query_interface(&*iid, result)
-            })* else {
-                ::com::sys::CLASS_E_CLASSNOTAVAILABLE
+            static REGISTER_IN_PROCESS: ::std::sync::Once = ::std::sync::Once::new();
+            REGISTER_IN_PROCESS.call_once(|| {
+                for class in class_registrations() {
+                    class.register_in_process();
+                }
+            });
+            // Class objects are created once and cached until the module is unloaded
+            let class_object = $(if class_id == &$class_id {
+                ::com::production::module::class_object(class_id, || {
+                    Ok(::com::__class_factory!($class_type $(, $key = $value)*).query::<IUnknown>().unwrap())
+                })
+            } else)+ {
+                Err(::com::sys::CLASS_E_CLASSNOTAVAILABLE)
+            };
+            match class_object {
+                Ok(class_object) => class_object.query_interface(iid, result),
+                Err(hr) => hr,
             }
         }
 
         #[no_mangle]
-        extern "stdcall" fn DllRegisterServer() -> ::com::sys::HRESULT {
-            ::com::production::registration::dll_register_server(&mut get_relevant_registry_keys())
+        extern "system" fn DllCanUnloadNow() -> ::com::sys::HRESULT {
+            ::com::production::module::can_unload_now()
         }
 
         #[no_mangle]
-        extern "stdcall" fn DllUnregisterServer() -> ::com::sys::HRESULT {
-            ::com::production::registration::dll_unregister_server(&mut get_relevant_registry_keys())
+        extern "system" fn DllRegisterServer() -> ::com::sys::HRESULT {
+            ::com::production::registration::dll_register_server(
+                &get_relevant_registry_keys(),
+                ::com::production::registry::Scope::Machine,
+            )
+        }
+
+        #[no_mangle]
+        extern "system" fn DllUnregisterServer() -> ::com::sys::HRESULT {
+            ::com::production::registration::dll_unregister_server(
+                &get_relevant_registry_keys(),
+                ::com::production::registry::Scope::Machine,
+            )
+        }
+
+        #[no_mangle]
+        unsafe extern "system" fn DllInstall(install: ::com::sys::BOOL, cmd_line: *const u16) -> ::com::sys::HRESULT {
+            ::com::production::registration::dll_install(
+                install,
+                cmd_line,
+                &get_relevant_registry_keys(),
+            )
         }
 
         fn get_relevant_registry_keys() -> Vec<::com::production::registration::RegistryKeyInfo> {
-            use ::com::production::registration::RegistryKeyInfo;
             let file_path = unsafe { ::com::production::registration::get_dll_file_path(_HMODULE) };
+            class_registrations()
+                .iter()
+                .flat_map(|class| class.inproc_keys(&file_path))
+                .collect()
+        }
+
+        /// The classes served by this module and their registry entries
+        pub fn class_registrations() -> Vec<::com::production::ClassRegistration> {
+            vec![$(
+                ::com::__class_registration!(
+                    ::com::production::ClassRegistration::new($class_id, stringify!($class_type))
+                    $(, $key = $value)*
+                )
+            ),+]
+        }
+    };
+}
+
+/// Allocate the class factory of a class from the keys of a module macro
+///
+/// The `state` key is passed to the factory, all other keys are registry entries.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __class_factory {
+    ($class_type:ty) => {
+        <$class_type as ::com::production::Class>::Factory::allocate()
+    };
+    ($class_type:ty, state = $state:expr $(, $key:ident = $value:expr)*) => {
+        <$class_type as ::com::production::Class>::Factory::allocate($state)
+    };
+    ($class_type:ty, $skipped:ident = $skipped_value:expr $(, $key:ident = $value:expr)*) => {
+        ::com::__class_factory!($class_type $(, $key = $value)*)
+    };
+}
+
+/// Apply the registry entries among the keys of a module macro to a `ClassRegistration`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __class_registration {
+    ($registration:expr) => {
+        $registration
+    };
+    ($registration:expr, state = $state:expr $(, $key:ident = $value:expr)*) => {
+        ::com::__class_registration!($registration $(, $key = $value)*)
+    };
+    ($registration:expr, $first:ident = $first_value:expr $(, $key:ident = $value:expr)*) => {
+        ::com::__class_registration!($registration.$first($first_value) $(, $key = $value)*)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLSID_CAT: CLSID = CLSID {
+        data1: 0xC5F4_5F1A,
+        data2: 0x3E2B,
+        data3: 0x4C6D,
+        data4: [0x9A, 0x1E, 0x6F, 0x2B, 0x8C, 0x3D, 0x4E, 0x5F],
+    };
+
+    fn entries(keys: &[RegistryKeyInfo]) -> Vec<(&str, &str, &str)> {
+        keys.iter()
+            .map(|key| {
+                (
+                    key.key_path.as_str(),
+                    key.key_value_name.as_str(),
+                    key.key_value_data.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dll_install_selects_the_scope() {
+        assert_eq!(install_scope(""), Ok(Scope::Machine));
+        assert_eq!(install_scope(" User "), Ok(Scope::User));
+        assert_eq!(install_scope("machine"), Err(E_INVALIDARG));
+    }
+
+    #[test]
+    fn classes_are_registered_with_their_prog_ids() {
+        let keys = ClassRegistration::new(CLSID_CAT, "Cat")
+            .prog_id("Animals.Cat.1")
+            .version_independent_prog_id("Animals.Cat")
+            .version("1.0")
+            .inproc_keys("cat.dll");
+        let class_key = "CLSID\\{C5F45F1A-3E2B-4C6D-9A1E-6F2B8C3D4E5F}";
+        let inproc_key = format!("{}\\InprocServer32", class_key);
+        let prog_id_key = format!("{}\\ProgID", class_key);
+        let vi_prog_id_key = format!("{}\\VersionIndependentProgID", class_key);
+        let version_key = format!("{}\\Version", class_key);
+        assert_eq!(
+            entries(&keys),
             vec![
-                RegistryKeyInfo::new(
-                    &::com::production::registration::class_key_path($class_id_one),
-                    "",
-                    stringify!($class_type_one),
-                ),
-                RegistryKeyInfo::new(
-                    &::com::production::registration::class_inproc_key_path($class_id_one),
+                (class_key, "", "Cat"),
+                (&*inproc_key, "", "cat.dll"),
+                (&*inproc_key, "ThreadingModel", "Apartment"),
+                (&*prog_id_key, "", "Animals.Cat.1"),
+                ("Animals.Cat.1", "", "Cat"),
+                (
+                    "Animals.Cat.1\\CLSID",
                     "",
-                    &file_path,
+                    "{C5F45F1A-3E2B-4C6D-9A1E-6F2B8C3D4E5F}"
                 ),
-                $(RegistryKeyInfo::new(
-                    &::com::production::registration::class_key_path($class_id),
+                (&*vi_prog_id_key, "", "Animals.Cat"),
+                ("Animals.Cat", "", "Cat"),
+                (
+                    "Animals.Cat\\CLSID",
                     "",
-                    stringify!($class_type),
+                    "{C5F45F1A-3E2B-4C6D-9A1E-6F2B8C3D4E5F}"
                 ),
-                RegistryKeyInfo::new(
-                    &::com::production::registration::class_inproc_key_path($class_id),
-                    "",
-                    &file_path,
-                )),*
+                ("Animals.Cat\\CurVer", "", "Animals.Cat.1"),
+                (&*version_key, "", "1.0"),
             ]
-        }
-    };
+        );
+    }
 }
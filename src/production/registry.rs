@@ -0,0 +1,650 @@
+//! Where class registrations are written to
+//!
+//! Registering a server writes string values below `HKEY_CLASSES_ROOT`, or below
+//! `HKEY_CURRENT_USER\\Software\\Classes` for the current user only (see [`Scope`]). A
+//! [`RegistryBackend`] decides where they end up: [`WindowsRegistry`] writes to the real
+//! registry, [`MemoryRegistry`] keeps them in memory for tests and [`RegFile`] records them
+//! as a `.reg` file that installers can diff or apply later.
+//!
+//! ```rust,ignore
+//! let mut preview = RegFile::new(Scope::User.root());
+//! registry::register(&mut preview, &class_registrations(), &Server::InProcess(path))?;
+//! println!("{}", preview);
+//! ```
+use super::registration::{ClassRegistration, RegistryKeyInfo};
+use crate::sys::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteKeyW, RegSetValueExW, ERROR_SUCCESS, HKEY, HRESULT,
+    LSTATUS,
+};
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::ffi::c_void;
+use std::fmt::{self, Display, Formatter};
+
+/// A registry that classes can be registered with
+///
+/// Key paths are relative to the root of the backend and use `\` as the separator. An empty
+/// value name stands for the default value of a key.
+pub trait RegistryBackend {
+    /// Set a string value, creating the key and its parents if necessary
+    fn set_value(&mut self, key_path: &str, value_name: &str, data: &str) -> Result<(), HRESULT>;
+
+    /// Delete a key that has no subkeys, including its values
+    fn delete_key(&mut self, key_path: &str) -> Result<(), HRESULT>;
+}
+
+/// Who a registration applies to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// All users of the machine, which requires administrator rights
+    Machine,
+    /// The current user only
+    User,
+}
+
+impl Scope {
+    /// The registry key that class registrations of the scope are written to
+    pub fn root(self) -> &'static str {
+        match self {
+            Scope::Machine => "HKEY_CLASSES_ROOT",
+            Scope::User => "HKEY_CURRENT_USER\\Software\\Classes",
+        }
+    }
+}
+
+/// The server that the classes of a registration are served by
+#[derive(Clone, Debug)]
+pub enum Server {
+    /// A DLL at the given path
+    InProcess(String),
+    /// An executable at the given path
+    Local(String),
+}
+
+/// Register `classes` with `registry`
+///
+/// If a value cannot be written, the keys written so far are removed again.
+pub fn register<R: RegistryBackend + ?Sized>(
+    registry: &mut R,
+    classes: &[ClassRegistration],
+    server: &Server,
+) -> Result<(), HRESULT> {
+    let keys = server_keys(classes, server);
+    hresult(super::registration::register_server(registry, &keys))
+}
+
+/// Remove the keys of `classes` from `registry`
+///
+/// All keys are attempted even if one of them cannot be removed.
+pub fn unregister<R: RegistryBackend + ?Sized>(
+    registry: &mut R,
+    classes: &[ClassRegistration],
+    server: &Server,
+) -> Result<(), HRESULT> {
+    let keys = server_keys(classes, server);
+    hresult(super::registration::unregister_server(registry, &keys))
+}
+
+fn server_keys(classes: &[ClassRegistration], server: &Server) -> Vec<RegistryKeyInfo> {
+    classes
+        .iter()
+        .flat_map(|class| match server {
+            Server::InProcess(path) => class.inproc_keys(path),
+            Server::Local(path) => class.local_server_keys(path),
+        })
+        .collect()
+}
+
+fn hresult(hr: HRESULT) -> Result<(), HRESULT> {
+    if hr < 0 {
+        Err(hr)
+    } else {
+        Ok(())
+    }
+}
+
+const ERROR_FILE_NOT_FOUND: u32 = 2;
+const ERROR_ACCESS_DENIED: u32 = 5;
+
+fn from_win32(status: u32) -> HRESULT {
+    if status == ERROR_SUCCESS {
+        0
+    } else {
+        ((status & 0xFFFF) | 0x8007_0000) as HRESULT
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// The Windows registry, written through the wide-character APIs
+#[derive(Debug)]
+pub struct WindowsRegistry {
+    root: HKEY,
+    prefix: &'static str,
+}
+
+const HKEY_CLASSES_ROOT: HKEY = 0x8000_0000 as HKEY;
+const HKEY_CURRENT_USER: HKEY = 0x8000_0001 as HKEY;
+const KEY_WRITE: u32 = 0x0002_0006;
+const REG_OPTION_NON_VOLATILE: u32 = 0x00000000;
+const REG_SZ: u32 = 1;
+
+impl WindowsRegistry {
+    /// The registrations of `scope`
+    pub fn new(scope: Scope) -> WindowsRegistry {
+        match scope {
+            Scope::Machine => WindowsRegistry {
+                root: HKEY_CLASSES_ROOT,
+                prefix: "",
+            },
+            Scope::User => WindowsRegistry {
+                root: HKEY_CURRENT_USER,
+                prefix: "Software\\Classes\\",
+            },
+        }
+    }
+
+    /// The machine wide registrations in `HKEY_CLASSES_ROOT`
+    pub fn classes_root() -> WindowsRegistry {
+        WindowsRegistry::new(Scope::Machine)
+    }
+
+    /// The registrations of the current user in `HKEY_CURRENT_USER\\Software\\Classes`
+    pub fn current_user() -> WindowsRegistry {
+        WindowsRegistry::new(Scope::User)
+    }
+
+    fn path(&self, key_path: &str) -> Vec<u16> {
+        wide(&format!("{}{}", self.prefix, key_path))
+    }
+
+    fn check(status: LSTATUS) -> Result<(), HRESULT> {
+        match from_win32(status as u32) {
+            0 => Ok(()),
+            hr => Err(hr),
+        }
+    }
+}
+
+impl RegistryBackend for WindowsRegistry {
+    fn set_value(&mut self, key_path: &str, value_name: &str, data: &str) -> Result<(), HRESULT> {
+        let mut key = std::ptr::null_mut::<c_void>();
+        Self::check(unsafe {
+            RegCreateKeyExW(
+                self.root,
+                self.path(key_path).as_ptr(),
+                0,
+                std::ptr::null_mut(),
+                REG_OPTION_NON_VOLATILE,
+                KEY_WRITE,
+                std::ptr::null_mut(),
+                &mut key,
+                std::ptr::null_mut(),
+            )
+        })?;
+        let data = wide(data);
+        let result = Self::check(unsafe {
+            RegSetValueExW(
+                key,
+                wide(value_name).as_ptr(),
+                0,
+                REG_SZ,
+                data.as_ptr() as *const u8,
+                (data.len() * 2).try_into().unwrap(),
+            )
+        });
+        unsafe { RegCloseKey(key) };
+        result
+    }
+
+    fn delete_key(&mut self, key_path: &str) -> Result<(), HRESULT> {
+        Self::check(unsafe { RegDeleteKeyW(self.root, self.path(key_path).as_ptr()) })
+    }
+}
+
+/// A registry kept in memory
+///
+/// Like the Windows registry, key paths and value names are case-insensitive and keys with
+/// subkeys cannot be deleted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryRegistry {
+    // Keyed by the lowercase path, holding the path as written and the values
+    keys: BTreeMap<String, (String, Values)>,
+}
+
+/// Values keyed by their lowercase name, holding the name as written and the data
+type Values = BTreeMap<String, (String, String)>;
+
+impl MemoryRegistry {
+    /// An empty registry
+    pub fn new() -> MemoryRegistry {
+        MemoryRegistry::default()
+    }
+
+    /// Create a key and its parents without any values
+    pub fn create_key(&mut self, key_path: &str) {
+        let mut end = 0;
+        for part in key_path.split('\\') {
+            end += part.len();
+            let path = &key_path[..end];
+            self.keys
+                .entry(path.to_ascii_lowercase())
+                .or_insert_with(|| (path.to_owned(), BTreeMap::new()));
+            end += 1;
+        }
+    }
+
+    /// Whether the key exists
+    pub fn contains_key(&self, key_path: &str) -> bool {
+        self.keys.contains_key(&key_path.to_ascii_lowercase())
+    }
+
+    /// The data of a value, `""` being the default value of the key
+    pub fn value(&self, key_path: &str, value_name: &str) -> Option<&str> {
+        let (_, values) = self.keys.get(&key_path.to_ascii_lowercase())?;
+        let (_, data) = values.get(&value_name.to_ascii_lowercase())?;
+        Some(data)
+    }
+
+    /// The paths of all keys in case-insensitive order
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.values().map(|(path, _)| path.as_str())
+    }
+
+    /// The values of a key as pairs of name and data
+    pub fn values(&self, key_path: &str) -> impl Iterator<Item = (&str, &str)> {
+        self.keys
+            .get(&key_path.to_ascii_lowercase())
+            .into_iter()
+            .flat_map(|(_, values)| values.values())
+            .map(|(name, data)| (name.as_str(), data.as_str()))
+    }
+
+    /// Whether there are no keys at all
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl RegistryBackend for MemoryRegistry {
+    fn set_value(&mut self, key_path: &str, value_name: &str, data: &str) -> Result<(), HRESULT> {
+        self.create_key(key_path);
+        let (_, values) = self.keys.get_mut(&key_path.to_ascii_lowercase()).unwrap();
+        values.insert(
+            value_name.to_ascii_lowercase(),
+            (value_name.to_owned(), data.to_owned()),
+        );
+        Ok(())
+    }
+
+    fn delete_key(&mut self, key_path: &str) -> Result<(), HRESULT> {
+        let key = key_path.to_ascii_lowercase();
+        if !self.keys.contains_key(&key) {
+            return Err(from_win32(ERROR_FILE_NOT_FOUND));
+        }
+        let prefix = format!("{}\\", key);
+        if self.keys.keys().any(|k| k.starts_with(&prefix)) {
+            return Err(from_win32(ERROR_ACCESS_DENIED));
+        }
+        self.keys.remove(&key);
+        Ok(())
+    }
+}
+
+/// A `.reg` file
+///
+/// Used as a [`RegistryBackend`] it records the values that are set and the keys that are
+/// deleted, and its `Display` implementation writes them in the format of the Registry
+/// Editor. [`RegFile::parse`] reads such a file back so that it can be
+/// [applied](RegFile::apply) to another backend.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegFile {
+    root: String,
+    entries: Vec<RegEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum RegEntry {
+    Key(String, Vec<(String, String)>),
+    DeletedKey(String),
+}
+
+/// A line of a `.reg` file that could not be read
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// The number of the line, starting at 1
+    pub line: usize,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid .reg file entry on line {}", self.line)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+const REG_FILE_HEADER: &str = "Windows Registry Editor Version 5.00";
+
+impl RegFile {
+    /// An empty file whose key paths are relative to `root`, e.g. `HKEY_CLASSES_ROOT`
+    pub fn new(root: &str) -> RegFile {
+        RegFile {
+            root: root.to_owned(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Read a `.reg` file whose keys are all below `root`
+    pub fn parse(root: &str, text: &str) -> Result<RegFile, ParseError> {
+        let mut file = RegFile::new(root);
+        let prefix = format!("{}\\", root).to_ascii_lowercase();
+        let relative = |path: &str| {
+            if path.to_ascii_lowercase().starts_with(&prefix) {
+                Some(path[prefix.len()..].to_owned())
+            } else {
+                None
+            }
+        };
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'));
+        match lines.next() {
+            Some((_, REG_FILE_HEADER)) => {}
+            Some((line, _)) => return Err(ParseError { line }),
+            None => return Err(ParseError { line: 1 }),
+        }
+        for (line, text) in lines {
+            let error = ParseError { line };
+            if text.starts_with("[-") && text.ends_with(']') {
+                let path = relative(&text[2..text.len() - 1]).ok_or(error)?;
+                file.entries.push(RegEntry::DeletedKey(path));
+            } else if text.starts_with('[') && text.ends_with(']') {
+                let path = relative(&text[1..text.len() - 1]).ok_or(error)?;
+                file.entries.push(RegEntry::Key(path, Vec::new()));
+            } else {
+                let (name, data) = parse_value(text).ok_or_else(|| error.clone())?;
+                match file.entries.last_mut() {
+                    Some(RegEntry::Key(_, values)) => values.push((name, data)),
+                    _ => return Err(error),
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    /// Set the values and delete the keys of this file in `registry`, in file order
+    pub fn apply<R: RegistryBackend + ?Sized>(&self, registry: &mut R) -> Result<(), HRESULT> {
+        for entry in &self.entries {
+            match entry {
+                RegEntry::Key(path, values) => {
+                    for (name, data) in values {
+                        registry.set_value(path, name, data)?;
+                    }
+                }
+                RegEntry::DeletedKey(path) => registry.delete_key(path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RegistryBackend for RegFile {
+    fn set_value(&mut self, key_path: &str, value_name: &str, data: &str) -> Result<(), HRESULT> {
+        // Values of the same key are grouped as long as the key is not deleted in between
+        let key = self.entries.iter_mut().rev().find_map(|entry| match entry {
+            RegEntry::Key(path, values) if path.eq_ignore_ascii_case(key_path) => Some(Ok(values)),
+            RegEntry::DeletedKey(path) if path.eq_ignore_ascii_case(key_path) => Some(Err(())),
+            _ => None,
+        });
+        let value = (value_name.to_owned(), data.to_owned());
+        match key {
+            Some(Ok(values)) => match values
+                .iter_mut()
+                .find(|(name, _)| name.eq_ignore_ascii_case(value_name))
+            {
+                Some(existing) => *existing = value,
+                None => values.push(value),
+            },
+            _ => self
+                .entries
+                .push(RegEntry::Key(key_path.to_owned(), vec![value])),
+        }
+        Ok(())
+    }
+
+    fn delete_key(&mut self, key_path: &str) -> Result<(), HRESULT> {
+        self.entries.push(RegEntry::DeletedKey(key_path.to_owned()));
+        Ok(())
+    }
+}
+
+impl Display for RegFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", REG_FILE_HEADER)?;
+        for entry in &self.entries {
+            writeln!(f)?;
+            match entry {
+                RegEntry::Key(path, values) => {
+                    writeln!(f, "[{}\\{}]", self.root, path)?;
+                    for (name, data) in values {
+                        if name.is_empty() {
+                            write!(f, "@")?;
+                        } else {
+                            write!(f, "\"{}\"", escape(name))?;
+                        }
+                        writeln!(f, "=\"{}\"", escape(data))?;
+                    }
+                }
+                RegEntry::DeletedKey(path) => writeln!(f, "[-{}\\{}]", self.root, path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Parse `@="data"` or `"name"="data"`
+fn parse_value(line: &str) -> Option<(String, String)> {
+    let (name, rest) = match skip(line, '@') {
+        Some(rest) => (String::new(), rest),
+        None => parse_string(line)?,
+    };
+    let rest = skip(rest.trim_start(), '=')?;
+    let (data, rest) = parse_string(rest.trim_start())?;
+    if rest.trim().is_empty() {
+        Some((name, data))
+    } else {
+        None
+    }
+}
+
+/// The rest of `s` if it starts with `c`
+fn skip(s: &str, c: char) -> Option<&str> {
+    let mut chars = s.chars();
+    if chars.next() == Some(c) {
+        Some(chars.as_str())
+    } else {
+        None
+    }
+}
+
+/// Parse a quoted string and return it along with the rest of the line
+fn parse_string(s: &str) -> Option<(String, &str)> {
+    let s = skip(s, '"')?;
+    let mut result = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((result, &s[i + 1..])),
+            '\\' => result.push(chars.next()?.1),
+            c => result.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::production::ComponentCategory;
+    use crate::sys::{CATID, CLSID, SELFREG_E_CLASS};
+
+    const CLSID_CAT: CLSID = CLSID {
+        data1: 0xC5F4_5F1A,
+        data2: 0x3E2B,
+        data3: 0x4C6D,
+        data4: [0x9A, 0x1E, 0x6F, 0x2B, 0x8C, 0x3D, 0x4E, 0x5F],
+    };
+
+    const PLUGINS: ComponentCategory = ComponentCategory {
+        id: CATID {
+            data1: 0xCA7E_0001,
+            data2: 0x6B2E,
+            data3: 0x4F1C,
+            data4: [0x8A, 0x3D, 0x5C, 0x7E, 0x9B, 0x1F, 0x2D, 0x4A],
+        },
+        description: "Animal Plugins",
+    };
+
+    fn classes() -> Vec<ClassRegistration> {
+        vec![ClassRegistration::new(CLSID_CAT, "Cat")
+            .prog_id("Animals.Cat.1")
+            .version_independent_prog_id("Animals.Cat")
+            .implemented_category(&PLUGINS)]
+    }
+
+    #[test]
+    fn unregistration_removes_what_registration_added() {
+        let mut registry = MemoryRegistry::new();
+        registry.create_key("CLSID");
+        let before = registry.clone();
+        let server = Server::InProcess("C:\\Animals\\cat.dll".to_owned());
+
+        register(&mut registry, &classes(), &server).unwrap();
+        let class_key = "CLSID\\{C5F45F1A-3E2B-4C6D-9A1E-6F2B8C3D4E5F}";
+        assert_eq!(
+            registry.value(&format!("{}\\inprocserver32", class_key), ""),
+            Some("C:\\Animals\\cat.dll")
+        );
+        assert_eq!(
+            registry.value("animals.cat\\CurVer", ""),
+            Some("Animals.Cat.1")
+        );
+        assert!(registry.contains_key(&format!(
+            "{}\\Implemented Categories\\{{CA7E0001-6B2E-4F1C-8A3D-5C7E9B1F2D4A}}",
+            class_key
+        )));
+
+        let category_key = "Component Categories\\{CA7E0001-6B2E-4F1C-8A3D-5C7E9B1F2D4A}";
+        assert_eq!(registry.value(category_key, "409"), Some("Animal Plugins"));
+
+        unregister(&mut registry, &classes(), &server).unwrap();
+        // The category stays described for other classes implementing it
+        assert_eq!(registry.value(category_key, "409"), Some("Animal Plugins"));
+        registry.delete_key(category_key).unwrap();
+        registry.delete_key("Component Categories").unwrap();
+        assert_eq!(registry, before);
+        assert_eq!(
+            unregister(&mut registry, &classes(), &server),
+            Err(SELFREG_E_CLASS)
+        );
+    }
+
+    /// Fails to write any value after the first `writes`
+    struct FailingRegistry {
+        registry: MemoryRegistry,
+        writes: usize,
+        deleted: Vec<String>,
+    }
+
+    impl RegistryBackend for FailingRegistry {
+        fn set_value(
+            &mut self,
+            key_path: &str,
+            value_name: &str,
+            data: &str,
+        ) -> Result<(), HRESULT> {
+            if self.writes == 0 {
+                return Err(from_win32(ERROR_ACCESS_DENIED));
+            }
+            self.writes -= 1;
+            self.registry.set_value(key_path, value_name, data)
+        }
+
+        fn delete_key(&mut self, key_path: &str) -> Result<(), HRESULT> {
+            self.deleted.push(key_path.to_owned());
+            self.registry.delete_key(key_path)
+        }
+    }
+
+    #[test]
+    fn failed_registrations_are_rolled_back() {
+        let mut registry = FailingRegistry {
+            registry: MemoryRegistry::new(),
+            writes: 4,
+            deleted: Vec::new(),
+        };
+        registry.registry.create_key("CLSID");
+        let before = registry.registry.clone();
+        let server = Server::InProcess("C:\\Animals\\cat.dll".to_owned());
+
+        assert_eq!(
+            register(&mut registry, &classes(), &server),
+            Err(from_win32(ERROR_ACCESS_DENIED))
+        );
+        assert_eq!(registry.registry, before);
+        // Only the keys written before the failure are removed, the subkeys first
+        let class_key = "CLSID\\{C5F45F1A-3E2B-4C6D-9A1E-6F2B8C3D4E5F}";
+        assert_eq!(
+            registry.deleted,
+            [
+                format!("{}\\ProgID", class_key),
+                format!("{}\\InprocServer32", class_key),
+                class_key.to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn reg_files_round_trip() {
+        let mut file = RegFile::new("HKEY_CLASSES_ROOT");
+        let server = Server::Local("C:\\Animals\\cat \"server\".exe".to_owned());
+        register(&mut file, &classes(), &server).unwrap();
+        let text = file.to_string();
+        assert!(text.starts_with(
+            "Windows Registry Editor Version 5.00\n\n\
+             [HKEY_CLASSES_ROOT\\CLSID\\{C5F45F1A-3E2B-4C6D-9A1E-6F2B8C3D4E5F}]\n\
+             @=\"Cat\"\n\n\
+             [HKEY_CLASSES_ROOT\\CLSID\\{C5F45F1A-3E2B-4C6D-9A1E-6F2B8C3D4E5F}\\LocalServer32]\n\
+             @=\"\\\"C:\\\\Animals\\\\cat \\\"server\\\".exe\\\"\"\n"
+        ));
+        assert_eq!(RegFile::parse("HKEY_CLASSES_ROOT", &text), Ok(file.clone()));
+
+        let mut registry = MemoryRegistry::new();
+        file.apply(&mut registry).unwrap();
+        let mut expected = MemoryRegistry::new();
+        register(&mut expected, &classes(), &server).unwrap();
+        assert_eq!(registry, expected);
+
+        assert_eq!(
+            RegFile::parse("HKEY_CLASSES_ROOT", "REGEDIT4\n"),
+            Err(ParseError { line: 1 })
+        );
+        assert_eq!(
+            RegFile::parse(
+                "HKEY_CLASSES_ROOT",
+                "Windows Registry Editor Version 5.00\n\n[HKEY_CURRENT_USER\\Software]\n"
+            ),
+            Err(ParseError { line: 3 })
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! The running object table (ROT)
+//!
+//! The running object table maps names to objects that are already running so that
+//! clients can find and connect to them instead of creating new instances.
+//!
+//! Registrations are kept in a process-local table. On Windows, registrations can
+//! additionally be forwarded to the system running object table (see [`set_system_forwarding`])
+//! which makes them visible to other processes.
+use crate::interfaces::IUnknown;
+use crate::lazy_global::LazyGlobal;
+use crate::sys::{HRESULT, MK_E_UNAVAILABLE, RPC_E_WRONG_THREAD};
+use crate::Interface;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::ThreadId;
+
+/// A registration in the running object table
+///
+/// Returned from [`register`] and used to [`revoke`] the registration again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cookie(u32);
+
+impl Cookie {
+    /// The raw value of the cookie
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+struct Registration {
+    cookie: u32,
+    name: String,
+    object: Registered,
+    thread: ThreadId,
+    #[cfg(windows)]
+    system_cookie: Option<u32>,
+}
+
+/// A registered object.
+///
+/// COM interface pointers are not `Send`. The table only ever hands the object out
+/// (or releases it) on the thread that registered it.
+struct Registered(IUnknown);
+
+unsafe impl Send for Registered {}
+
+static TABLE: LazyGlobal<Mutex<Vec<Registration>>> = LazyGlobal::new();
+static NEXT_COOKIE: AtomicU32 = AtomicU32::new(1);
+
+fn table() -> MutexGuard<'static, Vec<Registration>> {
+    TABLE.lock(Vec::new)
+}
+
+/// Register `object` as running under `name`
+///
+/// Names are compared case-insensitively. Registering a name that is already registered
+/// is allowed; lookups return the oldest registration that is still alive.
+///
+/// The object can only be retrieved through [`get_object`] on the thread that registered it.
+pub fn register<I: Interface>(name: &str, object: &I) -> Result<Cookie, HRESULT> {
+    let object = object.as_iunknown().clone();
+    #[cfg(windows)]
+    let system_cookie = if system::is_forwarding() {
+        Some(system::register(name, &object)?)
+    } else {
+        None
+    };
+    let cookie = NEXT_COOKIE.fetch_add(1, Ordering::Relaxed);
+    table().push(Registration {
+        cookie,
+        name: name.to_owned(),
+        object: Registered(object),
+        thread: std::thread::current().id(),
+        #[cfg(windows)]
+        system_cookie,
+    });
+    Ok(Cookie(cookie))
+}
+
+/// Revoke a registration previously made with [`register`]
+///
+/// The registration must be revoked on the thread that registered it.
+pub fn revoke(cookie: Cookie) -> Result<(), HRESULT> {
+    let registration = {
+        let mut table = table();
+        let index = table
+            .iter()
+            .position(|r| r.cookie == cookie.0)
+            .ok_or(MK_E_UNAVAILABLE)?;
+        if table[index].thread != std::thread::current().id() {
+            return Err(RPC_E_WRONG_THREAD);
+        }
+        table.remove(index)
+    };
+    #[cfg(windows)]
+    {
+        if let Some(system_cookie) = registration.system_cookie {
+            system::revoke(system_cookie)?;
+        }
+    }
+    // Release the object outside of the lock
+    drop(registration);
+    Ok(())
+}
+
+/// Get the object registered under `name` as the interface `I`
+///
+/// Returns `MK_E_UNAVAILABLE` if nothing is registered under `name`, `RPC_E_WRONG_THREAD`
+/// if the object was registered on a different thread, and `E_NOINTERFACE` if the object
+/// does not implement `I`.
+pub fn get_object<I: Interface>(name: &str) -> Result<I, HRESULT> {
+    let object = {
+        let table = table();
+        match table.iter().find(|r| r.name.eq_ignore_ascii_case(name)) {
+            Some(r) if r.thread != std::thread::current().id() => return Err(RPC_E_WRONG_THREAD),
+            Some(r) => Some(r.object.0.clone()),
+            None => None,
+        }
+    };
+    let object = match object {
+        Some(object) => object,
+        #[cfg(windows)]
+        None if system::is_forwarding() => system::get_object(name)?,
+        None => return Err(MK_E_UNAVAILABLE),
+    };
+    object.get_interface::<I>().ok_or(crate::sys::E_NOINTERFACE)
+}
+
+/// Whether an object is registered under `name`
+pub fn is_running(name: &str) -> bool {
+    let found = table().iter().any(|r| r.name.eq_ignore_ascii_case(name));
+    #[cfg(windows)]
+    {
+        if !found && system::is_forwarding() {
+            return system::get_object(name).is_ok();
+        }
+    }
+    found
+}
+
+/// Forward registrations to the system running object table
+///
+/// When enabled, [`register`] also registers the object with the system running object table
+/// and [`get_object`] falls back to the system table for names that are not registered in
+/// this process. Names starting with `!` are registered as item monikers and every other
+/// name as a file moniker.
+///
+/// Forwarding is disabled by default and only affects registrations made while it is enabled.
+#[cfg(windows)]
+pub fn set_system_forwarding(enabled: bool) {
+    system::FORWARD.store(enabled, Ordering::SeqCst)
+}
+
+#[cfg(windows)]
+mod system {
+    use crate::interfaces::{IMoniker, IRunningObjectTable, IUnknown};
+    use crate::sys::{
+        CreateFileMoniker, CreateItemMoniker, GetRunningObjectTable, FAILED, HRESULT,
+        ROTFLAGS_REGISTRATIONKEEPSALIVE,
+    };
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    pub(super) static FORWARD: AtomicBool = AtomicBool::new(false);
+
+    pub(super) fn is_forwarding() -> bool {
+        FORWARD.load(Ordering::SeqCst)
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(Some(0)).collect()
+    }
+
+    fn table() -> Result<IRunningObjectTable, HRESULT> {
+        let mut rot = None;
+        let hr = unsafe { GetRunningObjectTable(0, &mut rot as *mut _ as _) };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(rot.unwrap())
+    }
+
+    fn moniker(name: &str) -> Result<IMoniker, HRESULT> {
+        let mut moniker = None;
+        let hr = if name.starts_with('!') {
+            unsafe {
+                CreateItemMoniker(
+                    wide("!").as_ptr(),
+                    wide(&name[1..]).as_ptr(),
+                    &mut moniker as *mut _ as _,
+                )
+            }
+        } else {
+            unsafe { CreateFileMoniker(wide(name).as_ptr(), &mut moniker as *mut _ as _) }
+        };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(moniker.unwrap())
+    }
+
+    pub(super) fn register(name: &str, object: &IUnknown) -> Result<u32, HRESULT> {
+        let mut cookie = 0;
+        let hr = unsafe {
+            table()?.register(
+                ROTFLAGS_REGISTRATIONKEEPSALIVE,
+                object,
+                moniker(name)?,
+                &mut cookie,
+            )
+        };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(cookie)
+    }
+
+    pub(super) fn revoke(cookie: u32) -> Result<(), HRESULT> {
+        let hr = unsafe { table()?.revoke(cookie) };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(())
+    }
+
+    pub(super) fn get_object(name: &str) -> Result<IUnknown, HRESULT> {
+        let mut object = None;
+        let hr = unsafe { table()?.get_object(moniker(name)?, &mut object) };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(object.unwrap())
+    }
+}
+
+#[cfg(all(test, feature = "production"))]
+mod tests {
+    use super::*;
+    use crate::interfaces::IPersist;
+    use crate::sys::{CLSID, GUID, S_OK};
+    use crate::{class, moniker};
+
+    const CLSID_RUNNING: GUID = GUID {
+        data1: 0x6A1F_3C2E,
+        data2: 0x0D4B,
+        data3: 0x4E55,
+        data4: [0x9B, 0x61, 0x27, 0x08, 0xC3, 0x5D, 0x1E, 0x90],
+    };
+
+    class! {
+        #[no_class_factory]
+        class Running: IPersist {}
+
+        impl IPersist for Running {
+            unsafe fn get_class_id(&self, p_class_id: *mut CLSID) -> HRESULT {
+                *p_class_id = CLSID_RUNNING;
+                S_OK
+            }
+        }
+    }
+
+    #[test]
+    fn registered_objects_can_be_found_by_name() {
+        let object = Running::allocate().query::<IPersist>().unwrap();
+        let cookie = register("!running", &object).unwrap();
+
+        assert!(is_running("!RUNNING"));
+        let found = moniker::bind::<IPersist>("!running").unwrap();
+        let mut clsid = GUID {
+            data1: 0,
+            data2: 0,
+            data3: 0,
+            data4: [0; 8],
+        };
+        assert_eq!(unsafe { found.get_class_id(&mut clsid) }, S_OK);
+        assert_eq!(clsid, CLSID_RUNNING);
+
+        let other_thread = std::thread::spawn(|| get_object::<IUnknown>("!running").err());
+        assert_eq!(other_thread.join().unwrap(), Some(RPC_E_WRONG_THREAD));
+
+        revoke(cookie).unwrap();
+        assert!(!is_running("!running"));
+        assert_eq!(
+            get_object::<IUnknown>("!running").err(),
+            Some(MK_E_UNAVAILABLE)
+        );
+    }
+}
@@ -1,12 +1,24 @@
 //! COM runtime facilities
 //!
 //! This includes initializing the COM runtime as well as creating instances of COM classes
+mod create_options;
+mod dispatcher;
+mod prog_id;
+
+#[doc(inline)]
+pub use create_options::{CreateOptions, MultiQi, Requested};
+pub(crate) use dispatcher::install as install_dispatcher;
+#[doc(inline)]
+pub use dispatcher::Dispatcher;
+#[doc(inline)]
+pub use prog_id::{clsid_from_progid, progid_from_clsid, register_prog_id, revoke_prog_id};
+
 use crate::sys::{
-    CoCreateInstance, CoGetClassObject, CoIncrementMTAUsage, CoInitializeEx, CoUninitialize,
-    CLSCTX_INPROC_SERVER, CLSID, COINIT_APARTMENTTHREADED, COINIT_MULTITHREADED, FAILED, HRESULT,
-    IID, S_FALSE, S_OK,
+    CoIncrementMTAUsage, CoInitializeEx, CoUninitialize, CLSID, COINIT_APARTMENTTHREADED,
+    COINIT_MULTITHREADED, HRESULT, S_FALSE, S_OK,
 };
 use std::ffi::c_void;
+use std::thread::JoinHandle;
 
 use crate::Interface;
 
@@ -29,6 +41,7 @@ pub fn init_runtime() -> Result<(), HRESULT> {
 
 /// The threading model of the current thread's apartment
 #[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum ApartmentType {
     /// A single-threaded apartment (COINIT_APARTMENTTHREADED)
@@ -45,10 +58,8 @@ pub enum ApartmentType {
 /// In  general this should only be called on threads created by the user.
 ///
 /// This wraps `CoInitializeEx`. The user is still responsible for establishing
-/// a message pump in the case of an STA
-// TODO: create a special `spawn` function for spawning a thread
-// with a specific apartment type.
-// TODO: add helpers for establishing a message pump
+/// a message pump in the case of an STA. Threads started with [`spawn_in_apartment`]
+/// can use their [`Dispatcher`] for that.
 pub fn init_apartment(apartment_type: ApartmentType) -> Result<(), HRESULT> {
     match unsafe { CoInitializeEx(std::ptr::null_mut::<c_void>(), apartment_type as u32) } {
         // S_OK indicates the runtime was initialized
@@ -66,6 +77,10 @@ pub fn init_apartment(apartment_type: ApartmentType) -> Result<(), HRESULT> {
 /// (usually started through [`init_apartment`]).
 /// https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-couninitialize
 pub fn deinit_apartment() {
+    // Without the COM runtime, agile references stand in for its proxies and are
+    // disconnected here
+    #[cfg(not(windows))]
+    crate::agile::local::disconnect_thread();
     unsafe { CoUninitialize() }
 }
 
@@ -92,50 +107,52 @@ impl Drop for ApartmentRuntime {
     }
 }
 
+/// Spawn a new thread running `f` in an apartment of the given type
+///
+/// The apartment is initialized before `f` runs and uninitialized once it returns (or
+/// panics). Single-threaded apartments get a [`Dispatcher`] which other threads can use to
+/// run closures on the new thread while it pumps messages with [`Dispatcher::run`].
+///
+/// The thread's result is an error if the apartment could not be initialized.
+pub fn spawn_in_apartment<F, T>(
+    apartment_type: ApartmentType,
+    f: F,
+) -> JoinHandle<Result<T, HRESULT>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    std::thread::spawn(move || {
+        let _runtime = ApartmentRuntime::new(apartment_type)?;
+        // Declared after the runtime so that queued closures are dropped before uninitializing
+        let _dispatcher = match apartment_type {
+            ApartmentType::SingleThreaded => Some(dispatcher::install()),
+            ApartmentType::Multithreaded => None,
+        };
+        Ok(f())
+    })
+}
+
 /// Get the class object with the associated [`CLSID`]
 ///
-/// Calls `CoGetClassObject` internally
+/// Calls `CoGetClassObject` internally. Use [`CreateOptions`] for classes that are not served
+/// in process.
 pub fn get_class_object<T: Interface>(class_id: &CLSID) -> Result<T, HRESULT> {
-    let mut class = None;
-    let hr = unsafe {
-        CoGetClassObject(
-            class_id as *const CLSID,
-            CLSCTX_INPROC_SERVER,
-            std::ptr::null_mut::<c_void>(),
-            &T::IID as *const IID,
-            &mut class as *mut _ as _,
-        )
-    };
-    if FAILED(hr) {
-        return Err(hr);
-    }
-
-    Ok(class.unwrap())
+    CreateOptions::new().get_class_object(class_id)
 }
 
 /// Create an instance of a COM class with the associated class id
 ///
-/// Calls `CoCreateInstance` internally
+/// Calls `CoCreateInstanceEx` internally. Use [`CreateOptions`] for classes that are not
+/// served in process or to ask for several interfaces at once.
 pub fn create_instance<T: Interface>(class_id: &CLSID) -> Result<T, HRESULT> {
-    unsafe { Ok(create_raw_instance::<T>(class_id, std::ptr::null_mut())?) }
+    CreateOptions::new().create(class_id)
 }
 
-/// A helper for creating both regular and aggregated instances
-unsafe fn create_raw_instance<T: Interface>(
-    class_id: &CLSID,
-    outer: *mut c_void,
-) -> Result<T, HRESULT> {
-    let mut instance = None;
-    let hr = CoCreateInstance(
-        class_id as *const CLSID,
-        outer,
-        CLSCTX_INPROC_SERVER,
-        &T::IID as *const IID,
-        &mut instance as *mut _ as _,
-    );
-    if FAILED(hr) {
-        return Err(hr);
-    }
-
-    Ok(instance.unwrap())
+/// Create an instance of the COM class that a ProgID such as `Contoso.Widget.1` stands for
+///
+/// Version independent ProgIDs such as `Contoso.Widget` create the current version. See
+/// [`clsid_from_progid`].
+pub fn create_instance_from_progid<T: Interface>(prog_id: &str) -> Result<T, HRESULT> {
+    create_instance(&clsid_from_progid(prog_id)?)
 }
@@ -0,0 +1,299 @@
+//! Activating classes with explicit contexts, servers and several interfaces at once
+use crate::interfaces::IUnknown;
+use crate::sys::{
+    CoCreateInstanceEx, CoGetClassObject, CLSCTX_INPROC_HANDLER, CLSCTX_INPROC_SERVER,
+    CLSCTX_LOCAL_SERVER, CLSCTX_REMOTE_SERVER, CLSID, COSERVERINFO, E_NOINTERFACE, FAILED, HRESULT,
+    IID, MULTI_QI,
+};
+use crate::Interface;
+
+use std::ffi::c_void;
+
+/// How a class is activated
+///
+/// Without any context flags, classes are activated in process like with
+/// [`create_instance`](super::create_instance).
+///
+/// ```rust,no_run
+/// # use com::interfaces::{IUnknown, IPersist};
+/// # use com::runtime::CreateOptions;
+/// # let class_id = com::CLSID::default();
+/// let (object, persist): (IUnknown, Option<IPersist>) = CreateOptions::new()
+///     .local_server()
+///     .server("buildserver")
+///     .create_multi(&class_id)?;
+/// # Ok::<(), com::sys::HRESULT>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CreateOptions<'a> {
+    context: u32,
+    server: Option<String>,
+    outer: Option<&'a IUnknown>,
+}
+
+impl<'a> CreateOptions<'a> {
+    /// Options that activate classes in process
+    pub fn new() -> CreateOptions<'a> {
+        CreateOptions::default()
+    }
+
+    /// Allow servers loaded into this process
+    pub fn inproc_server(self) -> CreateOptions<'a> {
+        self.context(CLSCTX_INPROC_SERVER)
+    }
+
+    /// Allow in process handlers for servers in another process
+    pub fn inproc_handler(self) -> CreateOptions<'a> {
+        self.context(CLSCTX_INPROC_HANDLER)
+    }
+
+    /// Allow servers running in their own process on this machine
+    pub fn local_server(self) -> CreateOptions<'a> {
+        self.context(CLSCTX_LOCAL_SERVER)
+    }
+
+    /// Allow servers on another machine
+    pub fn remote_server(self) -> CreateOptions<'a> {
+        self.context(CLSCTX_REMOTE_SERVER)
+    }
+
+    /// Allow the contexts of the raw `CLSCTX` flags
+    pub fn context(mut self, flags: u32) -> CreateOptions<'a> {
+        self.context |= flags;
+        self
+    }
+
+    /// Activate the class on the machine `name`, which also allows remote servers
+    pub fn server(mut self, name: &str) -> CreateOptions<'a> {
+        self.server = Some(name.to_owned());
+        self.remote_server()
+    }
+
+    /// Create the object as part of the aggregate `outer`
+    ///
+    /// Aggregated objects can only be asked for [`IUnknown`].
+    pub fn outer(mut self, outer: &'a IUnknown) -> CreateOptions<'a> {
+        self.outer = Some(outer);
+        self
+    }
+
+    fn context_flags(&self) -> u32 {
+        if self.context == 0 {
+            CLSCTX_INPROC_SERVER
+        } else {
+            self.context
+        }
+    }
+
+    /// Call `f` with the server info for the options
+    fn with_server_info<T>(&self, f: impl FnOnce(*mut COSERVERINFO) -> T) -> T {
+        match &self.server {
+            Some(name) => {
+                let mut name = name.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+                let mut info = COSERVERINFO {
+                    dwReserved1: 0,
+                    pwszName: name.as_mut_ptr(),
+                    pAuthInfo: std::ptr::null_mut(),
+                    dwReserved2: 0,
+                };
+                f(&mut info)
+            }
+            None => f(std::ptr::null_mut()),
+        }
+    }
+
+    /// Create an instance of a class as the interface `I`
+    ///
+    /// Calls `CoCreateInstanceEx` internally
+    pub fn create<I: Interface>(&self, class_id: &CLSID) -> Result<I, HRESULT> {
+        let (instance,) = self.create_multi::<(I,)>(class_id)?;
+        Ok(instance)
+    }
+
+    /// Create an instance of a class and ask it for several interfaces in one round trip
+    ///
+    /// Each element of the tuple `Q` is an interface that is required, an `Option` of one
+    /// that is `None` if the object does not support it, or a `Result` carrying the error of
+    /// the query for that interface. Creation fails if a required interface is missing.
+    ///
+    /// Calls `CoCreateInstanceEx` internally
+    pub fn create_multi<Q: MultiQi>(&self, class_id: &CLSID) -> Result<Q, HRESULT> {
+        let iids = Q::iids();
+        let mut results = iids
+            .iter()
+            .map(|iid| MULTI_QI {
+                pIID: iid,
+                pItf: std::ptr::null_mut(),
+                hr: 0,
+            })
+            .collect::<Vec<_>>();
+        let outer = match self.outer {
+            Some(outer) => outer.as_raw().as_ptr() as *mut c_void,
+            None => std::ptr::null_mut(),
+        };
+        let hr = self.with_server_info(|server_info| unsafe {
+            CoCreateInstanceEx(
+                class_id,
+                outer,
+                self.context_flags(),
+                server_info,
+                results.len() as u32,
+                results.as_mut_ptr(),
+            )
+        });
+        // The interfaces are owned from here on so that they are released on every path
+        let mut interfaces = results
+            .iter()
+            .map(|result| unsafe { std::mem::transmute_copy::<_, Option<IUnknown>>(&result.pItf) })
+            .collect::<Vec<_>>();
+        if FAILED(hr) && hr != E_NOINTERFACE {
+            return Err(hr);
+        }
+        let hrs = results.iter().map(|result| result.hr).collect::<Vec<_>>();
+        Q::from_results(&mut interfaces, &hrs)
+    }
+
+    /// Get the class object of a class as the interface `I`
+    ///
+    /// Calls `CoGetClassObject` internally
+    pub fn get_class_object<I: Interface>(&self, class_id: &CLSID) -> Result<I, HRESULT> {
+        let mut class = None;
+        let hr = self.with_server_info(|server_info| unsafe {
+            CoGetClassObject(
+                class_id,
+                self.context_flags(),
+                server_info as *mut c_void,
+                &I::IID,
+                &mut class as *mut _ as _,
+            )
+        });
+        if FAILED(hr) {
+            return Err(hr);
+        }
+
+        Ok(class.unwrap())
+    }
+}
+
+/// An element of a [`MultiQi`] tuple
+///
+/// This is an interface, an `Option` of an interface or a `Result` of one.
+pub trait Requested: Sized {
+    #[doc(hidden)]
+    fn iid() -> IID;
+
+    /// Take the interface returned for this element, `hr` being the result of its query
+    #[doc(hidden)]
+    fn from_result(interface: Option<IUnknown>, hr: HRESULT) -> Result<Self, HRESULT>;
+}
+
+/// Reinterpret the pointer returned for the IID of `I` as `I`
+fn cast<I: Interface>(interface: Option<IUnknown>, hr: HRESULT) -> Result<I, HRESULT> {
+    match interface {
+        Some(interface) => {
+            let result = unsafe { std::mem::transmute_copy::<IUnknown, I>(&interface) };
+            std::mem::forget(interface);
+            Ok(result)
+        }
+        None if FAILED(hr) => Err(hr),
+        None => Err(E_NOINTERFACE),
+    }
+}
+
+impl<I: Interface> Requested for I {
+    fn iid() -> IID {
+        I::IID
+    }
+
+    fn from_result(interface: Option<IUnknown>, hr: HRESULT) -> Result<I, HRESULT> {
+        cast(interface, hr)
+    }
+}
+
+impl<I: Interface> Requested for Option<I> {
+    fn iid() -> IID {
+        I::IID
+    }
+
+    fn from_result(interface: Option<IUnknown>, hr: HRESULT) -> Result<Option<I>, HRESULT> {
+        Ok(cast(interface, hr).ok())
+    }
+}
+
+impl<I: Interface> Requested for Result<I, HRESULT> {
+    fn iid() -> IID {
+        I::IID
+    }
+
+    fn from_result(
+        interface: Option<IUnknown>,
+        hr: HRESULT,
+    ) -> Result<Result<I, HRESULT>, HRESULT> {
+        Ok(cast(interface, hr))
+    }
+}
+
+/// A tuple of interfaces requested with [`CreateOptions::create_multi`]
+pub trait MultiQi: Sized {
+    #[doc(hidden)]
+    fn iids() -> Vec<IID>;
+
+    #[doc(hidden)]
+    fn from_results(interfaces: &mut [Option<IUnknown>], hrs: &[HRESULT]) -> Result<Self, HRESULT>;
+}
+
+macro_rules! multi_qi {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Requested),+> MultiQi for ($($name,)+) {
+            fn iids() -> Vec<IID> {
+                vec![$($name::iid()),+]
+            }
+
+            fn from_results(
+                interfaces: &mut [Option<IUnknown>],
+                hrs: &[HRESULT],
+            ) -> Result<Self, HRESULT> {
+                Ok(($($name::from_result(interfaces[$index].take(), hrs[$index])?,)+))
+            }
+        }
+    };
+}
+
+multi_qi!(A 0);
+multi_qi!(A 0, B 1);
+multi_qi!(A 0, B 1, C 2);
+multi_qi!(A 0, B 1, C 2, D 3);
+multi_qi!(A 0, B 1, C 2, D 3, E 4);
+multi_qi!(A 0, B 1, C 2, D 3, E 4, F 5);
+multi_qi!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+multi_qi!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::IPersist;
+
+    #[test]
+    fn each_interface_gets_its_own_result() {
+        const E_FAIL: HRESULT = crate::sys::E_FAIL;
+        let hrs = [E_NOINTERFACE, E_FAIL, 0];
+        let (persist, unknown) = <(Option<IPersist>, Result<IUnknown, HRESULT>)>::from_results(
+            &mut [None, None],
+            &hrs[..2],
+        )
+        .unwrap();
+        assert!(persist.is_none());
+        assert_eq!(unknown.err(), Some(E_FAIL));
+
+        let required = <(Option<IPersist>, IUnknown)>::from_results(&mut [None, None], &hrs[..2]);
+        assert_eq!(required.err(), Some(E_FAIL));
+        assert_eq!(
+            <(IUnknown,)>::from_results(&mut [None], &hrs[2..]).err(),
+            Some(E_NOINTERFACE)
+        );
+        assert_eq!(
+            <(IPersist, IUnknown)>::iids(),
+            vec![IPersist::IID, IUnknown::IID]
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Running closures on single-threaded apartment threads
+use crate::lazy_global::LazyGlobal;
+use crate::sys::{HRESULT, RPC_E_DISCONNECTED, RPC_E_WRONG_THREAD};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::ThreadId;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+struct Queue {
+    state: Mutex<State>,
+    thread: ThreadId,
+    waker: Waker,
+}
+
+struct State {
+    tasks: VecDeque<Task>,
+    /// `run` returns once the tasks queued so far have run
+    stopping: bool,
+    /// The thread is gone and no more tasks are accepted
+    closed: bool,
+}
+
+impl Queue {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A handle for running closures on a single-threaded apartment (STA) thread
+///
+/// COM objects living in an STA may only be called on its thread. Other threads use the
+/// dispatcher to [`post`](Dispatcher::post) closures to the STA thread, which runs them while
+/// it pumps messages in [`run`](Dispatcher::run). On Windows this is the thread's message
+/// loop, so that the COM runtime can deliver calls into the apartment at the same time.
+///
+/// STA threads started with [`spawn_in_apartment`](super::spawn_in_apartment) have a
+/// dispatcher available through [`Dispatcher::current`].
+#[derive(Clone)]
+pub struct Dispatcher {
+    queue: Arc<Queue>,
+}
+
+/// The dispatchers of all threads that have one
+static DISPATCHERS: LazyGlobal<Mutex<Vec<Dispatcher>>> = LazyGlobal::new();
+
+fn dispatchers() -> MutexGuard<'static, Vec<Dispatcher>> {
+    DISPATCHERS.lock(Vec::new)
+}
+
+/// Closes the dispatcher of the current thread when the thread is done
+pub(crate) struct Installed {
+    dispatcher: Dispatcher,
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        dispatchers().retain(|d| !Arc::ptr_eq(&d.queue, &self.dispatcher.queue));
+        let tasks = {
+            let mut state = self.dispatcher.queue.state();
+            state.closed = true;
+            std::mem::take(&mut state.tasks)
+        };
+        // Closures may hold on to objects of the apartment so they are dropped on this thread
+        drop(tasks);
+    }
+}
+
+/// Give the current thread a dispatcher
+pub(crate) fn install() -> Installed {
+    let dispatcher = Dispatcher {
+        queue: Arc::new(Queue {
+            state: Mutex::new(State {
+                tasks: VecDeque::new(),
+                stopping: false,
+                closed: false,
+            }),
+            thread: std::thread::current().id(),
+            waker: Waker::new(),
+        }),
+    };
+    dispatchers().push(dispatcher.clone());
+    Installed { dispatcher }
+}
+
+impl Dispatcher {
+    /// The dispatcher of the current thread if it has one
+    pub fn current() -> Option<Dispatcher> {
+        dispatchers().iter().find(|d| d.is_current()).cloned()
+    }
+
+    /// Run `f` on the dispatcher's thread
+    ///
+    /// Fails with `RPC_E_DISCONNECTED` if the thread has already finished.
+    pub fn post<F: FnOnce() + Send + 'static>(&self, f: F) -> Result<(), HRESULT> {
+        {
+            let mut state = self.queue.state();
+            if state.closed {
+                return Err(RPC_E_DISCONNECTED);
+            }
+            state.tasks.push_back(Box::new(f));
+        }
+        self.queue.waker.wake();
+        Ok(())
+    }
+
+    /// Make [`run`](Dispatcher::run) return once the closures posted so far have run
+    pub fn stop(&self) {
+        self.queue.state().stopping = true;
+        self.queue.waker.wake();
+    }
+
+    /// Whether this is the dispatcher of the current thread
+    pub fn is_current(&self) -> bool {
+        self.queue.thread == std::thread::current().id()
+    }
+
+    /// Run posted closures and pump messages until [`stop`](Dispatcher::stop) is called
+    ///
+    /// This must be called on the dispatcher's thread and fails with `RPC_E_WRONG_THREAD`
+    /// otherwise.
+    pub fn run(&self) -> Result<(), HRESULT> {
+        if !self.is_current() {
+            return Err(RPC_E_WRONG_THREAD);
+        }
+        loop {
+            loop {
+                let task = {
+                    let mut state = self.queue.state();
+                    match state.tasks.pop_front() {
+                        Some(task) => task,
+                        None if state.stopping => {
+                            state.stopping = false;
+                            return Ok(());
+                        }
+                        None => break,
+                    }
+                };
+                task();
+            }
+            self.queue.waker.wait(&self.queue)?;
+        }
+    }
+}
+
+/// Wakes up the dispatcher's thread through its message queue
+#[cfg(windows)]
+struct Waker {
+    thread_id: u32,
+}
+
+#[cfg(windows)]
+const WM_DISPATCH: u32 = crate::sys::WM_APP + 0x0C0D;
+
+#[cfg(windows)]
+impl Waker {
+    fn new() -> Waker {
+        use crate::sys::{GetCurrentThreadId, PeekMessageW, MSG, PM_NOREMOVE};
+
+        let mut msg = MSG::default();
+        // Make sure the thread has a message queue before anyone posts to it
+        unsafe { PeekMessageW(&mut msg, std::ptr::null_mut(), 0, 0, PM_NOREMOVE) };
+        Waker {
+            thread_id: unsafe { GetCurrentThreadId() },
+        }
+    }
+
+    fn wake(&self) {
+        unsafe { crate::sys::PostThreadMessageW(self.thread_id, WM_DISPATCH, 0, 0) };
+    }
+
+    /// Dispatch messages until one of them could be for the dispatcher
+    fn wait(&self, queue: &Queue) -> Result<(), HRESULT> {
+        use crate::sys::{DispatchMessageW, GetMessageW, TranslateMessage, E_FAIL, MSG};
+
+        let mut msg = MSG::default();
+        loop {
+            match unsafe { GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) } {
+                -1 => return Err(E_FAIL),
+                // `WM_QUIT` ends the loop as if the dispatcher was stopped
+                0 => {
+                    queue.state().stopping = true;
+                    return Ok(());
+                }
+                _ if msg.hwnd.is_null() && msg.message == WM_DISPATCH => return Ok(()),
+                _ => unsafe {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                },
+            }
+        }
+    }
+}
+
+/// Wakes up the dispatcher's thread through a condition variable
+#[cfg(not(windows))]
+struct Waker {
+    woken: Mutex<bool>,
+    condvar: std::sync::Condvar,
+}
+
+#[cfg(not(windows))]
+impl Waker {
+    fn new() -> Waker {
+        Waker {
+            woken: Mutex::new(false),
+            condvar: std::sync::Condvar::new(),
+        }
+    }
+
+    fn wake(&self) {
+        *self.woken.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        self.condvar.notify_one();
+    }
+
+    fn wait(&self, _queue: &Queue) -> Result<(), HRESULT> {
+        let mut woken = self.woken.lock().unwrap_or_else(|e| e.into_inner());
+        while !*woken {
+            woken = self.condvar.wait(woken).unwrap_or_else(|e| e.into_inner());
+        }
+        *woken = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{spawn_in_apartment, ApartmentType};
+    use super::*;
+
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn runs_posted_closures_on_the_apartment_thread() {
+        let (dispatcher_sender, dispatcher_receiver) = channel();
+        let thread = spawn_in_apartment(ApartmentType::SingleThreaded, move || {
+            let dispatcher = Dispatcher::current().unwrap();
+            dispatcher_sender.send(dispatcher.clone()).unwrap();
+            dispatcher.run().unwrap();
+            std::thread::current().id()
+        });
+        let dispatcher = dispatcher_receiver.recv().unwrap();
+        assert_eq!(dispatcher.run(), Err(RPC_E_WRONG_THREAD));
+
+        let (sender, receiver) = channel();
+        for i in 0..3 {
+            let sender = sender.clone();
+            dispatcher
+                .post(move || {
+                    let dispatcher = Dispatcher::current().unwrap();
+                    sender.send((i, dispatcher.is_current())).unwrap();
+                })
+                .unwrap();
+        }
+        dispatcher.stop();
+        let apartment_thread = thread.join().unwrap().unwrap();
+        drop(sender);
+        assert_eq!(
+            receiver.iter().collect::<Vec<_>>(),
+            vec![(0, true), (1, true), (2, true)]
+        );
+        assert_ne!(apartment_thread, std::thread::current().id());
+        assert_eq!(dispatcher.post(|| {}), Err(RPC_E_DISCONNECTED));
+    }
+}
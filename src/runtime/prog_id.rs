@@ -0,0 +1,150 @@
+//! Resolving ProgIDs such as `Contoso.Widget.1` to class ids and back
+//!
+//! ProgIDs registered with [`register_prog_id`] are resolved within the process. On Windows,
+//! the others are looked up in the registry through `CLSIDFromProgID` and `ProgIDFromCLSID`.
+use crate::lazy_global::LazyGlobal;
+use crate::sys::{CLSID, HRESULT};
+
+use std::sync::{Mutex, MutexGuard};
+
+struct Registration {
+    prog_id: String,
+    version_independent_prog_id: Option<String>,
+    class_id: CLSID,
+}
+
+static TABLE: LazyGlobal<Mutex<Vec<Registration>>> = LazyGlobal::new();
+
+fn table() -> MutexGuard<'static, Vec<Registration>> {
+    TABLE.lock(Vec::new)
+}
+
+/// Make `prog_id` resolve to `class_id` within this process
+///
+/// If given, the version independent ProgID resolves to the class of the ProgID registered
+/// last with it, like the `CurVer` key in the registry does. Registering a ProgID again
+/// replaces the previous registration.
+pub fn register_prog_id(
+    prog_id: &str,
+    version_independent_prog_id: Option<&str>,
+    class_id: &CLSID,
+) {
+    let mut table = table();
+    table.retain(|r| !r.prog_id.eq_ignore_ascii_case(prog_id));
+    table.push(Registration {
+        prog_id: prog_id.to_owned(),
+        version_independent_prog_id: version_independent_prog_id.map(str::to_owned),
+        class_id: *class_id,
+    });
+}
+
+/// Remove a ProgID registered with [`register_prog_id`]
+pub fn revoke_prog_id(prog_id: &str) {
+    table().retain(|r| !r.prog_id.eq_ignore_ascii_case(prog_id));
+}
+
+/// The class id that a ProgID or version independent ProgID stands for
+pub fn clsid_from_progid(prog_id: &str) -> Result<CLSID, HRESULT> {
+    let found = {
+        let table = table();
+        let exact = table
+            .iter()
+            .find(|r| r.prog_id.eq_ignore_ascii_case(prog_id));
+        let current = || {
+            table
+                .iter()
+                .rev()
+                .find(|r| match &r.version_independent_prog_id {
+                    Some(name) => name.eq_ignore_ascii_case(prog_id),
+                    None => false,
+                })
+        };
+        exact.or_else(current).map(|r| r.class_id)
+    };
+    match found {
+        Some(class_id) => Ok(class_id),
+        #[cfg(windows)]
+        None => system::clsid_from_progid(prog_id),
+        #[cfg(not(windows))]
+        None => Err(crate::sys::CO_E_CLASSSTRING),
+    }
+}
+
+/// The ProgID of a class
+///
+/// For classes with several versions this is the ProgID registered last.
+pub fn progid_from_clsid(class_id: &CLSID) -> Result<String, HRESULT> {
+    let found = table()
+        .iter()
+        .rev()
+        .find(|r| r.class_id == *class_id)
+        .map(|r| r.prog_id.clone());
+    match found {
+        Some(prog_id) => Ok(prog_id),
+        #[cfg(windows)]
+        None => system::progid_from_clsid(class_id),
+        #[cfg(not(windows))]
+        None => Err(crate::sys::REGDB_E_CLASSNOTREG),
+    }
+}
+
+#[cfg(windows)]
+mod system {
+    use crate::strings::TaskString;
+    use crate::sys::{CLSIDFromProgID, ProgIDFromCLSID, CLSID, FAILED, HRESULT};
+    use crate::AbiTransferable;
+
+    pub(super) fn clsid_from_progid(prog_id: &str) -> Result<CLSID, HRESULT> {
+        let prog_id = prog_id.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+        let mut class_id = CLSID::default();
+        let hr = unsafe { CLSIDFromProgID(prog_id.as_ptr(), &mut class_id) };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(class_id)
+    }
+
+    pub(super) fn progid_from_clsid(class_id: &CLSID) -> Result<String, HRESULT> {
+        let mut prog_id = TaskString::default();
+        let hr = unsafe { ProgIDFromCLSID(class_id, prog_id.set_abi()) };
+        if FAILED(hr) {
+            return Err(hr);
+        }
+        Ok(String::from_utf16_lossy(unsafe { prog_id.as_wide() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clsid(data1: u32) -> CLSID {
+        CLSID {
+            data1,
+            data2: 0x4D2A,
+            data3: 0x4B7E,
+            data4: [0x91, 0x3C, 0x6E, 0x2F, 0x8A, 0x5D, 0x1B, 0x7C],
+        }
+    }
+
+    #[test]
+    fn prog_ids_resolve_to_the_current_version() {
+        let (v1, v2) = (clsid(0x0B1D_0001), clsid(0x0B1D_0002));
+        register_prog_id("Contoso.Widget.1", Some("Contoso.Widget"), &v1);
+        register_prog_id("Contoso.Widget.2", Some("Contoso.Widget"), &v2);
+
+        assert_eq!(clsid_from_progid("contoso.widget.1"), Ok(v1));
+        assert_eq!(clsid_from_progid("Contoso.Widget"), Ok(v2));
+        assert_eq!(progid_from_clsid(&v2), Ok("Contoso.Widget.2".to_owned()));
+
+        revoke_prog_id("Contoso.Widget.2");
+        assert_eq!(clsid_from_progid("Contoso.Widget"), Ok(v1));
+        revoke_prog_id("Contoso.Widget.1");
+        #[cfg(not(windows))]
+        {
+            use crate::sys::{CO_E_CLASSSTRING, REGDB_E_CLASSNOTREG};
+            assert_eq!(clsid_from_progid("Contoso.Widget"), Err(CO_E_CLASSSTRING));
+            assert_eq!(progid_from_clsid(&v1), Err(REGDB_E_CLASSNOTREG));
+        }
+    }
+}
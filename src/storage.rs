@@ -0,0 +1,17 @@
+//! Structured storage (OLE compound files)
+//!
+//! [`CompoundFile`] reads and writes the compound file binary format without
+//! any help from the operating system. With the `production` feature enabled
+//! a compound file can also be exposed to COM clients through the
+//! [`IStorage`](crate::interfaces::IStorage), [`IStream`](crate::interfaces::IStream)
+//! and [`IEnumSTATSTG`](crate::interfaces::IEnumSTATSTG) interfaces.
+
+mod compound_file;
+#[cfg(feature = "production")]
+mod objects;
+
+#[doc(inline)]
+pub use compound_file::{CompoundFile, Entry, Storage, Stream, Version};
+#[cfg(feature = "production")]
+#[doc(inline)]
+pub use objects::root_storage;
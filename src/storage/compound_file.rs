@@ -0,0 +1,1235 @@
+use crate::sys::CLSID;
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+const SIGNATURE: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+const HEADER_SIZE: usize = 512;
+const HEADER_DIFAT_ENTRIES: usize = 109;
+const MINOR_VERSION: u16 = 0x003E;
+const BYTE_ORDER_MARK: u16 = 0xFFFE;
+const MINI_SECTOR_SHIFT: u16 = 6;
+const MINI_SECTOR_SIZE: usize = 1 << MINI_SECTOR_SHIFT;
+const MINI_STREAM_CUTOFF: usize = 4096;
+const DIRECTORY_ENTRY_SIZE: usize = 128;
+const MAX_NAME_LENGTH: usize = 31;
+/// How deeply storages may be nested in a file that is read, so that a crafted file cannot
+/// exhaust the stack
+const MAX_STORAGE_DEPTH: usize = 256;
+
+const MAX_REGULAR_SECTOR: u32 = 0xFFFF_FFFA;
+const DIFAT_SECTOR: u32 = 0xFFFF_FFFC;
+const FAT_SECTOR: u32 = 0xFFFF_FFFD;
+const END_OF_CHAIN: u32 = 0xFFFF_FFFE;
+const FREE_SECTOR: u32 = 0xFFFF_FFFF;
+const NO_STREAM: u32 = 0xFFFF_FFFF;
+
+const OBJECT_UNKNOWN: u8 = 0;
+const OBJECT_STORAGE: u8 = 1;
+const OBJECT_STREAM: u8 = 2;
+const OBJECT_ROOT: u8 = 5;
+
+const COLOR_RED: u8 = 0;
+const COLOR_BLACK: u8 = 1;
+
+const NULL_CLSID: CLSID = CLSID {
+    data1: 0,
+    data2: 0,
+    data3: 0,
+    data4: [0; 8],
+};
+
+/// The major version of a compound file, which determines its sector size
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Version {
+    /// Version 3 files use 512 byte sectors
+    V3,
+    /// Version 4 files use 4096 byte sectors
+    V4,
+}
+
+impl Version {
+    fn major(self) -> u16 {
+        match self {
+            Version::V3 => 3,
+            Version::V4 => 4,
+        }
+    }
+
+    fn sector_shift(self) -> u16 {
+        match self {
+            Version::V3 => 9,
+            Version::V4 => 12,
+        }
+    }
+
+    /// The size of a sector in bytes
+    pub fn sector_size(self) -> usize {
+        1 << self.sector_shift()
+    }
+}
+
+/// An OLE compound file (also known as structured storage)
+///
+/// The whole file is held in memory as a tree of [`Storage`]s and [`Stream`]s
+/// rooted at [`CompoundFile::root`].
+#[derive(Clone, Debug)]
+pub struct CompoundFile {
+    version: Version,
+    root: Storage,
+}
+
+/// A storage element: a named container of other storages and streams
+#[derive(Clone, Debug)]
+pub struct Storage {
+    name: String,
+    clsid: CLSID,
+    state_bits: u32,
+    created: u64,
+    modified: u64,
+    entries: Vec<Entry>,
+}
+
+/// A stream element: a named sequence of bytes
+#[derive(Clone, Debug)]
+pub struct Stream {
+    name: String,
+    data: Vec<u8>,
+}
+
+/// An element contained in a [`Storage`]
+#[derive(Clone, Debug)]
+pub enum Entry {
+    /// A nested storage
+    Storage(Storage),
+    /// A stream
+    Stream(Stream),
+}
+
+impl CompoundFile {
+    /// Create an empty compound file of the given version
+    pub fn new(version: Version) -> CompoundFile {
+        CompoundFile {
+            version,
+            root: Storage::new("Root Entry".to_owned()),
+        }
+    }
+
+    /// Read a compound file from `reader`
+    pub fn open<R: Read>(mut reader: R) -> io::Result<CompoundFile> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        CompoundFile::from_bytes(&data)
+    }
+
+    /// Parse a compound file from its binary representation
+    pub fn from_bytes(data: &[u8]) -> io::Result<CompoundFile> {
+        FileReader::new(data)?.read()
+    }
+
+    /// Write the compound file to `writer`
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes()?)
+    }
+
+    /// Serialize the compound file to its binary representation
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        FileWriter::new(self).write()
+    }
+
+    /// The version the file was read as or will be written as
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Change the version the file will be written as
+    pub fn set_version(&mut self, version: Version) {
+        self.version = version;
+    }
+
+    /// The root storage of the file
+    pub fn root(&self) -> &Storage {
+        &self.root
+    }
+
+    /// The mutable root storage of the file
+    pub fn root_mut(&mut self) -> &mut Storage {
+        &mut self.root
+    }
+}
+
+impl Storage {
+    fn new(name: String) -> Storage {
+        Storage {
+            name,
+            clsid: NULL_CLSID,
+            state_bits: 0,
+            created: 0,
+            modified: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// The name of the storage
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The class associated with the storage
+    pub fn clsid(&self) -> CLSID {
+        self.clsid
+    }
+
+    /// Set the class associated with the storage
+    pub fn set_clsid(&mut self, clsid: CLSID) {
+        self.clsid = clsid;
+    }
+
+    /// The user defined state bits of the storage
+    pub fn state_bits(&self) -> u32 {
+        self.state_bits
+    }
+
+    /// Set the user defined state bits of the storage
+    pub fn set_state_bits(&mut self, state_bits: u32) {
+        self.state_bits = state_bits;
+    }
+
+    /// The creation time as a `FILETIME` value
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    /// Set the creation time as a `FILETIME` value
+    pub fn set_created(&mut self, created: u64) {
+        self.created = created;
+    }
+
+    /// The modification time as a `FILETIME` value
+    pub fn modified(&self) -> u64 {
+        self.modified
+    }
+
+    /// Set the modification time as a `FILETIME` value
+    pub fn set_modified(&mut self, modified: u64) {
+        self.modified = modified;
+    }
+
+    /// Iterate over the elements directly contained in this storage
+    pub fn entries(&self) -> std::slice::Iter<'_, Entry> {
+        self.entries.iter()
+    }
+
+    /// Look up a directly contained element by name
+    ///
+    /// Names are compared case-insensitively.
+    pub fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| names_equal(e.name(), name))
+    }
+
+    /// The mutable version of [`Storage::entry`]
+    pub fn entry_mut(&mut self, name: &str) -> Option<&mut Entry> {
+        self.entries
+            .iter_mut()
+            .find(|e| names_equal(e.name(), name))
+    }
+
+    /// Look up a directly contained storage by name
+    pub fn storage(&self, name: &str) -> Option<&Storage> {
+        match self.entry(name) {
+            Some(Entry::Storage(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The mutable version of [`Storage::storage`]
+    pub fn storage_mut(&mut self, name: &str) -> Option<&mut Storage> {
+        match self.entry_mut(name) {
+            Some(Entry::Storage(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Look up a directly contained stream by name
+    pub fn stream(&self, name: &str) -> Option<&Stream> {
+        match self.entry(name) {
+            Some(Entry::Stream(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The mutable version of [`Storage::stream`]
+    pub fn stream_mut(&mut self, name: &str) -> Option<&mut Stream> {
+        match self.entry_mut(name) {
+            Some(Entry::Stream(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Create a new, empty storage inside this storage
+    ///
+    /// Fails if the name is invalid or an element with that name already exists.
+    pub fn create_storage(&mut self, name: &str) -> io::Result<&mut Storage> {
+        match self.insert(Entry::Storage(Storage::new(name.to_owned())))? {
+            Entry::Storage(s) => Ok(s),
+            Entry::Stream(_) => unreachable!(),
+        }
+    }
+
+    /// Create a new, empty stream inside this storage
+    ///
+    /// Fails if the name is invalid or an element with that name already exists.
+    pub fn create_stream(&mut self, name: &str) -> io::Result<&mut Stream> {
+        let stream = Stream {
+            name: name.to_owned(),
+            data: Vec::new(),
+        };
+        match self.insert(Entry::Stream(stream))? {
+            Entry::Stream(s) => Ok(s),
+            Entry::Storage(_) => unreachable!(),
+        }
+    }
+
+    /// Add an existing element to this storage
+    ///
+    /// Fails if the name is invalid or an element with that name already exists.
+    pub fn insert(&mut self, entry: Entry) -> io::Result<&mut Entry> {
+        validate_name(entry.name())?;
+        if self.entry(entry.name()).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("an element named '{}' already exists", entry.name()),
+            ));
+        }
+        self.entries.push(entry);
+        Ok(self.entries.last_mut().unwrap())
+    }
+
+    /// Remove a directly contained element, returning it if it existed
+    pub fn remove(&mut self, name: &str) -> Option<Entry> {
+        let index = self
+            .entries
+            .iter()
+            .position(|e| names_equal(e.name(), name))?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Rename a directly contained element
+    pub fn rename(&mut self, old_name: &str, new_name: &str) -> io::Result<()> {
+        validate_name(new_name)?;
+        if !names_equal(old_name, new_name) && self.entry(new_name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("an element named '{}' already exists", new_name),
+            ));
+        }
+        match self.entry_mut(old_name) {
+            Some(entry) => {
+                entry.set_name(new_name.to_owned());
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no element named '{}'", old_name),
+            )),
+        }
+    }
+}
+
+impl Stream {
+    /// The name of the stream
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The contents of the stream
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The mutable contents of the stream
+    pub fn data_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    /// The length of the stream in bytes
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether the stream is empty
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Entry {
+    /// The name of the element
+    pub fn name(&self) -> &str {
+        match self {
+            Entry::Storage(s) => &s.name,
+            Entry::Stream(s) => &s.name,
+        }
+    }
+
+    fn set_name(&mut self, name: String) {
+        match self {
+            Entry::Storage(s) => s.name = name,
+            Entry::Stream(s) => s.name = name,
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+fn validate_name(name: &str) -> io::Result<()> {
+    let length = name.encode_utf16().count();
+    if length == 0 || length > MAX_NAME_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "element names must be between 1 and {} UTF-16 code units long",
+                MAX_NAME_LENGTH
+            ),
+        ));
+    }
+    if name.contains(&['/', '\\', ':', '!'][..]) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("element name '{}' contains an illegal character", name),
+        ));
+    }
+    Ok(())
+}
+
+fn uppercase_units(name: &str) -> Vec<u16> {
+    name.chars()
+        .map(|c| {
+            let mut upper = c.to_uppercase();
+            match (upper.next(), upper.next()) {
+                (Some(u), None) => u,
+                _ => c,
+            }
+        })
+        .collect::<String>()
+        .encode_utf16()
+        .collect()
+}
+
+pub(super) fn names_equal(a: &str, b: &str) -> bool {
+    uppercase_units(a) == uppercase_units(b)
+}
+
+/// The ordering used for the red-black trees of the directory: shorter names
+/// first, then a case-insensitive comparison of the UTF-16 code units
+fn compare_names(a: &str, b: &str) -> Ordering {
+    let a = uppercase_units(a);
+    let b = uppercase_units(b);
+    a.len().cmp(&b.len()).then_with(|| a.cmp(&b))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_clsid(data: &[u8], offset: usize) -> CLSID {
+    CLSID {
+        data1: read_u32(data, offset),
+        data2: read_u16(data, offset + 4),
+        data3: read_u16(data, offset + 6),
+        data4: data[offset + 8..offset + 16].try_into().unwrap(),
+    }
+}
+
+fn write_clsid(out: &mut Vec<u8>, clsid: &CLSID) {
+    out.extend_from_slice(&clsid.data1.to_le_bytes());
+    out.extend_from_slice(&clsid.data2.to_le_bytes());
+    out.extend_from_slice(&clsid.data3.to_le_bytes());
+    out.extend_from_slice(&clsid.data4);
+}
+
+/// A directory entry as it is stored in the file
+struct RawEntry {
+    name: String,
+    object_type: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    clsid: CLSID,
+    state_bits: u32,
+    created: u64,
+    modified: u64,
+    start: u32,
+    size: u64,
+}
+
+impl RawEntry {
+    fn parse(data: &[u8], version: Version) -> io::Result<RawEntry> {
+        let name_length = read_u16(data, 64) as usize;
+        let units = (0..(name_length / 2).min(32))
+            .map(|i| read_u16(data, i * 2))
+            .take_while(|u| *u != 0)
+            .collect::<Vec<_>>();
+        let name = String::from_utf16(&units)
+            .map_err(|_| invalid_data("directory entry name is not valid UTF-16"))?;
+        let mut size = read_u64(data, 120);
+        if version == Version::V3 {
+            // Version 3 writers are allowed to leave garbage in the high bits
+            size &= 0xFFFF_FFFF;
+        }
+        Ok(RawEntry {
+            name,
+            object_type: data[66],
+            left: read_u32(data, 68),
+            right: read_u32(data, 72),
+            child: read_u32(data, 76),
+            clsid: read_clsid(data, 80),
+            state_bits: read_u32(data, 96),
+            created: read_u64(data, 100),
+            modified: read_u64(data, 108),
+            start: read_u32(data, 116),
+            size,
+        })
+    }
+
+    fn empty() -> RawEntry {
+        RawEntry {
+            name: String::new(),
+            object_type: OBJECT_UNKNOWN,
+            left: NO_STREAM,
+            right: NO_STREAM,
+            child: NO_STREAM,
+            clsid: NULL_CLSID,
+            state_bits: 0,
+            created: 0,
+            modified: 0,
+            start: 0,
+            size: 0,
+        }
+    }
+
+    fn write(&self, color: u8, out: &mut Vec<u8>) {
+        let mut name = [0u8; 64];
+        let mut name_length = 0;
+        if !self.name.is_empty() {
+            for (i, unit) in self.name.encode_utf16().enumerate() {
+                name[i * 2..i * 2 + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            name_length = (self.name.encode_utf16().count() + 1) * 2;
+        }
+        out.extend_from_slice(&name);
+        out.extend_from_slice(&(name_length as u16).to_le_bytes());
+        out.push(self.object_type);
+        out.push(color);
+        out.extend_from_slice(&self.left.to_le_bytes());
+        out.extend_from_slice(&self.right.to_le_bytes());
+        out.extend_from_slice(&self.child.to_le_bytes());
+        write_clsid(out, &self.clsid);
+        out.extend_from_slice(&self.state_bits.to_le_bytes());
+        out.extend_from_slice(&self.created.to_le_bytes());
+        out.extend_from_slice(&self.modified.to_le_bytes());
+        out.extend_from_slice(&self.start.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+    }
+}
+
+struct FileReader<'a> {
+    data: &'a [u8],
+    version: Version,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    mini_stream: Vec<u8>,
+    entries: Vec<RawEntry>,
+}
+
+impl<'a> FileReader<'a> {
+    fn new(data: &'a [u8]) -> io::Result<FileReader<'a>> {
+        if data.len() < HEADER_SIZE || data[..8] != SIGNATURE {
+            return Err(invalid_data("not a compound file"));
+        }
+        if read_u16(data, 28) != BYTE_ORDER_MARK {
+            return Err(invalid_data("invalid byte order mark"));
+        }
+        let version = match (read_u16(data, 26), read_u16(data, 30)) {
+            (3, 9) => Version::V3,
+            (4, 12) => Version::V4,
+            _ => return Err(invalid_data("unsupported version or sector size")),
+        };
+        if read_u16(data, 32) != MINI_SECTOR_SHIFT {
+            return Err(invalid_data("unsupported mini sector size"));
+        }
+
+        let mut reader = FileReader {
+            data,
+            version,
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            mini_stream: Vec::new(),
+            entries: Vec::new(),
+        };
+
+        // Locate the FAT sectors through the header and the DIFAT chain
+        let fat_sector_count = read_u32(data, 44) as usize;
+        let mut fat_sectors = (0..HEADER_DIFAT_ENTRIES)
+            .map(|i| read_u32(data, 76 + i * 4))
+            .collect::<Vec<_>>();
+        let mut difat_sector = read_u32(data, 68);
+        let mut seen = HashSet::new();
+        while difat_sector <= MAX_REGULAR_SECTOR && fat_sectors.len() < fat_sector_count {
+            if !seen.insert(difat_sector) {
+                return Err(invalid_data("DIFAT chain contains a loop"));
+            }
+            let sector = reader.sector(difat_sector)?;
+            let entries = version.sector_size() / 4;
+            fat_sectors.extend((0..entries - 1).map(|i| read_u32(sector, i * 4)));
+            difat_sector = read_u32(sector, (entries - 1) * 4);
+        }
+        if fat_sectors.len() < fat_sector_count {
+            return Err(invalid_data(
+                "DIFAT is shorter than the number of FAT sectors",
+            ));
+        }
+        for &id in &fat_sectors[..fat_sector_count] {
+            let sector = reader.sector(id)?;
+            reader
+                .fat
+                .extend((0..sector.len() / 4).map(|i| read_u32(sector, i * 4)));
+        }
+
+        let directory = reader.read_chain(read_u32(data, 48))?;
+        reader.entries = directory
+            .chunks(DIRECTORY_ENTRY_SIZE)
+            .filter(|c| c.len() == DIRECTORY_ENTRY_SIZE)
+            .map(|c| RawEntry::parse(c, version))
+            .collect::<io::Result<_>>()?;
+        let root = match reader.entries.first() {
+            Some(root) if root.object_type == OBJECT_ROOT => root,
+            _ => return Err(invalid_data("missing root directory entry")),
+        };
+
+        let mini_fat = reader.read_chain(read_u32(data, 60))?;
+        reader.mini_fat = (0..mini_fat.len() / 4)
+            .map(|i| read_u32(&mini_fat, i * 4))
+            .collect();
+        let mut mini_stream = reader.read_chain(root.start)?;
+        mini_stream.truncate(root.size as usize);
+        reader.mini_stream = mini_stream;
+
+        Ok(reader)
+    }
+
+    fn sector(&self, id: u32) -> io::Result<&'a [u8]> {
+        let size = self.version.sector_size();
+        let start = (id as usize + 1) * size;
+        self.data
+            .get(start..start + size)
+            .ok_or_else(|| invalid_data("sector is out of bounds"))
+    }
+
+    fn chain(table: &[u32], start: u32) -> io::Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut current = start;
+        while current != END_OF_CHAIN {
+            if chain.len() > table.len() {
+                return Err(invalid_data("sector chain contains a loop"));
+            }
+            chain.push(current);
+            current = *table
+                .get(current as usize)
+                .ok_or_else(|| invalid_data("sector chain points outside the FAT"))?;
+        }
+        Ok(chain)
+    }
+
+    fn read_chain(&self, start: u32) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if start > MAX_REGULAR_SECTOR {
+            return Ok(data);
+        }
+        for id in Self::chain(&self.fat, start)? {
+            data.extend_from_slice(self.sector(id)?);
+        }
+        Ok(data)
+    }
+
+    fn read_mini_chain(&self, start: u32) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if start > MAX_REGULAR_SECTOR {
+            return Ok(data);
+        }
+        for id in Self::chain(&self.mini_fat, start)? {
+            let offset = id as usize * MINI_SECTOR_SIZE;
+            let sector = self
+                .mini_stream
+                .get(offset..offset + MINI_SECTOR_SIZE)
+                .ok_or_else(|| invalid_data("mini sector is out of bounds"))?;
+            data.extend_from_slice(sector);
+        }
+        Ok(data)
+    }
+
+    fn read(self) -> io::Result<CompoundFile> {
+        let mut visited = HashSet::new();
+        visited.insert(0);
+        let root = self.read_storage(0, 0, &mut visited)?;
+        Ok(CompoundFile {
+            version: self.version,
+            root,
+        })
+    }
+
+    fn read_storage(
+        &self,
+        id: u32,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+    ) -> io::Result<Storage> {
+        if depth > MAX_STORAGE_DEPTH {
+            return Err(invalid_data("storages are nested too deeply"));
+        }
+        let raw = &self.entries[id as usize];
+        let mut storage = Storage::new(raw.name.clone());
+        storage.clsid = raw.clsid;
+        storage.state_bits = raw.state_bits;
+        storage.created = raw.created;
+        storage.modified = raw.modified;
+
+        for child in self.collect_siblings(raw.child, visited)? {
+            let raw = &self.entries[child as usize];
+            match raw.object_type {
+                OBJECT_STORAGE => {
+                    let child = self.read_storage(child, depth + 1, visited)?;
+                    storage.entries.push(Entry::Storage(child));
+                }
+                OBJECT_STREAM => {
+                    let mut data = if (raw.size as usize) < MINI_STREAM_CUTOFF {
+                        self.read_mini_chain(raw.start)?
+                    } else {
+                        self.read_chain(raw.start)?
+                    };
+                    if data.len() < raw.size as usize {
+                        return Err(invalid_data("stream is shorter than its declared size"));
+                    }
+                    data.truncate(raw.size as usize);
+                    storage.entries.push(Entry::Stream(Stream {
+                        name: raw.name.clone(),
+                        data,
+                    }));
+                }
+                _ => {}
+            }
+        }
+        Ok(storage)
+    }
+
+    /// Walk the red-black tree of siblings in order
+    ///
+    /// The tree is walked with an explicit stack as a crafted file may degenerate it into a
+    /// list of any length.
+    fn collect_siblings(&self, id: u32, visited: &mut HashSet<u32>) -> io::Result<Vec<u32>> {
+        let mut siblings = Vec::new();
+        let mut stack = Vec::new();
+        let mut current = id;
+        loop {
+            while current != NO_STREAM {
+                if current as usize >= self.entries.len() {
+                    return Err(invalid_data("directory entry id is out of bounds"));
+                }
+                if !visited.insert(current) {
+                    return Err(invalid_data("directory tree contains a loop"));
+                }
+                stack.push(current);
+                current = self.entries[current as usize].left;
+            }
+            match stack.pop() {
+                Some(id) => {
+                    siblings.push(id);
+                    current = self.entries[id as usize].right;
+                }
+                None => return Ok(siblings),
+            }
+        }
+    }
+}
+
+struct FileWriter<'a> {
+    file: &'a CompoundFile,
+    entries: Vec<RawEntry>,
+    colors: Vec<u8>,
+    sectors: Vec<u8>,
+    fat: Vec<u32>,
+    mini_stream: Vec<u8>,
+    mini_fat: Vec<u32>,
+}
+
+impl<'a> FileWriter<'a> {
+    fn new(file: &'a CompoundFile) -> FileWriter<'a> {
+        FileWriter {
+            file,
+            entries: Vec::new(),
+            colors: Vec::new(),
+            sectors: Vec::new(),
+            fat: Vec::new(),
+            mini_stream: Vec::new(),
+            mini_fat: Vec::new(),
+        }
+    }
+
+    fn sector_size(&self) -> usize {
+        self.file.version.sector_size()
+    }
+
+    /// Append `data` as a chain of regular sectors returning the first sector
+    fn push_chain(&mut self, data: &[u8]) -> u32 {
+        if data.is_empty() {
+            return END_OF_CHAIN;
+        }
+        let sector_size = self.sector_size();
+        let start = self.fat.len() as u32;
+        let count = sector_count(data.len(), sector_size);
+        for i in 0..count {
+            let next = if i + 1 == count {
+                END_OF_CHAIN
+            } else {
+                start + i as u32 + 1
+            };
+            self.fat.push(next);
+        }
+        self.sectors.extend_from_slice(data);
+        self.sectors.resize(self.fat.len() * sector_size, 0);
+        start
+    }
+
+    /// Append `data` as a chain of mini sectors returning the first mini sector
+    fn push_mini_chain(&mut self, data: &[u8]) -> u32 {
+        if data.is_empty() {
+            return END_OF_CHAIN;
+        }
+        let start = self.mini_fat.len() as u32;
+        let count = sector_count(data.len(), MINI_SECTOR_SIZE);
+        for i in 0..count {
+            let next = if i + 1 == count {
+                END_OF_CHAIN
+            } else {
+                start + i as u32 + 1
+            };
+            self.mini_fat.push(next);
+        }
+        self.mini_stream.extend_from_slice(data);
+        self.mini_stream
+            .resize(self.mini_fat.len() * MINI_SECTOR_SIZE, 0);
+        start
+    }
+
+    /// Flatten the storage tree into directory entries
+    fn add_storage(&mut self, storage: &Storage, object_type: u8) -> io::Result<u32> {
+        let id = self.entries.len() as u32;
+        let mut raw = RawEntry::empty();
+        raw.name = storage.name.clone();
+        raw.object_type = object_type;
+        raw.clsid = storage.clsid;
+        raw.state_bits = storage.state_bits;
+        raw.created = storage.created;
+        raw.modified = storage.modified;
+        self.entries.push(raw);
+        self.colors.push(COLOR_BLACK);
+
+        let mut children = storage.entries.iter().collect::<Vec<_>>();
+        children.sort_by(|a, b| compare_names(a.name(), b.name()));
+        let mut ids = Vec::with_capacity(children.len());
+        for child in children {
+            let child_id = match child {
+                Entry::Storage(s) => self.add_storage(s, OBJECT_STORAGE)?,
+                Entry::Stream(s) => self.add_stream(s)?,
+            };
+            ids.push(child_id);
+        }
+        let depth = balanced_depth(ids.len());
+        self.entries[id as usize].child = self.link_siblings(&ids, 0, depth);
+        Ok(id)
+    }
+
+    fn add_stream(&mut self, stream: &Stream) -> io::Result<u32> {
+        if self.file.version == Version::V3 && stream.data.len() as u64 > u64::from(u32::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "version 3 compound files cannot hold streams larger than 4GB",
+            ));
+        }
+        let id = self.entries.len() as u32;
+        let mut raw = RawEntry::empty();
+        raw.name = stream.name.clone();
+        raw.object_type = OBJECT_STREAM;
+        raw.size = stream.data.len() as u64;
+        raw.start = if stream.data.len() < MINI_STREAM_CUTOFF {
+            self.push_mini_chain(&stream.data)
+        } else {
+            self.push_chain(&stream.data)
+        };
+        self.entries.push(raw);
+        self.colors.push(COLOR_BLACK);
+        Ok(id)
+    }
+
+    /// Build a balanced binary search tree out of the sorted `ids`
+    ///
+    /// Every level but the last is full, so coloring the nodes of an incomplete
+    /// last level red and all others black gives a valid red-black tree.
+    fn link_siblings(&mut self, ids: &[u32], level: usize, depth: usize) -> u32 {
+        if ids.is_empty() {
+            return NO_STREAM;
+        }
+        let middle = ids.len() / 2;
+        let id = ids[middle];
+        let left = self.link_siblings(&ids[..middle], level + 1, depth);
+        let right = self.link_siblings(&ids[middle + 1..], level + 1, depth);
+        let entry = &mut self.entries[id as usize];
+        entry.left = left;
+        entry.right = right;
+        self.colors[id as usize] = if level >= depth {
+            COLOR_RED
+        } else {
+            COLOR_BLACK
+        };
+        id
+    }
+
+    fn write(mut self) -> io::Result<Vec<u8>> {
+        let sector_size = self.sector_size();
+        let entries_per_sector = sector_size / 4;
+
+        let file = self.file;
+        self.add_storage(&file.root, OBJECT_ROOT)?;
+
+        let mini_stream = std::mem::take(&mut self.mini_stream);
+        self.entries[0].start = self.push_chain(&mini_stream);
+        self.entries[0].size = mini_stream.len() as u64;
+
+        let mut mini_fat = Vec::new();
+        for next in &self.mini_fat {
+            mini_fat.extend_from_slice(&next.to_le_bytes());
+        }
+        let mini_fat_sectors = sector_count(mini_fat.len(), sector_size);
+        mini_fat.resize(mini_fat_sectors * sector_size, 0xFF);
+        let mini_fat_start = self.push_chain(&mini_fat);
+
+        let mut directory = Vec::new();
+        for (entry, color) in self.entries.iter().zip(&self.colors) {
+            entry.write(*color, &mut directory);
+        }
+        while directory.len() % sector_size != 0 {
+            RawEntry::empty().write(COLOR_RED, &mut directory);
+        }
+        let directory_sectors = directory.len() / sector_size;
+        let directory_start = self.push_chain(&directory);
+
+        // The FAT has to describe its own sectors as well as the DIFAT sectors
+        let data_sectors = self.fat.len();
+        let mut fat_sectors = sector_count(data_sectors, entries_per_sector);
+        let difat_sectors = loop {
+            let difat_sectors = if fat_sectors > HEADER_DIFAT_ENTRIES {
+                (fat_sectors - HEADER_DIFAT_ENTRIES + entries_per_sector - 2)
+                    / (entries_per_sector - 1)
+            } else {
+                0
+            };
+            if fat_sectors * entries_per_sector >= data_sectors + fat_sectors + difat_sectors {
+                break difat_sectors;
+            }
+            fat_sectors += 1;
+        };
+        let fat_start = data_sectors as u32;
+        let difat_start = fat_start + fat_sectors as u32;
+        self.fat.resize(self.fat.len() + fat_sectors, FAT_SECTOR);
+        self.fat
+            .resize(self.fat.len() + difat_sectors, DIFAT_SECTOR);
+        self.fat
+            .resize(fat_sectors * entries_per_sector, FREE_SECTOR);
+
+        let fat_ids = (0..fat_sectors as u32)
+            .map(|i| fat_start + i)
+            .collect::<Vec<_>>();
+
+        let mut out = Vec::with_capacity(
+            sector_size.max(HEADER_SIZE)
+                + self.sectors.len()
+                + (fat_sectors + difat_sectors) * sector_size,
+        );
+        out.extend_from_slice(&SIGNATURE);
+        write_clsid(&mut out, &NULL_CLSID);
+        out.extend_from_slice(&MINOR_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.file.version.major().to_le_bytes());
+        out.extend_from_slice(&BYTE_ORDER_MARK.to_le_bytes());
+        out.extend_from_slice(&self.file.version.sector_shift().to_le_bytes());
+        out.extend_from_slice(&MINI_SECTOR_SHIFT.to_le_bytes());
+        out.extend_from_slice(&[0; 6]);
+        let directory_sector_count = match self.file.version {
+            Version::V3 => 0,
+            Version::V4 => directory_sectors as u32,
+        };
+        out.extend_from_slice(&directory_sector_count.to_le_bytes());
+        out.extend_from_slice(&(fat_sectors as u32).to_le_bytes());
+        out.extend_from_slice(&directory_start.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(MINI_STREAM_CUTOFF as u32).to_le_bytes());
+        out.extend_from_slice(&mini_fat_start.to_le_bytes());
+        out.extend_from_slice(&(mini_fat_sectors as u32).to_le_bytes());
+        let first_difat = if difat_sectors > 0 {
+            difat_start
+        } else {
+            END_OF_CHAIN
+        };
+        out.extend_from_slice(&first_difat.to_le_bytes());
+        out.extend_from_slice(&(difat_sectors as u32).to_le_bytes());
+        for i in 0..HEADER_DIFAT_ENTRIES {
+            let id = fat_ids.get(i).cloned().unwrap_or(FREE_SECTOR);
+            out.extend_from_slice(&id.to_le_bytes());
+        }
+        out.resize(sector_size.max(HEADER_SIZE), 0);
+
+        out.extend_from_slice(&self.sectors);
+        for next in &self.fat {
+            out.extend_from_slice(&next.to_le_bytes());
+        }
+        let remaining = fat_ids
+            .iter()
+            .skip(HEADER_DIFAT_ENTRIES)
+            .collect::<Vec<_>>();
+        for (index, chunk) in remaining.chunks(entries_per_sector - 1).enumerate() {
+            for i in 0..entries_per_sector - 1 {
+                let id = chunk.get(i).map(|id| **id).unwrap_or(FREE_SECTOR);
+                out.extend_from_slice(&id.to_le_bytes());
+            }
+            let next = if index + 1 == difat_sectors {
+                END_OF_CHAIN
+            } else {
+                difat_start + index as u32 + 1
+            };
+            out.extend_from_slice(&next.to_le_bytes());
+        }
+
+        Ok(out)
+    }
+}
+
+/// The number of sectors of `sector_size` needed to hold `len` items
+fn sector_count(len: usize, sector_size: usize) -> usize {
+    len / sector_size + if len % sector_size == 0 { 0 } else { 1 }
+}
+
+/// The depth of the last full level of a balanced tree with `count` nodes
+fn balanced_depth(count: usize) -> usize {
+    let mut depth = 0;
+    while (1usize << (depth + 1)) - 1 <= count {
+        depth += 1;
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(version: Version) -> CompoundFile {
+        let mut file = CompoundFile::new(version);
+        let root = file.root_mut();
+        root.create_stream("small")
+            .unwrap()
+            .data_mut()
+            .extend_from_slice(b"hello compound file");
+        root.create_stream("large")
+            .unwrap()
+            .data_mut()
+            .extend((0..20_000u32).map(|i| i as u8));
+        let nested = root.create_storage("nested").unwrap();
+        nested.set_state_bits(0x42);
+        nested.set_modified(0x01D5_0000_0000_0000);
+        nested.create_stream("empty").unwrap();
+        for i in 0..40 {
+            let name = format!("child {}", i);
+            let stream = nested.create_stream(&name).unwrap();
+            stream.data_mut().extend_from_slice(&vec![i as u8; i * 37]);
+        }
+        file
+    }
+
+    fn assert_storage_eq(a: &Storage, b: &Storage) {
+        assert_eq!(a.name(), b.name());
+        assert!(a.clsid() == b.clsid());
+        assert_eq!(a.state_bits(), b.state_bits());
+        assert_eq!(a.created(), b.created());
+        assert_eq!(a.modified(), b.modified());
+        assert_eq!(a.entries().count(), b.entries().count());
+        for entry in a.entries() {
+            match (entry, b.entry(entry.name())) {
+                (Entry::Storage(x), Some(Entry::Storage(y))) => assert_storage_eq(x, y),
+                (Entry::Stream(x), Some(Entry::Stream(y))) => assert_eq!(x.data(), y.data()),
+                _ => panic!("element '{}' did not round trip", entry.name()),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_v3() {
+        let file = sample(Version::V3);
+        let bytes = file.to_bytes().unwrap();
+        assert_eq!(bytes.len() % 512, 0);
+        let read = CompoundFile::from_bytes(&bytes).unwrap();
+        assert_eq!(read.version(), Version::V3);
+        assert_storage_eq(file.root(), read.root());
+        assert_eq!(read.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn round_trip_v4() {
+        let file = sample(Version::V4);
+        let bytes = file.to_bytes().unwrap();
+        assert_eq!(bytes.len() % 4096, 0);
+        let read = CompoundFile::from_bytes(&bytes).unwrap();
+        assert_eq!(read.version(), Version::V4);
+        assert_storage_eq(file.root(), read.root());
+    }
+
+    #[test]
+    fn round_trip_with_difat() {
+        // 110 FAT sectors at 512 bytes need more than the header's 109 DIFAT entries
+        let mut file = CompoundFile::new(Version::V3);
+        let data = (0..110 * 128 * 512)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        file.root_mut()
+            .create_stream("big")
+            .unwrap()
+            .data_mut()
+            .extend_from_slice(&data);
+        let bytes = file.to_bytes().unwrap();
+        assert!(read_u32(&bytes, 72) > 0, "expected DIFAT sectors");
+        let read = CompoundFile::from_bytes(&bytes).unwrap();
+        assert_eq!(read.root().stream("big").unwrap().data(), &data[..]);
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let mut file = CompoundFile::new(Version::V3);
+        file.root_mut().create_stream("Contents").unwrap();
+        assert!(file.root().stream("CONTENTS").is_some());
+        assert!(file.root_mut().create_storage("contents").is_err());
+        assert!(file.root_mut().create_stream("a/b").is_err());
+        assert!(file
+            .root_mut()
+            .create_stream("this name is far too long for a compound file")
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(CompoundFile::from_bytes(b"definitely not a compound file").is_err());
+        let mut bytes = sample(Version::V3).to_bytes().unwrap();
+        bytes.truncate(1024);
+        assert!(CompoundFile::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn reads_files_written_by_word() {
+        // A Word 97 document saved by Microsoft Word, taken from the test data of the
+        // `infer` crate (MIT licensed)
+        let bytes = include_bytes!("fixtures/word.doc");
+        let file = CompoundFile::from_bytes(bytes).unwrap();
+        assert_eq!(file.version(), Version::V3);
+
+        let root = file.root();
+        assert_eq!(root.name(), "Root Entry");
+        // CLSID_WordDocument {00020906-0000-0000-C000-000000000046}
+        assert_eq!(root.clsid().data1, 0x0002_0906);
+        let names = root.entries().map(Entry::name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "1Table",
+                "\u{1}CompObj",
+                "WordDocument",
+                "\u{5}SummaryInformation",
+                "\u{5}DocumentSummaryInformation",
+            ]
+        );
+
+        // The FIB starts with the Word magic number
+        let document = root.stream("WordDocument").unwrap();
+        assert_eq!(document.len(), 4096);
+        assert_eq!(read_u16(document.data(), 0), 0xA5EC);
+        // Streams below the mini stream cutoff are read from the mini stream
+        let comp_obj = root.stream("\u{1}CompObj").unwrap();
+        assert_eq!(comp_obj.len(), 114);
+        assert_eq!(read_u16(comp_obj.data(), 2), 0xFFFE);
+        assert_eq!(root.stream("1Table").unwrap().len(), 6733);
+        let summary = root.stream("\u{5}SummaryInformation").unwrap();
+        assert_eq!(read_u16(summary.data(), 0), 0xFFFE);
+
+        let read = CompoundFile::from_bytes(&file.to_bytes().unwrap()).unwrap();
+        assert_storage_eq(file.root(), read.root());
+    }
+
+    /// A reader for crafted directory entries without any sectors
+    fn crafted(entries: Vec<RawEntry>) -> FileReader<'static> {
+        FileReader {
+            data: &[],
+            version: Version::V3,
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            mini_stream: Vec::new(),
+            entries,
+        }
+    }
+
+    fn raw_entry(object_type: u8, right: u32, child: u32) -> RawEntry {
+        RawEntry {
+            name: String::new(),
+            object_type,
+            left: NO_STREAM,
+            right,
+            child,
+            clsid: NULL_CLSID,
+            state_bits: 0,
+            created: 0,
+            modified: 0,
+            start: END_OF_CHAIN,
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn reads_degenerate_sibling_trees() {
+        let count = 300_000;
+        let mut entries = vec![raw_entry(OBJECT_ROOT, NO_STREAM, 1)];
+        for id in 1..=count {
+            let right = if id == count { NO_STREAM } else { id + 1 };
+            entries.push(raw_entry(OBJECT_STREAM, right, NO_STREAM));
+        }
+        let file = crafted(entries).read().unwrap();
+        assert_eq!(file.root().entries().count(), count as usize);
+    }
+
+    #[test]
+    fn rejects_deeply_nested_storages() {
+        let depth = 100_000;
+        let mut entries = vec![raw_entry(OBJECT_ROOT, NO_STREAM, 1)];
+        for id in 1..=depth {
+            let child = if id == depth { NO_STREAM } else { id + 1 };
+            entries.push(raw_entry(OBJECT_STORAGE, NO_STREAM, child));
+        }
+        let error = crafted(entries).read().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,940 @@
+use super::compound_file::{names_equal, CompoundFile, Entry, Storage};
+use crate::interfaces::{IEnumSTATSTG, ISequentialStream, IStorage, IStream};
+use crate::sys::{
+    task_mem_alloc, CLSID, E_OUTOFMEMORY, FAILED, FILETIME, HRESULT, IID, STATFLAG_NONAME, STATSTG,
+    STGMOVE_COPY, STGMOVE_MOVE, STGM_CREATE, STGM_READWRITE, STGM_SHARE_EXCLUSIVE, STGM_WRITE,
+    STGTY_STORAGE, STGTY_STREAM, STG_E_ACCESSDENIED, STG_E_FILEALREADYEXISTS, STG_E_FILENOTFOUND,
+    STG_E_INVALIDFLAG, STG_E_INVALIDFUNCTION, STG_E_INVALIDNAME, STG_E_INVALIDPARAMETER,
+    STG_E_INVALIDPOINTER, STG_E_MEDIUMFULL, STG_E_REVERTED, STREAM_SEEK_CUR, STREAM_SEEK_END,
+    STREAM_SEEK_SET, S_FALSE, S_OK,
+};
+use crate::{class, AbiTransferable, Interface};
+
+use std::cell::{Cell, RefCell};
+use std::ffi::c_void;
+use std::io;
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+type SharedFile = Rc<RefCell<CompoundFile>>;
+
+/// The largest stream a version 3 compound file can hold
+///
+/// Streams are kept in memory, so writes beyond it fail with `STG_E_MEDIUMFULL` for any
+/// version instead of trying to allocate whatever size a client asks for.
+const MAX_STREAM_SIZE: u64 = 0x8000_0000;
+
+/// Expose a compound file to COM clients as a read/write `IStorage`
+///
+/// The returned storage and everything opened through it share `file` with
+/// the caller, so changes made through COM can be saved with
+/// [`CompoundFile::write_to`] once the calls return.
+pub fn root_storage(file: &Rc<RefCell<CompoundFile>>) -> IStorage {
+    let storage = StorageObject::allocate(
+        file.clone(),
+        Vec::new(),
+        STGM_READWRITE | STGM_SHARE_EXCLUSIVE,
+    );
+    storage.query::<IStorage>().unwrap()
+}
+
+class! {
+    /// An `IStorage` over a storage element of a shared [`CompoundFile`]
+    #[no_class_factory]
+    pub class StorageObject: IStorage {
+        file: SharedFile,
+        path: Vec<String>,
+        mode: u32,
+    }
+
+    impl IStorage for StorageObject {
+        unsafe fn create_stream(
+            &self,
+            pwcs_name: *const u16,
+            grf_mode: u32,
+            _reserved1: u32,
+            _reserved2: u32,
+            ppstm: *mut Option<IStream>,
+        ) -> HRESULT {
+            hresult(self.create_stream_object(pwcs_name, grf_mode, ppstm))
+        }
+
+        unsafe fn open_stream(
+            &self,
+            pwcs_name: *const u16,
+            _reserved1: *mut c_void,
+            grf_mode: u32,
+            _reserved2: u32,
+            ppstm: *mut Option<IStream>,
+        ) -> HRESULT {
+            hresult(self.open_stream_object(pwcs_name, grf_mode, ppstm))
+        }
+
+        unsafe fn create_storage(
+            &self,
+            pwcs_name: *const u16,
+            grf_mode: u32,
+            _reserved1: u32,
+            _reserved2: u32,
+            ppstg: *mut Option<IStorage>,
+        ) -> HRESULT {
+            hresult(self.create_storage_object(pwcs_name, grf_mode, ppstg))
+        }
+
+        unsafe fn open_storage(
+            &self,
+            pwcs_name: *const u16,
+            _pstg_priority: *mut NonNull<<IStorage as Interface>::VTable>,
+            grf_mode: u32,
+            _snb_exclude: *const *const u16,
+            _reserved: u32,
+            ppstg: *mut Option<IStorage>,
+        ) -> HRESULT {
+            hresult(self.open_storage_object(pwcs_name, grf_mode, ppstg))
+        }
+
+        unsafe fn copy_to(
+            &self,
+            ciid_exclude: u32,
+            rgiid_exclude: *const IID,
+            snb_exclude: *const *const u16,
+            pstg_dest: NonNull<NonNull<<IStorage as Interface>::VTable>>,
+        ) -> HRESULT {
+            hresult(self.copy_to_storage(ciid_exclude, rgiid_exclude, snb_exclude, IStorage::from_abi(&pstg_dest)))
+        }
+
+        unsafe fn move_element_to(
+            &self,
+            pwcs_name: *const u16,
+            pstg_dest: NonNull<NonNull<<IStorage as Interface>::VTable>>,
+            pwcs_new_name: *const u16,
+            grf_flags: u32,
+        ) -> HRESULT {
+            hresult(self.move_element(pwcs_name, IStorage::from_abi(&pstg_dest), pwcs_new_name, grf_flags))
+        }
+
+        unsafe fn commit(&self, _grf_commit_flags: u32) -> HRESULT {
+            // Changes are made directly to the shared file
+            S_OK
+        }
+
+        unsafe fn revert(&self) -> HRESULT {
+            // Transactions are not supported, so changes can not be discarded
+            STG_E_INVALIDFUNCTION
+        }
+
+        unsafe fn enum_elements(
+            &self,
+            _reserved1: u32,
+            _reserved2: *mut c_void,
+            _reserved3: u32,
+            ppenum: *mut Option<IEnumSTATSTG>,
+        ) -> HRESULT {
+            hresult(self.enumerate(ppenum))
+        }
+
+        unsafe fn destroy_element(&self, pwcs_name: *const u16) -> HRESULT {
+            hresult(self.destroy(pwcs_name))
+        }
+
+        unsafe fn rename_element(&self, pwcs_old_name: *const u16, pwcs_new_name: *const u16) -> HRESULT {
+            hresult(self.rename(pwcs_old_name, pwcs_new_name))
+        }
+
+        unsafe fn set_element_times(
+            &self,
+            pwcs_name: *const u16,
+            pctime: *const FILETIME,
+            _patime: *const FILETIME,
+            pmtime: *const FILETIME,
+        ) -> HRESULT {
+            hresult(self.set_times(pwcs_name, pctime, pmtime))
+        }
+
+        unsafe fn set_class(&self, clsid: *const CLSID) -> HRESULT {
+            if clsid.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            hresult(self.modify(|storage| {
+                storage.set_clsid(*clsid);
+                Ok(())
+            }))
+        }
+
+        unsafe fn set_state_bits(&self, grf_state_bits: u32, grf_mask: u32) -> HRESULT {
+            hresult(self.modify(|storage| {
+                let bits = (storage.state_bits() & !grf_mask) | (grf_state_bits & grf_mask);
+                storage.set_state_bits(bits);
+                Ok(())
+            }))
+        }
+
+        unsafe fn stat(&self, pstatstg: *mut STATSTG, grf_stat_flag: u32) -> HRESULT {
+            if pstatstg.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let file = self.file.borrow();
+            match resolve(file.root(), &self.path) {
+                Some(storage) => {
+                    *pstatstg = ElementStat::of_storage(storage).to_statstg(self.mode, grf_stat_flag);
+                    S_OK
+                }
+                None => STG_E_REVERTED,
+            }
+        }
+    }
+}
+
+class! {
+    /// An `IStream` over a stream element of a shared [`CompoundFile`]
+    #[no_class_factory]
+    pub class StreamObject: IStream(ISequentialStream) {
+        file: SharedFile,
+        path: Vec<String>,
+        name: String,
+        mode: u32,
+        position: Cell<u64>,
+    }
+
+    impl ISequentialStream for StreamObject {
+        unsafe fn read(&self, pv: *mut c_void, cb: u32, pcb_read: *mut u32) -> HRESULT {
+            if pv.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let read = match self.with_data(|data| {
+                let start = (self.position.get() as usize).min(data.len());
+                let count = (cb as usize).min(data.len() - start);
+                std::ptr::copy_nonoverlapping(data[start..].as_ptr(), pv as *mut u8, count);
+                Ok(count)
+            }) {
+                Ok(count) => count,
+                Err(hr) => return hr,
+            };
+            self.position.set(self.position.get() + read as u64);
+            if !pcb_read.is_null() {
+                *pcb_read = read as u32;
+            }
+            S_OK
+        }
+
+        unsafe fn write(&self, pv: *const c_void, cb: u32, pcb_written: *mut u32) -> HRESULT {
+            if pv.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let bytes = std::slice::from_raw_parts(pv as *const u8, cb as usize);
+            let hr = hresult(self.write_bytes(bytes));
+            if !pcb_written.is_null() {
+                *pcb_written = if FAILED(hr) { 0 } else { cb };
+            }
+            hr
+        }
+    }
+
+    impl IStream for StreamObject {
+        unsafe fn seek(&self, dlib_move: i64, dw_origin: u32, plib_new_position: *mut u64) -> HRESULT {
+            let base = match dw_origin {
+                STREAM_SEEK_SET => 0,
+                STREAM_SEEK_CUR => self.position.get() as i64,
+                STREAM_SEEK_END => match self.with_data(|data| Ok(data.len())) {
+                    Ok(len) => len as i64,
+                    Err(hr) => return hr,
+                },
+                _ => return STG_E_INVALIDFUNCTION,
+            };
+            let position = match base.checked_add(dlib_move) {
+                Some(position) if position >= 0 => position,
+                _ => return STG_E_INVALIDFUNCTION,
+            };
+            self.position.set(position as u64);
+            if !plib_new_position.is_null() {
+                *plib_new_position = position as u64;
+            }
+            S_OK
+        }
+
+        unsafe fn set_size(&self, lib_new_size: u64) -> HRESULT {
+            if !is_writable(self.mode) {
+                return STG_E_ACCESSDENIED;
+            }
+            if lib_new_size > MAX_STREAM_SIZE {
+                return STG_E_MEDIUMFULL;
+            }
+            hresult(self.with_data(|data| {
+                data.resize(lib_new_size as usize, 0);
+                Ok(())
+            }))
+        }
+
+        unsafe fn copy_to(
+            &self,
+            pstm: NonNull<NonNull<<IStream as Interface>::VTable>>,
+            cb: u64,
+            pcb_read: *mut u64,
+            pcb_written: *mut u64,
+        ) -> HRESULT {
+            // Copy out of the shared file first in case `pstm` lives in the same file
+            let bytes = match self.with_data(|data| {
+                let start = (self.position.get() as usize).min(data.len());
+                let count = (cb.min(u64::from(u32::MAX)) as usize).min(data.len() - start);
+                Ok(data[start..start + count].to_vec())
+            }) {
+                Ok(bytes) => bytes,
+                Err(hr) => return hr,
+            };
+            self.position.set(self.position.get() + bytes.len() as u64);
+            let mut written = 0u32;
+            let hr = IStream::from_abi(&pstm).write(
+                bytes.as_ptr() as *const c_void,
+                bytes.len() as u32,
+                &mut written,
+            );
+            if !pcb_read.is_null() {
+                *pcb_read = bytes.len() as u64;
+            }
+            if !pcb_written.is_null() {
+                *pcb_written = u64::from(written);
+            }
+            hr
+        }
+
+        unsafe fn commit(&self, _grf_commit_flags: u32) -> HRESULT {
+            S_OK
+        }
+
+        unsafe fn revert(&self) -> HRESULT {
+            STG_E_INVALIDFUNCTION
+        }
+
+        unsafe fn lock_region(&self, _lib_offset: u64, _cb: u64, _dw_lock_type: u32) -> HRESULT {
+            STG_E_INVALIDFUNCTION
+        }
+
+        unsafe fn unlock_region(&self, _lib_offset: u64, _cb: u64, _dw_lock_type: u32) -> HRESULT {
+            STG_E_INVALIDFUNCTION
+        }
+
+        unsafe fn stat(&self, pstatstg: *mut STATSTG, grf_stat_flag: u32) -> HRESULT {
+            if pstatstg.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let name = self.name.clone();
+            match self.with_data(|data| Ok(ElementStat::of_stream(name, data.len()))) {
+                Ok(stat) => {
+                    *pstatstg = stat.to_statstg(self.mode, grf_stat_flag);
+                    S_OK
+                }
+                Err(hr) => hr,
+            }
+        }
+
+        unsafe fn clone_stream(&self, ppstm: *mut Option<IStream>) -> HRESULT {
+            if ppstm.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let clone = StreamObject::allocate(
+                self.file.clone(),
+                self.path.clone(),
+                self.name.clone(),
+                self.mode,
+                Cell::new(self.position.get()),
+            );
+            *ppstm = clone.query::<IStream>();
+            S_OK
+        }
+    }
+}
+
+class! {
+    /// An `IEnumSTATSTG` over a snapshot of the elements of a storage
+    #[no_class_factory]
+    pub class StatStgEnumerator: IEnumSTATSTG {
+        elements: Rc<Vec<ElementStat>>,
+        position: Cell<usize>,
+        mode: u32,
+    }
+
+    impl IEnumSTATSTG for StatStgEnumerator {
+        unsafe fn next(&self, celt: u32, rgelt: *mut STATSTG, pcelt_fetched: *mut u32) -> HRESULT {
+            if rgelt.is_null() || (pcelt_fetched.is_null() && celt != 1) {
+                return STG_E_INVALIDPARAMETER;
+            }
+            let start = self.position.get();
+            let end = (start + celt as usize).min(self.elements.len());
+            for (i, element) in self.elements[start..end].iter().enumerate() {
+                *rgelt.add(i) = element.to_statstg(self.mode, 0);
+            }
+            self.position.set(end);
+            let fetched = (end - start) as u32;
+            if !pcelt_fetched.is_null() {
+                *pcelt_fetched = fetched;
+            }
+            if fetched == celt { S_OK } else { S_FALSE }
+        }
+
+        unsafe fn skip(&self, celt: u32) -> HRESULT {
+            let position = self.position.get() + celt as usize;
+            self.position.set(position.min(self.elements.len()));
+            if position <= self.elements.len() { S_OK } else { S_FALSE }
+        }
+
+        unsafe fn reset(&self) -> HRESULT {
+            self.position.set(0);
+            S_OK
+        }
+
+        unsafe fn clone_enum(&self, ppenum: *mut Option<IEnumSTATSTG>) -> HRESULT {
+            if ppenum.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let clone = StatStgEnumerator::allocate(
+                self.elements.clone(),
+                Cell::new(self.position.get()),
+                self.mode,
+            );
+            *ppenum = clone.query::<IEnumSTATSTG>();
+            S_OK
+        }
+    }
+}
+
+impl StorageObject {
+    /// Run `f` on the storage this object refers to
+    fn modify<R>(&self, f: impl FnOnce(&mut Storage) -> Result<R, HRESULT>) -> Result<R, HRESULT> {
+        if !is_writable(self.mode) {
+            return Err(STG_E_ACCESSDENIED);
+        }
+        let mut file = self.file.borrow_mut();
+        match resolve_mut(file.root_mut(), &self.path) {
+            Some(storage) => f(storage),
+            None => Err(STG_E_REVERTED),
+        }
+    }
+
+    fn child_path(&self, name: String) -> Vec<String> {
+        let mut path = self.path.clone();
+        path.push(name);
+        path
+    }
+
+    unsafe fn create_stream_object(
+        &self,
+        name: *const u16,
+        mode: u32,
+        out: *mut Option<IStream>,
+    ) -> Result<(), HRESULT> {
+        let out = out.as_mut().ok_or(STG_E_INVALIDPOINTER)?;
+        *out = None;
+        let name = read_name(name)?;
+        self.modify(|storage| {
+            if storage.entry(&name).is_some() {
+                if mode & STGM_CREATE == 0 {
+                    return Err(STG_E_FILEALREADYEXISTS);
+                }
+                storage.remove(&name);
+            }
+            storage.create_stream(&name).map_err(storage_error)?;
+            Ok(())
+        })?;
+        let stream = StreamObject::allocate(
+            self.file.clone(),
+            self.path.clone(),
+            name,
+            mode,
+            Cell::new(0),
+        );
+        *out = stream.query::<IStream>();
+        Ok(())
+    }
+
+    unsafe fn open_stream_object(
+        &self,
+        name: *const u16,
+        mode: u32,
+        out: *mut Option<IStream>,
+    ) -> Result<(), HRESULT> {
+        let out = out.as_mut().ok_or(STG_E_INVALIDPOINTER)?;
+        *out = None;
+        let name = read_name(name)?;
+        {
+            let file = self.file.borrow();
+            let storage = resolve(file.root(), &self.path).ok_or(STG_E_REVERTED)?;
+            storage.stream(&name).ok_or(STG_E_FILENOTFOUND)?;
+        }
+        let stream = StreamObject::allocate(
+            self.file.clone(),
+            self.path.clone(),
+            name,
+            mode,
+            Cell::new(0),
+        );
+        *out = stream.query::<IStream>();
+        Ok(())
+    }
+
+    unsafe fn create_storage_object(
+        &self,
+        name: *const u16,
+        mode: u32,
+        out: *mut Option<IStorage>,
+    ) -> Result<(), HRESULT> {
+        let out = out.as_mut().ok_or(STG_E_INVALIDPOINTER)?;
+        *out = None;
+        let name = read_name(name)?;
+        self.modify(|storage| {
+            if storage.entry(&name).is_some() {
+                if mode & STGM_CREATE == 0 {
+                    return Err(STG_E_FILEALREADYEXISTS);
+                }
+                storage.remove(&name);
+            }
+            storage.create_storage(&name).map_err(storage_error)?;
+            Ok(())
+        })?;
+        let storage = StorageObject::allocate(self.file.clone(), self.child_path(name), mode);
+        *out = storage.query::<IStorage>();
+        Ok(())
+    }
+
+    unsafe fn open_storage_object(
+        &self,
+        name: *const u16,
+        mode: u32,
+        out: *mut Option<IStorage>,
+    ) -> Result<(), HRESULT> {
+        let out = out.as_mut().ok_or(STG_E_INVALIDPOINTER)?;
+        *out = None;
+        let name = read_name(name)?;
+        {
+            let file = self.file.borrow();
+            let storage = resolve(file.root(), &self.path).ok_or(STG_E_REVERTED)?;
+            storage.storage(&name).ok_or(STG_E_FILENOTFOUND)?;
+        }
+        let storage = StorageObject::allocate(self.file.clone(), self.child_path(name), mode);
+        *out = storage.query::<IStorage>();
+        Ok(())
+    }
+
+    unsafe fn copy_to_storage(
+        &self,
+        iid_count: u32,
+        iids: *const IID,
+        exclude: *const *const u16,
+        dest: &IStorage,
+    ) -> Result<(), HRESULT> {
+        let iids = if iid_count == 0 {
+            &[]
+        } else if iids.is_null() {
+            return Err(STG_E_INVALIDPOINTER);
+        } else {
+            std::slice::from_raw_parts(iids, iid_count as usize)
+        };
+        let skip_streams = iids.iter().any(|iid| iid == &IStream::IID);
+        let skip_storages = iids.iter().any(|iid| iid == &IStorage::IID);
+        let mut excluded = Vec::new();
+        if !exclude.is_null() {
+            let mut current = exclude;
+            while !(*current).is_null() {
+                excluded.push(read_name(*current)?);
+                current = current.add(1);
+            }
+        }
+
+        // Take a snapshot so that copying into this same file can't alias the borrow
+        let source = {
+            let file = self.file.borrow();
+            resolve(file.root(), &self.path)
+                .ok_or(STG_E_REVERTED)?
+                .clone()
+        };
+        for entry in source.entries() {
+            let skipped = match entry {
+                Entry::Stream(_) => skip_streams,
+                Entry::Storage(_) => skip_storages,
+            };
+            if skipped || excluded.iter().any(|e| names_equal(e, entry.name())) {
+                continue;
+            }
+            copy_entry(entry, entry.name(), dest)?;
+        }
+        Ok(())
+    }
+
+    unsafe fn move_element(
+        &self,
+        name: *const u16,
+        dest: &IStorage,
+        new_name: *const u16,
+        flags: u32,
+    ) -> Result<(), HRESULT> {
+        if flags != STGMOVE_MOVE && flags != STGMOVE_COPY {
+            return Err(STG_E_INVALIDFLAG);
+        }
+        let name = read_name(name)?;
+        let new_name = read_name(new_name)?;
+        let entry = {
+            let file = self.file.borrow();
+            let storage = resolve(file.root(), &self.path).ok_or(STG_E_REVERTED)?;
+            storage.entry(&name).ok_or(STG_E_FILENOTFOUND)?.clone()
+        };
+        copy_entry(&entry, &new_name, dest)?;
+        if flags == STGMOVE_MOVE {
+            self.modify(|storage| {
+                storage.remove(&name);
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    unsafe fn enumerate(&self, out: *mut Option<IEnumSTATSTG>) -> Result<(), HRESULT> {
+        let out = out.as_mut().ok_or(STG_E_INVALIDPOINTER)?;
+        let elements = {
+            let file = self.file.borrow();
+            let storage = resolve(file.root(), &self.path).ok_or(STG_E_REVERTED)?;
+            storage
+                .entries()
+                .map(|entry| match entry {
+                    Entry::Storage(s) => ElementStat::of_storage(s),
+                    Entry::Stream(s) => ElementStat::of_stream(s.name().to_owned(), s.len()),
+                })
+                .collect::<Vec<_>>()
+        };
+        let enumerator = StatStgEnumerator::allocate(Rc::new(elements), Cell::new(0), self.mode);
+        *out = enumerator.query::<IEnumSTATSTG>();
+        Ok(())
+    }
+
+    unsafe fn destroy(&self, name: *const u16) -> Result<(), HRESULT> {
+        let name = read_name(name)?;
+        self.modify(|storage| storage.remove(&name).map(|_| ()).ok_or(STG_E_FILENOTFOUND))
+    }
+
+    unsafe fn rename(&self, old_name: *const u16, new_name: *const u16) -> Result<(), HRESULT> {
+        let old_name = read_name(old_name)?;
+        let new_name = read_name(new_name)?;
+        self.modify(|storage| storage.rename(&old_name, &new_name).map_err(storage_error))
+    }
+
+    unsafe fn set_times(
+        &self,
+        name: *const u16,
+        created: *const FILETIME,
+        modified: *const FILETIME,
+    ) -> Result<(), HRESULT> {
+        let name = if name.is_null() {
+            None
+        } else {
+            Some(read_name(name)?)
+        };
+        self.modify(|storage| {
+            let target = match &name {
+                None => storage,
+                Some(name) => match storage.entry_mut(name) {
+                    Some(Entry::Storage(s)) => s,
+                    // Streams don't carry timestamps in a compound file
+                    Some(Entry::Stream(_)) => return Ok(()),
+                    None => return Err(STG_E_FILENOTFOUND),
+                },
+            };
+            if let Some(created) = created.as_ref() {
+                target.set_created(created.to_u64());
+            }
+            if let Some(modified) = modified.as_ref() {
+                target.set_modified(modified.to_u64());
+            }
+            Ok(())
+        })
+    }
+}
+
+impl StreamObject {
+    /// Run `f` on the contents of the stream this object refers to
+    fn with_data<R>(
+        &self,
+        f: impl FnOnce(&mut Vec<u8>) -> Result<R, HRESULT>,
+    ) -> Result<R, HRESULT> {
+        let mut file = self.file.borrow_mut();
+        let stream = resolve_mut(file.root_mut(), &self.path)
+            .and_then(|storage| storage.stream_mut(&self.name))
+            .ok_or(STG_E_REVERTED)?;
+        f(stream.data_mut())
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) -> Result<(), HRESULT> {
+        if !is_writable(self.mode) {
+            return Err(STG_E_ACCESSDENIED);
+        }
+        let position = self.position.get();
+        let end = position
+            .checked_add(bytes.len() as u64)
+            .filter(|&end| end <= MAX_STREAM_SIZE)
+            .ok_or(STG_E_MEDIUMFULL)?;
+        let (position, end) = (position as usize, end as usize);
+        self.with_data(|data| {
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[position..end].copy_from_slice(bytes);
+            Ok(())
+        })?;
+        self.position.set(end as u64);
+        Ok(())
+    }
+}
+
+/// The statistics of an element, owned so they can outlive the borrow of the file
+#[derive(Clone)]
+pub struct ElementStat {
+    name: String,
+    kind: u32,
+    size: u64,
+    clsid: CLSID,
+    state_bits: u32,
+    created: u64,
+    modified: u64,
+}
+
+impl ElementStat {
+    fn of_storage(storage: &Storage) -> ElementStat {
+        ElementStat {
+            name: storage.name().to_owned(),
+            kind: STGTY_STORAGE,
+            size: 0,
+            clsid: storage.clsid(),
+            state_bits: storage.state_bits(),
+            created: storage.created(),
+            modified: storage.modified(),
+        }
+    }
+
+    fn of_stream(name: String, size: usize) -> ElementStat {
+        ElementStat {
+            name,
+            kind: STGTY_STREAM,
+            size: size as u64,
+            clsid: CLSID {
+                data1: 0,
+                data2: 0,
+                data3: 0,
+                data4: [0; 8],
+            },
+            state_bits: 0,
+            created: 0,
+            modified: 0,
+        }
+    }
+
+    /// Fill in a `STATSTG`, allocating the name with `CoTaskMemAlloc` unless
+    /// `STATFLAG_NONAME` is set
+    fn to_statstg(&self, mode: u32, flags: u32) -> STATSTG {
+        let name = if flags & STATFLAG_NONAME == 0 {
+            task_alloc_name(&self.name)
+        } else {
+            std::ptr::null_mut()
+        };
+        STATSTG {
+            pwcsName: name,
+            r#type: self.kind,
+            cbSize: self.size,
+            mtime: FILETIME::from_u64(self.modified),
+            ctime: FILETIME::from_u64(self.created),
+            atime: FILETIME::default(),
+            grfMode: mode,
+            grfLocksSupported: 0,
+            clsid: self.clsid,
+            grfStateBits: self.state_bits,
+            reserved: 0,
+        }
+    }
+}
+
+fn hresult(result: Result<(), HRESULT>) -> HRESULT {
+    match result {
+        Ok(()) => S_OK,
+        Err(hr) => hr,
+    }
+}
+
+fn is_writable(mode: u32) -> bool {
+    mode & (STGM_WRITE | STGM_READWRITE) != 0
+}
+
+fn storage_error(error: io::Error) -> HRESULT {
+    match error.kind() {
+        io::ErrorKind::AlreadyExists => STG_E_FILEALREADYEXISTS,
+        io::ErrorKind::NotFound => STG_E_FILENOTFOUND,
+        io::ErrorKind::InvalidInput => STG_E_INVALIDNAME,
+        _ => STG_E_INVALIDFUNCTION,
+    }
+}
+
+fn resolve<'a>(root: &'a Storage, path: &[String]) -> Option<&'a Storage> {
+    path.iter()
+        .try_fold(root, |storage, name| storage.storage(name))
+}
+
+fn resolve_mut<'a>(root: &'a mut Storage, path: &[String]) -> Option<&'a mut Storage> {
+    let mut current = root;
+    for name in path {
+        current = current.storage_mut(name)?;
+    }
+    Some(current)
+}
+
+/// Read a null terminated UTF-16 element name
+unsafe fn read_name(name: *const u16) -> Result<String, HRESULT> {
+    if name.is_null() {
+        return Err(STG_E_INVALIDPOINTER);
+    }
+    let mut len = 0;
+    while *name.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16(std::slice::from_raw_parts(name, len)).map_err(|_| STG_E_INVALIDNAME)
+}
+
+fn wide_name(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+fn task_alloc_name(name: &str) -> *mut u16 {
+    let wide = wide_name(name);
+    unsafe {
+        let ptr = task_mem_alloc(wide.len() * std::mem::size_of::<u16>()) as *mut u16;
+        if !ptr.is_null() {
+            std::ptr::copy_nonoverlapping(wide.as_ptr(), ptr, wide.len());
+        }
+        ptr
+    }
+}
+
+/// Copy `entry` into `dest` as `name` through `dest`'s COM interface
+unsafe fn copy_entry(entry: &Entry, name: &str, dest: &IStorage) -> Result<(), HRESULT> {
+    let name = wide_name(name);
+    let mode = STGM_CREATE | STGM_WRITE | STGM_SHARE_EXCLUSIVE;
+    match entry {
+        Entry::Stream(stream) => {
+            let mut out = None::<IStream>;
+            check(dest.create_stream(name.as_ptr(), mode, 0u32, 0u32, &mut out))?;
+            let out = out.ok_or(E_OUTOFMEMORY)?;
+            for chunk in stream.data().chunks(u32::MAX as usize) {
+                let mut written = 0u32;
+                check(out.write(
+                    chunk.as_ptr() as *const c_void,
+                    chunk.len() as u32,
+                    &mut written,
+                ))?;
+            }
+            Ok(())
+        }
+        Entry::Storage(storage) => {
+            let mut out = None::<IStorage>;
+            check(dest.create_storage(name.as_ptr(), mode, 0u32, 0u32, &mut out))?;
+            let out = out.ok_or(E_OUTOFMEMORY)?;
+            let clsid = storage.clsid();
+            check(out.set_class(&clsid as *const CLSID))?;
+            check(out.set_state_bits(storage.state_bits(), u32::MAX))?;
+            for child in storage.entries() {
+                copy_entry(child, child.name(), &out)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn check(hr: HRESULT) -> Result<(), HRESULT> {
+    if FAILED(hr) {
+        Err(hr)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Version;
+
+    #[test]
+    fn streams_written_through_istorage_round_trip() {
+        let file = Rc::new(RefCell::new(CompoundFile::new(Version::V3)));
+        let root = root_storage(&file);
+        let name = wide_name("Contents");
+        let payload = b"written through IStream";
+        unsafe {
+            let mut storage = None::<IStorage>;
+            let hr = root.create_storage(
+                wide_name("Nested").as_ptr(),
+                STGM_READWRITE | STGM_SHARE_EXCLUSIVE,
+                0u32,
+                0u32,
+                &mut storage,
+            );
+            assert_eq!(hr, S_OK);
+            let storage = storage.unwrap();
+
+            let mut stream = None::<IStream>;
+            let hr = storage.create_stream(
+                name.as_ptr(),
+                STGM_READWRITE | STGM_SHARE_EXCLUSIVE,
+                0u32,
+                0u32,
+                &mut stream,
+            );
+            assert_eq!(hr, S_OK);
+            let stream = stream.unwrap();
+            let mut written = 0u32;
+            let hr = stream.write(
+                payload.as_ptr() as *const c_void,
+                payload.len() as u32,
+                &mut written,
+            );
+            assert_eq!(hr, S_OK);
+            assert_eq!(written as usize, payload.len());
+
+            let mut position = 1u64;
+            assert_eq!(stream.seek(0i64, STREAM_SEEK_SET, &mut position), S_OK);
+            assert_eq!(position, 0);
+            let mut buffer = [0u8; 64];
+            let mut read = 0u32;
+            let hr = stream.read(
+                buffer.as_mut_ptr() as *mut c_void,
+                buffer.len() as u32,
+                &mut read,
+            );
+            assert_eq!(hr, S_OK);
+            assert_eq!(&buffer[..read as usize], &payload[..]);
+
+            assert_eq!(stream.set_size(MAX_STREAM_SIZE + 1), STG_E_MEDIUMFULL);
+            assert_eq!(
+                stream.seek(i64::MAX, STREAM_SEEK_CUR, std::ptr::null_mut()),
+                STG_E_INVALIDFUNCTION
+            );
+            assert_eq!(
+                stream.seek(
+                    MAX_STREAM_SIZE as i64,
+                    STREAM_SEEK_SET,
+                    std::ptr::null_mut()
+                ),
+                S_OK
+            );
+            let hr = stream.write(payload.as_ptr() as *const c_void, 1, &mut written);
+            assert_eq!(hr, STG_E_MEDIUMFULL);
+            assert_eq!(written, 0);
+            assert_eq!(stream.revert(), STG_E_INVALIDFUNCTION);
+
+            let mut duplicate = None::<IStream>;
+            let hr = storage.create_stream(name.as_ptr(), STGM_WRITE, 0u32, 0u32, &mut duplicate);
+            assert_eq!(hr, STG_E_FILEALREADYEXISTS);
+        }
+
+        let bytes = file.borrow().to_bytes().unwrap();
+        let read = CompoundFile::from_bytes(&bytes).unwrap();
+        let stream = read
+            .root()
+            .storage("nested")
+            .and_then(|s| s.stream("contents"))
+            .unwrap();
+        assert_eq!(stream.data(), &payload[..]);
+    }
+}
@@ -0,0 +1,383 @@
+//! Wide strings as they are passed to and from COM methods
+//!
+//! [`PCWSTR`] and [`PWSTR`] are borrowed pointers to null terminated UTF-16 strings,
+//! [`WideString`] owns one converted from a Rust string and [`TaskString`] owns one
+//! allocated with `CoTaskMemAlloc`, which is how COM methods return strings to their callers.
+//!
+//! Arguments declared as `PCWSTR` in [`interfaces!`](crate::interfaces) accept `&str` and
+//! `&OsStr` directly. [`Param`] converts them into a buffer that lives until the call
+//! returns:
+//!
+//! ```rust,no_run
+//! # use com::interfaces::{IBindCtx, IUnknown};
+//! # fn lookup(bind_ctx: &IBindCtx) {
+//! let mut object = None::<IUnknown>;
+//! unsafe { bind_ctx.get_object_param("ExistingObject", &mut object) };
+//! # }
+//! ```
+//!
+//! A string containing a null is cut short at it by these conversions, as the callee could
+//! not see past it anyway. Convert it with [`WideString::new`] first to reject it instead.
+use crate::sys::{task_mem_alloc, task_mem_free, E_INVALIDARG, E_OUTOFMEMORY, HRESULT};
+use crate::{AbiTransferable, Param};
+
+use std::ffi::{OsStr, OsString};
+use std::string::FromUtf16Error;
+
+/// The length of the null terminated string at `ptr`
+unsafe fn wide_len(ptr: *const u16) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+    (0..).take_while(|&i| *ptr.add(i) != 0).count()
+}
+
+/// The null terminated UTF-16 encoding of `s`
+///
+/// Strings containing nulls are rejected as they would be cut short.
+fn wide(s: &str) -> Result<Vec<u16>, HRESULT> {
+    terminate(s.encode_utf16())
+}
+
+fn wide_os(s: &OsStr) -> Result<Vec<u16>, HRESULT> {
+    terminate(encode_os(s).into_iter())
+}
+
+#[cfg(windows)]
+fn encode_os(s: &OsStr) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    s.encode_wide().collect()
+}
+
+/// Strings that are not valid Unicode are converted lossily outside of Windows
+#[cfg(not(windows))]
+fn encode_os(s: &OsStr) -> Vec<u16> {
+    s.to_string_lossy().encode_utf16().collect()
+}
+
+fn terminate<I: Iterator<Item = u16>>(units: I) -> Result<Vec<u16>, HRESULT> {
+    let mut buffer = units.collect::<Vec<_>>();
+    if buffer.contains(&0) {
+        return Err(E_INVALIDARG);
+    }
+    buffer.push(0);
+    Ok(buffer)
+}
+
+/// Null terminate `units` at the first null
+fn truncate<I: Iterator<Item = u16>>(units: I) -> Vec<u16> {
+    units.take_while(|&u| u != 0).chain(Some(0)).collect()
+}
+
+macro_rules! wide_string_methods {
+    () => {
+        /// Whether the pointer is null
+        pub fn is_null(&self) -> bool {
+            self.0.is_null()
+        }
+
+        /// The UTF-16 code units of the string without the terminating null
+        ///
+        /// # Safety
+        ///
+        /// The pointer must be null or point to a null terminated string that outlives `'a`.
+        pub unsafe fn as_wide<'a>(&self) -> &'a [u16] {
+            match wide_len(self.0) {
+                0 => &[],
+                len => std::slice::from_raw_parts(self.0, len),
+            }
+        }
+
+        /// Decode the string
+        ///
+        /// # Safety
+        ///
+        /// The pointer must be null or point to a null terminated string.
+        pub unsafe fn to_string(&self) -> Result<String, FromUtf16Error> {
+            String::from_utf16(self.as_wide())
+        }
+
+        /// Copy the string into an `OsString`, which keeps invalid UTF-16 on Windows
+        ///
+        /// # Safety
+        ///
+        /// The pointer must be null or point to a null terminated string.
+        pub unsafe fn to_os_string(&self) -> OsString {
+            #[cfg(windows)]
+            {
+                use std::os::windows::ffi::OsStringExt;
+                OsString::from_wide(self.as_wide())
+            }
+            #[cfg(not(windows))]
+            {
+                OsString::from(String::from_utf16_lossy(self.as_wide()))
+            }
+        }
+    };
+}
+
+/// A pointer to a constant null terminated UTF-16 string, `LPCWSTR` in the Windows headers
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PCWSTR(pub *const u16);
+
+impl PCWSTR {
+    /// The null pointer
+    pub fn null() -> PCWSTR {
+        PCWSTR(std::ptr::null())
+    }
+
+    wide_string_methods!();
+}
+
+impl Default for PCWSTR {
+    fn default() -> PCWSTR {
+        PCWSTR::null()
+    }
+}
+
+unsafe impl AbiTransferable for PCWSTR {
+    type Abi = *const u16;
+    fn get_abi(&self) -> Self::Abi {
+        self.0
+    }
+    fn set_abi(&mut self) -> *mut Self::Abi {
+        &mut self.0
+    }
+}
+
+/// A pointer to a mutable null terminated UTF-16 string, `LPWSTR` in the Windows headers
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PWSTR(pub *mut u16);
+
+impl PWSTR {
+    /// The null pointer
+    pub fn null() -> PWSTR {
+        PWSTR(std::ptr::null_mut())
+    }
+
+    wide_string_methods!();
+}
+
+impl Default for PWSTR {
+    fn default() -> PWSTR {
+        PWSTR::null()
+    }
+}
+
+unsafe impl AbiTransferable for PWSTR {
+    type Abi = *mut u16;
+    fn get_abi(&self) -> Self::Abi {
+        self.0
+    }
+    fn set_abi(&mut self) -> *mut Self::Abi {
+        &mut self.0
+    }
+}
+
+/// A null terminated UTF-16 string converted from a Rust string
+///
+/// The string owns its buffer, and the `PCWSTR` borrowed from it stays valid while the
+/// string is alive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WideString(Vec<u16>);
+
+impl WideString {
+    /// Convert `s`, failing with `E_INVALIDARG` if it contains a null
+    pub fn new(s: &str) -> Result<WideString, HRESULT> {
+        wide(s).map(WideString)
+    }
+
+    /// Convert `s`, failing with `E_INVALIDARG` if it contains a null
+    ///
+    /// On Windows the string is kept as is even if it is not valid Unicode.
+    pub fn from_os_str(s: &OsStr) -> Result<WideString, HRESULT> {
+        wide_os(s).map(WideString)
+    }
+
+    /// Borrow the string as a `PCWSTR`
+    pub fn as_pcwstr(&self) -> PCWSTR {
+        PCWSTR(self.0.as_ptr())
+    }
+
+    /// The UTF-16 code units of the string without the terminating null
+    pub fn as_wide(&self) -> &[u16] {
+        &self.0[..self.0.len() - 1]
+    }
+}
+
+/// A null terminated UTF-16 string allocated with `CoTaskMemAlloc`
+///
+/// Methods returning strings through a `*mut TaskString` out-param hand ownership to the
+/// caller, and the string is freed with `CoTaskMemFree` when dropped. The default value
+/// is the null pointer, which is what such out-params start as.
+///
+/// Outside of Windows the string is allocated with [`task_mem_alloc`] instead.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct TaskString(*mut u16);
+
+impl TaskString {
+    /// Allocate a copy of `s`, failing with `E_INVALIDARG` if it contains a null
+    pub fn new(s: &str) -> Result<TaskString, HRESULT> {
+        let s = wide(s)?;
+        let ptr = unsafe { task_mem_alloc(s.len() * 2) } as *mut u16;
+        if ptr.is_null() {
+            return Err(E_OUTOFMEMORY);
+        }
+        unsafe { std::ptr::copy_nonoverlapping(s.as_ptr(), ptr, s.len()) };
+        Ok(TaskString(ptr))
+    }
+
+    /// Take ownership of a string allocated with `CoTaskMemAlloc`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or a null terminated string allocated with `CoTaskMemAlloc` (or
+    /// [`task_mem_alloc`]) that nothing else frees.
+    pub unsafe fn from_raw(ptr: *mut u16) -> TaskString {
+        TaskString(ptr)
+    }
+
+    /// Give up ownership of the string, e.g. to return it from a COM method
+    pub fn into_raw(self) -> *mut u16 {
+        let ptr = self.0;
+        std::mem::forget(self);
+        ptr
+    }
+
+    /// Borrow the string as a `PCWSTR`
+    pub fn as_pcwstr(&self) -> PCWSTR {
+        PCWSTR(self.0)
+    }
+
+    wide_string_methods!();
+}
+
+impl Default for TaskString {
+    fn default() -> TaskString {
+        TaskString(std::ptr::null_mut())
+    }
+}
+
+impl Drop for TaskString {
+    fn drop(&mut self) {
+        if !self.is_null() {
+            unsafe { task_mem_free(self.0 as *mut _) };
+        }
+    }
+}
+
+unsafe impl AbiTransferable for TaskString {
+    type Abi = *mut u16;
+    fn get_abi(&self) -> Self::Abi {
+        self.0
+    }
+    fn set_abi(&mut self) -> *mut Self::Abi {
+        &mut self.0
+    }
+}
+
+impl<'a> Param<'a, PCWSTR> {
+    fn converted(buffer: Vec<u16>) -> Param<'a, PCWSTR> {
+        // Moving the vector does not move its heap buffer, so the pointer stays valid
+        Param::Converted(PCWSTR(buffer.as_ptr()), buffer)
+    }
+}
+
+/// Converts the string up to its first null
+impl<'a> From<&'a str> for Param<'a, PCWSTR> {
+    fn from(value: &'a str) -> Param<'a, PCWSTR> {
+        Param::converted(truncate(value.encode_utf16()))
+    }
+}
+
+/// Converts the string up to its first null
+impl<'a> From<&'a String> for Param<'a, PCWSTR> {
+    fn from(value: &'a String) -> Param<'a, PCWSTR> {
+        Param::from(value.as_str())
+    }
+}
+
+/// Converts the string up to its first null
+impl<'a> From<&'a OsStr> for Param<'a, PCWSTR> {
+    fn from(value: &'a OsStr) -> Param<'a, PCWSTR> {
+        Param::converted(truncate(encode_os(value).into_iter()))
+    }
+}
+
+/// Converts the string up to its first null
+impl<'a> From<&'a OsString> for Param<'a, PCWSTR> {
+    fn from(value: &'a OsString) -> Param<'a, PCWSTR> {
+        Param::from(value.as_os_str())
+    }
+}
+
+impl<'a> From<&'a WideString> for Param<'a, PCWSTR> {
+    fn from(value: &'a WideString) -> Param<'a, PCWSTR> {
+        Param::Owned(value.as_pcwstr())
+    }
+}
+
+impl<'a> From<&'a TaskString> for Param<'a, PCWSTR> {
+    fn from(value: &'a TaskString) -> Param<'a, PCWSTR> {
+        Param::Owned(value.as_pcwstr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_converted_for_the_call() {
+        let hello = WideString::new("héllo").unwrap();
+        let mut param = Param::<PCWSTR>::from(&hello);
+        let abi = param.get_abi();
+        assert_eq!(unsafe { PCWSTR(abi).to_string() }.unwrap(), "héllo");
+        assert_eq!(unsafe { *abi.add(5) }, 0);
+        assert_eq!(hello.as_wide().len(), 5);
+
+        let path = WideString::from_os_str(OsStr::new("path")).unwrap();
+        let mut param = Param::<PCWSTR>::from(&path);
+        let abi = param.get_abi();
+        assert_eq!(
+            unsafe { PCWSTR(abi).to_os_string() },
+            OsString::from("path")
+        );
+        assert!(unsafe { PCWSTR::null().as_wide() }.is_empty());
+    }
+
+    #[test]
+    fn rust_strings_are_converted_by_the_param() {
+        let hello = String::from("héllo");
+        let mut param = Param::<PCWSTR>::from(&hello);
+        let abi = param.get_abi();
+        assert_eq!(unsafe { PCWSTR(abi).to_string() }.unwrap(), "héllo");
+
+        let mut param = Param::<PCWSTR>::from(OsStr::new("path"));
+        let abi = param.get_abi();
+        assert_eq!(
+            unsafe { PCWSTR(abi).to_os_string() },
+            OsString::from("path")
+        );
+    }
+
+    #[test]
+    fn params_are_cut_short_at_nulls() {
+        let mut param = Param::<PCWSTR>::from("key\0suffix");
+        let abi = param.get_abi();
+        assert_eq!(unsafe { PCWSTR(abi).as_wide() }, &[0x6B, 0x65, 0x79]);
+        let null = OsString::from("\0");
+        let mut param = Param::<PCWSTR>::from(&null);
+        assert!(unsafe { PCWSTR(param.get_abi()).as_wide() }.is_empty());
+    }
+
+    #[test]
+    fn strings_with_nulls_are_rejected() {
+        assert_eq!(WideString::new("key\0suffix"), Err(E_INVALIDARG));
+        assert_eq!(WideString::from_os_str(OsStr::new("\0")), Err(E_INVALIDARG));
+        assert_eq!(TaskString::new("a\0b").err(), Some(E_INVALIDARG));
+    }
+}
@@ -16,6 +16,8 @@ pub type BOOL = i32;
 pub type LSTATUS = i32;
 /// HKEY type
 pub type HKEY = *mut c_void;
+/// HWND type
+pub type HWND = *mut c_void;
 
 /// No error
 pub const S_OK: HRESULT = 0;
@@ -30,18 +32,115 @@ pub const E_INVALIDARG: HRESULT = -0x7FF8_FFA9;
 pub const E_NOINTERFACE: HRESULT = -0x7FFF_BFFE;
 /// Invalid pointer
 pub const E_POINTER: HRESULT = -0x7FFF_BFFD;
+/// Not implemented
+pub const E_NOTIMPL: HRESULT = -0x7FFF_BFFF;
+/// Unspecified failure
+pub const E_FAIL: HRESULT = -0x7FFF_BFFB;
+/// Ran out of memory
+pub const E_OUTOFMEMORY: HRESULT = -0x7FF8_FFF2;
+/// Catastrophic failure
+pub const E_UNEXPECTED: HRESULT = -0x7FFF_0001;
 
 /// No aggregation for class
 pub const CLASS_E_NOAGGREGATION: HRESULT = -0x7FFB_FEF0;
 /// Class is not available
 pub const CLASS_E_CLASSNOTAVAILABLE: HRESULT = -0x7FFB_FEEF;
 
+/// The object the moniker refers to is not running
+pub const MK_E_UNAVAILABLE: HRESULT = -0x7FFB_FE1D;
+/// The display name could not be parsed into a moniker
+pub const MK_E_SYNTAX: HRESULT = -0x7FFB_FE1C;
+/// The object named by the moniker could not be found
+pub const MK_E_NOOBJECT: HRESULT = -0x7FFB_FE1B;
+/// The string is not a valid class id
+pub const CO_E_CLASSSTRING: HRESULT = -0x7FFB_FE0D;
+/// The class is not registered
+pub const REGDB_E_CLASSNOTREG: HRESULT = -0x7FFB_FEAC;
+/// The interface was used from a thread other than the one it belongs to
+pub const RPC_E_WRONG_THREAD: HRESULT = -0x7FFE_FEF2;
+/// The object invoked has disconnected from its clients
+pub const RPC_E_DISCONNECTED: HRESULT = -0x7FFE_FEF8;
+/// The method called does not exist on the remote interface
+pub const RPC_E_INVALIDMETHOD: HRESULT = -0x7FFE_FEFC;
+/// A malformed packet was received from the remote side
+pub const RPC_E_INVALID_DATAPACKET: HRESULT = -0x7FFE_FFF7;
+
+/// Keep the registering server alive while it is in the running object table
+pub const ROTFLAGS_REGISTRATIONKEEPSALIVE: u32 = 0x1;
+
 /// No error
 pub const ERROR_SUCCESS: u32 = 0;
 /// Registration error
 pub const SELFREG_E_CLASS: HRESULT = -0x7FFB_FDFF;
 /// A in process server
 pub const CLSCTX_INPROC_SERVER: u32 = 0x1;
+/// An in process handler for a server in another process
+pub const CLSCTX_INPROC_HANDLER: u32 = 0x2;
+/// A server running in its own process on the same machine
+pub const CLSCTX_LOCAL_SERVER: u32 = 0x4;
+/// A server on another machine
+pub const CLSCTX_REMOTE_SERVER: u32 = 0x10;
+
+/// Multiple clients can connect to a registered class object
+pub const REGCLS_MULTIPLEUSE: u32 = 1;
+/// Registered class objects stay invisible until `CoResumeClassObjects` is called
+pub const REGCLS_SUSPENDED: u32 = 4;
+
+/// Unable to perform requested operation
+pub const STG_E_INVALIDFUNCTION: HRESULT = -0x7FFC_FFFF;
+/// The storage element could not be found
+pub const STG_E_FILENOTFOUND: HRESULT = -0x7FFC_FFFE;
+/// Access denied
+pub const STG_E_ACCESSDENIED: HRESULT = -0x7FFC_FFFB;
+/// Invalid pointer error
+pub const STG_E_INVALIDPOINTER: HRESULT = -0x7FFC_FFF7;
+/// An error occurred during a seek operation
+pub const STG_E_SEEKERROR: HRESULT = -0x7FFC_FFE7;
+/// There is not enough space to complete the operation
+pub const STG_E_MEDIUMFULL: HRESULT = -0x7FFC_FF90;
+/// The storage element already exists
+pub const STG_E_FILEALREADYEXISTS: HRESULT = -0x7FFC_FFB0;
+/// Invalid parameter error
+pub const STG_E_INVALIDPARAMETER: HRESULT = -0x7FFC_FFA9;
+/// The name is not valid for a storage element
+pub const STG_E_INVALIDNAME: HRESULT = -0x7FFC_FF04;
+/// Invalid flag error
+pub const STG_E_INVALIDFLAG: HRESULT = -0x7FFC_FF01;
+/// The storage element has been removed or reverted
+pub const STG_E_REVERTED: HRESULT = -0x7FFC_FEFE;
+
+/// Open for reading only
+pub const STGM_READ: u32 = 0x0000_0000;
+/// Open for writing only
+pub const STGM_WRITE: u32 = 0x0000_0001;
+/// Open for reading and writing
+pub const STGM_READWRITE: u32 = 0x0000_0002;
+/// Deny other openers all access
+pub const STGM_SHARE_EXCLUSIVE: u32 = 0x0000_0010;
+/// Replace an existing element when creating
+pub const STGM_CREATE: u32 = 0x0000_1000;
+
+/// The element is a storage object
+pub const STGTY_STORAGE: u32 = 1;
+/// The element is a stream object
+pub const STGTY_STREAM: u32 = 2;
+
+/// Seek relative to the beginning of the stream
+pub const STREAM_SEEK_SET: u32 = 0;
+/// Seek relative to the current position
+pub const STREAM_SEEK_CUR: u32 = 1;
+/// Seek relative to the end of the stream
+pub const STREAM_SEEK_END: u32 = 2;
+
+/// Include the element name in a `STATSTG`
+pub const STATFLAG_DEFAULT: u32 = 0;
+/// Omit the element name from a `STATSTG`
+pub const STATFLAG_NONAME: u32 = 1;
+
+/// Move the element, removing it from its source storage
+pub const STGMOVE_MOVE: u32 = 0;
+/// Copy the element, leaving its source in place
+pub const STGMOVE_COPY: u32 = 1;
 
 /// An single threaded apartment (STA)
 pub const COINIT_APARTMENTTHREADED: u32 = 0x2;
@@ -50,7 +149,7 @@ pub const COINIT_MULTITHREADED: u32 = 0x0;
 
 /// A globally unique identifier
 #[repr(C)]
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Default, PartialEq)]
 pub struct GUID {
     #[allow(missing_docs)]
     pub data1: u32,
@@ -62,10 +161,137 @@ pub struct GUID {
     pub data4: [u8; 8],
 }
 
+/// A 64-bit count of 100-nanosecond intervals since January 1, 1601 (UTC)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[allow(non_snake_case, missing_docs)]
+pub struct FILETIME {
+    pub dwLowDateTime: u32,
+    pub dwHighDateTime: u32,
+}
+
+impl FILETIME {
+    /// Create a `FILETIME` from its 64-bit value
+    pub fn from_u64(value: u64) -> FILETIME {
+        FILETIME {
+            dwLowDateTime: value as u32,
+            dwHighDateTime: (value >> 32) as u32,
+        }
+    }
+
+    /// The 64-bit value of the `FILETIME`
+    pub fn to_u64(self) -> u64 {
+        u64::from(self.dwHighDateTime) << 32 | u64::from(self.dwLowDateTime)
+    }
+}
+
+/// Statistics about an open storage, stream or byte array object
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[allow(non_snake_case, missing_docs)]
+pub struct STATSTG {
+    pub pwcsName: *mut u16,
+    pub r#type: u32,
+    pub cbSize: u64,
+    pub mtime: FILETIME,
+    pub ctime: FILETIME,
+    pub atime: FILETIME,
+    pub grfMode: u32,
+    pub grfLocksSupported: u32,
+    pub clsid: CLSID,
+    pub grfStateBits: u32,
+    pub reserved: u32,
+}
+
+/// Parameters used during a moniker binding operation
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(non_snake_case, missing_docs)]
+pub struct BIND_OPTS {
+    pub cbStruct: u32,
+    pub grfFlags: u32,
+    pub grfMode: u32,
+    pub dwTickCountDeadline: u32,
+}
+
+/// The description of a component category in one locale
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[allow(non_snake_case, missing_docs)]
+pub struct CATEGORYINFO {
+    pub catid: CATID,
+    pub lcid: u32,
+    pub szDescription: [u16; 128],
+}
+
+/// The class of the system's component categories manager
+pub const CLSID_STD_COMPONENT_CATEGORIES_MGR: CLSID = GUID {
+    data1: 0x0002_E005,
+    data2: 0x0000,
+    data3: 0x0000,
+    data4: [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+};
+
+/// The machine that a class is activated on
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[allow(non_snake_case, missing_docs)]
+pub struct COSERVERINFO {
+    pub dwReserved1: u32,
+    pub pwszName: *mut u16,
+    pub pAuthInfo: *mut c_void,
+    pub dwReserved2: u32,
+}
+
+/// One of the interfaces requested from `CoCreateInstanceEx`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[allow(non_snake_case, missing_docs)]
+pub struct MULTI_QI {
+    pub pIID: *const IID,
+    pub pItf: *mut c_void,
+    pub hr: HRESULT,
+}
+
+/// A message from a thread's message queue
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[allow(non_snake_case, missing_docs)]
+pub struct MSG {
+    pub hwnd: HWND,
+    pub message: u32,
+    pub wParam: usize,
+    pub lParam: isize,
+    pub time: u32,
+    pub pt_x: i32,
+    pub pt_y: i32,
+}
+
+impl Default for MSG {
+    fn default() -> MSG {
+        MSG {
+            hwnd: std::ptr::null_mut(),
+            message: 0,
+            wParam: 0,
+            lParam: 0,
+            time: 0,
+            pt_x: 0,
+            pt_y: 0,
+        }
+    }
+}
+
+/// The first message number available to applications
+pub const WM_APP: u32 = 0x8000;
+/// Leave messages in the queue when peeking
+pub const PM_NOREMOVE: u32 = 0x0000;
+
 /// An interface ID
 pub type IID = GUID;
 /// A class ID
 pub type CLSID = GUID;
+/// A component category ID
+pub type CATID = GUID;
 
 impl std::fmt::Debug for GUID {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -87,6 +313,47 @@ impl std::fmt::Debug for GUID {
     }
 }
 
+impl std::str::FromStr for GUID {
+    type Err = HRESULT;
+
+    /// Parse a GUID in registry format, with or without the surrounding braces
+    fn from_str(s: &str) -> Result<GUID, HRESULT> {
+        let s = s.trim();
+        let s = if s.starts_with('{') && s.ends_with('}') {
+            &s[1..s.len() - 1]
+        } else {
+            s
+        };
+        let parts = s.split('-').collect::<Vec<_>>();
+        let lengths = [8, 4, 4, 4, 12];
+        if parts.len() != lengths.len()
+            || parts
+                .iter()
+                .zip(&lengths)
+                .any(|(p, l)| p.len() != *l || !p.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(CO_E_CLASSSTRING);
+        }
+        let byte = |s: &str, i: usize| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+        let clock = u16::from_str_radix(parts[3], 16).unwrap().to_be_bytes();
+        Ok(GUID {
+            data1: u32::from_str_radix(parts[0], 16).unwrap(),
+            data2: u16::from_str_radix(parts[1], 16).unwrap(),
+            data3: u16::from_str_radix(parts[2], 16).unwrap(),
+            data4: [
+                clock[0],
+                clock[1],
+                byte(parts[4], 0),
+                byte(parts[4], 1),
+                byte(parts[4], 2),
+                byte(parts[4], 3),
+                byte(parts[4], 4),
+                byte(parts[4], 5),
+            ],
+        })
+    }
+}
+
 #[link(name = "ole32")]
 extern "system" {
     pub fn CoIncrementMTAUsage(cookie: *mut c_void) -> HRESULT;
@@ -112,6 +379,39 @@ extern "system" {
         cbData: u32,
     ) -> LSTATUS;
     pub fn RegDeleteKeyA(hKey: HKEY, lpSubKey: *const i8) -> LSTATUS;
+    /// Create a registry key or open it if it already exists
+    ///
+    /// See [RegCreateKeyExW](https://docs.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regcreatekeyexw).
+    pub fn RegCreateKeyExW(
+        hKey: HKEY,
+        lpSubKey: *const u16,
+        Reserved: u32,
+        lpClass: *mut u16,
+        dwOptions: u32,
+        samDesired: u32,
+        lpSecurityAttributes: *mut c_void,
+        phkResult: *mut HKEY,
+        lpdwDisposition: *mut u32,
+    ) -> LSTATUS;
+    /// Set the data of a value of a registry key
+    ///
+    /// See [RegSetValueExW](https://docs.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regsetvalueexw).
+    pub fn RegSetValueExW(
+        hKey: HKEY,
+        lpValueName: *const u16,
+        Reserved: u32,
+        dwType: u32,
+        lpData: *const u8,
+        cbData: u32,
+    ) -> LSTATUS;
+    /// Delete a registry key that has no subkeys
+    ///
+    /// See [RegDeleteKeyW](https://docs.microsoft.com/en-us/windows/win32/api/winreg/nf-winreg-regdeletekeyw).
+    pub fn RegDeleteKeyW(hKey: HKEY, lpSubKey: *const u16) -> LSTATUS;
+    /// Get the path of the file a module was loaded from
+    ///
+    /// See [GetModuleFileNameW](https://docs.microsoft.com/en-us/windows/win32/api/libloaderapi/nf-libloaderapi-getmodulefilenamew).
+    pub fn GetModuleFileNameW(hModule: *mut c_void, lpFilename: *mut u16, nSize: u32) -> u32;
     pub fn GetModuleHandleA(lpModuleName: *const i8) -> *mut c_void;
     pub fn CoInitializeEx(pvReserved: *mut c_void, dwCoInit: u32) -> HRESULT;
     pub fn CoGetClassObject(
@@ -128,5 +428,177 @@ extern "system" {
         riid: *const IID,
         ppv: *mut *mut c_void,
     ) -> HRESULT;
+    /// Create an object on a given machine and query it for several interfaces
+    ///
+    /// See [CoCreateInstanceEx](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-cocreateinstanceex).
+    pub fn CoCreateInstanceEx(
+        rclsid: *const IID,
+        pUnkOuter: *mut c_void,
+        dwClsCtx: u32,
+        pServerInfo: *mut COSERVERINFO,
+        dwCount: u32,
+        pResults: *mut MULTI_QI,
+    ) -> HRESULT;
     pub fn CoUninitialize();
+    /// Look up the CLSID registered for a ProgID
+    ///
+    /// See [CLSIDFromProgID](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-clsidfromprogid).
+    pub fn CLSIDFromProgID(lpszProgID: *const u16, lpclsid: *mut CLSID) -> HRESULT;
+    /// Look up the ProgID registered for a CLSID
+    ///
+    /// See [ProgIDFromCLSID](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-progidfromclsid).
+    pub fn ProgIDFromCLSID(clsid: *const CLSID, lplpszProgID: *mut *mut u16) -> HRESULT;
+    /// Allocate memory that COM callers free with `CoTaskMemFree`
+    ///
+    /// See [CoTaskMemAlloc](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-cotaskmemalloc).
+    pub fn CoTaskMemAlloc(cb: usize) -> *mut c_void;
+    /// Free memory allocated with `CoTaskMemAlloc`
+    ///
+    /// See [CoTaskMemFree](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-cotaskmemfree).
+    pub fn CoTaskMemFree(pv: *mut c_void);
+    /// Register a class object so that other processes can create the class
+    ///
+    /// See [CoRegisterClassObject](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-coregisterclassobject).
+    pub fn CoRegisterClassObject(
+        rclsid: *const IID,
+        pUnk: *mut c_void,
+        dwClsContext: u32,
+        flags: u32,
+        lpdwRegister: *mut u32,
+    ) -> HRESULT;
+    /// Revoke a class object registered with `CoRegisterClassObject`
+    ///
+    /// See [CoRevokeClassObject](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-corevokeclassobject).
+    pub fn CoRevokeClassObject(dwRegister: u32) -> HRESULT;
+    /// Let clients activate the class objects registered as suspended
+    ///
+    /// See [CoResumeClassObjects](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-coresumeclassobjects).
+    pub fn CoResumeClassObjects() -> HRESULT;
+    /// Stop activation requests for the registered class objects
+    ///
+    /// See [CoSuspendClassObjects](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-cosuspendclassobjects).
+    pub fn CoSuspendClassObjects() -> HRESULT;
+    /// Count a reference to the local server process
+    ///
+    /// See [CoAddRefServerProcess](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-coaddrefserverprocess).
+    pub fn CoAddRefServerProcess() -> u32;
+    /// Release a reference to the local server process and suspend its class objects on the last one
+    ///
+    /// See [CoReleaseServerProcess](https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-coreleaseserverprocess).
+    pub fn CoReleaseServerProcess() -> u32;
+    /// Get the running object table of the local machine
+    ///
+    /// See [GetRunningObjectTable](https://docs.microsoft.com/en-us/windows/win32/api/objbase/nf-objbase-getrunningobjecttable).
+    pub fn GetRunningObjectTable(reserved: u32, pprot: *mut *mut c_void) -> HRESULT;
+    /// Create a bind context for moniker operations
+    ///
+    /// See [CreateBindCtx](https://docs.microsoft.com/en-us/windows/win32/api/objbase/nf-objbase-createbindctx).
+    pub fn CreateBindCtx(reserved: u32, ppbc: *mut *mut c_void) -> HRESULT;
+    /// Create a moniker for a file path
+    ///
+    /// See [CreateFileMoniker](https://docs.microsoft.com/en-us/windows/win32/api/objbase/nf-objbase-createfilemoniker).
+    pub fn CreateFileMoniker(lpszPathName: *const u16, ppmk: *mut *mut c_void) -> HRESULT;
+    /// Create a moniker for an item inside another object
+    ///
+    /// See [CreateItemMoniker](https://docs.microsoft.com/en-us/windows/win32/api/objbase/nf-objbase-createitemmoniker).
+    pub fn CreateItemMoniker(
+        lpszDelim: *const u16,
+        lpszItem: *const u16,
+        ppmk: *mut *mut c_void,
+    ) -> HRESULT;
+    /// Parse a display name into a moniker
+    ///
+    /// See [MkParseDisplayName](https://docs.microsoft.com/en-us/windows/win32/api/objbase/nf-objbase-mkparsedisplayname).
+    pub fn MkParseDisplayName(
+        pbc: *mut c_void,
+        szUserName: *const u16,
+        pchEaten: *mut u32,
+        ppmk: *mut *mut c_void,
+    ) -> HRESULT;
+}
+
+#[cfg(windows)]
+#[link(name = "user32")]
+extern "system" {
+    /// Wait for a message of the calling thread
+    ///
+    /// See [GetMessageW](https://docs.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-getmessagew).
+    pub fn GetMessageW(lpMsg: *mut MSG, hWnd: HWND, wMsgFilterMin: u32, wMsgFilterMax: u32)
+        -> BOOL;
+    /// Check for a message of the calling thread without waiting
+    ///
+    /// See [PeekMessageW](https://docs.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-peekmessagew).
+    pub fn PeekMessageW(
+        lpMsg: *mut MSG,
+        hWnd: HWND,
+        wMsgFilterMin: u32,
+        wMsgFilterMax: u32,
+        wRemoveMsg: u32,
+    ) -> BOOL;
+    /// Post a message to the queue of a thread
+    ///
+    /// See [PostThreadMessageW](https://docs.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-postthreadmessagew).
+    pub fn PostThreadMessageW(idThread: u32, Msg: u32, wParam: usize, lParam: isize) -> BOOL;
+    /// Translate virtual key messages into character messages
+    ///
+    /// See [TranslateMessage](https://docs.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-translatemessage).
+    pub fn TranslateMessage(lpMsg: *const MSG) -> BOOL;
+    /// Dispatch a message to its window procedure
+    ///
+    /// See [DispatchMessageW](https://docs.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-dispatchmessagew).
+    pub fn DispatchMessageW(lpMsg: *const MSG) -> isize;
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    /// Get the identifier of the calling thread
+    ///
+    /// See [GetCurrentThreadId](https://docs.microsoft.com/en-us/windows/win32/api/processthreadsapi/nf-processthreadsapi-getcurrentthreadid).
+    pub fn GetCurrentThreadId() -> u32;
+    /// Look up an exported function of a loaded module
+    ///
+    /// See [GetProcAddress](https://docs.microsoft.com/en-us/windows/win32/api/libloaderapi/nf-libloaderapi-getprocaddress).
+    pub fn GetProcAddress(hModule: *mut c_void, lpProcName: *const i8) -> *mut c_void;
+}
+
+#[cfg(not(windows))]
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+/// Allocate `size` bytes that the receiver frees with [`task_mem_free`]
+///
+/// This is `CoTaskMemAlloc` on Windows. Elsewhere there is no COM allocator, and the C
+/// allocator stands in for it so that objects handing out task memory can be tested.
+///
+/// # Safety
+///
+/// The memory is uninitialized.
+pub unsafe fn task_mem_alloc(size: usize) -> *mut c_void {
+    #[cfg(windows)]
+    {
+        CoTaskMemAlloc(size)
+    }
+    #[cfg(not(windows))]
+    {
+        malloc(size)
+    }
+}
+
+/// Free memory allocated with [`task_mem_alloc`]
+///
+/// # Safety
+///
+/// `ptr` must be null or allocated with [`task_mem_alloc`] and not freed yet.
+pub unsafe fn task_mem_free(ptr: *mut c_void) {
+    #[cfg(windows)]
+    {
+        CoTaskMemFree(ptr)
+    }
+    #[cfg(not(windows))]
+    {
+        free(ptr)
+    }
 }
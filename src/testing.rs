@@ -0,0 +1,472 @@
+//! Conformance checks for COM objects
+//!
+//! [`check_iunknown_rules`] verifies that an object follows the rules of `QueryInterface`,
+//! `AddRef` and `Release`, which gives any class a single line conformance test:
+//!
+//! ```rust,no_run
+//! # use com::interfaces::IUnknown;
+//! # fn object() -> IUnknown { unimplemented!() }
+//! use com::interfaces::IClassFactory;
+//! use com::Interface;
+//!
+//! com::testing::check_iunknown_rules(&object(), &[IClassFactory::IID]).unwrap();
+//! ```
+//!
+//! With the `production` feature, the [`mock`] module holds the expectations of the test
+//! doubles declared with [`mock!`](crate::mock).
+#[cfg(feature = "production")]
+pub mod mock;
+
+use crate::interfaces::IUnknown;
+use crate::sys::{E_NOINTERFACE, E_POINTER, FAILED, HRESULT, IID};
+use crate::Interface;
+
+use std::ffi::c_void;
+use std::fmt;
+
+/// An interface that no object implements
+const IID_UNSUPPORTED: IID = IID {
+    data1: 0x6C0A_3E2B,
+    data2: 0x9D4F,
+    data3: 0x4B17,
+    data4: [0xA5, 0x0E, 0x73, 0x1C, 0x88, 0x2D, 0x41, 0xF6],
+};
+
+/// A broken rule of `IUnknown`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// Querying for an interface that should be supported failed
+    NotSupported {
+        /// The interface that was queried for
+        iid: IID,
+        /// The error returned by `QueryInterface`
+        hr: HRESULT,
+    },
+    /// `QueryInterface` failed with something else than `E_NOINTERFACE`
+    NonStandardError {
+        /// The interface that was queried for
+        iid: IID,
+        /// The error returned by `QueryInterface`
+        hr: HRESULT,
+    },
+    /// A failed `QueryInterface` did not set the out pointer to null
+    OutPointerNotCleared {
+        /// The interface that was queried for
+        iid: IID,
+    },
+    /// A successful `QueryInterface` did not write the out pointer
+    OutPointerNotSet {
+        /// The interface that was queried for
+        iid: IID,
+    },
+    /// Querying for `IUnknown` through `iid` gave a different pointer than through the object
+    Identity {
+        /// The interface that was queried through
+        iid: IID,
+    },
+    /// Querying an interface for itself failed
+    Reflexive {
+        /// The interface
+        iid: IID,
+    },
+    /// `to` could be queried from `from` but not the other way around
+    Symmetric {
+        /// The interface that was queried through
+        from: IID,
+        /// The interface that was queried for
+        to: IID,
+    },
+    /// `to` could be queried through `through` from `from` but not from `from` directly
+    Transitive {
+        /// The interface the chain started with
+        from: IID,
+        /// The interface in the middle of the chain
+        through: IID,
+        /// The interface the chain ended with
+        to: IID,
+    },
+    /// Querying for `iid` again gave a different result
+    Unstable {
+        /// The interface that was queried for
+        iid: IID,
+    },
+    /// `AddRef` or `Release` did not move the reference count in the right direction
+    RefCount {
+        /// `"AddRef"` or `"Release"`
+        method: &'static str,
+        /// The count returned by the previous call
+        before: u32,
+        /// The count returned by this call
+        after: u32,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::NotSupported { iid, hr } => {
+                write!(f, "QueryInterface for {:?} failed with {:#x}", iid, hr)
+            }
+            Violation::NonStandardError { iid, hr } => write!(
+                f,
+                "QueryInterface for {:?} failed with {:#x} instead of E_NOINTERFACE",
+                iid, hr
+            ),
+            Violation::OutPointerNotCleared { iid } => write!(
+                f,
+                "QueryInterface for {:?} failed without setting the out pointer to null",
+                iid
+            ),
+            Violation::OutPointerNotSet { iid } => write!(
+                f,
+                "QueryInterface for {:?} succeeded without writing the out pointer",
+                iid
+            ),
+            Violation::Identity { iid } => write!(
+                f,
+                "IUnknown queried through {:?} is not the object's IUnknown",
+                iid
+            ),
+            Violation::Reflexive { iid } => {
+                write!(f, "{:?} cannot be queried from itself", iid)
+            }
+            Violation::Symmetric { from, to } => write!(
+                f,
+                "{:?} can be queried from {:?} but not the other way around",
+                to, from
+            ),
+            Violation::Transitive { from, through, to } => write!(
+                f,
+                "{:?} can be queried from {:?} through {:?} but not directly",
+                to, from, through
+            ),
+            Violation::Unstable { iid } => {
+                write!(
+                    f,
+                    "QueryInterface for {:?} did not give the same result twice",
+                    iid
+                )
+            }
+            Violation::RefCount {
+                method,
+                before,
+                after,
+            } => write!(
+                f,
+                "{} changed the reference count from {} to {}",
+                method, before, after
+            ),
+        }
+    }
+}
+
+/// The violations found by [`check_iunknown_rules`]
+#[derive(Clone, PartialEq)]
+pub struct Violations(pub Vec<Violation>);
+
+impl fmt::Debug for Violations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Violations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} violation(s) of the IUnknown rules", self.0.len())?;
+        for violation in &self.0 {
+            write!(f, "\n  {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Violations {}
+
+struct Checker {
+    violations: Vec<Violation>,
+}
+
+impl Checker {
+    fn report(&mut self, violation: Violation) {
+        if !self.violations.contains(&violation) {
+            self.violations.push(violation);
+        }
+    }
+
+    /// Query `object` for `iid` and check how a failure is reported
+    fn query(&mut self, object: &IUnknown, iid: &IID) -> Result<IUnknown, HRESULT> {
+        // Any interface pointer can stand in for `IUnknown`
+        let sentinel = std::ptr::NonNull::<c_void>::dangling().as_ptr();
+        let mut ppv = sentinel;
+        let hr = unsafe { object.query_interface(iid as *const IID, &mut ppv) };
+        if !FAILED(hr) && ppv == sentinel {
+            // The sentinel must never be released as an interface pointer
+            self.report(Violation::OutPointerNotSet { iid: *iid });
+            return Err(E_POINTER);
+        }
+        if !FAILED(hr) && !ppv.is_null() {
+            return Ok(unsafe { std::mem::transmute::<*mut c_void, IUnknown>(ppv) });
+        }
+        if hr != E_NOINTERFACE && hr != E_POINTER {
+            self.report(Violation::NonStandardError { iid: *iid, hr });
+        }
+        if !ppv.is_null() {
+            self.report(Violation::OutPointerNotCleared { iid: *iid });
+        }
+        Err(if FAILED(hr) { hr } else { E_POINTER })
+    }
+
+    fn identity(&mut self, object: &IUnknown) -> Option<usize> {
+        self.query(object, &IUnknown::IID)
+            .ok()
+            .map(|unknown| unknown.as_raw().as_ptr() as usize)
+    }
+
+    fn check_ref_counts(&mut self, object: &IUnknown) {
+        let first = unsafe { object.add_ref() };
+        let second = unsafe { object.add_ref() };
+        let released = unsafe { object.release() };
+        let last = unsafe { object.release() };
+        if second <= first {
+            self.report(Violation::RefCount {
+                method: "AddRef",
+                before: first,
+                after: second,
+            });
+        }
+        if released >= second {
+            self.report(Violation::RefCount {
+                method: "Release",
+                before: second,
+                after: released,
+            });
+        }
+        if last >= released {
+            self.report(Violation::RefCount {
+                method: "Release",
+                before: released,
+                after: last,
+            });
+        }
+    }
+}
+
+/// Check that `object` follows the rules of `IUnknown`
+///
+/// `iids` are the interfaces the object is expected to implement besides `IUnknown`.
+/// The following is checked for them:
+/// * they can all be queried for, successful queries write the out pointer, and failing
+///   queries return `E_NOINTERFACE` and a null out pointer
+/// * `IUnknown` queried through any of them is the same pointer (identity)
+/// * each can be queried from itself (reflexive), from all others and back (symmetric and
+///   transitive)
+/// * querying for an interface a second time gives the same result (stable interface set)
+/// * `AddRef` and `Release` increment and decrement the reference count
+///
+/// All violations are returned, not only the first one.
+pub fn check_iunknown_rules<I: Interface>(object: &I, iids: &[IID]) -> Result<(), Violations> {
+    let object = object.as_iunknown();
+    let mut checker = Checker {
+        violations: Vec::new(),
+    };
+
+    checker.check_ref_counts(object);
+    let identity = checker.identity(object);
+    if identity.is_none() {
+        checker.report(Violation::Reflexive { iid: IUnknown::IID });
+    }
+    let unsupported = checker.query(object, &IID_UNSUPPORTED).is_ok();
+
+    let mut interfaces = Vec::with_capacity(iids.len());
+    for iid in iids {
+        match checker.query(object, iid) {
+            Ok(interface) => interfaces.push((*iid, interface)),
+            Err(hr) => checker.report(Violation::NotSupported { iid: *iid, hr }),
+        }
+    }
+
+    for (iid, interface) in &interfaces {
+        checker.check_ref_counts(interface);
+        if checker.identity(interface) != identity {
+            checker.report(Violation::Identity { iid: *iid });
+        }
+        if checker.query(object, iid).is_err() {
+            checker.report(Violation::Unstable { iid: *iid });
+        }
+        if checker.query(interface, iid).is_err() {
+            checker.report(Violation::Reflexive { iid: *iid });
+        }
+        if checker.query(interface, &IID_UNSUPPORTED).is_ok() != unsupported {
+            checker.report(Violation::Unstable {
+                iid: IID_UNSUPPORTED,
+            });
+        }
+        for (to, _) in interfaces.iter().filter(|(to, _)| to != iid) {
+            let through = match checker.query(interface, to) {
+                Ok(through) => through,
+                Err(_) => {
+                    // `to` is reachable through the object's `IUnknown`
+                    checker.report(Violation::Transitive {
+                        from: *iid,
+                        through: IUnknown::IID,
+                        to: *to,
+                    });
+                    continue;
+                }
+            };
+            if checker.query(&through, iid).is_err() {
+                checker.report(Violation::Symmetric {
+                    from: *iid,
+                    to: *to,
+                });
+            }
+            for (last, _) in &interfaces {
+                if checker.query(&through, last).is_ok() && checker.query(interface, last).is_err()
+                {
+                    checker.report(Violation::Transitive {
+                        from: *iid,
+                        through: *to,
+                        to: *last,
+                    });
+                }
+            }
+        }
+    }
+
+    if checker.violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Violations(checker.violations))
+    }
+}
+
+#[cfg(all(test, feature = "production"))]
+mod tests {
+    use super::*;
+    use crate::interfaces::iunknown::{IUnknownVPtr, IUnknownVTable};
+    use crate::sys::NOERROR;
+
+    use std::ptr::NonNull;
+
+    crate::interfaces! {
+        #[uuid("0B1E5C2A-6F3D-4E8B-9A47-5C2D1E0F3B68")]
+        unsafe interface IShape: IUnknown {
+            fn sides(&self) -> u32;
+        }
+
+        #[uuid("0B1E5C2B-6F3D-4E8B-9A47-5C2D1E0F3B68")]
+        unsafe interface ISquare: IShape {
+            fn side(&self) -> u32;
+        }
+
+        #[uuid("0B1E5C2C-6F3D-4E8B-9A47-5C2D1E0F3B68")]
+        unsafe interface IColored: IUnknown {
+            fn color(&self) -> u32;
+        }
+    }
+
+    crate::class! {
+        class Square: ISquare, IColored {
+            side: u32,
+        }
+
+        impl ISquare for Square {
+            fn side(&self) -> u32 {
+                self.side
+            }
+        }
+
+        impl IShape for Square {
+            fn sides(&self) -> u32 {
+                4
+            }
+        }
+
+        impl IColored for Square {
+            fn color(&self) -> u32 {
+                0xFF
+            }
+        }
+    }
+
+    #[test]
+    fn generated_classes_follow_the_rules() {
+        let square = Square::allocate(2).query::<IShape>().unwrap();
+        check_iunknown_rules(&square, &[IShape::IID, ISquare::IID, IColored::IID]).unwrap();
+    }
+
+    /// Forgets to clear the out pointer, claims to implement `IColored` without writing it
+    /// and never counts references
+    #[repr(C)]
+    struct Sloppy {
+        vtable: NonNull<IUnknownVTable>,
+    }
+
+    unsafe extern "system" fn sloppy_query_interface(
+        this: NonNull<IUnknownVPtr>,
+        riid: *const IID,
+        ppv: *mut *mut c_void,
+    ) -> HRESULT {
+        if *riid == IUnknown::IID {
+            *ppv = this.as_ptr() as *mut c_void;
+            return NOERROR;
+        }
+        if *riid == IColored::IID {
+            return NOERROR;
+        }
+        E_NOINTERFACE
+    }
+
+    unsafe extern "system" fn sloppy_add_ref(_: NonNull<IUnknownVPtr>) -> u32 {
+        1
+    }
+
+    unsafe extern "system" fn sloppy_release(_: NonNull<IUnknownVPtr>) -> u32 {
+        1
+    }
+
+    static SLOPPY_VTABLE: IUnknownVTable = IUnknownVTable {
+        QueryInterface: sloppy_query_interface,
+        AddRef: sloppy_add_ref,
+        Release: sloppy_release,
+    };
+
+    #[test]
+    fn violations_are_reported() {
+        let mut sloppy = Sloppy {
+            vtable: NonNull::from(&SLOPPY_VTABLE),
+        };
+        let object = std::mem::ManuallyDrop::new(unsafe {
+            std::mem::transmute::<*mut Sloppy, IUnknown>(&mut sloppy)
+        });
+        let violations = check_iunknown_rules(&*object, &[IShape::IID, IColored::IID]).unwrap_err();
+        assert_eq!(
+            violations.0,
+            vec![
+                Violation::RefCount {
+                    method: "AddRef",
+                    before: 1,
+                    after: 1
+                },
+                Violation::RefCount {
+                    method: "Release",
+                    before: 1,
+                    after: 1
+                },
+                Violation::OutPointerNotCleared {
+                    iid: IID_UNSUPPORTED
+                },
+                Violation::OutPointerNotCleared { iid: IShape::IID },
+                Violation::NotSupported {
+                    iid: IShape::IID,
+                    hr: E_NOINTERFACE
+                },
+                Violation::OutPointerNotSet { iid: IColored::IID },
+                Violation::NotSupported {
+                    iid: IColored::IID,
+                    hr: E_POINTER
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! Expectations for the test doubles generated by [`mock!`](crate::mock)
+//!
+//! Every method of a mock has a list of [`Expectation`]s. A call is handled by the first
+//! expectation (in the order they were added) whose matcher accepts the arguments and which
+//! has not been called as often as it allows yet. The arguments are passed to the expectation
+//! as a tuple, e.g. `(u32, *mut u32)` for a method `fn get(&self, index: u32, value: *mut u32)`
+//! and `()` for a method without parameters.
+//!
+//! Expectations are verified by the mock's `checkpoint` method and again when the
+//! [`MockHandle`] returned by its `new` function is dropped, even if COM references to the
+//! mock are still alive.
+//!
+//! Calls go through the COM vtable, where a panic cannot unwind into the caller. An unexpected
+//! call, or one without a configured return value, is therefore recorded and answered with
+//! [`MockReturn::unexpected`], e.g. `E_UNEXPECTED` for methods returning `HRESULT`. The
+//! failure is reported by the next `checkpoint` on the test's thread. A `returning` closure
+//! that panics still aborts the process.
+use crate::production::{Class, ClassAllocation};
+use crate::sys::{E_UNEXPECTED, HRESULT};
+
+use std::cell::{Cell, RefCell, RefMut};
+use std::rc::Rc;
+
+type Matcher<A> = Box<dyn Fn(&A) -> bool>;
+type Returning<A, R> = Rc<RefCell<dyn FnMut(A) -> R>>;
+
+/// An expected call of a mock method
+pub struct Expectation<A, R> {
+    matcher: Option<Matcher<A>>,
+    min: usize,
+    max: Option<usize>,
+    calls: Cell<usize>,
+    returning: Option<Returning<A, R>>,
+}
+
+impl<A, R> Expectation<A, R> {
+    fn new() -> Expectation<A, R> {
+        Expectation {
+            matcher: None,
+            min: 0,
+            max: None,
+            calls: Cell::new(0),
+            returning: None,
+        }
+    }
+
+    /// Only match calls whose arguments are accepted by `matcher`
+    pub fn with<F: Fn(&A) -> bool + 'static>(&mut self, matcher: F) -> &mut Self {
+        self.matcher = Some(Box::new(matcher));
+        self
+    }
+
+    /// Expect exactly `n` calls
+    pub fn times(&mut self, n: usize) -> &mut Self {
+        self.times_between(n, n)
+    }
+
+    /// Expect at least `min` and at most `max` calls
+    pub fn times_between(&mut self, min: usize, max: usize) -> &mut Self {
+        assert!(min <= max, "expected more calls than allowed");
+        self.min = min;
+        self.max = Some(max);
+        self
+    }
+
+    /// Expect at least one call
+    pub fn at_least_once(&mut self) -> &mut Self {
+        self.min = 1;
+        self.max = None;
+        self
+    }
+
+    /// Expect no calls
+    pub fn never(&mut self) -> &mut Self {
+        self.times(0)
+    }
+
+    /// Handle calls with `f`
+    pub fn returning<F: FnMut(A) -> R + 'static>(&mut self, f: F) -> &mut Self {
+        self.returning = Some(Rc::new(RefCell::new(f)));
+        self
+    }
+
+    /// Return `value` from every call
+    pub fn return_const(&mut self, value: R) -> &mut Self
+    where
+        A: 'static,
+        R: Clone + 'static,
+    {
+        self.returning(move |_| value.clone())
+    }
+
+    fn accepts(&self, args: &A) -> bool {
+        if let Some(max) = self.max {
+            if self.calls.get() >= max {
+                return false;
+            }
+        }
+        match &self.matcher {
+            Some(matcher) => matcher(args),
+            None => true,
+        }
+    }
+
+    fn is_satisfied(&self) -> bool {
+        self.calls.get() >= self.min
+    }
+}
+
+/// A value a mock method returns when a call could not be handled
+pub trait MockReturn {
+    /// The value returned from an unexpected call
+    fn unexpected() -> Self;
+}
+
+impl MockReturn for HRESULT {
+    fn unexpected() -> Self {
+        E_UNEXPECTED
+    }
+}
+
+impl MockReturn for () {
+    fn unexpected() -> Self {}
+}
+
+impl MockReturn for bool {
+    fn unexpected() -> Self {
+        false
+    }
+}
+
+macro_rules! zero_mock_return {
+    ($($t:ty),+) => {
+        $(
+            impl MockReturn for $t {
+                fn unexpected() -> Self {
+                    0 as $t
+                }
+            }
+        )+
+    };
+}
+
+zero_mock_return!(u8, u16, u32, u64, usize, i8, i16, i64, isize, f32, f64);
+
+impl<T> MockReturn for Option<T> {
+    fn unexpected() -> Self {
+        None
+    }
+}
+
+impl<T> MockReturn for *mut T {
+    fn unexpected() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+impl<T> MockReturn for *const T {
+    fn unexpected() -> Self {
+        std::ptr::null()
+    }
+}
+
+/// The expectations of one mock method
+pub struct Expectations<A, R> {
+    method: &'static str,
+    expectations: RefCell<Vec<Expectation<A, R>>>,
+    /// The calls that could not be handled since the last checkpoint
+    failures: RefCell<Vec<String>>,
+}
+
+impl<A, R: MockReturn> Expectations<A, R> {
+    #[doc(hidden)]
+    pub fn new(method: &'static str) -> Expectations<A, R> {
+        Expectations {
+            method,
+            expectations: RefCell::new(Vec::new()),
+            failures: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Add an expectation that matches any call until it is configured otherwise
+    pub fn expect(&self) -> RefMut<'_, Expectation<A, R>> {
+        RefMut::map(self.expectations.borrow_mut(), |expectations| {
+            expectations.push(Expectation::new());
+            expectations.last_mut().unwrap()
+        })
+    }
+
+    #[doc(hidden)]
+    pub fn call(&self, args: A) -> R {
+        let returning = {
+            let expectations = self.expectations.borrow();
+            let expectation = match expectations.iter().find(|e| e.accepts(&args)) {
+                Some(expectation) => expectation,
+                None => return self.fail(format!("unexpected call to {}", self.method)),
+            };
+            expectation.calls.set(expectation.calls.get() + 1);
+            match expectation.returning.clone() {
+                Some(returning) => returning,
+                None => {
+                    return self.fail(format!(
+                        "the expectation for {} has no return value configured",
+                        self.method
+                    ))
+                }
+            }
+        };
+        // The expectations are not borrowed here so that `f` may call the mock again
+        let mut f = returning.borrow_mut();
+        (*f)(args)
+    }
+
+    fn fail(&self, message: String) -> R {
+        self.failures.borrow_mut().push(message);
+        R::unexpected()
+    }
+}
+
+impl<A, R> Expectations<A, R> {
+    /// Check that there were no unexpected calls and that all expectations got as many
+    /// calls as they expected, and remove the expectations
+    ///
+    /// Panics otherwise.
+    pub fn checkpoint(&self) {
+        let failures = std::mem::take(&mut *self.failures.borrow_mut());
+        if let Some(failure) = failures.first() {
+            panic!("{}", failure);
+        }
+        let expectations = std::mem::take(&mut *self.expectations.borrow_mut());
+        if let Some(e) = expectations.iter().find(|e| !e.is_satisfied()) {
+            panic!(
+                "{} was called {} time(s) but expected at least {}",
+                self.method,
+                e.calls.get(),
+                e.min
+            );
+        }
+    }
+}
+
+/// A class declared with `mock!`
+pub trait Mock: Class {
+    /// Verify the expectations set so far and remove them
+    fn checkpoint(&self);
+}
+
+/// The test's handle to a mock
+///
+/// Dereferences to the mock's allocation, so the handle is used to set expectations and
+/// to query for interfaces. The expectations are verified when the handle is dropped.
+pub struct MockHandle<T: Mock> {
+    allocation: ClassAllocation<T>,
+}
+
+impl<T: Mock> MockHandle<T> {
+    #[doc(hidden)]
+    pub fn new(allocation: ClassAllocation<T>) -> MockHandle<T> {
+        MockHandle { allocation }
+    }
+
+    /// Verify the expectations set so far and remove them
+    ///
+    /// Panics if an expectation did not get as many calls as it expected.
+    pub fn checkpoint(&self) {
+        Mock::checkpoint(&**self.allocation)
+    }
+}
+
+impl<T: Mock> std::ops::Deref for MockHandle<T> {
+    type Target = ClassAllocation<T>;
+
+    fn deref(&self) -> &ClassAllocation<T> {
+        &self.allocation
+    }
+}
+
+impl<T: Mock> Drop for MockHandle<T> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            self.checkpoint();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interfaces::IUnknown;
+    use crate::sys::{E_FAIL, E_UNEXPECTED, HRESULT, S_OK};
+
+    crate::interfaces! {
+        #[uuid("2E6B7C10-4D3A-4B8F-9C21-6A0E5F1D8B42")]
+        unsafe interface IFeeder: IUnknown {
+            fn feed(&self, portions: u32) -> HRESULT;
+        }
+
+        #[uuid("2E6B7C11-4D3A-4B8F-9C21-6A0E5F1D8B42")]
+        unsafe interface IKeeper: IFeeder {
+            fn clean(&self);
+        }
+    }
+
+    crate::mock! {
+        class MockKeeper: IKeeper(IFeeder);
+
+        impl IKeeper {
+            fn clean(&self);
+        }
+
+        impl IFeeder {
+            fn feed(&self, portions: u32) -> HRESULT;
+        }
+    }
+
+    #[test]
+    fn calls_are_handled_by_matching_expectations() {
+        let mock = MockKeeper::new();
+        mock.expect_feed()
+            .with(|&(portions,)| portions > 10)
+            .times(1)
+            .return_const(E_FAIL);
+        mock.expect_feed().times_between(1, 2).return_const(S_OK);
+        mock.expect_clean().returning(|()| {});
+
+        let keeper = mock.query::<IKeeper>().unwrap();
+        let feeder = mock.query::<IFeeder>().unwrap();
+        unsafe {
+            assert_eq!(feeder.feed(20u32), E_FAIL);
+            assert_eq!(keeper.feed(2u32), S_OK);
+            assert_eq!(keeper.feed(20u32), S_OK);
+            keeper.clean();
+        }
+        mock.checkpoint();
+    }
+
+    #[test]
+    #[should_panic(expected = "MockKeeper::feed was called 0 time(s) but expected at least 1")]
+    fn expectations_are_verified_when_the_handle_is_dropped() {
+        let mock = MockKeeper::new();
+        mock.expect_feed().times(1).return_const(S_OK);
+        // The mock outlives the handle, so its final release does not verify anything
+        let feeder = mock.query::<IFeeder>().unwrap();
+        drop(mock);
+        drop(feeder);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected call to MockKeeper::feed")]
+    fn unexpected_calls_fail_the_test() {
+        let mock = MockKeeper::new();
+        mock.expect_feed().times(1).return_const(S_OK);
+        let feeder = mock.query::<IFeeder>().unwrap();
+        unsafe {
+            assert_eq!(feeder.feed(1u32), S_OK);
+            assert_eq!(feeder.feed(2u32), E_UNEXPECTED);
+        }
+        drop(feeder);
+        drop(mock);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "the expectation for MockKeeper::clean has no return value configured"
+    )]
+    fn calls_without_a_return_value_fail_the_test() {
+        let mock = MockKeeper::new();
+        mock.expect_clean();
+        let keeper = mock.query::<IKeeper>().unwrap();
+        unsafe { keeper.clean() };
+        mock.checkpoint();
+    }
+
+    #[test]
+    #[should_panic(expected = "MockKeeper::clean was called 0 time(s) but expected at least 1")]
+    fn missing_calls_fail_the_checkpoint() {
+        let mock = MockKeeper::new();
+        mock.expect_clean().at_least_once().returning(|()| {});
+        mock.checkpoint();
+    }
+}
@@ -0,0 +1,174 @@
+use com::interfaces::{IClassFactory, IUnknown};
+use com::production::ClassAllocation;
+use com::sys::{E_INVALIDARG, HRESULT, S_OK};
+use com::Interface;
+
+use std::sync::Arc;
+
+com::interfaces! {
+    #[uuid("9C3F1E20-5B7A-4D62-8E14-2F6A0B9D7C55")]
+    pub unsafe interface IValue: IUnknown {
+        fn value(&self) -> u32;
+    }
+
+    #[uuid("4E7B2A91-0C3D-4F58-9A61-7D2E5B8C1F04")]
+    pub unsafe interface IArea: IUnknown {
+        fn area(&self, size: Size) -> u32;
+    }
+
+    #[uuid("2A8D4C61-7B3E-4F90-A152-6E0C9D3B7F28")]
+    pub unsafe interface ISum: IUnknown {
+        unsafe fn sum(
+            &self,
+            #[size_is(count)] values: &[u8],
+            count: u8,
+            total: *mut u32,
+        ) -> HRESULT;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, com::AbiTransferable)]
+pub struct Size {
+    width: u32,
+    height: u32,
+}
+
+mod classes {
+    use super::*;
+
+    com::class! {
+        #[no_class_factory]
+        pub class Area: IArea {}
+
+        impl IArea for Area {
+            fn area(&self, size: Size) -> u32 {
+                size.width * size.height
+            }
+        }
+    }
+
+    com::class! {
+        #[no_class_factory]
+        pub class Sum: ISum {}
+
+        impl ISum for Sum {
+            unsafe fn sum(
+                &self,
+                #[size_is(count)] values: &[u8],
+                count: u8,
+                total: *mut u32,
+            ) -> HRESULT {
+                assert_eq!(values.len(), count as usize);
+                *total = values.iter().map(|&v| u32::from(v)).sum();
+                S_OK
+            }
+        }
+    }
+
+    com::class! {
+        #[no_class_factory]
+        pub class Value<T>: IValue where T: Copy + Into<u32> {
+            value: T,
+            name: &'static str,
+        }
+
+        impl IValue for Value {
+            fn value(&self) -> u32 {
+                self.value.into() + self.name.len() as u32
+            }
+        }
+    }
+
+    com::class! {
+        pub class Pair: IValue {
+            first: u32,
+            second: u32,
+        }
+
+        impl IValue for Pair {
+            fn value(&self) -> u32 {
+                self.first + self.second + 1
+            }
+        }
+    }
+
+    com::class! {
+        #[factory(init = Configured::create, state = Arc<u32>)]
+        pub class Configured: IValue {
+            value: u32,
+        }
+
+        impl IValue for Configured {
+            fn value(&self) -> u32 {
+                self.value
+            }
+        }
+    }
+
+    impl Configured {
+        fn create(config: &Arc<u32>) -> Result<ClassAllocation<Configured>, HRESULT> {
+            match **config {
+                0 => Err(E_INVALIDARG),
+                value => Ok(Configured::allocate(value)),
+            }
+        }
+    }
+}
+
+use classes::*;
+
+#[test]
+fn factories_create_instances() {
+    let factory = PairClassFactory::allocate()
+        .query::<IClassFactory>()
+        .unwrap();
+    let pair = factory.get_instance::<IValue>().unwrap();
+    assert_eq!(unsafe { pair.value() }, 1);
+
+    let factory = ConfiguredClassFactory::allocate(Arc::new(7))
+        .query::<IClassFactory>()
+        .unwrap();
+    let configured = factory.get_instance::<IValue>().unwrap();
+    assert_eq!(unsafe { configured.value() }, 7);
+
+    let factory = ConfiguredClassFactory::allocate(Arc::new(0))
+        .query::<IClassFactory>()
+        .unwrap();
+    let mut ppv = std::ptr::NonNull::dangling().as_ptr();
+    let hr = unsafe { factory.create_instance(None, &IValue::IID, &mut ppv) };
+    assert_eq!(hr, E_INVALIDARG);
+    assert!(ppv.is_null());
+}
+
+#[test]
+fn generic_classes_are_monomorphized() {
+    let byte = Value::allocate(1u8, "four").query::<IValue>().unwrap();
+    let word = Value::allocate(2u16, "").query::<IValue>().unwrap();
+    unsafe {
+        assert_eq!(byte.value(), 5);
+        assert_eq!(word.value(), 2);
+    }
+}
+
+#[test]
+fn slices_longer_than_their_size_are_rejected() {
+    let sum = Sum::allocate().query::<ISum>().unwrap();
+    let mut total = 0;
+    unsafe {
+        assert_eq!(sum.sum(&[1, 2, 3], &mut total), S_OK);
+        assert_eq!(total, 6);
+        assert_eq!(sum.sum(&[1; 256], &mut total), E_INVALIDARG);
+    }
+    assert_eq!(total, 6);
+}
+
+#[test]
+fn structs_are_passed_by_value() {
+    let area = Area::allocate().query::<IArea>().unwrap();
+    let size = Size {
+        width: 3,
+        height: 4,
+    };
+    assert_eq!(unsafe { area.area(size) }, 12);
+}
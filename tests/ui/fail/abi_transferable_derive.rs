@@ -0,0 +1,20 @@
+#[derive(Clone, Copy, com::AbiTransferable)]
+pub enum Shape {
+    Square,
+    Circle,
+}
+
+#[derive(Clone, Copy, com::AbiTransferable)]
+pub struct Unordered {
+    pub x: u32,
+    pub y: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, com::AbiTransferable)]
+pub struct Borrowed {
+    pub len: u32,
+    pub text: &'static str,
+}
+
+fn main() {}
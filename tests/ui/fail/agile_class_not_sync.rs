@@ -0,0 +1,55 @@
+mod counter {
+    use com::interfaces::{IAgileObject, IUnknown};
+
+    com::interfaces! {
+        #[uuid("7D6EA052-3FB1-4C9E-A04D-5B6C7E8F9A01")]
+        pub unsafe interface ICount: IUnknown {
+            fn next(&self) -> u32;
+        }
+    }
+
+    com::class! {
+        #[no_class_factory]
+        pub class Counter: ICount, IAgileObject {
+            count: std::cell::Cell<u32>,
+        }
+
+        impl ICount for Counter {
+            fn next(&self) -> u32 {
+                self.count.set(self.count.get() + 1);
+                self.count.get()
+            }
+        }
+
+        impl IAgileObject for Counter {}
+    }
+}
+
+mod renamed {
+    use com::interfaces::IAgileObject as Agile;
+    use com::interfaces::IUnknown;
+
+    com::interfaces! {
+        #[uuid("7D6EA053-3FB1-4C9E-A04D-5B6C7E8F9A01")]
+        pub unsafe interface IName: IUnknown {
+            fn len(&self) -> u32;
+        }
+    }
+
+    com::class! {
+        #[no_class_factory]
+        pub class Name: IName, Agile {
+            name: std::rc::Rc<String>,
+        }
+
+        impl IName for Name {
+            fn len(&self) -> u32 {
+                self.name.len() as u32
+            }
+        }
+
+        impl Agile for Name {}
+    }
+}
+
+fn main() {}
@@ -0,0 +1,33 @@
+mod probe {
+    use com::interfaces::IUnknown;
+
+    com::interfaces! {
+        #[uuid("5B4C8E30-1D9F-4A7C-8E2B-3F4A5C6D7E8F")]
+        pub unsafe interface IProbe: IUnknown {
+            fn len(&self) -> usize;
+        }
+    }
+
+    com::class! {
+        #[no_class_factory]
+        pub class Probe<T>: IProbe where T: AsRef<str> {
+            text: T,
+        }
+
+        impl IProbe for Probe {
+            fn len(&self) -> usize {
+                self.text.as_ref().len()
+            }
+        }
+    }
+}
+
+use probe::{IProbe, Probe};
+
+fn main() {
+    let probe = {
+        let text = String::from("x");
+        Probe::allocate(&text).query::<IProbe>().unwrap()
+    };
+    drop(probe);
+}
@@ -0,0 +1,23 @@
+use com::interfaces::IUnknown;
+
+com::interfaces! {
+    #[uuid("3F2A6C1E-9B7D-4E5A-8C0F-1D2E3A4B5C6D")]
+    pub unsafe interface IProbe: IUnknown {
+        fn len(&self) -> usize;
+    }
+}
+
+com::class! {
+    #[no_class_factory]
+    pub class Probe<'a>: IProbe {
+        text: &'a str,
+    }
+
+    impl IProbe for Probe {
+        fn len(&self) -> usize {
+            self.text.len()
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,22 @@
+use com::interfaces::IUnknown;
+
+com::interfaces! {
+    #[uuid("4A3B7D2F-0C8E-4F6B-9D1A-2E3F4B5C6D7E")]
+    pub unsafe interface IHolder: IUnknown {
+        fn get(&self) -> u32;
+    }
+}
+
+com::class! {
+    pub class Holder<T>: IHolder where T: Copy + Into<u32> {
+        value: T,
+    }
+
+    impl IHolder for Holder {
+        fn get(&self) -> u32 {
+            self.value.into()
+        }
+    }
+}
+
+fn main() {}
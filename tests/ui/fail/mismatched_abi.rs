@@ -0,0 +1,11 @@
+use com::interfaces;
+
+interfaces! {
+    #[uuid("B1D4F6A8-2C3E-4A5B-9D7F-0E1A2B3C4D5E")]
+    #[abi("C")]
+    pub unsafe interface IPortable: com::interfaces::IUnknown {
+        fn run(&self);
+    }
+}
+
+fn main() {}
@@ -0,0 +1,26 @@
+mod counter {
+    use com::interfaces::IUnknown;
+
+    com::interfaces! {
+        #[uuid("6C5D9F41-2EA0-4B8D-9F3C-4A5B6D7E8F90")]
+        pub unsafe interface ICount: IUnknown {
+            fn next(&self) -> u32;
+        }
+    }
+
+    com::class! {
+        #[singleton]
+        pub class Counter: ICount {
+            count: std::cell::Cell<u32>,
+        }
+
+        impl ICount for Counter {
+            fn next(&self) -> u32 {
+                self.count.set(self.count.get() + 1);
+                self.count.get()
+            }
+        }
+    }
+}
+
+fn main() {}
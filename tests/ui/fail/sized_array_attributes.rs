@@ -0,0 +1,86 @@
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F1")]
+    pub unsafe interface INoSize: com::interfaces::IUnknown {
+        fn fill(&self, #[length_is(filled)] values: &mut [u32], filled: *mut u32) -> com::sys::HRESULT;
+    }
+}
+
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F2")]
+    pub unsafe interface INotASlice: com::interfaces::IUnknown {
+        fn send(&self, #[size_is(count)] values: *const u32, count: u32) -> com::sys::HRESULT;
+    }
+}
+
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F3")]
+    pub unsafe interface IReadOnlyLength: com::interfaces::IUnknown {
+        fn send(
+            &self,
+            #[size_is(count)]
+            #[length_is(filled)]
+            values: &[u32],
+            count: u32,
+            filled: *mut u32,
+        ) -> com::sys::HRESULT;
+    }
+}
+
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F4")]
+    pub unsafe interface IDuplicateSize: com::interfaces::IUnknown {
+        fn send(&self, #[size_is(count)] #[size_is(count)] values: &[u32], count: u32) -> com::sys::HRESULT;
+    }
+}
+
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F5")]
+    pub unsafe interface IPassThrough: com::interfaces::IUnknown {
+        fn send(&self, #[pass_through] #[size_is(count)] values: &[u32], count: u32) -> com::sys::HRESULT;
+    }
+}
+
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F6")]
+    pub unsafe interface IMissingSize: com::interfaces::IUnknown {
+        fn send(&self, #[size_is(count)] values: &[u32]) -> com::sys::HRESULT;
+    }
+}
+
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F7")]
+    pub unsafe interface IArraySize: com::interfaces::IUnknown {
+        fn send(
+            &self,
+            #[size_is(counts)] values: &[u32],
+            #[size_is(values)] counts: &[u32],
+        ) -> com::sys::HRESULT;
+    }
+}
+
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F8")]
+    pub unsafe interface ISharedSize: com::interfaces::IUnknown {
+        fn send(
+            &self,
+            #[size_is(count)] keys: &[u32],
+            #[size_is(count)] values: &[u32],
+            count: u32,
+        ) -> com::sys::HRESULT;
+    }
+}
+
+com::interfaces! {
+    #[uuid("0B1C2D3E-4F50-4617-8293-A4B5C6D7E8F9")]
+    pub unsafe interface IMissingLength: com::interfaces::IUnknown {
+        fn fill(
+            &self,
+            #[size_is(count)]
+            #[length_is(filled)]
+            values: &mut [u32],
+            count: u32,
+        ) -> com::sys::HRESULT;
+    }
+}
+
+fn main() {}
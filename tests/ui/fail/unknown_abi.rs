@@ -0,0 +1,11 @@
+use com::interfaces;
+
+interfaces! {
+    #[uuid("E3A1B9C7-4D2F-4E6A-8B1C-9F0D2E4A6B8C")]
+    #[abi("fastcall")]
+    pub unsafe interface IFast: com::interfaces::IUnknown {
+        fn run(&self);
+    }
+}
+
+fn main() {}
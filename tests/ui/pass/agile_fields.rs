@@ -0,0 +1,54 @@
+use std::cell::Cell;
+
+mod local {
+    use com::interfaces::IUnknown;
+
+    com::interfaces! {
+        /// Not `com::interfaces::IAgileObject`, only named like it
+        #[uuid("7D6EA054-3FB1-4C9E-A04D-5B6C7E8F9A01")]
+        pub unsafe interface IAgileObject: IUnknown {
+            fn get(&self) -> u32;
+        }
+    }
+}
+
+mod classes {
+    use super::*;
+    use com::interfaces::IAgileObject;
+
+    com::class! {
+        #[no_class_factory]
+        pub class Local: local::IAgileObject {
+            value: Cell<u32>,
+        }
+
+        impl local::IAgileObject for Local {
+            fn get(&self) -> u32 {
+                self.value.get()
+            }
+        }
+    }
+
+    com::class! {
+        #[no_class_factory]
+        pub class Shared<T>: local::IAgileObject, IAgileObject where T: Copy + Into<u32> + Send + Sync {
+            value: T,
+        }
+
+        impl local::IAgileObject for Shared {
+            fn get(&self) -> u32 {
+                self.value.into()
+            }
+        }
+
+        impl IAgileObject for Shared {}
+    }
+}
+
+fn assert_agile<I: com::Agile>() {}
+
+fn main() {
+    assert_agile::<com::interfaces::IAgileObject>();
+    let _ = classes::Local::allocate(Cell::new(1));
+    let _ = classes::Shared::allocate(2u8);
+}
@@ -0,0 +1,59 @@
+use std::ptr::NonNull;
+
+mod portable {
+    com::interfaces! {
+        #[uuid("00000000-0000-0000-C000-000000000046")]
+        #[abi("C")]
+        pub unsafe interface IUnknown {
+            pub unsafe fn query_interface(&self, riid: *const com::sys::IID, ppv: *mut *mut std::ffi::c_void) -> com::sys::HRESULT;
+            pub unsafe fn add_ref(&self) -> u32;
+            pub unsafe fn release(&self) -> u32;
+        }
+
+        #[uuid("5A2C8E13-7B4D-4F0A-B9E6-1C3D5F7A9B20")]
+        #[abi("C")]
+        pub unsafe interface IPlugin: IUnknown {
+            pub unsafe fn version(&self) -> u32;
+        }
+    }
+}
+
+use portable::{IPlugin, IPluginVPtr, IPluginVTable, IUnknownVPtr};
+
+mod classes {
+    use super::*;
+
+    com::class! {
+        #[no_class_factory]
+        pub class Plugin: IPlugin {
+            version: u32,
+        }
+
+        impl IPlugin for Plugin {
+            fn version(&self) -> u32 {
+                self.version
+            }
+        }
+    }
+}
+
+fn version(vtable: &IPluginVTable) -> unsafe extern "C" fn(NonNull<IPluginVPtr>) -> u32 {
+    vtable.Version
+}
+
+fn release(vtable: &IPluginVTable) -> unsafe extern "C" fn(NonNull<IUnknownVPtr>) -> u32 {
+    vtable.parent.Release
+}
+
+fn main() {
+    let _ = (version, release);
+
+    let plugin = classes::Plugin::allocate(3).query::<IPlugin>().unwrap();
+    let copy = plugin.clone();
+    let unknown: portable::IUnknown = plugin.into();
+    unsafe {
+        assert_eq!(copy.version(), 3);
+        assert_eq!(unknown.add_ref(), 3);
+        assert_eq!(unknown.release(), 2);
+    }
+}
@@ -0,0 +1,53 @@
+use com::production::ClassAllocation;
+use com::sys::{CLSID, HRESULT};
+use std::sync::Arc;
+
+com::interfaces! {
+    #[uuid("7E2B9C04-1D6A-4F83-B5E0-3C9A8D2F6B17")]
+    pub unsafe interface IGreeting: com::interfaces::IUnknown {
+        fn len(&self) -> u32;
+    }
+}
+
+mod classes {
+    use super::*;
+
+    com::class! {
+        #[factory(init = Greeting::create, state = Arc<String>)]
+        pub class Greeting: IGreeting {
+            text: Arc<String>,
+        }
+
+        impl IGreeting for Greeting {
+            fn len(&self) -> u32 {
+                self.text.len() as u32
+            }
+        }
+    }
+
+    impl Greeting {
+        fn create(text: &Arc<String>) -> Result<ClassAllocation<Greeting>, HRESULT> {
+            Ok(Greeting::allocate(text.clone()))
+        }
+    }
+}
+
+pub const CLSID_GREETING: CLSID = CLSID {
+    data1: 0x7E2B_9C05,
+    data2: 0x1D6A,
+    data3: 0x4F83,
+    data4: [0xB5, 0xE0, 0x3C, 0x9A, 0x8D, 0x2F, 0x6B, 0x17],
+};
+
+com::inproc_dll_module![(
+    CLSID_GREETING,
+    classes::Greeting,
+    prog_id = "Greetings.Greeting.1",
+    state = Arc::new("hello".to_owned()),
+    threading_model = "Both"
+)];
+
+fn main() {
+    let registration = &class_registrations()[0];
+    assert_eq!(*registration.class_id(), CLSID_GREETING);
+}